@@ -0,0 +1,322 @@
+//! Command line tool to inspect and program a W25QXX.
+//!
+//! ```text
+//! cli [--backend spidev:/dev/spidev0.0 | --backend file:flash.img]
+//!     [--metrics-file metrics.prom] <command>
+//!
+//!   info                       identification and geometry
+//!   dump <address> <len> [out] hex dump, or raw bytes into `out`
+//!   flash <address> <image>    erase, program and verify an image
+//!   verify <address> <image>   compare the flash with an image
+//!   patch <address> <hex>      rewrite a few bytes, keeping the rest
+//!   protection                 write-protected ranges
+//! ```
+//!
+//! The `file:` backend runs every command against an emulated chip stored in
+//! an image file, so the tool can be exercised without hardware.
+//!
+//! `--metrics-file` writes operation counts, bytes and latency histograms of
+//! the run in the Prometheus text exposition format, for example into the
+//! directory read by the node exporter textfile collector.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt::{Debug, Write};
+use std::fs;
+use std::ops::ControlFlow;
+use std::path::PathBuf;
+use std::time::Instant;
+
+use anyhow::{anyhow, bail};
+use embedded_hal::spi::SpiDevice;
+use linux_embedded_hal::spidev::{SpiModeFlags, SpidevOptions};
+use linux_embedded_hal::{Delay, SpidevDevice};
+
+use w25qxx::sim::SimFlash;
+use w25qxx::{ChipParams, Error, Progress, W25qxx, W25QXX_SECTOR_SIZE};
+
+const W25QXX_HZ: u32 = 10_000_000;
+
+const DEFAULT_BACKEND: &str = "spidev:/dev/spidev0.0";
+
+/// Bytes shown per line by `dump`.
+const DUMP_LINE_SIZE: usize = 16;
+
+/// Upper bounds of the latency histogram buckets, in seconds.
+const LATENCY_BUCKETS: [f64; 7] = [0.0001, 0.001, 0.01, 0.1, 1.0, 10.0, 100.0];
+
+#[derive(Default)]
+struct OperationStats {
+    ok: u64,
+    failed: u64,
+    bytes: u64,
+    /// Cumulative counts, one per bucket of `LATENCY_BUCKETS`.
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    seconds: f64,
+}
+
+/// Flash operations of one run, keyed by operation name.
+#[derive(Default)]
+struct Metrics {
+    operations: BTreeMap<&'static str, OperationStats>,
+}
+
+impl Metrics {
+    /// Runs and times one flash operation touching `bytes` bytes.
+    fn record<T, E>(&mut self, operation: &'static str, bytes: usize, run: impl FnOnce() -> Result<T, E>) -> Result<T, E> {
+        let start = Instant::now();
+        let result = run();
+        let seconds = start.elapsed().as_secs_f64();
+
+        let stats = self.operations.entry(operation).or_default();
+        match result {
+            Ok(_) => stats.ok += 1,
+            Err(_) => stats.failed += 1,
+        }
+        stats.bytes += bytes as u64;
+        stats.seconds += seconds;
+        for (bucket, bound) in stats.buckets.iter_mut().zip(LATENCY_BUCKETS) {
+            if seconds <= bound {
+                *bucket += 1;
+            }
+        }
+
+        result
+    }
+
+    /// Prometheus text exposition of the collected metrics.
+    fn render(&self) -> String {
+        let mut text = String::new();
+
+        text.push_str("# HELP w25qxx_operations_total Flash operations by outcome.\n");
+        text.push_str("# TYPE w25qxx_operations_total counter\n");
+        for (operation, stats) in &self.operations {
+            let _ = writeln!(text, "w25qxx_operations_total{{operation=\"{}\",result=\"ok\"}} {}", operation, stats.ok);
+            let _ = writeln!(text, "w25qxx_operations_total{{operation=\"{}\",result=\"error\"}} {}", operation, stats.failed);
+        }
+
+        text.push_str("# HELP w25qxx_bytes_total Bytes read, programmed or erased.\n");
+        text.push_str("# TYPE w25qxx_bytes_total counter\n");
+        for (operation, stats) in &self.operations {
+            let _ = writeln!(text, "w25qxx_bytes_total{{operation=\"{}\"}} {}", operation, stats.bytes);
+        }
+
+        text.push_str("# HELP w25qxx_operation_duration_seconds Latency of flash operations.\n");
+        text.push_str("# TYPE w25qxx_operation_duration_seconds histogram\n");
+        for (operation, stats) in &self.operations {
+            let count = stats.ok + stats.failed;
+            for (bucket, bound) in stats.buckets.iter().zip(LATENCY_BUCKETS) {
+                let _ = writeln!(text, "w25qxx_operation_duration_seconds_bucket{{operation=\"{}\",le=\"{}\"}} {}", operation, bound, bucket);
+            }
+            let _ = writeln!(text, "w25qxx_operation_duration_seconds_bucket{{operation=\"{}\",le=\"+Inf\"}} {}", operation, count);
+            let _ = writeln!(text, "w25qxx_operation_duration_seconds_sum{{operation=\"{}\"}} {}", operation, stats.seconds);
+            let _ = writeln!(text, "w25qxx_operation_duration_seconds_count{{operation=\"{}\"}} {}", operation, count);
+        }
+
+        text
+    }
+}
+
+enum Backend {
+    Spidev(PathBuf),
+    File(PathBuf),
+}
+
+impl Backend {
+    fn parse(spec: &str) -> anyhow::Result<Backend> {
+        match spec.split_once(':') {
+            Some(("spidev", path)) => Ok(Backend::Spidev(path.into())),
+            Some(("file", path)) => Ok(Backend::File(path.into())),
+            _ => bail!("unknown backend `{}`, expected spidev:<device> or file:<image>", spec),
+        }
+    }
+}
+
+fn flash_error<E: Debug>(error: Error<E>) -> anyhow::Error {
+    anyhow!("flash error: {:?}", error)
+}
+
+fn parse_number(text: &str) -> anyhow::Result<usize> {
+    let value = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => usize::from_str_radix(hex, 16)?,
+        None => text.parse()?,
+    };
+
+    Ok(value)
+}
+
+fn parse_hex(text: &str) -> anyhow::Result<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        bail!("hex data must have an even number of digits");
+    }
+
+    (0..text.len())
+        .step_by(2)
+        .map(|index| Ok(u8::from_str_radix(&text[index..index + 2], 16)?))
+        .collect()
+}
+
+/// Removes `--name <value>` from the arguments and returns the value.
+fn take_option(args: &mut Vec<String>, name: &str) -> anyhow::Result<Option<String>> {
+    let Some(index) = args.iter().position(|arg| arg == name) else {
+        return Ok(None);
+    };
+    if index + 1 >= args.len() {
+        bail!("{} needs a value", name);
+    }
+
+    let value = args.remove(index + 1);
+    args.remove(index);
+    Ok(Some(value))
+}
+
+fn main() -> anyhow::Result<()> {
+    let mut args: Vec<String> = env::args().skip(1).collect();
+
+    let backend = Backend::parse(&take_option(&mut args, "--backend")?.unwrap_or(DEFAULT_BACKEND.into()))?;
+    let metrics_file = take_option(&mut args, "--metrics-file")?;
+
+    let mut metrics = Metrics::default();
+    let result = match backend {
+        Backend::Spidev(path) => {
+            let mut spi = SpidevDevice::open(&path)?;
+            let options = SpidevOptions::new()
+                .bits_per_word(8)
+                .max_speed_hz(W25QXX_HZ)
+                .mode(SpiModeFlags::SPI_MODE_0)
+                .build();
+            spi.configure(&options)?;
+            run(spi, &args, &mut metrics)
+        }
+        Backend::File(path) => run(SimFlash::open(&path, ChipParams::default())?, &args, &mut metrics),
+    };
+
+    /* Failed runs are worth monitoring too */
+    if let Some(path) = metrics_file {
+        fs::write(path, metrics.render())?;
+    }
+
+    result
+}
+
+fn run<SPI: SpiDevice>(spi: SPI, args: &[String], metrics: &mut Metrics) -> anyhow::Result<()> {
+    let flash = W25qxx::new(spi, Delay).map_err(flash_error)?;
+    let mut flash = flash.init().map_err(|e| flash_error(e.error))?;
+
+    let Some((command, args)) = args.split_first() else {
+        bail!("missing command: info, dump, flash, verify, patch or protection");
+    };
+
+    match (command.as_str(), args) {
+        ("info", []) => info(&mut flash),
+        ("dump", [address, len]) => dump(&mut flash, metrics, parse_number(address)?, parse_number(len)?, None),
+        ("dump", [address, len, out]) => dump(&mut flash, metrics, parse_number(address)?, parse_number(len)?, Some(out)),
+        ("flash", [address, image]) => program(&mut flash, metrics, parse_number(address)?, &fs::read(image)?),
+        ("verify", [address, image]) => verify(&mut flash, metrics, parse_number(address)?, &fs::read(image)?),
+        ("patch", [address, data]) => patch(&mut flash, metrics, parse_number(address)?, &parse_hex(data)?),
+        ("protection", []) => protection(&mut flash),
+        _ => bail!("unknown command or wrong arguments: {}", command),
+    }
+}
+
+fn info<SPI: SpiDevice>(flash: &mut W25qxx<SPI, Delay>) -> anyhow::Result<()> {
+    let device = flash.device_info();
+    println!("Manufacturer ID: 0x{:02X}", device.manufacturer_id);
+    println!("Device ID: 0x{:04X}", device.device_id);
+    println!("Family: {:?}", device.family);
+    println!("Unique ID: {:016X}", flash.unique_id().map_err(flash_error)?);
+    let geometry = flash.geometry();
+    println!("Capacity: {} bytes", geometry.capacity);
+    println!("Page size: {} bytes", geometry.page_size);
+    println!("Sector size: {} bytes ({} sectors)", geometry.sector_size, geometry.sectors());
+    println!("Block sizes: {} / {} bytes", geometry.block32_size, geometry.block64_size);
+    match flash.read_sfdp().map_err(flash_error)? {
+        Some(sfdp) => println!("SFDP: rev {}.{}, {} bytes, {:?}", sfdp.revision.0, sfdp.revision.1, sfdp.capacity, sfdp.addressing),
+        None => println!("SFDP: not supported"),
+    }
+    println!("Read mode: {:?}", flash.config().read_mode);
+    println!("Address width: {:?}", flash.address_width());
+
+    Ok(())
+}
+
+fn dump<SPI: SpiDevice>(flash: &mut W25qxx<SPI, Delay>, metrics: &mut Metrics, address: usize, len: usize, out: Option<&String>) -> anyhow::Result<()> {
+    let mut data = vec![0; len];
+    metrics.record("read", len, || flash.read(address as u32, &mut data)).map_err(flash_error)?;
+
+    if let Some(out) = out {
+        fs::write(out, &data)?;
+        println!("Wrote {} bytes to {}", len, out);
+        return Ok(());
+    }
+
+    for (index, line) in data.chunks(DUMP_LINE_SIZE).enumerate() {
+        let hex: Vec<String> = line.iter().map(|byte| format!("{:02X}", byte)).collect();
+        println!("{:08X}: {}", address + index * DUMP_LINE_SIZE, hex.join(" "));
+    }
+
+    Ok(())
+}
+
+fn program<SPI: SpiDevice>(flash: &mut W25qxx<SPI, Delay>, metrics: &mut Metrics, address: usize, image: &[u8]) -> anyhow::Result<()> {
+    if !address.is_multiple_of(W25QXX_SECTOR_SIZE) {
+        bail!("flash address must be sector aligned (0x{:X})", W25QXX_SECTOR_SIZE);
+    }
+
+    let erase_len = image.len().div_ceil(W25QXX_SECTOR_SIZE) * W25QXX_SECTOR_SIZE;
+    /* The chip silently ignores programs and erases of protected sectors */
+    if let Some(range) = flash.protected_overlap(address as u32, erase_len).map_err(flash_error)? {
+        bail!("0x{:08X}..0x{:08X} is write-protected", range.start, range.end);
+    }
+    flash.on_progress(print_progress);
+    metrics.record("erase", erase_len, || flash.erase(address as u32, erase_len)).map_err(flash_error)?;
+    metrics.record("write", image.len(), || flash.write(address as u32, image)).map_err(flash_error)?;
+    println!("Programmed {} bytes at 0x{:08X}", image.len(), address);
+
+    verify(flash, metrics, address, image)
+}
+
+/// One status line on stderr, rewritten in place until the operation ends.
+fn print_progress(progress: &Progress) -> ControlFlow<()> {
+    eprint!("\r{:?} 0x{:08X}: {}/{} bytes", progress.operation, progress.address, progress.done, progress.total);
+    if progress.done == progress.total {
+        eprintln!();
+    }
+    ControlFlow::Continue(())
+}
+
+fn verify<SPI: SpiDevice>(flash: &mut W25qxx<SPI, Delay>, metrics: &mut Metrics, address: usize, image: &[u8]) -> anyhow::Result<()> {
+    let mut data = vec![0; image.len()];
+    metrics.record("read", image.len(), || flash.read(address as u32, &mut data)).map_err(flash_error)?;
+
+    match data.iter().zip(image).position(|(read, expected)| read != expected) {
+        Some(offset) => bail!("mismatch at 0x{:08X}", address + offset),
+        None => {
+            println!("Verified {} bytes at 0x{:08X}", image.len(), address);
+            Ok(())
+        }
+    }
+}
+
+fn protection<SPI: SpiDevice>(flash: &mut W25qxx<SPI, Delay>) -> anyhow::Result<()> {
+    println!("Status registers: {:?}", flash.status_protection().map_err(flash_error)?);
+
+    let mut count = 0;
+    flash.protection_map(|protected| {
+        println!("0x{:08X}..0x{:08X}: {:?}", protected.range.start, protected.range.end, protected.source);
+        count += 1;
+    }).map_err(flash_error)?;
+    if count == 0 {
+        println!("Nothing write-protected");
+    }
+
+    Ok(())
+}
+
+fn patch<SPI: SpiDevice>(flash: &mut W25qxx<SPI, Delay>, metrics: &mut Metrics, address: usize, data: &[u8]) -> anyhow::Result<()> {
+    /* Read-modify-erase-write of every sector touched by the patch */
+    metrics.record("overwrite", data.len(), || flash.overwrite(address as u32, data)).map_err(flash_error)?;
+
+    println!("Patched {} bytes at 0x{:08X}", data.len(), address);
+    verify(flash, metrics, address, data)
+}
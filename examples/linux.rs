@@ -1,11 +1,10 @@
 use std::collections::HashMap;
 use anyhow::Ok;
-use linux_embedded_hal::spidev::{Spidev, SpiModeFlags, SpidevOptions};
+use linux_embedded_hal::spidev::{SpiModeFlags, SpidevOptions};
 use linux_embedded_hal::sysfs_gpio::Direction;
-use linux_embedded_hal::SysfsPin;
+use linux_embedded_hal::{Delay, SpidevBus, SysfsPin};
 
-mod w25qxx;
-use w25qxx::W25qxx;
+use w25qxx::{SpiBusDevice, W25qxx};
 
 const W25QXX_HZ: u32 = 10_000_000;
 
@@ -50,7 +49,7 @@ fn main() -> anyhow::Result<()> {
     spi_flash_cs.set_direction(Direction::Out).unwrap();
     spi_flash_cs.set_value(1).unwrap();
 
-    let mut spi1 = Spidev::open("/dev/spidev0.0")?;
+    let mut spi1 = SpidevBus::open("/dev/spidev0.0")?;
     let options = SpidevOptions::new()
         .bits_per_word(8)
         .max_speed_hz(W25QXX_HZ)
@@ -58,13 +57,14 @@ fn main() -> anyhow::Result<()> {
         .build();
     spi1.configure(&options)?;
 
-    let mut flash_memory: W25qxx<Spidev, SysfsPin> = W25qxx::new(spi1, spi_flash_cs).expect("Error to initializate interface SPI");
+    let spi_flash = SpiBusDevice::new(spi1, spi_flash_cs)?;
+    let flash_memory = W25qxx::new(spi_flash, Delay).expect("Error to initializate interface SPI");
 
     // Ahora puedes continuar con el uso de `flash`
-    if let Err(e) = flash_memory.init() {
+    let mut flash_memory: W25qxx<SpiBusDevice<SpidevBus, SysfsPin>, Delay> = flash_memory.init().map_err(|e| {
         eprintln!("Error Initialize: {:?}", e);
-        return Err(anyhow::Error::msg("Initialization failed"));
-    }
+        anyhow::Error::msg("Initialization failed")
+    })?;
 
     // Direccion y datos de ejemplo para escribir y leer
     let address: u32 = 0x00000000;
@@ -72,8 +72,8 @@ fn main() -> anyhow::Result<()> {
     let mut read_buffer: [u8; 64] = [0x00; 64];  // Buffer para almacenar datos leídos
 
     // Fill Write Buffer
-    for i in 0..data_to_write.len() {
-        data_to_write[i] = i as u8; // Rellena con valores de 0 a 15
+    for (i, byte) in data_to_write.iter_mut().enumerate() {
+        *byte = i as u8; // Rellena con valores de 0 a 15
     }
 
     println!("Erase Data {:?}:\n", 4096);
@@ -82,7 +82,7 @@ fn main() -> anyhow::Result<()> {
 
     println!("Write Data: {:?}\n", data_to_write);
 
-    let _ = flash_memory.write(address, &mut data_to_write);
+    let _ = flash_memory.write(address, &data_to_write);
 
     let _ = flash_memory.read(address, &mut read_buffer);
 
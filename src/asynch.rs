@@ -0,0 +1,326 @@
+//! Async variant of the driver on top of `embedded-hal-async`.
+//!
+//! [`W25qxxAsync`] mirrors the blocking [`W25qxx`](crate::W25qxx) API but
+//! awaits the SPI transfers and yields to the executor through [`DelayNs`]
+//! while the chip is busy, so it can run under Embassy or any other executor.
+//!
+//! It does not switch dies: on stacked-die parts only the first die is
+//! used. Parts larger than 16 MiB are addressed with the 4-byte opcodes
+//! (0x0C/0x12/0x21/0xDC), leaving the chip in 3-byte mode.
+
+use crate::builder::{AddressWidth, BusyPoll, Config, IdCheck};
+use crate::checksum::Checksum;
+use crate::chip::{ChipParams, Geometry, Opcodes};
+use crate::w25qxx::{
+    check_id, erase_operation, erase_step, trace_command, trace_transaction, wide_address_frame,
+    BusyOperation, Error, StatusRegister, RESET_DELAY_US, W25QXX_PAGE_SIZE, W25QXX_SECTOR_SIZE,
+};
+use embedded_hal_async::delay::DelayNs;
+use embedded_hal_async::spi::{Operation, SpiDevice};
+
+/// Async W25QXX driver on top of an `embedded-hal-async` [`SpiDevice`].
+pub struct W25qxxAsync<SPI, D> {
+    spi: SPI,
+    delay: D,
+    params: ChipParams,
+    /// Busy polling and verify-after-write; the other options are for the
+    /// blocking driver.
+    config: Config,
+    /// Last command that may keep the chip busy.
+    pending: BusyOperation,
+}
+
+impl<SPI, D> W25qxxAsync<SPI, D>
+where
+    SPI: SpiDevice,
+    D: DelayNs,
+{
+    pub fn new(spi: SPI, delay: D) -> Result<W25qxxAsync<SPI, D>, Error<SPI::Error>> {
+        Self::with_params(spi, delay, ChipParams::default(), Config::default())
+    }
+
+    /// Creates a driver for a part described by `params` instead of the
+    /// default W25Q128. Of `config`, the busy poll, busy timeouts and
+    /// verify-after-write apply.
+    pub fn with_params(spi: SPI, delay: D, params: ChipParams, config: Config) -> Result<W25qxxAsync<SPI, D>, Error<SPI::Error>> {
+        Ok(W25qxxAsync { spi, delay, params, config, pending: BusyOperation::Unknown })
+    }
+
+    /// Parameters of the part being driven.
+    pub fn params(&self) -> &ChipParams {
+        &self.params
+    }
+
+    /// Size, page, sector and block sizes of the chip.
+    pub fn geometry(&self) -> Geometry {
+        self.params.geometry()
+    }
+
+    /// Total size of the chip in bytes.
+    pub fn capacity(&self) -> usize {
+        self.params.capacity
+    }
+
+    /// Gives back the SPI device and the delay.
+    pub fn release(self) -> (SPI, D) {
+        (self.spi, self.delay)
+    }
+
+    /// Reads every programmed page back, failing the write with
+    /// [`Error::VerifyFailed`] on the first mismatch.
+    pub fn set_verify_after_write(&mut self, enabled: bool) {
+        self.config.verify_after_write = enabled;
+    }
+
+    /// Address width for the detected part.
+    pub fn address_width(&self) -> AddressWidth {
+        AddressWidth::Auto.resolve(self.params.die_size())
+    }
+
+    /// Commands for the address width in use.
+    fn opcodes(&self) -> Opcodes {
+        match self.address_width() {
+            AddressWidth::Four => self.params.opcodes.four_byte(),
+            _ => self.params.opcodes,
+        }
+    }
+
+    pub async fn init(&mut self) -> Result<(), Error<SPI::Error>> {
+        /* A restart without power cycle may leave another die selected */
+        if self.params.dies > 1 {
+            self.command(&[self.params.opcodes.die_select, 0]).await?;
+        }
+
+        self.read_jedec_register().await?;
+        self.reset().await
+    }
+
+    pub async fn read(&mut self, address: u32, buffer: &mut [u8]) -> Result<(), Error<SPI::Error>> {
+        /* Argument check */
+        if buffer.is_empty() {
+            return Err(Error::InvalidArgument);
+        }
+        self.check_bounds(address, buffer.len())?;
+
+        /* The array only reads back once the chip is idle */
+        self.busy_wait().await?;
+
+        let (tx_cmd, cmd_len) = wide_address_frame(self.opcodes().fast_read, address, self.address_width());
+        let dummy_buffer: [u8; 1] = [0x00; 1];
+
+        self.transfer(&mut [
+            Operation::Write(&tx_cmd[..cmd_len]),
+            Operation::Write(&dummy_buffer),
+            Operation::Read(buffer),
+        ])
+        .await
+        .map_err(Error::SPIError)
+    }
+
+    /// Streams `len` bytes starting at `address` through `hasher` and returns
+    /// the resulting digest.
+    pub async fn digest<C: Checksum>(&mut self, address: u32, len: usize, mut hasher: C) -> Result<C::Output, Error<SPI::Error>> {
+        let mut chunk: [u8; W25QXX_PAGE_SIZE] = [0; W25QXX_PAGE_SIZE];
+        let mut offset: usize = 0;
+
+        while offset < len {
+            let chunk_size = core::cmp::min(len - offset, chunk.len());
+            self.read(address + offset as u32, &mut chunk[..chunk_size]).await?;
+            hasher.update(&chunk[..chunk_size]);
+            offset += chunk_size;
+        }
+
+        Ok(hasher.finalize())
+    }
+
+    pub async fn write(&mut self, address: u32, buffer: &[u8]) -> Result<(), Error<SPI::Error>> {
+        /* Past the end, 3-byte addresses would wrap around to 0 */
+        self.check_bounds(address, buffer.len())?;
+
+        let mut offset: usize = 0;
+        let mut addr: u32 = address;
+
+        while offset < buffer.len() {
+            /* Never cross a page boundary within one program command */
+            let write_size = core::cmp::min(
+                W25QXX_PAGE_SIZE - (addr as usize % W25QXX_PAGE_SIZE),
+                buffer.len() - offset,
+            );
+
+            self.busy_wait().await?;
+            self.write_enable().await?;
+
+            let (tx_cmd, cmd_len) = wide_address_frame(self.opcodes().page_program, addr, self.address_width());
+            self.pending = BusyOperation::PageProgram;
+            self.transfer(&mut [
+                Operation::Write(&tx_cmd[..cmd_len]),
+                Operation::Write(&buffer[offset..(offset + write_size)]),
+            ])
+            .await
+            .map_err(Error::SPIError)?;
+
+            if self.config.verify_after_write {
+                self.verify_page(addr, &buffer[offset..(offset + write_size)]).await?;
+            }
+
+            offset += write_size;
+            addr += write_size as u32;
+        }
+
+        Ok(())
+    }
+
+    /// Reads back a freshly programmed page and compares it with `expected`.
+    async fn verify_page(&mut self, address: u32, expected: &[u8]) -> Result<(), Error<SPI::Error>> {
+        let mut readback: [u8; W25QXX_PAGE_SIZE] = [0; W25QXX_PAGE_SIZE];
+
+        self.busy_wait().await?;
+        self.read(address, &mut readback[..expected.len()]).await?;
+
+        if let Some(offset) = readback.iter().zip(expected).position(|(read, written)| read != written) {
+            return Err(Error::VerifyFailed { address: address + offset as u32 });
+        }
+
+        Ok(())
+    }
+
+    pub async fn erase(&mut self, address: u32, len: usize) -> Result<(), Error<SPI::Error>> {
+        /* The whole chip: one Chip Erase beats thousands of block erases */
+        if address == 0 && len == self.params.capacity {
+            return self.chip_erase().await;
+        }
+
+        self.check_bounds(address, len)?;
+        let u_end: u32 = address.checked_add(len as u32).ok_or(Error::InvalidAddress)?;
+        let mut size: usize = len;
+        let mut addr: u32 = address;
+
+        if !addr.is_multiple_of(W25QXX_SECTOR_SIZE as u32) || !len.is_multiple_of(W25QXX_SECTOR_SIZE) {
+            return Err(Error::NotAligned);
+        }
+
+        while addr < u_end {
+            let opcodes = self.opcodes();
+            let Some((cmd, erase_size)) = erase_step(&opcodes, addr, size, self.address_width() == AddressWidth::Three) else {
+                return Err(Error::NotAligned);
+            };
+
+            self.busy_wait().await?;
+            self.write_enable().await?;
+            self.pending = erase_operation(&opcodes, cmd);
+            let (tx_cmd, cmd_len) = wide_address_frame(cmd, addr, self.address_width());
+            self.command(&tx_cmd[..cmd_len]).await?;
+
+            size -= erase_size;
+            addr += erase_size as u32;
+        }
+
+        Ok(())
+    }
+
+    pub async fn chip_erase(&mut self) -> Result<(), Error<SPI::Error>> {
+        self.busy_wait().await?;
+        self.write_enable().await?;
+        self.pending = BusyOperation::ChipErase;
+        self.command(&[self.params.opcodes.chip_erase]).await
+    }
+
+    async fn read_jedec_register(&mut self) -> Result<(), Error<SPI::Error>> {
+        let tx_cmd: [u8; 1] = [self.params.opcodes.read_id];
+        let mut rx_buffer: [u8; 3] = [0; 3];
+
+        self.transfer(&mut [Operation::Write(&tx_cmd), Operation::Read(&mut rx_buffer)])
+            .await
+            .map_err(Error::SPIError)?;
+
+        check_id(&mut self.params, IdCheck::Params, rx_buffer)?;
+
+        /* Die 0 is selected; the others stay out of reach */
+        self.params.capacity = self.params.die_size();
+        self.params.dies = 1;
+
+        Ok(())
+    }
+
+    async fn reset(&mut self) -> Result<(), Error<SPI::Error>> {
+        self.busy_wait().await?;
+        self.command(&[self.params.opcodes.enable_reset]).await?;
+        self.command(&[self.params.opcodes.reset]).await?;
+        self.delay.delay_us(RESET_DELAY_US).await;
+
+        Ok(())
+    }
+
+    async fn read_status_register1(&mut self) -> Result<u8, Error<SPI::Error>> {
+        let mut rx_buffer: [u8; 1] = [0; 1];
+        let read_status1 = self.params.opcodes.read_status1;
+
+        self.transfer(&mut [
+            Operation::Write(&[read_status1]),
+            Operation::Read(&mut rx_buffer),
+        ])
+        .await
+        .map_err(Error::SPIError)?;
+
+        Ok(rx_buffer[0])
+    }
+
+    /// Polls until the chip is idle, as the blocking driver does, for at
+    /// most the configured timeout of the pending operation.
+    async fn busy_wait(&mut self) -> Result<(), Error<SPI::Error>> {
+        let timeout_us = self.config.busy_timeouts.for_operation(self.pending);
+        let typical_us = self.pending.typical_us();
+        let mut interval_us = match self.config.busy_poll {
+            BusyPoll::Fixed => self.config.busy_poll_interval_us,
+            BusyPoll::Backoff => (typical_us / 16).max(1),
+        };
+        let mut waited_us: u32 = 0;
+
+        while self.read_status_register1().await? & StatusRegister::Busy as u8 != 0 {
+            if waited_us >= timeout_us {
+                return Err(Error::Timeout { operation: self.pending });
+            }
+
+            self.delay.delay_us(interval_us).await;
+            waited_us = waited_us.saturating_add(interval_us);
+
+            if self.config.busy_poll == BusyPoll::Backoff {
+                interval_us = interval_us.saturating_mul(2).min((typical_us / 4).max(1));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Fails with [`Error::InvalidAddress`] unless `[address, address + len)`
+    /// lies on the part.
+    fn check_bounds(&self, address: u32, len: usize) -> Result<(), Error<SPI::Error>> {
+        if address as u64 + len as u64 > self.params.capacity as u64 {
+            return Err(Error::InvalidAddress);
+        }
+
+        Ok(())
+    }
+
+    async fn write_enable(&mut self) -> Result<(), Error<SPI::Error>> {
+        self.command(&[self.params.opcodes.write_enable]).await?;
+
+        if self.read_status_register1().await? & StatusRegister::WriteEnable as u8 == 0 {
+            return Err(Error::WriteEnableFailed);
+        }
+
+        Ok(())
+    }
+
+    async fn command(&mut self, tx_cmd: &[u8]) -> Result<(), Error<SPI::Error>> {
+        self.transfer(&mut [Operation::Write(tx_cmd)]).await.map_err(Error::SPIError)
+    }
+
+    /// Runs one chip select cycle and dumps its bytes under `trace-bytes`.
+    async fn transfer(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), SPI::Error> {
+        let result = self.spi.transaction(operations).await;
+        trace_transaction(operations);
+        trace_command(&self.params.opcodes, operations);
+        result
+    }
+}
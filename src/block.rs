@@ -0,0 +1,223 @@
+//! 512-byte block device view of the flash and MBR partition table helpers.
+//!
+//! [`FlashBlockDevice`] turns any `embedded-storage` [`NorFlash`] (such as
+//! [`W25qxx`](crate::W25qxx)) into a [`BlockDevice`] with PC-style 512-byte
+//! blocks, and [`read_mbr`] / [`write_mbr`] keep a classic MBR partition
+//! table in block 0. Block writes are turned into read-modify-erase-write
+//! cycles of the underlying sector.
+//!
+//! [`BlockDevice`] is this crate's own trait; a filesystem crate expecting
+//! its own block device trait needs a small adapter on top.
+
+use embedded_storage::nor_flash::NorFlash;
+
+/// Size of one logical block.
+pub const BLOCK_SIZE: usize = 512;
+
+/// Largest erase unit supported by [`FlashBlockDevice`].
+const MAX_SECTOR_SIZE: usize = 4096;
+
+/// One logical block.
+pub type Block = [u8; BLOCK_SIZE];
+
+/// Byte offset of the partition table inside the MBR.
+const MBR_TABLE_OFFSET: usize = 0x1BE;
+
+/// Size of one partition table entry.
+const MBR_ENTRY_SIZE: usize = 16;
+
+/// Boot signature found at the end of a valid MBR.
+const MBR_SIGNATURE: [u8; 2] = [0x55, 0xAA];
+
+/// Block-addressed storage.
+pub trait BlockDevice {
+    type Error;
+
+    /// Reads consecutive blocks starting at block `start`.
+    fn read_blocks(&mut self, start: u32, blocks: &mut [Block]) -> Result<(), Self::Error>;
+
+    /// Writes consecutive blocks starting at block `start`.
+    fn write_blocks(&mut self, start: u32, blocks: &[Block]) -> Result<(), Self::Error>;
+
+    /// Number of blocks on the device.
+    fn num_blocks(&self) -> u32;
+}
+
+/// Error of a [`FlashBlockDevice`]: either the blocks lie past the end of
+/// the device or the flash failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum BlockError<E> {
+    OutOfBounds,
+    Flash(E),
+}
+
+/// [`BlockDevice`] adapter over a NOR flash.
+pub struct FlashBlockDevice<F> {
+    flash: F,
+    sector: [u8; MAX_SECTOR_SIZE],
+}
+
+impl<F: NorFlash> FlashBlockDevice<F> {
+    pub fn new(flash: F) -> Self {
+        /* The adapter buffers whole erase units and assumes blocks never straddle them */
+        const { assert!(F::ERASE_SIZE <= MAX_SECTOR_SIZE && F::ERASE_SIZE.is_multiple_of(BLOCK_SIZE)) };
+
+        FlashBlockDevice { flash, sector: [0xFF; MAX_SECTOR_SIZE] }
+    }
+
+    /// Gives back the wrapped flash.
+    pub fn into_inner(self) -> F {
+        self.flash
+    }
+
+    /// Fails with [`BlockError::OutOfBounds`] unless `count` blocks from
+    /// `start` lie on the device.
+    fn check_bounds(&self, start: u32, count: usize) -> Result<(), BlockError<F::Error>> {
+        if start as u64 + count as u64 > self.num_blocks() as u64 {
+            return Err(BlockError::OutOfBounds);
+        }
+
+        Ok(())
+    }
+}
+
+impl<F: NorFlash> BlockDevice for FlashBlockDevice<F> {
+    type Error = BlockError<F::Error>;
+
+    fn read_blocks(&mut self, start: u32, blocks: &mut [Block]) -> Result<(), Self::Error> {
+        self.check_bounds(start, blocks.len())?;
+
+        for (index, block) in blocks.iter_mut().enumerate() {
+            let address = (start as usize + index) * BLOCK_SIZE;
+            self.flash.read(address as u32, block).map_err(BlockError::Flash)?;
+        }
+
+        Ok(())
+    }
+
+    fn write_blocks(&mut self, start: u32, blocks: &[Block]) -> Result<(), Self::Error> {
+        self.check_bounds(start, blocks.len())?;
+
+        let blocks_per_sector = F::ERASE_SIZE / BLOCK_SIZE;
+        let mut index: usize = 0;
+
+        while index < blocks.len() {
+            let block = start as usize + index;
+            let sector_address = (block / blocks_per_sector * F::ERASE_SIZE) as u32;
+            let first = block % blocks_per_sector;
+            let count = core::cmp::min(blocks_per_sector - first, blocks.len() - index);
+
+            /* Merge the new blocks into the current sector contents */
+            let sector = &mut self.sector[..F::ERASE_SIZE];
+            self.flash.read(sector_address, sector).map_err(BlockError::Flash)?;
+
+            let mut needs_erase = false;
+            for (slot, new) in sector[first * BLOCK_SIZE..(first + count) * BLOCK_SIZE]
+                .chunks_exact_mut(BLOCK_SIZE)
+                .zip(&blocks[index..index + count])
+            {
+                /* Programming can only clear bits */
+                needs_erase |= slot.iter().zip(new.iter()).any(|(old, new)| old & new != *new);
+                slot.copy_from_slice(new);
+            }
+
+            if needs_erase {
+                self.flash.erase(sector_address, sector_address + F::ERASE_SIZE as u32).map_err(BlockError::Flash)?;
+                self.flash.write(sector_address, sector).map_err(BlockError::Flash)?;
+            } else {
+                let offset = first * BLOCK_SIZE;
+                self.flash
+                    .write(sector_address + offset as u32, &sector[offset..offset + count * BLOCK_SIZE])
+                    .map_err(BlockError::Flash)?;
+            }
+
+            index += count;
+        }
+
+        Ok(())
+    }
+
+    fn num_blocks(&self) -> u32 {
+        (self.flash.capacity() / BLOCK_SIZE) as u32
+    }
+}
+
+/// One primary partition of an MBR.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PartitionEntry {
+    /// 0x80 for the active (bootable) partition.
+    pub status: u8,
+    /// Partition type (0x0C FAT32 LBA, 0x83 Linux, ...). Zero for unused slots.
+    pub kind: u8,
+    /// First block of the partition.
+    pub start_lba: u32,
+    /// Length of the partition in blocks.
+    pub num_blocks: u32,
+}
+
+impl PartitionEntry {
+    pub fn is_used(&self) -> bool {
+        self.kind != 0
+    }
+
+    fn parse(raw: &[u8]) -> Self {
+        PartitionEntry {
+            status: raw[0],
+            kind: raw[4],
+            start_lba: u32::from_le_bytes([raw[8], raw[9], raw[10], raw[11]]),
+            num_blocks: u32::from_le_bytes([raw[12], raw[13], raw[14], raw[15]]),
+        }
+    }
+
+    fn encode(&self, raw: &mut [u8]) {
+        raw.fill(0);
+        raw[0] = self.status;
+        /* CHS fields are obsolete; 0xFFFFFE tells readers to use the LBA fields */
+        if self.is_used() {
+            raw[1..4].copy_from_slice(&[0xFE, 0xFF, 0xFF]);
+            raw[5..8].copy_from_slice(&[0xFE, 0xFF, 0xFF]);
+        }
+        raw[4] = self.kind;
+        raw[8..12].copy_from_slice(&self.start_lba.to_le_bytes());
+        raw[12..16].copy_from_slice(&self.num_blocks.to_le_bytes());
+    }
+}
+
+/// Reads the MBR from block 0. Returns `None` when the boot signature is
+/// missing.
+pub fn read_mbr<D: BlockDevice>(device: &mut D) -> Result<Option<[PartitionEntry; 4]>, D::Error> {
+    let mut mbr: [Block; 1] = [[0; BLOCK_SIZE]];
+    device.read_blocks(0, &mut mbr)?;
+
+    if mbr[0][BLOCK_SIZE - 2..] != MBR_SIGNATURE {
+        return Ok(None);
+    }
+
+    let mut entries = [PartitionEntry::default(); 4];
+    for (entry, raw) in entries.iter_mut().zip(mbr[0][MBR_TABLE_OFFSET..].chunks_exact(MBR_ENTRY_SIZE)) {
+        *entry = PartitionEntry::parse(raw);
+    }
+
+    Ok(Some(entries))
+}
+
+/// Writes `entries` as the partition table of block 0, keeping the boot code
+/// area of an existing MBR.
+pub fn write_mbr<D: BlockDevice>(device: &mut D, entries: &[PartitionEntry; 4]) -> Result<(), D::Error> {
+    let mut mbr: [Block; 1] = [[0; BLOCK_SIZE]];
+    device.read_blocks(0, &mut mbr)?;
+
+    if mbr[0][BLOCK_SIZE - 2..] != MBR_SIGNATURE {
+        /* No previous MBR, start from an empty boot code area */
+        mbr[0].fill(0);
+    }
+
+    for (entry, raw) in entries.iter().zip(mbr[0][MBR_TABLE_OFFSET..].chunks_exact_mut(MBR_ENTRY_SIZE)) {
+        entry.encode(raw);
+    }
+    mbr[0][BLOCK_SIZE - 2..].copy_from_slice(&MBR_SIGNATURE);
+
+    device.write_blocks(0, &mbr)
+}
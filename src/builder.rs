@@ -0,0 +1,491 @@
+//! Construction of a [`W25qxx`] with non-default runtime options.
+//!
+//! [`W25qxx::new`] picks the W25Q128 table, fast reads, addresses as wide as
+//! the detected part needs and a 1 ms busy poll bounded by the datasheet
+//! timeouts. [`W25qxxBuilder`] lets
+//! each of these be chosen before the driver is created.
+
+use crate::chip::{ChipParams, Quirks};
+use crate::multi_io::{Lines, MultiIoTransfer};
+use crate::w25qxx::{BusyOperation, Error, Uninit, W25qxx, W25QXX_MAX_DIES, W25QXX_PAGE_SIZE};
+use embedded_hal::delay::DelayNs;
+use embedded_hal::spi::{ErrorType, SpiDevice};
+
+/// Default interval between two status register polls while the chip is busy.
+pub const DEFAULT_BUSY_POLL_INTERVAL_US: u32 = 1_000;
+
+/// How [`W25qxx`] spaces its status register polls while the chip is busy.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum BusyPoll {
+    /// Poll every [`Config::busy_poll_interval_us`].
+    #[default]
+    Fixed,
+    /// Start at a sixteenth of the typical duration of the pending operation
+    /// and double the interval up to a quarter of it: page programs are
+    /// polled every few tens of microseconds, chip erases every few seconds.
+    Backoff,
+}
+
+/// Longest time the chip may stay busy after each kind of operation before
+/// the driver gives up with [`Error::Timeout`](crate::Error::Timeout). The
+/// defaults are the W25Q128JV datasheet maximums (tPP, tSE, tBE1, tBE2, tW,
+/// tCE).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct BusyTimeouts {
+    pub page_program_us: u32,
+    pub sector_erase_us: u32,
+    pub block32_erase_us: u32,
+    pub block64_erase_us: u32,
+    /// Status register write (tW).
+    pub write_status_us: u32,
+    /// Also used when the chip may still be busy with anything, such as
+    /// before the reset of `init()`.
+    pub chip_erase_us: u32,
+}
+
+impl BusyTimeouts {
+    /// Longest wait allowed for `operation` to finish.
+    pub fn for_operation(&self, operation: BusyOperation) -> u32 {
+        match operation {
+            BusyOperation::PageProgram => self.page_program_us,
+            BusyOperation::SectorErase => self.sector_erase_us,
+            BusyOperation::Block32Erase => self.block32_erase_us,
+            BusyOperation::Block64Erase => self.block64_erase_us,
+            BusyOperation::WriteStatusRegister => self.write_status_us,
+            BusyOperation::ChipErase | BusyOperation::Unknown => self.chip_erase_us,
+        }
+    }
+}
+
+impl Default for BusyTimeouts {
+    fn default() -> Self {
+        BusyTimeouts {
+            page_program_us: 3_000,
+            sector_erase_us: 400_000,
+            block32_erase_us: 1_600_000,
+            block64_erase_us: 2_000_000,
+            write_status_us: 15_000,
+            chip_erase_us: 200_000_000,
+        }
+    }
+}
+
+/// Command used by [`W25qxx::read`](crate::W25qxx::read).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ReadMode {
+    /// Read Data (0x03), no dummy byte. Limited to lower SPI clocks.
+    Normal,
+    /// Fast Read (0x0B), one dummy byte by default.
+    #[default]
+    Fast,
+    /// Fast Read Dual Output (0x3B): data on two lines, about twice the
+    /// throughput of `Fast`. Needs a [`MultiIoTransfer`].
+    DualOutput,
+    /// Fast Read Quad Output (0x6B): data on four lines. `init()` sets the
+    /// Quad Enable bit. Needs a [`MultiIoTransfer`].
+    QuadOutput,
+    /// Fast Read Dual I/O (0xBB): address, mode bits and data on two lines,
+    /// saving the dummy clocks of `DualOutput` on short reads. Needs a
+    /// [`MultiIoTransfer`].
+    DualIo,
+    /// Fast Read Quad I/O (0xEB): address, mode bits and data on four
+    /// lines, the fastest single-command read. `init()` sets the Quad Enable
+    /// bit. Needs a [`MultiIoTransfer`].
+    QuadIo,
+}
+
+impl ReadMode {
+    /// Lines the data is received on.
+    pub fn data_lines(&self) -> Lines {
+        match self {
+            ReadMode::Normal | ReadMode::Fast => Lines::Single,
+            ReadMode::DualOutput | ReadMode::DualIo => Lines::Dual,
+            ReadMode::QuadOutput | ReadMode::QuadIo => Lines::Quad,
+        }
+    }
+}
+
+/// Line length of wrap-around reads, set with Set Burst with Wrap (0x77).
+/// Fast Read Quad I/O then stays inside the aligned line holding the start
+/// address, returning to its start at the end: a cache line fill gets the
+/// requested word first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum BurstWrap {
+    Bytes8,
+    Bytes16,
+    Bytes32,
+    Bytes64,
+}
+
+impl BurstWrap {
+    /// Line length in bytes.
+    pub fn line_size(&self) -> usize {
+        8 << *self as usize
+    }
+
+    /// W6-W5 select the length, W4 cleared enables the wrap.
+    pub(crate) fn wrap_bits(&self) -> u8 {
+        (*self as u8) << 5
+    }
+}
+
+/// Most dummy clocks of Fast Read, which are sent as whole bytes.
+pub const MAX_FAST_READ_DUMMY_CYCLES: u8 = 32;
+
+/// Default [`Config::max_transfer_size`]: the `bufsiz` of Linux spidev.
+pub const DEFAULT_MAX_TRANSFER_SIZE: usize = 4096;
+
+/// Opcode, 4-byte address and the most dummy bytes in front of read data.
+pub(crate) const READ_HEADER_MAX_SIZE: usize = 5 + MAX_FAST_READ_DUMMY_CYCLES as usize / 8;
+
+/// Dummy clocks each fast read command waits for between its address (and
+/// mode bits) and the data. The defaults are the W25Q..JV values, valid up
+/// to its highest clock; parts or clocks needing others set them here, or
+/// [`Config::use_sfdp`] takes them from the chip.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DummyCycles {
+    /// Fast Read (0x0B), a multiple of eight.
+    pub fast: u8,
+    pub dual_output: u8,
+    pub quad_output: u8,
+    /// After the four clocks of mode bits.
+    pub dual_io: u8,
+    /// After the two clocks of mode bits.
+    pub quad_io: u8,
+}
+
+impl Default for DummyCycles {
+    fn default() -> Self {
+        DummyCycles { fast: 8, dual_output: 8, quad_output: 8, dual_io: 0, quad_io: 4 }
+    }
+}
+
+/// Largest chip (or die) reachable with 3-byte addresses.
+pub const THREE_BYTE_ADDRESS_LIMIT: usize = 16 * 1024 * 1024;
+
+/// Number of address bytes sent after the opcode.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum AddressWidth {
+    Three,
+    /// 4-byte addresses, for parts larger than 16 MiB. `init()` switches the
+    /// chip to 4-byte address mode (0xB7), unless
+    /// [`Config::four_byte_opcodes`] is set.
+    Four,
+    /// [`Four`](AddressWidth::Four) when the detected part is larger than
+    /// 16 MiB, [`Three`](AddressWidth::Three) otherwise.
+    #[default]
+    Auto,
+}
+
+impl AddressWidth {
+    /// Width used on a part of `capacity` bytes per die.
+    pub fn resolve(self, capacity: usize) -> AddressWidth {
+        match self {
+            AddressWidth::Auto if capacity > THREE_BYTE_ADDRESS_LIMIT => AddressWidth::Four,
+            AddressWidth::Auto => AddressWidth::Three,
+            width => width,
+        }
+    }
+}
+
+/// How `init()` checks the JEDEC ID of the chip.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum IdCheck {
+    /// Against the [`ChipParams`]: one exact part, or any known part with
+    /// capacity detection.
+    #[default]
+    Params,
+    /// Any of the listed manufacturer and device IDs, for clones and second
+    /// sources. The capacity is taken from the ID when its code is known.
+    AnyOf(&'static [(u8, u16)]),
+    /// No identification at all: the [`ChipParams`] are trusted as they are.
+    /// For bring-up of boards with unknown parts; a warning is logged.
+    Skip,
+}
+
+/// Runtime behaviour of the driver.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Config {
+    pub read_mode: ReadMode,
+    pub dummy_cycles: DummyCycles,
+    pub id_check: IdCheck,
+    pub address_width: AddressWidth,
+    /// With 4-byte addresses, use the dedicated 4-byte commands (0x13, 0x0C,
+    /// 0x12, 0x21, 0xDC) and leave the chip in 3-byte mode, so a boot ROM
+    /// still finds it there after a reset of the MCU alone. There is no
+    /// 4-byte 32K block erase: those blocks are erased sector by sector.
+    pub four_byte_opcodes: bool,
+    pub busy_poll: BusyPoll,
+    /// Poll interval of [`BusyPoll::Fixed`].
+    pub busy_poll_interval_us: u32,
+    pub busy_timeouts: BusyTimeouts,
+    /// Wrap of Fast Read Quad I/O set by `init()`. `read()` stays linear,
+    /// splitting at the line ends; `read_wrapped()` fills one line.
+    pub burst_wrap: Option<BurstWrap>,
+    /// Keep Dual/Quad I/O reads in continuous read mode (mode bits
+    /// 0bxx10xxxx), so consecutive reads leave out the opcode. Any other
+    /// command ends it first.
+    pub continuous_read: bool,
+    /// Read every programmed page back and fail the write on a mismatch.
+    pub verify_after_write: bool,
+    /// Set Quad Enable with a volatile status register write (0x50), so the
+    /// non-volatile bit is left alone. `init()` and the recovery set it again
+    /// after every reset.
+    pub volatile_quad_enable: bool,
+    /// Read the SFDP table during `init()` and take the capacity and erase
+    /// opcodes from it.
+    pub use_sfdp: bool,
+    /// Settle time after chip select is released, before the next command.
+    /// Needed on some boards with level shifters. Zero disables it.
+    pub cs_deselect_delay_ns: u32,
+    /// How many times a failed operation is retried after resetting and
+    /// re-probing the chip. Zero disables recovery.
+    pub recovery_retries: u8,
+    /// Most bytes one chip select cycle may carry, command bytes included.
+    /// Longer reads are split into several commands; see
+    /// [`W25qxx::read_chunk_size`].
+    pub max_transfer_size: usize,
+    /// Put the chip in Deep Power-Down once no command went out for this
+    /// many milliseconds, as measured by [`W25qxx::poll_idle`], and wake it
+    /// transparently before the next one. `None` leaves it awake.
+    pub idle_power_down_ms: Option<u32>,
+    /// Let `read()` suspend a sector or block erase running elsewhere on
+    /// the die and resume it afterwards, instead of reading a busy chip.
+    /// Reads inside the erased unit, or behind any other operation, wait
+    /// for it to finish.
+    #[cfg(feature = "suspend")]
+    pub suspend_for_reads: bool,
+    /// Run the chip in QPI mode, with every command on four lines. Only for
+    /// controllers whose [`MultiIoTransfer`] can send opcodes on four lines.
+    #[cfg(feature = "qpi")]
+    pub qpi: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            read_mode: ReadMode::default(),
+            dummy_cycles: DummyCycles::default(),
+            id_check: IdCheck::default(),
+            address_width: AddressWidth::default(),
+            four_byte_opcodes: false,
+            busy_poll: BusyPoll::default(),
+            busy_poll_interval_us: DEFAULT_BUSY_POLL_INTERVAL_US,
+            busy_timeouts: BusyTimeouts::default(),
+            burst_wrap: None,
+            continuous_read: false,
+            verify_after_write: false,
+            volatile_quad_enable: false,
+            use_sfdp: false,
+            cs_deselect_delay_ns: 0,
+            recovery_retries: 0,
+            max_transfer_size: DEFAULT_MAX_TRANSFER_SIZE,
+            idle_power_down_ms: None,
+            #[cfg(feature = "suspend")]
+            suspend_for_reads: false,
+            #[cfg(feature = "qpi")]
+            qpi: false,
+        }
+    }
+}
+
+/// Step-by-step construction of a [`W25qxx`]. Obtained from
+/// [`W25qxx::builder`].
+pub struct W25qxxBuilder<SPI: ErrorType, D> {
+    spi: SPI,
+    delay: D,
+    params: ChipParams,
+    config: Config,
+    multi_io: Option<MultiIoTransfer<SPI>>,
+}
+
+impl<SPI, D> W25qxxBuilder<SPI, D>
+where
+    SPI: SpiDevice,
+    D: DelayNs,
+{
+    pub fn new(spi: SPI, delay: D) -> Self {
+        W25qxxBuilder { spi, delay, params: ChipParams::default(), config: Config::default(), multi_io: None }
+    }
+
+    /// Describes the part being driven. Resets the expected JEDEC ID to the
+    /// one in `params`.
+    pub fn params(mut self, params: ChipParams) -> Self {
+        self.params = params;
+        self
+    }
+
+    /// Manufacturer and device ID (memory type, capacity) that `init()` must
+    /// read back. Turns off capacity detection. Parts listed in
+    /// [`QUIRKS`](crate::chip::QUIRKS) get their opcode and status register
+    /// adjustments.
+    pub fn jedec_id(mut self, manufacturer_id: u8, device_id: u16) -> Self {
+        self.params.manufacturer_id = manufacturer_id;
+        self.params.device_id = device_id;
+        self.params.detect_capacity = false;
+        if let Some(quirks) = Quirks::lookup(manufacturer_id, device_id) {
+            quirks.apply(&mut self.params);
+        }
+        self
+    }
+
+    /// Accepts any of `ids` (manufacturer ID, device ID) instead of the part
+    /// in the parameters.
+    pub fn expected_ids(mut self, ids: &'static [(u8, u16)]) -> Self {
+        self.config.id_check = IdCheck::AnyOf(ids);
+        self
+    }
+
+    /// Skips the identification: `init()` and recovery no longer read the
+    /// JEDEC ID.
+    pub fn skip_id_check(mut self) -> Self {
+        self.config.id_check = IdCheck::Skip;
+        self
+    }
+
+    pub fn read_mode(mut self, read_mode: ReadMode) -> Self {
+        self.config.read_mode = read_mode;
+        self
+    }
+
+    /// Registers the transfer running the dual and quad read modes on the
+    /// host's controller.
+    pub fn multi_io(mut self, transfer: MultiIoTransfer<SPI>) -> Self {
+        self.multi_io = Some(transfer);
+        self
+    }
+
+    pub fn address_width(mut self, address_width: AddressWidth) -> Self {
+        self.config.address_width = address_width;
+        self
+    }
+
+    pub fn four_byte_opcodes(mut self, enabled: bool) -> Self {
+        self.config.four_byte_opcodes = enabled;
+        self
+    }
+
+    pub fn busy_poll(mut self, poll: BusyPoll) -> Self {
+        self.config.busy_poll = poll;
+        self
+    }
+
+    pub fn busy_poll_interval_us(mut self, interval_us: u32) -> Self {
+        self.config.busy_poll_interval_us = interval_us;
+        self
+    }
+
+    pub fn busy_timeouts(mut self, timeouts: BusyTimeouts) -> Self {
+        self.config.busy_timeouts = timeouts;
+        self
+    }
+
+    pub fn dummy_cycles(mut self, dummy_cycles: DummyCycles) -> Self {
+        self.config.dummy_cycles = dummy_cycles;
+        self
+    }
+
+    /// Wrap of Fast Read Quad I/O. Needs [`ReadMode::QuadIo`].
+    pub fn burst_wrap(mut self, wrap: Option<BurstWrap>) -> Self {
+        self.config.burst_wrap = wrap;
+        self
+    }
+
+    pub fn continuous_read(mut self, enabled: bool) -> Self {
+        self.config.continuous_read = enabled;
+        self
+    }
+
+    pub fn verify_after_write(mut self, enabled: bool) -> Self {
+        self.config.verify_after_write = enabled;
+        self
+    }
+
+    pub fn volatile_quad_enable(mut self, enabled: bool) -> Self {
+        self.config.volatile_quad_enable = enabled;
+        self
+    }
+
+    pub fn use_sfdp(mut self, enabled: bool) -> Self {
+        self.config.use_sfdp = enabled;
+        self
+    }
+
+    pub fn cs_deselect_delay_ns(mut self, delay_ns: u32) -> Self {
+        self.config.cs_deselect_delay_ns = delay_ns;
+        self
+    }
+
+    /// Largest transfer the SPI device takes, such as spidev's `bufsiz`
+    /// module parameter. At least one page program: 261 bytes.
+    pub fn max_transfer_size(mut self, size: usize) -> Self {
+        self.config.max_transfer_size = size;
+        self
+    }
+
+    pub fn recovery_retries(mut self, retries: u8) -> Self {
+        self.config.recovery_retries = retries;
+        self
+    }
+
+    /// Automatic Deep Power-Down after `timeout_ms` without commands. The
+    /// application calls [`W25qxx::poll_idle`] with a millisecond timestamp
+    /// from its main loop or a timer.
+    pub fn idle_power_down_ms(mut self, timeout_ms: Option<u32>) -> Self {
+        self.config.idle_power_down_ms = timeout_ms;
+        self
+    }
+
+    #[cfg(feature = "suspend")]
+    pub fn suspend_for_reads(mut self, enabled: bool) -> Self {
+        self.config.suspend_for_reads = enabled;
+        self
+    }
+
+    /// Runs the chip in QPI mode after `init()`. Needs a
+    /// [`multi_io`](Self::multi_io) transfer that sends opcodes on four lines.
+    #[cfg(feature = "qpi")]
+    pub fn qpi(mut self, enabled: bool) -> Self {
+        self.config.qpi = enabled;
+        self
+    }
+
+    /// Creates the uninitialized driver.
+    pub fn build(self) -> Result<W25qxx<SPI, D, Uninit>, Error<SPI::Error>> {
+        /* A zero interval would poll the bus as fast as it goes */
+        if self.config.busy_poll_interval_us == 0 {
+            return Err(Error::InvalidArgument);
+        }
+        if self.params.dies == 0 || self.params.dies as usize > W25QXX_MAX_DIES {
+            return Err(Error::InvalidArgument);
+        }
+        /* A page program is sent whole */
+        if self.config.max_transfer_size < W25QXX_PAGE_SIZE + 5 {
+            return Err(Error::InvalidArgument);
+        }
+        if !self.config.dummy_cycles.fast.is_multiple_of(8) || self.config.dummy_cycles.fast > MAX_FAST_READ_DUMMY_CYCLES {
+            return Err(Error::InvalidArgument);
+        }
+
+        if self.config.read_mode.data_lines() != Lines::Single && self.multi_io.is_none() {
+            return Err(Error::Unsupported);
+        }
+        if self.config.burst_wrap.is_some() && self.config.read_mode != ReadMode::QuadIo {
+            return Err(Error::Unsupported);
+        }
+        #[cfg(feature = "qpi")]
+        if self.config.qpi && (self.multi_io.is_none() || self.params.dies > 1) {
+            return Err(Error::Unsupported);
+        }
+
+        Ok(W25qxx::from_parts(self.spi, self.delay, self.params, self.config, self.multi_io))
+    }
+}
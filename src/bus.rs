@@ -0,0 +1,118 @@
+//! `SpiDevice` adapter for a bare SPI bus plus a chip select GPIO.
+
+use embedded_hal::digital::OutputPin;
+use embedded_hal::spi::{self, ErrorKind, ErrorType, Operation, SpiBus, SpiDevice};
+
+/// Exclusive [`SpiDevice`] built from an [`SpiBus`] and a chip select pin.
+///
+/// Chip select is asserted (driven low) for the whole transaction and
+/// released afterwards, also when the transaction bails out early.
+/// `Operation::DelayNs` is not supported and is skipped; the driver never
+/// issues it.
+pub struct SpiBusDevice<BUS, CS> {
+    bus: BUS,
+    cs: CS,
+}
+
+/// Error of a [`SpiBusDevice`]: either the bus or the chip select pin failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DeviceError<BUS, CS> {
+    Spi(BUS),
+    Cs(CS),
+}
+
+impl<BUS, CS> spi::Error for DeviceError<BUS, CS>
+where
+    BUS: spi::Error,
+    CS: core::fmt::Debug,
+{
+    fn kind(&self) -> ErrorKind {
+        match self {
+            DeviceError::Spi(e) => e.kind(),
+            DeviceError::Cs(_) => ErrorKind::ChipSelectFault,
+        }
+    }
+}
+
+/// Asserted chip select. Dropping the guard releases the line, so neither an
+/// early return nor a panic leaves the chip selected.
+struct ChipSelect<'a, CS: OutputPin> {
+    pin: &'a mut CS,
+}
+
+impl<'a, CS: OutputPin> ChipSelect<'a, CS> {
+    fn assert(pin: &'a mut CS) -> Result<Self, CS::Error> {
+        pin.set_low()?;
+        Ok(ChipSelect { pin })
+    }
+
+    /// Releases the line and reports a pin failure, unlike `drop`.
+    fn release(self) -> Result<(), CS::Error> {
+        let mut guard = core::mem::ManuallyDrop::new(self);
+        guard.pin.set_high()
+    }
+}
+
+impl<CS: OutputPin> Drop for ChipSelect<'_, CS> {
+    fn drop(&mut self) {
+        let _ = self.pin.set_high();
+    }
+}
+
+impl<BUS, CS> SpiBusDevice<BUS, CS>
+where
+    BUS: SpiBus,
+    CS: OutputPin,
+{
+    /// Takes ownership of the bus and the pin, leaving chip select released.
+    pub fn new(bus: BUS, cs: CS) -> Result<Self, CS::Error> {
+        let mut device = SpiBusDevice { bus, cs };
+
+        device.cs.set_high()?;
+
+        Ok(device)
+    }
+
+    /// Gives back the bus and the chip select pin.
+    pub fn release(self) -> (BUS, CS) {
+        (self.bus, self.cs)
+    }
+}
+
+impl<BUS, CS> ErrorType for SpiBusDevice<BUS, CS>
+where
+    BUS: SpiBus,
+    CS: OutputPin,
+{
+    type Error = DeviceError<BUS::Error, CS::Error>;
+}
+
+impl<BUS, CS> SpiDevice for SpiBusDevice<BUS, CS>
+where
+    BUS: SpiBus,
+    CS: OutputPin,
+{
+    fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), Self::Error> {
+        let SpiBusDevice { bus, cs } = self;
+
+        /* Chip select low */
+        let cs = ChipSelect::assert(cs).map_err(DeviceError::Cs)?;
+
+        let result = operations.iter_mut().try_for_each(|operation| match operation {
+            Operation::Read(buffer) => bus.read(buffer),
+            Operation::Write(buffer) => bus.write(buffer),
+            Operation::Transfer(read, write) => bus.transfer(read, write),
+            Operation::TransferInPlace(buffer) => bus.transfer_in_place(buffer),
+            Operation::DelayNs(_) => Ok(()),
+        });
+        let flushed = bus.flush();
+
+        /* Chip select high */
+        let released = cs.release();
+
+        result.map_err(DeviceError::Spi)?;
+        flushed.map_err(DeviceError::Spi)?;
+        released.map_err(DeviceError::Cs)
+    }
+}
@@ -0,0 +1,89 @@
+//! Pluggable integrity checks for verify operations.
+//!
+//! Anything implementing [`Checksum`] can be streamed over a flash region with
+//! [`W25qxx::digest`](crate::W25qxx::digest). A CRC32 implementation is built
+//! in; with the `digest` feature every [`digest::Digest`] hasher (SHA-256,
+//! BLAKE2, ...) is accepted as well.
+
+/// Incremental hasher fed with the bytes of a flash region.
+pub trait Checksum {
+    /// Value produced once every byte has been fed.
+    type Output: PartialEq;
+
+    /// Feeds the next chunk of data.
+    fn update(&mut self, data: &[u8]);
+
+    /// Consumes the hasher and returns the final value.
+    fn finalize(self) -> Self::Output;
+}
+
+/// Lookup table for the reflected IEEE 802.3 polynomial.
+const CRC32_TABLE: [u32; 256] = crc32_table();
+
+const fn crc32_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// CRC-32 (IEEE 802.3), the variant used by zlib, PNG and most bootloaders.
+#[derive(Clone, Copy, Debug)]
+pub struct Crc32 {
+    crc: u32,
+}
+
+impl Crc32 {
+    pub const fn new() -> Self {
+        Crc32 { crc: 0xFFFF_FFFF }
+    }
+
+    /// Computes the CRC of `data` in one go.
+    pub fn checksum(data: &[u8]) -> u32 {
+        let mut crc = Crc32::new();
+        crc.update(data);
+        crc.finalize()
+    }
+}
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Crc32::new()
+    }
+}
+
+impl Checksum for Crc32 {
+    type Output = u32;
+
+    fn update(&mut self, data: &[u8]) {
+        for &byte in data {
+            self.crc = CRC32_TABLE[((self.crc ^ byte as u32) & 0xFF) as usize] ^ (self.crc >> 8);
+        }
+    }
+
+    fn finalize(self) -> u32 {
+        !self.crc
+    }
+}
+
+#[cfg(feature = "digest")]
+impl<D: digest::Digest> Checksum for D {
+    type Output = digest::Output<D>;
+
+    fn update(&mut self, data: &[u8]) {
+        digest::Digest::update(self, data);
+    }
+
+    fn finalize(self) -> Self::Output {
+        digest::Digest::finalize(self)
+    }
+}
@@ -0,0 +1,400 @@
+//! Per-part parameters: identification, size and the opcode table.
+//!
+//! Everything the driver sends on the bus comes from [`ChipParams`], so a
+//! derivative part with different opcodes (0x60 chip erase, other reset
+//! sequences, ...) only needs its own table instead of a code fork.
+//!
+//! [`QUIRKS`] lists the pin-compatible parts of other vendors (GigaDevice
+//! GD25Q, Macronix MX25L, ISSI IS25LP) and how they differ from the W25Q, so
+//! capacity detection can take them over as well.
+//!
+//! Stacked-die parts such as the W25M512JV (two W25Q256JV dies behind one
+//! chip select) are described by [`ChipParams::dies`]: `capacity` is the
+//! total of all dies and the driver switches between them with Software Die
+//! Select (0xC2).
+
+use crate::w25qxx::{
+    Command, W25M_DEVID_VALUE_512, W25QXX_BLOCK32K_SIZE, W25QXX_BLOCK64K_SIZE, W25QXX_CAPACITY_128,
+    W25QXX_CAPACITY_256, W25QXX_DEVID_VALUE_128, W25QXX_MANID_VALUE, W25QXX_PAGE_SIZE, W25QXX_SECTOR_SIZE,
+};
+
+/// Opcodes used by the driver for each operation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Opcodes {
+    pub read_id: u8,
+    pub page_program: u8,
+    pub read_data: u8,
+    pub fast_read: u8,
+    pub fast_read_dual_output: u8,
+    pub fast_read_quad_output: u8,
+    pub fast_read_dual_io: u8,
+    pub fast_read_quad_io: u8,
+    pub read_status1: u8,
+    pub read_status2: u8,
+    pub read_status3: u8,
+    pub write_enable: u8,
+    /// Makes the next status register write volatile.
+    pub volatile_write_enable: u8,
+    pub write_status1: u8,
+    pub write_status2: u8,
+    pub write_status3: u8,
+    pub sector_erase: u8,
+    pub block32_erase: u8,
+    pub block64_erase: u8,
+    pub chip_erase: u8,
+    pub enable_reset: u8,
+    pub reset: u8,
+    /// Clears the mode bits of Dual/Quad I/O continuous read.
+    pub continuous_read_reset: u8,
+    /// Switch to and from QPI, where every command is clocked on four lines.
+    pub enter_qpi: u8,
+    pub exit_qpi: u8,
+    /// Dummy clocks and wrap length of reads in QPI mode.
+    pub set_read_parameters: u8,
+    /// Wrap length of Fast Read Quad I/O (0xEB) in SPI mode.
+    pub set_burst_with_wrap: u8,
+    /// Switches parts larger than 16 MiB to 4-byte addresses.
+    pub enter_4byte_address: u8,
+    /// Returns to 3-byte addresses.
+    pub exit_4byte_address: u8,
+    /// Read, program and erase commands taking a 4-byte address in 3-byte
+    /// address mode.
+    pub read_data_4b: u8,
+    pub fast_read_4b: u8,
+    pub fast_read_dual_output_4b: u8,
+    pub fast_read_quad_output_4b: u8,
+    pub fast_read_dual_io_4b: u8,
+    pub fast_read_quad_io_4b: u8,
+    pub page_program_4b: u8,
+    pub sector_erase_4b: u8,
+    pub block64_erase_4b: u8,
+    /// Reads the Serial Flash Discoverable Parameters.
+    pub read_sfdp: u8,
+    /// Reads the 64-bit factory serial number.
+    pub read_unique_id: u8,
+    /// Software Die Select of stacked-die parts, followed by the die number.
+    pub die_select: u8,
+    /// Program/Erase Suspend and Resume.
+    pub suspend: u8,
+    pub resume: u8,
+    /// Individual block locks of the WPS=1 protection scheme.
+    pub individual_lock: u8,
+    pub individual_unlock: u8,
+    pub read_block_lock: u8,
+    pub global_lock: u8,
+    pub global_unlock: u8,
+    /// Deep Power-Down and Release from it.
+    pub power_down: u8,
+    pub release_power_down: u8,
+    /// Security registers (OTP): erase, program and read.
+    pub erase_security: u8,
+    pub program_security: u8,
+    pub read_security: u8,
+}
+
+impl Opcodes {
+    /// Command set of the Winbond W25Q family.
+    pub const W25QXX: Opcodes = Opcodes {
+        read_id: Command::JedecId as u8,
+        page_program: Command::PageProgram as u8,
+        read_data: Command::ReadData as u8,
+        fast_read: Command::FastRead as u8,
+        fast_read_dual_output: Command::FastReadDualOutput as u8,
+        fast_read_quad_output: Command::FastReadQuadOutput as u8,
+        fast_read_dual_io: Command::FastReadDualIo as u8,
+        fast_read_quad_io: Command::FastReadQuadIo as u8,
+        read_status1: Command::ReadStatusRegister1 as u8,
+        read_status2: Command::ReadStatusRegister2 as u8,
+        read_status3: Command::ReadStatusRegister3 as u8,
+        write_enable: Command::WriteEnable as u8,
+        volatile_write_enable: Command::VolatileWriteEnable as u8,
+        write_status1: Command::WriteStatusRegister1 as u8,
+        write_status2: Command::WriteStatusRegister2 as u8,
+        write_status3: Command::WriteStatusRegister3 as u8,
+        sector_erase: Command::SectorErase as u8,
+        block32_erase: Command::Block32Erase as u8,
+        block64_erase: Command::Block64Erase as u8,
+        chip_erase: Command::ChipErase as u8,
+        enable_reset: Command::EnableReset as u8,
+        reset: Command::Reset as u8,
+        continuous_read_reset: Command::ContinuousReadReset as u8,
+        enter_qpi: Command::EnterQpi as u8,
+        /* Same opcode: 0xFF clocked on four lines */
+        exit_qpi: Command::ContinuousReadReset as u8,
+        set_read_parameters: Command::SetReadParameters as u8,
+        set_burst_with_wrap: Command::SetBurstWithWrap as u8,
+        enter_4byte_address: Command::Enter4ByteAddressMode as u8,
+        exit_4byte_address: Command::Exit4ByteAddressMode as u8,
+        read_data_4b: Command::ReadData4ByteAddress as u8,
+        fast_read_4b: Command::FastRead4ByteAddress as u8,
+        fast_read_dual_output_4b: Command::FastReadDualOutput4ByteAddress as u8,
+        fast_read_quad_output_4b: Command::FastReadQuadOutput4ByteAddress as u8,
+        fast_read_dual_io_4b: Command::FastReadDualIo4ByteAddress as u8,
+        fast_read_quad_io_4b: Command::FastReadQuadIo4ByteAddress as u8,
+        page_program_4b: Command::PageProgram4ByteAddress as u8,
+        sector_erase_4b: Command::SectorErase4ByteAddress as u8,
+        block64_erase_4b: Command::Block64Erase4ByteAddress as u8,
+        read_sfdp: Command::ReadSfdp as u8,
+        read_unique_id: Command::ReadUniqueId as u8,
+        die_select: Command::DieSelect as u8,
+        suspend: Command::Suspend as u8,
+        resume: Command::Resume as u8,
+        individual_lock: Command::IndividualBlockLock as u8,
+        individual_unlock: Command::IndividualBlockUnlock as u8,
+        read_block_lock: Command::ReadBlockLock as u8,
+        global_lock: Command::GlobalBlockLock as u8,
+        global_unlock: Command::GlobalBlockUnlock as u8,
+        power_down: Command::PowerDown as u8,
+        release_power_down: Command::ReleasePowerDown as u8,
+        erase_security: Command::EraseSecurityRegister as u8,
+        program_security: Command::ProgramSecurityRegister as u8,
+        read_security: Command::ReadSecurityRegister as u8,
+    };
+}
+
+impl Opcodes {
+    /// Table with the 4-byte address commands in place of their 3-byte
+    /// counterparts.
+    pub fn four_byte(&self) -> Opcodes {
+        Opcodes {
+            read_data: self.read_data_4b,
+            fast_read: self.fast_read_4b,
+            fast_read_dual_output: self.fast_read_dual_output_4b,
+            fast_read_quad_output: self.fast_read_quad_output_4b,
+            fast_read_dual_io: self.fast_read_dual_io_4b,
+            fast_read_quad_io: self.fast_read_quad_io_4b,
+            page_program: self.page_program_4b,
+            sector_erase: self.sector_erase_4b,
+            block64_erase: self.block64_erase_4b,
+            ..*self
+        }
+    }
+}
+
+impl Default for Opcodes {
+    fn default() -> Self {
+        Opcodes::W25QXX
+    }
+}
+
+/// Description of one flash part.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ChipParams {
+    /// Manufacturer ID returned by the JEDEC ID command.
+    pub manufacturer_id: u8,
+    /// Memory type (high byte) and capacity code (low byte) returned by the
+    /// JEDEC ID command.
+    pub device_id: u16,
+    /// Total size in bytes, of all dies together.
+    pub capacity: usize,
+    /// Number of stacked dies sharing the chip select, 1 for monolithic
+    /// parts. Each die holds `capacity / dies` bytes.
+    pub dies: u8,
+    /// Accept any part of `manufacturer_id` and take `device_id` and
+    /// `capacity` from its JEDEC ID during `init()`.
+    pub detect_capacity: bool,
+    /// Where the Quad Enable bit lives.
+    pub quad_enable: QuadEnable,
+    pub opcodes: Opcodes,
+}
+
+impl ChipParams {
+    /// Winbond W25Q128 (128Mbit), and only that part.
+    pub const W25Q128: ChipParams = ChipParams {
+        manufacturer_id: W25QXX_MANID_VALUE,
+        device_id: W25QXX_DEVID_VALUE_128,
+        capacity: W25QXX_CAPACITY_128,
+        dies: 1,
+        detect_capacity: false,
+        quad_enable: QuadEnable::Status2Bit1,
+        opcodes: Opcodes::W25QXX,
+    };
+
+    /// Any Winbond W25Q part (W25Q16 to W25Q512) or compatible part listed
+    /// in [`QUIRKS`]. Starts with the W25Q128 values; `init()` replaces them
+    /// with the detected ones.
+    pub const W25QXX: ChipParams = ChipParams { detect_capacity: true, ..ChipParams::W25Q128 };
+
+    /// Winbond W25M512JV (512Mbit): two W25Q256JV dies of 32 MiB.
+    pub const W25M512: ChipParams = ChipParams {
+        device_id: W25M_DEVID_VALUE_512,
+        capacity: 2 * W25QXX_CAPACITY_256,
+        dies: 2,
+        ..ChipParams::W25Q128
+    };
+
+    /// Bytes held by one die.
+    pub fn die_size(&self) -> usize {
+        self.capacity / self.dies.max(1) as usize
+    }
+
+    /// Identification data of the part.
+    pub fn device_info(&self) -> DeviceInfo {
+        DeviceInfo {
+            manufacturer_id: self.manufacturer_id,
+            device_id: self.device_id,
+            capacity: self.capacity,
+            family: Family::from_id(self.manufacturer_id, self.device_id),
+        }
+    }
+
+    /// Size and program/erase units of the part.
+    pub fn geometry(&self) -> Geometry {
+        Geometry {
+            capacity: self.capacity,
+            page_size: W25QXX_PAGE_SIZE,
+            sector_size: W25QXX_SECTOR_SIZE,
+            block32_size: W25QXX_BLOCK32K_SIZE,
+            block64_size: W25QXX_BLOCK64K_SIZE,
+        }
+    }
+}
+
+impl Default for ChipParams {
+    fn default() -> Self {
+        ChipParams::W25QXX
+    }
+}
+
+/// Product line of an identified part, from its JEDEC ID.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Family {
+    /// Winbond W25Q serial NOR.
+    W25q,
+    /// Winbond W25M stacked-die serial NOR.
+    W25m,
+    /// GigaDevice GD25Q/GD25LQ.
+    Gd25q,
+    /// Macronix MX25L.
+    Mx25l,
+    /// ISSI IS25LP/IS25WP.
+    Is25lp,
+    /// Anything else.
+    Unknown,
+}
+
+impl Family {
+    pub fn from_id(manufacturer_id: u8, device_id: u16) -> Self {
+        Quirks::lookup(manufacturer_id, device_id).map_or(Family::Unknown, |quirks| quirks.family)
+    }
+}
+
+/// Location of the Quad Enable bit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum QuadEnable {
+    /// Bit 1 of status register 2 (Winbond, GigaDevice).
+    Status2Bit1,
+    /// Bit 6 of status register 1 (Macronix, ISSI).
+    Status1Bit6,
+}
+
+/// How one vendor's product line differs from the W25Q command set.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Quirks {
+    pub manufacturer_id: u8,
+    /// Memory type byte of the JEDEC ID.
+    pub memory_type: u8,
+    pub family: Family,
+    /// Opcode reading the second status byte. 0x35 enters QPI mode on
+    /// Macronix and ISSI parts, which report their configuration (0x15) and
+    /// function (0x48) registers instead.
+    pub read_status2: u8,
+    pub quad_enable: QuadEnable,
+    /// Stacked dies; the capacity code of the JEDEC ID is the size of one.
+    pub dies: u8,
+}
+
+/// Parts the driver knows how to drive, keyed by manufacturer ID and memory
+/// type.
+pub const QUIRKS: [Quirks; 9] = [
+    Quirks::w25q(0x40),
+    Quirks::w25q(0x60),
+    Quirks::w25q(0x70),
+    Quirks { family: Family::W25m, dies: 2, ..Quirks::w25q(0x71) },
+    Quirks { family: Family::Gd25q, ..Quirks::w25q(0x40).vendor(0xC8) },
+    Quirks { family: Family::Gd25q, ..Quirks::w25q(0x60).vendor(0xC8) },
+    Quirks { family: Family::Mx25l, read_status2: 0x15, quad_enable: QuadEnable::Status1Bit6, ..Quirks::w25q(0x20).vendor(0xC2) },
+    Quirks { family: Family::Is25lp, read_status2: 0x48, quad_enable: QuadEnable::Status1Bit6, ..Quirks::w25q(0x60).vendor(0x9D) },
+    Quirks { family: Family::Is25lp, read_status2: 0x48, quad_enable: QuadEnable::Status1Bit6, ..Quirks::w25q(0x70).vendor(0x9D) },
+];
+
+impl Quirks {
+    const fn w25q(memory_type: u8) -> Self {
+        Quirks {
+            manufacturer_id: W25QXX_MANID_VALUE,
+            memory_type,
+            family: Family::W25q,
+            read_status2: Command::ReadStatusRegister2 as u8,
+            quad_enable: QuadEnable::Status2Bit1,
+            dies: 1,
+        }
+    }
+
+    const fn vendor(self, manufacturer_id: u8) -> Self {
+        Quirks { manufacturer_id, ..self }
+    }
+
+    /// Entry of [`QUIRKS`] for a JEDEC ID.
+    pub fn lookup(manufacturer_id: u8, device_id: u16) -> Option<&'static Quirks> {
+        let memory_type = (device_id >> 8) as u8;
+        QUIRKS.iter().find(|quirks| quirks.manufacturer_id == manufacturer_id && quirks.memory_type == memory_type)
+    }
+
+    /// Adjusts the opcodes, status register layout and die count of
+    /// `params`.
+    pub fn apply(&self, params: &mut ChipParams) {
+        params.opcodes.read_status2 = self.read_status2;
+        params.quad_enable = self.quad_enable;
+        params.dies = self.dies;
+    }
+}
+
+/// What identification found.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DeviceInfo {
+    pub manufacturer_id: u8,
+    /// Memory type (high byte) and capacity code (low byte).
+    pub device_id: u16,
+    /// Total size in bytes.
+    pub capacity: usize,
+    pub family: Family,
+}
+
+/// Memory layout of a part, in bytes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Geometry {
+    pub capacity: usize,
+    /// Largest unit of one program command.
+    pub page_size: usize,
+    /// Smallest erase unit.
+    pub sector_size: usize,
+    pub block32_size: usize,
+    pub block64_size: usize,
+}
+
+impl Geometry {
+    pub fn pages(&self) -> usize {
+        self.capacity / self.page_size
+    }
+
+    pub fn sectors(&self) -> usize {
+        self.capacity / self.sector_size
+    }
+}
+
+/// Size in bytes encoded by the capacity byte of a JEDEC ID: 2^n bytes up to
+/// 0x19 (W25Q256), then 0x20 (W25Q512) continues at 2^26.
+pub fn capacity_from_id(capacity_id: u8) -> Option<usize> {
+    match capacity_id {
+        0x11..=0x19 => Some(1 << capacity_id),
+        0x20..=0x22 => Some(1 << (capacity_id - 6)),
+        _ => None,
+    }
+}
@@ -0,0 +1,254 @@
+//! Circular log of variable-length records.
+//!
+//! A [`CircularLog`] appends records to the sectors of its area in turn.
+//! When the newest sector has no room left, the log moves on to the next one,
+//! erasing it first if it holds the oldest records. The log thus always keeps
+//! the latest records and drops the oldest a sector at a time.
+//!
+//! Every sector starts with a sequence number and every record carries a CRC,
+//! so [`CircularLog::open`] finds the newest and the oldest record again
+//! after a reboot. A record cut short by a power loss is skipped.
+
+use crate::checksum::{Checksum, Crc32};
+use crate::w25qxx::{Error, W25qxx, W25QXX_SECTOR_SIZE};
+use embedded_hal::delay::DelayNs;
+use embedded_hal::spi::SpiDevice;
+
+/// Marks a sector of the log ("CLOG" in little endian).
+const LOG_MAGIC: u32 = 0x474F_4C43;
+
+/// Magic and sequence number.
+const SECTOR_HEADER_SIZE: usize = 8;
+
+/// Length ahead of the data and CRC behind it.
+const RECORD_OVERHEAD: usize = 2 + 4;
+
+/// Longest record the log takes.
+pub const MAX_LOG_RECORD_SIZE: usize = W25QXX_SECTOR_SIZE - SECTOR_HEADER_SIZE - RECORD_OVERHEAD;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum SectorState {
+    Erased,
+    InUse(u32),
+    /// Neither erased nor a sector of the log, as an interrupted erase leaves
+    /// it.
+    Garbage,
+}
+
+/// What the bytes at an offset of a sector hold.
+enum Parsed {
+    /// Record of `len` bytes, complete if `valid`.
+    Record { len: usize, valid: bool },
+    /// Erased bytes: room for the next record.
+    End,
+    /// A length that makes no sense, left by a write cut short.
+    Torn,
+}
+
+/// Circular log in a sector-aligned area of at least two sectors.
+#[derive(Clone, Copy, Debug)]
+pub struct CircularLog {
+    address: u32,
+    sectors: usize,
+    /// Sector records are appended to, and its sequence number.
+    head: usize,
+    sequence: u32,
+    /// Where the next record goes, `None` once the sector is closed.
+    offset: Option<usize>,
+}
+
+impl CircularLog {
+    /// Opens the log in the `len` bytes at `address`, both sector aligned,
+    /// formatting it if it holds none.
+    pub fn open<SPI: SpiDevice, D: DelayNs>(flash: &mut W25qxx<SPI, D>, address: u32, len: usize) -> Result<CircularLog, Error<SPI::Error>> {
+        if !address.is_multiple_of(W25QXX_SECTOR_SIZE as u32) || !len.is_multiple_of(W25QXX_SECTOR_SIZE) {
+            return Err(Error::NotAligned);
+        }
+        if len < 2 * W25QXX_SECTOR_SIZE {
+            return Err(Error::InvalidArgument);
+        }
+
+        let mut log = CircularLog {
+            address,
+            sectors: len / W25QXX_SECTOR_SIZE,
+            head: 0,
+            sequence: 0,
+            offset: None,
+        };
+        let mut newest = None;
+
+        for sector in 0..log.sectors {
+            match log.sector_state(flash, sector)? {
+                SectorState::InUse(sequence) => {
+                    if newest.is_none_or(|(_, newest)| sequence > newest) {
+                        newest = Some((sector, sequence));
+                    }
+                }
+                SectorState::Erased => {}
+                SectorState::Garbage => flash.erase(log.sector_address(sector), W25QXX_SECTOR_SIZE)?,
+            }
+        }
+
+        match newest {
+            Some((sector, sequence)) => {
+                log.head = sector;
+                log.sequence = sequence;
+                log.offset = log.append_offset(flash)?;
+            }
+            None => log.start_sector(flash, 0, 0)?,
+        }
+
+        Ok(log)
+    }
+
+    /// Appends `data`, at most [`MAX_LOG_RECORD_SIZE`] bytes. May erase the
+    /// sector of the oldest records to make room.
+    pub fn append<SPI: SpiDevice, D: DelayNs>(&mut self, flash: &mut W25qxx<SPI, D>, data: &[u8]) -> Result<(), Error<SPI::Error>> {
+        if data.len() > MAX_LOG_RECORD_SIZE {
+            return Err(Error::InvalidArgument);
+        }
+
+        let size = data.len() + RECORD_OVERHEAD;
+        let offset = match self.offset {
+            Some(offset) if offset + size <= W25QXX_SECTOR_SIZE => offset,
+            _ => {
+                let next = (self.head + 1) % self.sectors;
+                if self.sector_state(flash, next)? != SectorState::Erased {
+                    flash.erase(self.sector_address(next), W25QXX_SECTOR_SIZE)?;
+                }
+                self.start_sector(flash, next, self.sequence.wrapping_add(1))?;
+                SECTOR_HEADER_SIZE
+            }
+        };
+
+        let address = self.sector_address(self.head) + offset as u32;
+        let len = (data.len() as u16).to_le_bytes();
+        let mut crc = Crc32::new();
+        crc.update(&len);
+        crc.update(data);
+
+        /* The length goes first: once it is written, the record's size is known after a power loss */
+        self.offset = Some(offset + size);
+        flash.write(address, &len)?;
+        if !data.is_empty() {
+            flash.write(address + 2, data)?;
+        }
+        flash.write(address + 2 + data.len() as u32, &crc.finalize().to_le_bytes())
+    }
+
+    /// Calls `f` with every complete record, oldest first, read into
+    /// `buffer`. A buffer shorter than [`MAX_LOG_RECORD_SIZE`] fails with
+    /// [`Error::InvalidArgument`] on the first record that does not fit.
+    pub fn iter<SPI: SpiDevice, D: DelayNs>(&self, flash: &mut W25qxx<SPI, D>, buffer: &mut [u8], mut f: impl FnMut(&[u8])) -> Result<(), Error<SPI::Error>> {
+        for ring in 1..=self.sectors {
+            let sector = (self.head + ring) % self.sectors;
+            if !matches!(self.sector_state(flash, sector)?, SectorState::InUse(_)) {
+                continue;
+            }
+
+            let mut offset = SECTOR_HEADER_SIZE;
+            while let Parsed::Record { len, valid } = self.parse(flash, sector, offset)? {
+                if valid {
+                    if buffer.len() < len {
+                        return Err(Error::InvalidArgument);
+                    }
+                    flash.read(self.sector_address(sector) + offset as u32 + 2, &mut buffer[..len])?;
+                    f(&buffer[..len]);
+                }
+                offset += len + RECORD_OVERHEAD;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Drops every record by erasing the whole area.
+    pub fn clear<SPI: SpiDevice, D: DelayNs>(&mut self, flash: &mut W25qxx<SPI, D>) -> Result<(), Error<SPI::Error>> {
+        flash.erase(self.address, self.sectors * W25QXX_SECTOR_SIZE)?;
+        self.start_sector(flash, 0, 0)
+    }
+
+    /// Bytes of records the log holds at least before it drops old ones: all
+    /// sectors but the one being erased.
+    pub fn capacity(&self) -> usize {
+        (self.sectors - 1) * (W25QXX_SECTOR_SIZE - SECTOR_HEADER_SIZE)
+    }
+
+    /// Where the next record of the head sector goes, `None` when a torn
+    /// record or stray bytes close it.
+    fn append_offset<SPI: SpiDevice, D: DelayNs>(&self, flash: &mut W25qxx<SPI, D>) -> Result<Option<usize>, Error<SPI::Error>> {
+        let mut offset = SECTOR_HEADER_SIZE;
+
+        loop {
+            match self.parse(flash, self.head, offset)? {
+                Parsed::Record { len, .. } => offset += len + RECORD_OVERHEAD,
+                Parsed::Torn => return Ok(None),
+                Parsed::End => break,
+            }
+        }
+
+        let address = self.sector_address(self.head) + offset as u32;
+        Ok(flash.is_blank(address, W25QXX_SECTOR_SIZE - offset)?.then_some(offset))
+    }
+
+    /// Parses the record at `offset` of `sector`.
+    fn parse<SPI: SpiDevice, D: DelayNs>(&self, flash: &mut W25qxx<SPI, D>, sector: usize, offset: usize) -> Result<Parsed, Error<SPI::Error>> {
+        if offset + RECORD_OVERHEAD > W25QXX_SECTOR_SIZE {
+            return Ok(Parsed::End);
+        }
+
+        let address = self.sector_address(sector) + offset as u32;
+        let mut len: [u8; 2] = [0; 2];
+        flash.read(address, &mut len)?;
+        if len == [0xFF; 2] {
+            return Ok(Parsed::End);
+        }
+
+        let len = u16::from_le_bytes(len) as usize;
+        if offset + len + RECORD_OVERHEAD > W25QXX_SECTOR_SIZE {
+            return Ok(Parsed::Torn);
+        }
+
+        let crc = flash.digest(address, 2 + len, Crc32::new())?;
+        let mut stored: [u8; 4] = [0; 4];
+        flash.read(address + 2 + len as u32, &mut stored)?;
+
+        Ok(Parsed::Record {
+            len,
+            valid: crc == u32::from_le_bytes(stored),
+        })
+    }
+
+    fn sector_state<SPI: SpiDevice, D: DelayNs>(&self, flash: &mut W25qxx<SPI, D>, sector: usize) -> Result<SectorState, Error<SPI::Error>> {
+        let address = self.sector_address(sector);
+        let mut header: [u8; SECTOR_HEADER_SIZE] = [0; SECTOR_HEADER_SIZE];
+        flash.read(address, &mut header)?;
+
+        if header == [0xFF; SECTOR_HEADER_SIZE] {
+            let erased = flash.is_blank(address, W25QXX_SECTOR_SIZE)?;
+            return Ok(if erased { SectorState::Erased } else { SectorState::Garbage });
+        }
+        if header[..4] != LOG_MAGIC.to_le_bytes() {
+            return Ok(SectorState::Garbage);
+        }
+
+        Ok(SectorState::InUse(u32::from_le_bytes([header[4], header[5], header[6], header[7]])))
+    }
+
+    /// Writes the header of the erased `sector` and makes it the head.
+    fn start_sector<SPI: SpiDevice, D: DelayNs>(&mut self, flash: &mut W25qxx<SPI, D>, sector: usize, sequence: u32) -> Result<(), Error<SPI::Error>> {
+        let mut header: [u8; SECTOR_HEADER_SIZE] = [0; SECTOR_HEADER_SIZE];
+        header[..4].copy_from_slice(&LOG_MAGIC.to_le_bytes());
+        header[4..].copy_from_slice(&sequence.to_le_bytes());
+        flash.write(self.sector_address(sector), &header)?;
+
+        self.head = sector;
+        self.sequence = sequence;
+        self.offset = Some(SECTOR_HEADER_SIZE);
+        Ok(())
+    }
+
+    fn sector_address(&self, sector: usize) -> u32 {
+        self.address + (sector * W25QXX_SECTOR_SIZE) as u32
+    }
+}
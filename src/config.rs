@@ -0,0 +1,153 @@
+//! Configuration blob kept in two copies.
+//!
+//! [`ConfigBlob`] stores a serialized configuration in two sectors. Every
+//! copy carries a sequence number and a CRC, and [`ConfigBlob::save`] always
+//! erases and programs the copy that does not hold the current
+//! configuration. A power cut during a save thus leaves the previous good
+//! copy untouched, and [`ConfigBlob::load`] returns it.
+//!
+//! The caller serializes the configuration, with `postcard` or a plain
+//! `#[repr(C)]` struct. `Settings`, behind the `settings` feature, does the
+//! encoding and caches the value but keeps a single copy.
+
+use crate::checksum::{Checksum, Crc32};
+use crate::w25qxx::{Error, W25qxx, W25QXX_SECTOR_SIZE};
+use embedded_hal::delay::DelayNs;
+use embedded_hal::spi::SpiDevice;
+
+/// Marks a copy ("CFG2" in little endian).
+const CONFIG_MAGIC: u32 = 0x3247_4643;
+
+/// Magic, sequence number and blob length.
+const CONFIG_HEADER_SIZE: usize = 12;
+
+/// Largest blob a copy holds.
+pub const MAX_CONFIG_BLOB_SIZE: usize = W25QXX_SECTOR_SIZE - CONFIG_HEADER_SIZE - 4;
+
+/// Header of a copy whose CRC matches.
+#[derive(Clone, Copy, Debug)]
+struct CopyHeader {
+    sequence: u32,
+    len: usize,
+}
+
+/// Configuration stored in the two sectors at `address`.
+#[derive(Clone, Copy, Debug)]
+pub struct ConfigBlob {
+    address: u32,
+    /// Copy holding the current configuration and its header.
+    current: Option<(usize, CopyHeader)>,
+}
+
+impl ConfigBlob {
+    /// Finds the newest valid copy in the two sectors at `address`, which
+    /// must be sector aligned.
+    pub fn open<SPI: SpiDevice, D: DelayNs>(flash: &mut W25qxx<SPI, D>, address: u32) -> Result<ConfigBlob, Error<SPI::Error>> {
+        if !address.is_multiple_of(W25QXX_SECTOR_SIZE as u32) {
+            return Err(Error::NotAligned);
+        }
+
+        let mut blob = ConfigBlob { address, current: None };
+        blob.current = match (blob.read_copy(flash, 0)?, blob.read_copy(flash, 1)?) {
+            (Some(first), Some(second)) => {
+                /* Sequence numbers of the two copies differ by one, even across a wrap */
+                if (second.sequence.wrapping_sub(first.sequence) as i32) > 0 {
+                    Some((1, second))
+                } else {
+                    Some((0, first))
+                }
+            }
+            (Some(first), None) => Some((0, first)),
+            (None, Some(second)) => Some((1, second)),
+            (None, None) => None,
+        };
+
+        Ok(blob)
+    }
+
+    /// Reads the current configuration into `buffer` and returns its length,
+    /// or `None` if neither copy is valid. A buffer shorter than the blob
+    /// fails with [`Error::InvalidArgument`].
+    pub fn load<SPI: SpiDevice, D: DelayNs>(&self, flash: &mut W25qxx<SPI, D>, buffer: &mut [u8]) -> Result<Option<usize>, Error<SPI::Error>> {
+        let Some((index, copy)) = self.current else {
+            return Ok(None);
+        };
+        if buffer.len() < copy.len {
+            return Err(Error::InvalidArgument);
+        }
+
+        flash.read(self.copy_address(index) + CONFIG_HEADER_SIZE as u32, &mut buffer[..copy.len])?;
+        Ok(Some(copy.len))
+    }
+
+    /// Stores `data`, at most [`MAX_CONFIG_BLOB_SIZE`] bytes, in the other
+    /// copy and makes it the current one once it reads back valid. Fails
+    /// with [`Error::VerifyFailed`] otherwise, keeping the previous
+    /// configuration.
+    pub fn save<SPI: SpiDevice, D: DelayNs>(&mut self, flash: &mut W25qxx<SPI, D>, data: &[u8]) -> Result<(), Error<SPI::Error>> {
+        if data.len() > MAX_CONFIG_BLOB_SIZE {
+            return Err(Error::InvalidArgument);
+        }
+
+        let (index, sequence) = match self.current {
+            Some((index, copy)) => (1 - index, copy.sequence.wrapping_add(1)),
+            None => (0, 0),
+        };
+        let address = self.copy_address(index);
+
+        let mut header: [u8; CONFIG_HEADER_SIZE] = [0; CONFIG_HEADER_SIZE];
+        header[..4].copy_from_slice(&CONFIG_MAGIC.to_le_bytes());
+        header[4..8].copy_from_slice(&sequence.to_le_bytes());
+        header[8..].copy_from_slice(&(data.len() as u32).to_le_bytes());
+        let mut crc = Crc32::new();
+        crc.update(&header);
+        crc.update(data);
+        let crc = crc.finalize();
+
+        /* The header goes last: the copy is not valid before all of it is written */
+        flash.erase(address, W25QXX_SECTOR_SIZE)?;
+        flash.write(address + CONFIG_HEADER_SIZE as u32, data)?;
+        flash.write(address + (CONFIG_HEADER_SIZE + data.len()) as u32, &crc.to_le_bytes())?;
+        flash.write(address, &header)?;
+
+        match self.read_copy(flash, index)? {
+            Some(copy) if copy.sequence == sequence && copy.len == data.len() => {
+                self.current = Some((index, copy));
+                Ok(())
+            }
+            _ => Err(Error::VerifyFailed { address }),
+        }
+    }
+
+    /// Sequence number of the current copy, counting saves.
+    pub fn sequence(&self) -> Option<u32> {
+        self.current.map(|(_, copy)| copy.sequence)
+    }
+
+    /// Header of copy `index` if its CRC matches.
+    fn read_copy<SPI: SpiDevice, D: DelayNs>(&self, flash: &mut W25qxx<SPI, D>, index: usize) -> Result<Option<CopyHeader>, Error<SPI::Error>> {
+        let address = self.copy_address(index);
+
+        let mut header: [u8; CONFIG_HEADER_SIZE] = [0; CONFIG_HEADER_SIZE];
+        flash.read(address, &mut header)?;
+        if header[..4] != CONFIG_MAGIC.to_le_bytes() {
+            return Ok(None);
+        }
+
+        let sequence = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+        let len = u32::from_le_bytes([header[8], header[9], header[10], header[11]]) as usize;
+        if len > MAX_CONFIG_BLOB_SIZE {
+            return Ok(None);
+        }
+
+        let crc = flash.digest(address, CONFIG_HEADER_SIZE + len, Crc32::new())?;
+        let mut stored: [u8; 4] = [0; 4];
+        flash.read(address + (CONFIG_HEADER_SIZE + len) as u32, &mut stored)?;
+
+        Ok((crc == u32::from_le_bytes(stored)).then_some(CopyHeader { sequence, len }))
+    }
+
+    fn copy_address(&self, index: usize) -> u32 {
+        self.address + (index * W25QXX_SECTOR_SIZE) as u32
+    }
+}
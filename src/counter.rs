@@ -0,0 +1,161 @@
+//! Persistent monotonic counters.
+//!
+//! A [`MonotonicCounter`] counts boots, operating hours or other events
+//! without an erase per increment. Each sector of its area starts with a
+//! header holding the count it was started at; every increment then programs
+//! one more bit of the sector from 1 to 0, lowest bit of each byte first, so
+//! a sector takes tens of thousands of increments. The count is the header
+//! value plus the programmed bits. Once the bits of a sector are used up, the
+//! counter erases the next sector and starts it at the current count.
+//!
+//! The count never goes back: a power loss during an increment leaves the
+//! old or the new value, and a sector is only used once its header is
+//! complete, while the previous sector still holds the count until then.
+//! Keep one area per counter.
+
+use crate::checksum::Crc32;
+use crate::w25qxx::{Error, W25qxx, W25QXX_PAGE_SIZE, W25QXX_SECTOR_SIZE};
+use embedded_hal::delay::DelayNs;
+use embedded_hal::spi::SpiDevice;
+
+/// Marks a sector of a counter ("MCNT" in little endian).
+const COUNTER_MAGIC: u32 = 0x544E_434D;
+
+/// Magic, start count and CRC of both.
+const COUNTER_HEADER_SIZE: usize = 16;
+
+/// Increments a sector takes, one per bit after the header.
+pub const COUNTER_SECTOR_INCREMENTS: u64 = ((W25QXX_SECTOR_SIZE - COUNTER_HEADER_SIZE) * 8) as u64;
+
+/// Monotonic counter in a sector-aligned area of at least two sectors.
+#[derive(Clone, Copy, Debug)]
+pub struct MonotonicCounter {
+    address: u32,
+    sectors: usize,
+    /// Sector counting, and the count its header holds.
+    active: usize,
+    base: u64,
+    /// Bits programmed in the active sector.
+    bits: u64,
+}
+
+impl MonotonicCounter {
+    /// Reads the counter in the `len` bytes at `address`, both sector
+    /// aligned, starting it at 0 if the area holds none.
+    pub fn open<SPI: SpiDevice, D: DelayNs>(flash: &mut W25qxx<SPI, D>, address: u32, len: usize) -> Result<MonotonicCounter, Error<SPI::Error>> {
+        if !address.is_multiple_of(W25QXX_SECTOR_SIZE as u32) || !len.is_multiple_of(W25QXX_SECTOR_SIZE) {
+            return Err(Error::NotAligned);
+        }
+        if len < 2 * W25QXX_SECTOR_SIZE {
+            return Err(Error::InvalidArgument);
+        }
+
+        let mut counter = MonotonicCounter {
+            address,
+            sectors: len / W25QXX_SECTOR_SIZE,
+            active: 0,
+            base: 0,
+            bits: 0,
+        };
+        let mut highest = None;
+
+        for sector in 0..counter.sectors {
+            let Some(base) = counter.read_header(flash, sector)? else {
+                continue;
+            };
+            let bits = counter.count_bits(flash, sector)?;
+            if highest.is_none_or(|(_, highest_base, highest_bits)| base + bits > highest_base + highest_bits) {
+                highest = Some((sector, base, bits));
+            }
+        }
+
+        match highest {
+            Some((sector, base, bits)) => {
+                counter.active = sector;
+                counter.base = base;
+                counter.bits = bits;
+            }
+            None => {
+                if !flash.is_blank(counter.sector_address(0), W25QXX_SECTOR_SIZE)? {
+                    flash.erase(counter.sector_address(0), W25QXX_SECTOR_SIZE)?;
+                }
+                counter.start_sector(flash, 0, 0)?;
+            }
+        }
+
+        Ok(counter)
+    }
+
+    /// Current count.
+    pub fn value(&self) -> u64 {
+        self.base + self.bits
+    }
+
+    /// Adds one to the count and returns the new value. Moves on to the next
+    /// sector, erasing it, when the bits of the current one are used up.
+    pub fn increment<SPI: SpiDevice, D: DelayNs>(&mut self, flash: &mut W25qxx<SPI, D>) -> Result<u64, Error<SPI::Error>> {
+        if self.bits >= COUNTER_SECTOR_INCREMENTS {
+            let next = (self.active + 1) % self.sectors;
+            flash.erase(self.sector_address(next), W25QXX_SECTOR_SIZE)?;
+            self.start_sector(flash, next, self.value())?;
+        }
+
+        let offset = COUNTER_HEADER_SIZE + (self.bits / 8) as usize;
+        let address = self.sector_address(self.active) + offset as u32;
+        let byte = !((1u16 << (self.bits % 8 + 1)) - 1) as u8;
+
+        flash.write(address, &[byte])?;
+        self.bits += 1;
+        Ok(self.value())
+    }
+
+    /// Start count of `sector` if its header is complete.
+    fn read_header<SPI: SpiDevice, D: DelayNs>(&self, flash: &mut W25qxx<SPI, D>, sector: usize) -> Result<Option<u64>, Error<SPI::Error>> {
+        let mut header: [u8; COUNTER_HEADER_SIZE] = [0; COUNTER_HEADER_SIZE];
+        flash.read(self.sector_address(sector), &mut header)?;
+
+        let crc = u32::from_le_bytes([header[12], header[13], header[14], header[15]]);
+        if header[..4] != COUNTER_MAGIC.to_le_bytes() || Crc32::checksum(&header[..12]) != crc {
+            return Ok(None);
+        }
+
+        let mut base: [u8; 8] = [0; 8];
+        base.copy_from_slice(&header[4..12]);
+        Ok(Some(u64::from_le_bytes(base)))
+    }
+
+    /// Bits programmed after the header of `sector`.
+    fn count_bits<SPI: SpiDevice, D: DelayNs>(&self, flash: &mut W25qxx<SPI, D>, sector: usize) -> Result<u64, Error<SPI::Error>> {
+        let mut chunk: [u8; W25QXX_PAGE_SIZE] = [0; W25QXX_PAGE_SIZE];
+        let mut offset = COUNTER_HEADER_SIZE;
+        let mut bits = 0;
+
+        while offset < W25QXX_SECTOR_SIZE {
+            let chunk = &mut chunk[..(W25QXX_SECTOR_SIZE - offset).min(W25QXX_PAGE_SIZE)];
+            flash.read(self.sector_address(sector) + offset as u32, chunk)?;
+            bits += chunk.iter().map(|byte| byte.count_zeros() as u64).sum::<u64>();
+            offset += chunk.len();
+        }
+
+        Ok(bits)
+    }
+
+    /// Writes the header of the erased `sector` and makes it the active one.
+    fn start_sector<SPI: SpiDevice, D: DelayNs>(&mut self, flash: &mut W25qxx<SPI, D>, sector: usize, base: u64) -> Result<(), Error<SPI::Error>> {
+        let mut header: [u8; COUNTER_HEADER_SIZE] = [0; COUNTER_HEADER_SIZE];
+        header[..4].copy_from_slice(&COUNTER_MAGIC.to_le_bytes());
+        header[4..12].copy_from_slice(&base.to_le_bytes());
+        let crc = Crc32::checksum(&header[..12]);
+        header[12..].copy_from_slice(&crc.to_le_bytes());
+        flash.write(self.sector_address(sector), &header)?;
+
+        self.active = sector;
+        self.base = base;
+        self.bits = 0;
+        Ok(())
+    }
+
+    fn sector_address(&self, sector: usize) -> u32 {
+        self.address + (sector * W25QXX_SECTOR_SIZE) as u32
+    }
+}
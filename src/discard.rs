@@ -0,0 +1,148 @@
+//! Deferred erase of sectors that are no longer needed.
+//!
+//! Erasing on demand puts a sector erase (up to 400 ms) in front of the write
+//! that needs the space. [`DeferredErase`] lets the application
+//! [`discard`](DeferredErase::discard) sectors as soon as their contents are
+//! dead, and erases them later: in idle time through
+//! [`erase_pending`](DeferredErase::erase_pending), or at the latest right
+//! before a write lands in one of them.
+
+use crate::w25qxx::{Error, W25qxx, W25QXX_SECTOR_SIZE};
+use core::ops::{Deref, DerefMut};
+use embedded_hal::delay::DelayNs;
+use embedded_hal::spi::{ErrorType, SpiDevice};
+
+/// Driver wrapper keeping track of discarded sectors among `N` consecutive
+/// sectors starting at `base`.
+///
+/// Discarded sectors still read back their stale contents until they are
+/// erased. All other driver methods are reachable through `Deref`; writes
+/// that bypass [`DeferredErase::write`] do not trigger the pending erases.
+pub struct DeferredErase<SPI: ErrorType, D, const N: usize> {
+    flash: W25qxx<SPI, D>,
+    base: u32,
+    discarded: [bool; N],
+}
+
+impl<SPI: ErrorType, D, const N: usize> DeferredErase<SPI, D, N> {
+    /// Tracks the `N` sectors starting at `base`, none of them discarded.
+    pub fn new(flash: W25qxx<SPI, D>, base: u32) -> Self {
+        DeferredErase { flash, base, discarded: [false; N] }
+    }
+
+    /// Number of discarded sectors not erased yet.
+    pub fn pending(&self) -> usize {
+        self.discarded.iter().filter(|discarded| **discarded).count()
+    }
+
+    /// Whether the sector containing `address` waits for an erase.
+    pub fn is_discarded(&self, address: u32) -> bool {
+        self.index(address).is_some_and(|index| self.discarded[index])
+    }
+
+    /// Gives back the wrapped driver. Pending erases are dropped.
+    pub fn into_inner(self) -> W25qxx<SPI, D> {
+        self.flash
+    }
+
+    fn index(&self, address: u32) -> Option<usize> {
+        let index = address.checked_sub(self.base)? as usize / W25QXX_SECTOR_SIZE;
+        (index < N).then_some(index)
+    }
+
+    fn address(&self, index: usize) -> u32 {
+        self.base + (index * W25QXX_SECTOR_SIZE) as u32
+    }
+
+    /// Indexes of the tracked sectors overlapping `[address, address + len)`.
+    fn sectors(&self, address: u32, len: usize) -> core::ops::Range<usize> {
+        let start = (address.saturating_sub(self.base) as usize / W25QXX_SECTOR_SIZE).min(N);
+        let end = ((address as usize + len).saturating_sub(self.base as usize)).div_ceil(W25QXX_SECTOR_SIZE).min(N);
+        start..end.max(start)
+    }
+}
+
+impl<SPI, D, const N: usize> DeferredErase<SPI, D, N>
+where
+    SPI: SpiDevice,
+    D: DelayNs,
+{
+    /// Marks the whole sectors of `[address, address + len)` as no longer
+    /// needed. Nothing is sent to the chip.
+    pub fn discard(&mut self, address: u32, len: usize) -> Result<(), Error<SPI::Error>> {
+        if !address.is_multiple_of(W25QXX_SECTOR_SIZE as u32) || !len.is_multiple_of(W25QXX_SECTOR_SIZE) {
+            return Err(Error::NotAligned);
+        }
+
+        let first = self.index(address).ok_or(Error::InvalidAddress)?;
+        let count = len / W25QXX_SECTOR_SIZE;
+        if first + count > N {
+            return Err(Error::InvalidAddress);
+        }
+
+        self.discarded[first..first + count].fill(true);
+        Ok(())
+    }
+
+    /// Erases up to `max_sectors` discarded sectors, for idle periods.
+    /// Returns how many were erased.
+    pub fn erase_pending(&mut self, max_sectors: usize) -> Result<usize, Error<SPI::Error>> {
+        let mut erased = 0;
+
+        for index in 0..N {
+            if erased == max_sectors {
+                break;
+            }
+            if self.discarded[index] {
+                self.flash.erase(self.address(index), W25QXX_SECTOR_SIZE)?;
+                self.discarded[index] = false;
+                erased += 1;
+            }
+        }
+
+        Ok(erased)
+    }
+
+    /// Erases the discarded sectors the write lands in, then writes.
+    pub fn write(&mut self, address: u32, buffer: &[u8]) -> Result<(), Error<SPI::Error>> {
+        for index in self.sectors(address, buffer.len()) {
+            if self.discarded[index] {
+                self.flash.erase(self.address(index), W25QXX_SECTOR_SIZE)?;
+                self.discarded[index] = false;
+            }
+        }
+
+        self.flash.write(address, buffer)
+    }
+
+    /// Erases now, dropping any pending erase of the same sectors.
+    pub fn erase(&mut self, address: u32, len: usize) -> Result<(), Error<SPI::Error>> {
+        self.flash.erase(address, len)?;
+
+        for index in self.sectors(address, len) {
+            self.discarded[index] = false;
+        }
+
+        Ok(())
+    }
+
+    pub fn chip_erase(&mut self) -> Result<(), Error<SPI::Error>> {
+        self.flash.chip_erase()?;
+        self.discarded = [false; N];
+        Ok(())
+    }
+}
+
+impl<SPI: ErrorType, D, const N: usize> Deref for DeferredErase<SPI, D, N> {
+    type Target = W25qxx<SPI, D>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.flash
+    }
+}
+
+impl<SPI: ErrorType, D, const N: usize> DerefMut for DeferredErase<SPI, D, N> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.flash
+    }
+}
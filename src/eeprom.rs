@@ -0,0 +1,187 @@
+//! EEPROM emulation.
+//!
+//! [`Eeprom`] gives `N` bytes with the update semantics of an EEPROM: any
+//! byte range can be rewritten at any time, without erasing first. The
+//! contents are cached in RAM, and every [`write`](Eeprom::write) programs a
+//! new copy of the whole image, tagged with a version number, into the next
+//! free slot of its area. Slots are one or more pages; a sector is only
+//! erased when the writes come back to it, so each erase covers as many
+//! writes as the area has slots.
+//!
+//! A copy only counts once its CRC matches, so a power loss during a write
+//! leaves the previous contents. [`Eeprom::open`] loads the copy with the
+//! highest version.
+
+use crate::checksum::{Checksum, Crc32};
+use crate::w25qxx::{Error, W25qxx, W25QXX_PAGE_SIZE, W25QXX_SECTOR_SIZE};
+use core::ops::Range;
+use embedded_hal::delay::DelayNs;
+use embedded_hal::spi::SpiDevice;
+
+/// Marks a copy of the image ("EEPR" in little endian).
+const EEPROM_MAGIC: u32 = 0x5250_4545;
+
+/// Magic + version.
+const EEPROM_HEADER_SIZE: usize = 8;
+
+/// Emulated EEPROM of `N` bytes in a sector-aligned area of at least two
+/// sectors. Erased bytes read as 0xFF.
+pub struct Eeprom<const N: usize> {
+    address: u32,
+    slots: usize,
+    image: [u8; N],
+    version: u32,
+    /// Slot the next copy goes to.
+    next: usize,
+}
+
+impl<const N: usize> Eeprom<N> {
+    /// Bytes of one copy: header, image and CRC, rounded up to whole pages.
+    const SLOT_SIZE: usize = (EEPROM_HEADER_SIZE + N + 4).next_multiple_of(W25QXX_PAGE_SIZE);
+
+    /// Loads the latest copy stored in the `len` bytes at `address`, both
+    /// sector aligned, or an erased image when there is none.
+    pub fn open<SPI: SpiDevice, D: DelayNs>(flash: &mut W25qxx<SPI, D>, address: u32, len: usize) -> Result<Self, Error<SPI::Error>> {
+        /* A copy must fit in a sector */
+        const { assert!(Self::SLOT_SIZE <= W25QXX_SECTOR_SIZE) };
+
+        if !address.is_multiple_of(W25QXX_SECTOR_SIZE as u32) || !len.is_multiple_of(W25QXX_SECTOR_SIZE) {
+            return Err(Error::NotAligned);
+        }
+        if len < 2 * W25QXX_SECTOR_SIZE {
+            return Err(Error::InvalidArgument);
+        }
+
+        let mut eeprom = Eeprom {
+            address,
+            slots: len / W25QXX_SECTOR_SIZE * Self::slots_per_sector(),
+            image: [0xFF; N],
+            version: 0,
+            next: 0,
+        };
+
+        let mut newest = None;
+        for slot in 0..eeprom.slots {
+            if let Some(version) = eeprom.read_slot(flash, slot)? {
+                if newest.is_none_or(|(_, newest)| version > newest) {
+                    newest = Some((slot, version));
+                }
+            }
+        }
+
+        if let Some((slot, version)) = newest {
+            flash.read(eeprom.slot_address(slot) + EEPROM_HEADER_SIZE as u32, &mut eeprom.image)?;
+            eeprom.version = version;
+            eeprom.next = (slot + 1) % eeprom.slots;
+        }
+
+        Ok(eeprom)
+    }
+
+    /// The `len` bytes at `offset`, served from RAM. `None` if they reach
+    /// past the `N` bytes of the EEPROM.
+    pub fn read(&self, offset: usize, len: usize) -> Option<&[u8]> {
+        Self::range(offset, len).map(|range| &self.image[range])
+    }
+
+    /// Writes `data` at `offset`, failing with [`Error::InvalidAddress`] past
+    /// the end of the EEPROM. Nothing is programmed if the image holds
+    /// that data already. The cache keeps the previous contents if the write
+    /// fails.
+    pub fn write<SPI: SpiDevice, D: DelayNs>(&mut self, flash: &mut W25qxx<SPI, D>, offset: usize, data: &[u8]) -> Result<(), Error<SPI::Error>> {
+        let range = Self::range(offset, data.len()).ok_or(Error::InvalidAddress)?;
+        if self.image[range.clone()] == *data {
+            return Ok(());
+        }
+
+        let mut image = self.image;
+        image[range].copy_from_slice(data);
+
+        let slot = self.free_slot(flash)?;
+        let version = self.version.wrapping_add(1);
+        let address = self.slot_address(slot);
+
+        let mut header: [u8; EEPROM_HEADER_SIZE] = [0; EEPROM_HEADER_SIZE];
+        header[..4].copy_from_slice(&EEPROM_MAGIC.to_le_bytes());
+        header[4..].copy_from_slice(&version.to_le_bytes());
+        let mut crc = Crc32::new();
+        crc.update(&header);
+        crc.update(&image);
+
+        self.next = (slot + 1) % self.slots;
+        flash.write(address, &header)?;
+        flash.write(address + EEPROM_HEADER_SIZE as u32, &image)?;
+        flash.write(address + (EEPROM_HEADER_SIZE + N) as u32, &crc.finalize().to_le_bytes())?;
+
+        self.image = image;
+        self.version = version;
+        Ok(())
+    }
+
+    /// The cached image.
+    pub fn as_bytes(&self) -> &[u8; N] {
+        &self.image
+    }
+
+    /// Number of writes since the area was first used.
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// First blank slot from `next` on, erasing the sector it starts when
+    /// needed. The previous slot holds the latest copy and lies in another
+    /// sector then, as there are at least two.
+    fn free_slot<SPI: SpiDevice, D: DelayNs>(&mut self, flash: &mut W25qxx<SPI, D>) -> Result<usize, Error<SPI::Error>> {
+        /* Slots a torn write left programmed are skipped */
+        for _ in 0..self.slots {
+            let slot = self.next;
+            if slot.is_multiple_of(Self::slots_per_sector()) {
+                let sector = self.slot_address(slot);
+                if !flash.is_blank(sector, W25QXX_SECTOR_SIZE)? {
+                    flash.erase(sector, W25QXX_SECTOR_SIZE)?;
+                }
+                return Ok(slot);
+            }
+            if flash.is_blank(self.slot_address(slot), Self::SLOT_SIZE)? {
+                return Ok(slot);
+            }
+            self.next = (slot + 1) % self.slots;
+        }
+
+        Err(Error::NoSpace)
+    }
+
+    /// Version of the copy in `slot`, `None` unless it is complete.
+    fn read_slot<SPI: SpiDevice, D: DelayNs>(&self, flash: &mut W25qxx<SPI, D>, slot: usize) -> Result<Option<u32>, Error<SPI::Error>> {
+        let address = self.slot_address(slot);
+
+        let mut header: [u8; EEPROM_HEADER_SIZE] = [0; EEPROM_HEADER_SIZE];
+        flash.read(address, &mut header)?;
+        if header[..4] != EEPROM_MAGIC.to_le_bytes() {
+            return Ok(None);
+        }
+
+        let crc = flash.digest(address, EEPROM_HEADER_SIZE + N, Crc32::new())?;
+        let mut stored: [u8; 4] = [0; 4];
+        flash.read(address + (EEPROM_HEADER_SIZE + N) as u32, &mut stored)?;
+        if crc != u32::from_le_bytes(stored) {
+            return Ok(None);
+        }
+
+        Ok(Some(u32::from_le_bytes([header[4], header[5], header[6], header[7]])))
+    }
+
+    fn range(offset: usize, len: usize) -> Option<Range<usize>> {
+        offset.checked_add(len).filter(|end| *end <= N).map(|end| offset..end)
+    }
+
+    fn slots_per_sector() -> usize {
+        W25QXX_SECTOR_SIZE / Self::SLOT_SIZE
+    }
+
+    fn slot_address(&self, slot: usize) -> u32 {
+        let sector = slot / Self::slots_per_sector();
+        let index = slot % Self::slots_per_sector();
+        self.address + (sector * W25QXX_SECTOR_SIZE + index * Self::SLOT_SIZE) as u32
+    }
+}
@@ -0,0 +1,256 @@
+//! Encryption at rest.
+//!
+//! [`EncryptedRegion`] encrypts everything written to a range of the chip
+//! with AES-128-XTS, the mode disk encryption uses, and decrypts it again on
+//! reads. The tweak is the index of the 4 KiB sector, so each sector is
+//! encrypted differently and moving ciphertext to another sector does not
+//! decrypt to the original data. A dumped chip shows no plaintext.
+//!
+//! XTS works on 16-byte blocks: writes cover whole blocks. To keep the NOR
+//! rules usable, a block whose ciphertext reads as all 0xFF is taken as
+//! erased and reads as 0xFF, and a plaintext block of all 0xFF is left
+//! erased instead of being encrypted. Each block can then be written once
+//! per erase, as with any flash.
+//!
+//! Encryption hides the contents, not whether a block changed: the same data
+//! written to the same place gives the same ciphertext. It does not detect
+//! tampering either; check the region with a signature or a MAC for that.
+//! Enabled by the `encryption` feature.
+//!
+//! The region also implements the `embedded-storage` [`NorFlash`] traits,
+//! with offsets counted from the start of the range, so it can sit under a
+//! [`Scrambled`](crate::Scrambled) layer or a filesystem.
+
+use crate::w25qxx::{Error, W25qxx, W25QXX_PAGE_SIZE, W25QXX_SECTOR_SIZE};
+use aes::cipher::{BlockDecrypt, BlockEncrypt, KeyInit};
+use aes::Aes128;
+use core::ops::Range;
+use embedded_hal::delay::DelayNs;
+use embedded_hal::spi::{ErrorType, SpiDevice};
+use embedded_storage::nor_flash::{self, NorFlash, ReadNorFlash};
+
+/// AES block, the write unit of the region.
+pub const ENCRYPTION_BLOCK_SIZE: usize = 16;
+
+/// Driver wrapper encrypting the sectors of `range`.
+///
+/// Addresses are chip addresses inside the range. It has no `DerefMut`, so
+/// the driver's own writes cannot put plaintext into the range;
+/// [`get_ref`](Self::get_ref) gives the `&self` methods of the driver.
+pub struct EncryptedRegion<SPI: ErrorType, D> {
+    flash: W25qxx<SPI, D>,
+    range: Range<u32>,
+    data_key: Aes128,
+    tweak_key: Aes128,
+}
+
+impl<SPI: ErrorType, D> EncryptedRegion<SPI, D> {
+    pub fn range(&self) -> Range<u32> {
+        self.range.clone()
+    }
+
+    /// The wrapped driver, for its parameters and cached state.
+    pub fn get_ref(&self) -> &W25qxx<SPI, D> {
+        &self.flash
+    }
+
+    /// Gives back the wrapped driver.
+    pub fn into_inner(self) -> W25qxx<SPI, D> {
+        self.flash
+    }
+
+    /// Fails with [`Error::InvalidAddress`] unless `[address, address + len)`
+    /// lies inside the region.
+    fn check_range(&self, address: u32, len: usize) -> Result<(), Error<SPI::Error>> {
+        match address.checked_add(len as u32) {
+            Some(end) if address >= self.range.start && end <= self.range.end => Ok(()),
+            _ => Err(Error::InvalidAddress),
+        }
+    }
+
+    /// Encrypts or decrypts the whole blocks of `buffer`, which starts at the
+    /// block aligned chip address `address`.
+    fn crypt(&self, address: u32, buffer: &mut [u8], encrypt: bool) {
+        let mut sector = u32::MAX;
+        let mut tweak = [0u8; ENCRYPTION_BLOCK_SIZE];
+
+        for (index, block) in buffer.chunks_exact_mut(ENCRYPTION_BLOCK_SIZE).enumerate() {
+            let block_address = address as usize + index * ENCRYPTION_BLOCK_SIZE;
+
+            /* T = E(K2, sector) * alpha^j for block j of the sector */
+            if block_address / W25QXX_SECTOR_SIZE != sector as usize {
+                sector = (block_address / W25QXX_SECTOR_SIZE) as u32;
+                tweak = [0; ENCRYPTION_BLOCK_SIZE];
+                tweak[..4].copy_from_slice(&sector.to_le_bytes());
+                self.tweak_key.encrypt_block((&mut tweak).into());
+                for _ in 0..block_address % W25QXX_SECTOR_SIZE / ENCRYPTION_BLOCK_SIZE {
+                    multiply_by_alpha(&mut tweak);
+                }
+            }
+
+            if encrypt && block.iter().all(|byte| *byte == 0xFF) {
+                /* Left erased, see the module documentation */
+            } else if encrypt {
+                xor(block, &tweak);
+                self.data_key.encrypt_block(block.into());
+                xor(block, &tweak);
+            } else if !block.iter().all(|byte| *byte == 0xFF) {
+                xor(block, &tweak);
+                self.data_key.decrypt_block(block.into());
+                xor(block, &tweak);
+            }
+
+            multiply_by_alpha(&mut tweak);
+        }
+    }
+}
+
+impl<SPI, D> EncryptedRegion<SPI, D>
+where
+    SPI: SpiDevice,
+    D: DelayNs,
+{
+    /// Encrypts the sectors of `range` with `key`: the data key followed by
+    /// the tweak key, which must differ. The same key must be used every time
+    /// the region is opened.
+    pub fn new(flash: W25qxx<SPI, D>, range: Range<u32>, key: &[u8; 32]) -> Result<Self, Error<SPI::Error>> {
+        if !range.start.is_multiple_of(W25QXX_SECTOR_SIZE as u32) || !range.end.is_multiple_of(W25QXX_SECTOR_SIZE as u32) {
+            return Err(Error::NotAligned);
+        }
+        if range.end as usize > flash.params().capacity || range.start > range.end {
+            return Err(Error::InvalidAddress);
+        }
+
+        let (data_key, tweak_key) = key.split_at(16);
+        if data_key == tweak_key {
+            return Err(Error::InvalidArgument);
+        }
+
+        Ok(EncryptedRegion {
+            flash,
+            range,
+            data_key: Aes128::new(data_key.into()),
+            tweak_key: Aes128::new(tweak_key.into()),
+        })
+    }
+
+    /// Reads and decrypts `buffer.len()` bytes at `address`. Any address and
+    /// length inside the region work; the blocks around them are read whole.
+    pub fn read(&mut self, address: u32, buffer: &mut [u8]) -> Result<(), Error<SPI::Error>> {
+        self.check_range(address, buffer.len())?;
+
+        let end = address as usize + buffer.len();
+        let mut chunk: [u8; W25QXX_PAGE_SIZE] = [0; W25QXX_PAGE_SIZE];
+        let mut chunk_start = address as usize / ENCRYPTION_BLOCK_SIZE * ENCRYPTION_BLOCK_SIZE;
+
+        while chunk_start < end {
+            let chunk_end = end.next_multiple_of(ENCRYPTION_BLOCK_SIZE).min(chunk_start + W25QXX_PAGE_SIZE);
+            let chunk = &mut chunk[..chunk_end - chunk_start];
+            self.flash.read(chunk_start as u32, chunk)?;
+            self.crypt(chunk_start as u32, chunk, false);
+
+            let from = chunk_start.max(address as usize);
+            let to = chunk_end.min(end);
+            buffer[from - address as usize..to - address as usize].copy_from_slice(&chunk[from - chunk_start..to - chunk_start]);
+            chunk_start = chunk_end;
+        }
+
+        Ok(())
+    }
+
+    /// Encrypts and writes `buffer` at `address`. Both have to be multiples
+    /// of [`ENCRYPTION_BLOCK_SIZE`], and the blocks erased.
+    pub fn write(&mut self, address: u32, buffer: &[u8]) -> Result<(), Error<SPI::Error>> {
+        if !address.is_multiple_of(ENCRYPTION_BLOCK_SIZE as u32) || !buffer.len().is_multiple_of(ENCRYPTION_BLOCK_SIZE) {
+            return Err(Error::NotAligned);
+        }
+        self.check_range(address, buffer.len())?;
+
+        let mut chunk: [u8; W25QXX_PAGE_SIZE] = [0; W25QXX_PAGE_SIZE];
+        for (index, data) in buffer.chunks(W25QXX_PAGE_SIZE).enumerate() {
+            let chunk_address = address + (index * W25QXX_PAGE_SIZE) as u32;
+            let chunk = &mut chunk[..data.len()];
+            chunk.copy_from_slice(data);
+            self.crypt(chunk_address, chunk, true);
+            self.flash.write(chunk_address, chunk)?;
+        }
+
+        Ok(())
+    }
+
+    /// Erases the sectors of `[address, address + len)`; they read as 0xFF
+    /// afterwards.
+    pub fn erase(&mut self, address: u32, len: usize) -> Result<(), Error<SPI::Error>> {
+        self.check_range(address, len)?;
+        self.flash.erase(address, len)
+    }
+}
+
+impl<SPI: SpiDevice, D> nor_flash::ErrorType for EncryptedRegion<SPI, D> {
+    type Error = Error<SPI::Error>;
+}
+
+impl<SPI, D> ReadNorFlash for EncryptedRegion<SPI, D>
+where
+    SPI: SpiDevice,
+    D: DelayNs,
+{
+    const READ_SIZE: usize = 1;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        let address = self.range.start.checked_add(offset).ok_or(Error::InvalidAddress)?;
+        self.check_range(address, bytes.len())?;
+
+        /* Zero-length reads are valid for the trait but would still read a block */
+        if bytes.is_empty() {
+            return Ok(());
+        }
+
+        EncryptedRegion::read(self, address, bytes)
+    }
+
+    fn capacity(&self) -> usize {
+        self.range.len()
+    }
+}
+
+/// Each block can be written once per erase, so there is no
+/// `MultiwriteNorFlash`.
+impl<SPI, D> NorFlash for EncryptedRegion<SPI, D>
+where
+    SPI: SpiDevice,
+    D: DelayNs,
+{
+    const WRITE_SIZE: usize = ENCRYPTION_BLOCK_SIZE;
+    const ERASE_SIZE: usize = W25QXX_SECTOR_SIZE;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        if from > to {
+            return Err(Error::InvalidArgument);
+        }
+
+        let address = self.range.start.checked_add(from).ok_or(Error::InvalidAddress)?;
+        EncryptedRegion::erase(self, address, (to - from) as usize)
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        let address = self.range.start.checked_add(offset).ok_or(Error::InvalidAddress)?;
+        EncryptedRegion::write(self, address, bytes)
+    }
+}
+
+/// Multiplication of an XTS tweak by the primitive element of GF(2^128),
+/// little endian as in IEEE 1619.
+fn multiply_by_alpha(tweak: &mut [u8; ENCRYPTION_BLOCK_SIZE]) {
+    let carry = tweak[ENCRYPTION_BLOCK_SIZE - 1] >> 7;
+    for index in (1..ENCRYPTION_BLOCK_SIZE).rev() {
+        tweak[index] = tweak[index] << 1 | tweak[index - 1] >> 7;
+    }
+    tweak[0] = tweak[0] << 1 ^ (carry * 0x87);
+}
+
+fn xor(block: &mut [u8], tweak: &[u8; ENCRYPTION_BLOCK_SIZE]) {
+    for (byte, tweak) in block.iter_mut().zip(tweak) {
+        *byte ^= tweak;
+    }
+}
@@ -0,0 +1,109 @@
+//! The HOLD# / RESET# pin.
+//!
+//! Depending on the HOLD/RST bit of status register 3 (or a dedicated pin on
+//! 16-pin packages), the pin either pauses the chip while other devices use
+//! a shared bus, or resets it. [`HoldReset`] owns the GPIO driving it and
+//! keeps it high, its inactive level, except while holding or resetting.
+//! Both functions are gone while the pin is IO3 of quad reads.
+
+use crate::multi_io::Lines;
+use crate::w25qxx::{Error, W25qxx};
+use core::ops::{Deref, DerefMut};
+use embedded_hal::delay::DelayNs;
+use embedded_hal::digital::OutputPin;
+use embedded_hal::spi::{ErrorType, SpiDevice};
+
+/// Driver wrapper owning the HOLD# / RESET# pin.
+///
+/// All other driver methods are reachable through `Deref`. Commands sent
+/// while the chip is held are ignored by it.
+pub struct HoldReset<SPI: ErrorType, D, P> {
+    flash: W25qxx<SPI, D>,
+    pin: P,
+    held: bool,
+}
+
+impl<SPI: ErrorType, D, P> HoldReset<SPI, D, P> {
+    /// Whether [`hold`](Self::hold) is in effect.
+    pub fn is_held(&self) -> bool {
+        self.held
+    }
+
+    /// Gives back the wrapped driver and the pin, as it was left.
+    pub fn into_inner(self) -> (W25qxx<SPI, D>, P) {
+        (self.flash, self.pin)
+    }
+}
+
+impl<SPI, D, P> HoldReset<SPI, D, P>
+where
+    SPI: SpiDevice,
+    D: DelayNs,
+    P: OutputPin,
+{
+    /// Takes the driver and the pin, and drives the pin high.
+    pub fn new(flash: W25qxx<SPI, D>, mut pin: P) -> Result<Self, Error<SPI::Error>> {
+        pin.set_high().map_err(|_| Error::PinFailed)?;
+        Ok(HoldReset { flash, pin, held: false })
+    }
+
+    /// Drives HOLD# low: the chip ignores the clock and leaves MISO floating
+    /// until [`release_hold`](Self::release_hold), so the bus can serve
+    /// other devices. Needs HOLD/RST clear in
+    /// [`StatusRegister3`](crate::StatusRegister3); fails with
+    /// [`Error::Unsupported`] while the pin is a quad data line.
+    pub fn hold(&mut self) -> Result<(), Error<SPI::Error>> {
+        if self.pin_is_data_line() {
+            return Err(Error::Unsupported);
+        }
+
+        self.pin.set_low().map_err(|_| Error::PinFailed)?;
+        self.held = true;
+        Ok(())
+    }
+
+    /// Drives HOLD# high again; the chip continues where it was held.
+    pub fn release_hold(&mut self) -> Result<(), Error<SPI::Error>> {
+        self.pin.set_high().map_err(|_| Error::PinFailed)?;
+        self.held = false;
+        Ok(())
+    }
+
+    /// Pulses RESET# low, then identifies the chip and sets up its modes
+    /// again. Unlike Enable Reset/Reset this reaches a chip that no longer
+    /// decodes commands. Needs HOLD/RST set, or a dedicated RESET# pin; a
+    /// program or erase in progress is aborted.
+    pub fn hardware_reset(&mut self) -> Result<(), Error<SPI::Error>> {
+        let pin = &mut self.pin;
+        let result = self.flash.pin_reset(|active| {
+            if active { pin.set_low() } else { pin.set_high() }.map_err(|_| Error::PinFailed)
+        });
+
+        self.held = false;
+        result
+    }
+
+    /// IO3 of quad reads and QPI commands shares the pin.
+    fn pin_is_data_line(&self) -> bool {
+        #[cfg(feature = "qpi")]
+        if self.flash.is_qpi() {
+            return true;
+        }
+
+        self.flash.config().read_mode.data_lines() == Lines::Quad
+    }
+}
+
+impl<SPI: ErrorType, D, P> Deref for HoldReset<SPI, D, P> {
+    type Target = W25qxx<SPI, D>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.flash
+    }
+}
+
+impl<SPI: ErrorType, D, P> DerefMut for HoldReset<SPI, D, P> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.flash
+    }
+}
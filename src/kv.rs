@@ -0,0 +1,529 @@
+//! Key-value store for device settings.
+//!
+//! A [`KvStore`] appends every [`set`](KvStore::set) and
+//! [`remove`](KvStore::remove) as a record with a CRC to the sectors of its
+//! area; the latest record of a key wins. Nothing is overwritten in place,
+//! so a power loss at any point leaves either the old or the new value.
+//!
+//! Sectors are used in turn, each one tagged with a sequence number. One of
+//! them is always kept erased: when the current sector is full, the store
+//! moves on to it and reclaims the oldest sector by copying its live records
+//! over before erasing it. Until the copy is complete the new sector is
+//! marked as receiving, and [`KvStore::open`] discards an interrupted copy,
+//! whose originals are still in place.
+//!
+//! Lookups scan the area instead of keeping an index in RAM, which suits a
+//! few dozen settings read at startup.
+
+use crate::checksum::{Checksum, Crc32};
+use crate::w25qxx::{Error, W25qxx, W25QXX_PAGE_SIZE, W25QXX_SECTOR_SIZE};
+use embedded_hal::delay::DelayNs;
+use embedded_hal::spi::SpiDevice;
+
+/// Longest key, in bytes.
+pub const MAX_KEY_SIZE: usize = 64;
+
+/// Marks a sector of the store ("KVS1" in little endian).
+const KV_MAGIC: u32 = 0x3153_564B;
+
+/// Magic, sequence number, in-use and retired markers, reserved bytes.
+const SECTOR_HEADER_SIZE: usize = 16;
+
+/// Programmed once the sector takes records (after the copies of a reclaim).
+const IN_USE_OFFSET: usize = 8;
+
+/// Programmed once the live records of the sector were copied elsewhere,
+/// right before it is erased.
+const RETIRED_OFFSET: usize = 9;
+
+/// Key length, kind and value length.
+const RECORD_HEADER_SIZE: usize = 4;
+
+/// Record kinds.
+const KIND_REMOVED: u8 = 0x00;
+const KIND_VALUE: u8 = 0x01;
+
+/// Largest record a sector holds.
+const MAX_RECORD_SIZE: usize = W25QXX_SECTOR_SIZE - SECTOR_HEADER_SIZE;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum SectorState {
+    Erased,
+    InUse(u32),
+    /// Target of a reclaim that did not finish.
+    Receiving,
+    /// Reclaimed, but the erase did not finish.
+    Retired,
+    /// Neither erased nor a sector of the store.
+    Garbage,
+}
+
+/// A record whose header parsed, with the key read.
+struct Record {
+    address: u32,
+    kind: u8,
+    key_len: usize,
+    value_len: usize,
+    key: [u8; MAX_KEY_SIZE],
+    /// The CRC matches: the record was written completely.
+    valid: bool,
+}
+
+impl Record {
+    fn key(&self) -> &[u8] {
+        &self.key[..self.key_len]
+    }
+
+    fn value_address(&self) -> u32 {
+        self.address + (RECORD_HEADER_SIZE + self.key_len) as u32
+    }
+
+    fn size(&self) -> usize {
+        record_size(self.key_len, self.value_len)
+    }
+}
+
+/// What follows the records parsed so far in a sector.
+enum Parsed {
+    Record(Record),
+    /// Erased bytes: room for the next record.
+    End,
+    /// A header that makes no sense, left by a write cut short. Nothing can
+    /// be appended to the sector anymore.
+    Torn,
+}
+
+/// Position in the store, oldest sector first.
+#[derive(Clone, Copy)]
+struct Cursor {
+    /// Sectors passed, counting from the one after the current sector.
+    ring: usize,
+    /// Offset of the next record in that sector, 0 before its header.
+    offset: usize,
+}
+
+/// Key-value store in a sector-aligned area of at least two sectors.
+#[derive(Clone, Copy, Debug)]
+pub struct KvStore {
+    address: u32,
+    sectors: usize,
+    /// Sector records are appended to, and its sequence number.
+    active: usize,
+    sequence: u32,
+    /// Where the next record goes, `None` once the sector is closed.
+    offset: Option<usize>,
+}
+
+impl KvStore {
+    /// Opens the store in the `len` bytes at `address`, both sector aligned,
+    /// formatting it if it holds none and finishing what an interrupted
+    /// reclaim left behind.
+    pub fn open<SPI: SpiDevice, D: DelayNs>(flash: &mut W25qxx<SPI, D>, address: u32, len: usize) -> Result<KvStore, Error<SPI::Error>> {
+        if !address.is_multiple_of(W25QXX_SECTOR_SIZE as u32) || !len.is_multiple_of(W25QXX_SECTOR_SIZE) {
+            return Err(Error::NotAligned);
+        }
+        if len < 2 * W25QXX_SECTOR_SIZE {
+            return Err(Error::InvalidArgument);
+        }
+
+        let mut store = KvStore {
+            address,
+            sectors: len / W25QXX_SECTOR_SIZE,
+            active: 0,
+            sequence: 0,
+            offset: None,
+        };
+        let mut newest = None;
+
+        for sector in 0..store.sectors {
+            match store.sector_state(flash, sector)? {
+                SectorState::InUse(sequence) => {
+                    if newest.is_none_or(|(_, newest)| sequence > newest) {
+                        newest = Some((sector, sequence));
+                    }
+                }
+                SectorState::Erased => {}
+                SectorState::Receiving | SectorState::Retired | SectorState::Garbage => {
+                    flash.erase(store.sector_address(sector), W25QXX_SECTOR_SIZE)?;
+                }
+            }
+        }
+
+        match newest {
+            Some((sector, sequence)) => {
+                store.active = sector;
+                store.sequence = sequence;
+                store.offset = store.append_offset(flash)?;
+
+                /* Copies done but the victim not retired yet: it only holds duplicates */
+                let next = (sector + 1) % store.sectors;
+                if matches!(store.sector_state(flash, next)?, SectorState::InUse(_)) {
+                    flash.erase(store.sector_address(next), W25QXX_SECTOR_SIZE)?;
+                }
+            }
+            None => {
+                store.start_sector(flash, 0, 0)?;
+                store.mark(flash, 0, IN_USE_OFFSET)?;
+            }
+        }
+
+        Ok(store)
+    }
+
+    /// Reads the value of `key` into `buffer` and returns its length, or
+    /// `None` when the key is not set. A buffer shorter than the value fails
+    /// with [`Error::InvalidArgument`].
+    pub fn get<SPI: SpiDevice, D: DelayNs>(&self, flash: &mut W25qxx<SPI, D>, key: &[u8], buffer: &mut [u8]) -> Result<Option<usize>, Error<SPI::Error>> {
+        let Some(record) = self.latest(flash, key)? else {
+            return Ok(None);
+        };
+        if buffer.len() < record.value_len {
+            return Err(Error::InvalidArgument);
+        }
+
+        flash.read(record.value_address(), &mut buffer[..record.value_len])?;
+        Ok(Some(record.value_len))
+    }
+
+    /// Stores `value` under `key`. Nothing is written if the key holds that
+    /// value already. Fails with [`Error::NoSpace`] when the live records do
+    /// not leave room for it.
+    pub fn set<SPI: SpiDevice, D: DelayNs>(&mut self, flash: &mut W25qxx<SPI, D>, key: &[u8], value: &[u8]) -> Result<(), Error<SPI::Error>> {
+        check_key(key)?;
+
+        if let Some(record) = self.latest(flash, key)? {
+            if record.value_len == value.len() && self.value_equals(flash, &record, value)? {
+                return Ok(());
+            }
+        }
+
+        self.append(flash, KIND_VALUE, key, value)
+    }
+
+    /// Removes `key`. Returns whether it was set.
+    pub fn remove<SPI: SpiDevice, D: DelayNs>(&mut self, flash: &mut W25qxx<SPI, D>, key: &[u8]) -> Result<bool, Error<SPI::Error>> {
+        check_key(key)?;
+
+        if self.latest(flash, key)?.is_none() {
+            return Ok(false);
+        }
+
+        self.append(flash, KIND_REMOVED, key, &[])?;
+        Ok(true)
+    }
+
+    /// Calls `f` with every key that is set and its value, read into
+    /// `buffer`. Values longer than `buffer` fail with
+    /// [`Error::InvalidArgument`]. Keys come in the order they were last set.
+    pub fn iter<SPI: SpiDevice, D: DelayNs>(
+        &self,
+        flash: &mut W25qxx<SPI, D>,
+        buffer: &mut [u8],
+        mut f: impl FnMut(&[u8], &[u8]),
+    ) -> Result<(), Error<SPI::Error>> {
+        let mut cursor = Cursor { ring: 0, offset: 0 };
+
+        while let Some(record) = self.next_record(flash, &mut cursor)? {
+            if record.kind != KIND_VALUE || self.superseded(flash, &record, cursor)? {
+                continue;
+            }
+            if buffer.len() < record.value_len {
+                return Err(Error::InvalidArgument);
+            }
+
+            flash.read(record.value_address(), &mut buffer[..record.value_len])?;
+            f(record.key(), &buffer[..record.value_len]);
+        }
+
+        Ok(())
+    }
+
+    /// Removes every key by erasing the whole area.
+    pub fn clear<SPI: SpiDevice, D: DelayNs>(&mut self, flash: &mut W25qxx<SPI, D>) -> Result<(), Error<SPI::Error>> {
+        flash.erase(self.address, self.sectors * W25QXX_SECTOR_SIZE)?;
+
+        self.start_sector(flash, 0, 0)?;
+        self.mark(flash, 0, IN_USE_OFFSET)?;
+        self.active = 0;
+        self.sequence = 0;
+        self.offset = Some(SECTOR_HEADER_SIZE);
+        Ok(())
+    }
+
+    /// Latest complete record of `key`, `None` if it is missing or removed.
+    fn latest<SPI: SpiDevice, D: DelayNs>(&self, flash: &mut W25qxx<SPI, D>, key: &[u8]) -> Result<Option<Record>, Error<SPI::Error>> {
+        let mut cursor = Cursor { ring: 0, offset: 0 };
+        let mut latest = None;
+
+        while let Some(record) = self.next_record(flash, &mut cursor)? {
+            if record.key() == key {
+                latest = Some(record);
+            }
+        }
+
+        Ok(latest.filter(|record| record.kind == KIND_VALUE))
+    }
+
+    /// Whether a complete record of the same key follows `cursor`.
+    fn superseded<SPI: SpiDevice, D: DelayNs>(&self, flash: &mut W25qxx<SPI, D>, record: &Record, mut cursor: Cursor) -> Result<bool, Error<SPI::Error>> {
+        while let Some(later) = self.next_record(flash, &mut cursor)? {
+            if later.key() == record.key() {
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+
+    /// Next complete record at or after `cursor`, oldest sector first.
+    fn next_record<SPI: SpiDevice, D: DelayNs>(&self, flash: &mut W25qxx<SPI, D>, cursor: &mut Cursor) -> Result<Option<Record>, Error<SPI::Error>> {
+        while cursor.ring < self.sectors {
+            let sector = (self.active + 1 + cursor.ring) % self.sectors;
+
+            if cursor.offset == 0 {
+                if !matches!(self.sector_state(flash, sector)?, SectorState::InUse(_)) {
+                    cursor.ring += 1;
+                    continue;
+                }
+                cursor.offset = SECTOR_HEADER_SIZE;
+            }
+
+            match self.parse(flash, sector, cursor.offset)? {
+                Parsed::Record(record) => {
+                    cursor.offset += record.size();
+                    if record.valid {
+                        return Ok(Some(record));
+                    }
+                }
+                Parsed::End | Parsed::Torn => {
+                    cursor.ring += 1;
+                    cursor.offset = 0;
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Appends a record, moving on to the next sector as often as needed.
+    fn append<SPI: SpiDevice, D: DelayNs>(&mut self, flash: &mut W25qxx<SPI, D>, kind: u8, key: &[u8], value: &[u8]) -> Result<(), Error<SPI::Error>> {
+        let size = record_size(key.len(), value.len());
+        if size > MAX_RECORD_SIZE {
+            return Err(Error::InvalidArgument);
+        }
+
+        /* Each pass reclaims one sector; after a full round only live data is left */
+        let mut passes = 0;
+        let offset = loop {
+            match self.offset {
+                Some(offset) if offset + size <= W25QXX_SECTOR_SIZE => break offset,
+                _ if passes == self.sectors => return Err(Error::NoSpace),
+                _ => {}
+            }
+            self.advance(flash)?;
+            passes += 1;
+        };
+
+        let address = self.sector_address(self.active) + offset as u32;
+        let header = record_header(kind, key.len(), value.len());
+
+        let mut crc = Crc32::new();
+        crc.update(&header);
+        crc.update(key);
+        crc.update(value);
+
+        /* The sector stays usable after a power loss from here on: the header gives the record size */
+        self.offset = Some(offset + size);
+        let mut start: [u8; RECORD_HEADER_SIZE + MAX_KEY_SIZE] = [0; RECORD_HEADER_SIZE + MAX_KEY_SIZE];
+        start[..RECORD_HEADER_SIZE].copy_from_slice(&header);
+        start[RECORD_HEADER_SIZE..RECORD_HEADER_SIZE + key.len()].copy_from_slice(key);
+        flash.write(address, &start[..RECORD_HEADER_SIZE + key.len()])?;
+        if !value.is_empty() {
+            flash.write(address + (RECORD_HEADER_SIZE + key.len()) as u32, value)?;
+        }
+        flash.write(address + (size - 4) as u32, &crc.finalize().to_le_bytes())
+    }
+
+    /// Moves on to the erased sector after the current one and reclaims the
+    /// oldest sector into it.
+    fn advance<SPI: SpiDevice, D: DelayNs>(&mut self, flash: &mut W25qxx<SPI, D>) -> Result<(), Error<SPI::Error>> {
+        let target = (self.active + 1) % self.sectors;
+        let victim = (self.active + 2) % self.sectors;
+
+        if self.sector_state(flash, target)? != SectorState::Erased {
+            return Err(Error::NoSpace);
+        }
+        self.start_sector(flash, target, self.sequence.wrapping_add(1))?;
+
+        /* The target is not in use yet, so the scans below still see the old state */
+        let mut offset = SECTOR_HEADER_SIZE;
+        let reclaim = matches!(self.sector_state(flash, victim)?, SectorState::InUse(_));
+        if reclaim {
+            /* The victim is the oldest sector, the first one after the target in age order */
+            let mut source = SECTOR_HEADER_SIZE;
+            while let Parsed::Record(record) = self.parse(flash, victim, source)? {
+                source += record.size();
+                let later = Cursor { ring: 1, offset: source };
+                if record.valid && record.kind == KIND_VALUE && !self.superseded(flash, &record, later)? {
+                    copy(flash, record.address, self.sector_address(target) + offset as u32, record.size())?;
+                    offset += record.size();
+                }
+            }
+        }
+
+        self.mark(flash, target, IN_USE_OFFSET)?;
+        if reclaim {
+            self.mark(flash, victim, RETIRED_OFFSET)?;
+            flash.erase(self.sector_address(victim), W25QXX_SECTOR_SIZE)?;
+        }
+
+        self.active = target;
+        self.sequence = self.sequence.wrapping_add(1);
+        self.offset = Some(offset);
+        Ok(())
+    }
+
+    /// Where the next record of the current sector goes, `None` when a torn
+    /// record or stray bytes close it.
+    fn append_offset<SPI: SpiDevice, D: DelayNs>(&self, flash: &mut W25qxx<SPI, D>) -> Result<Option<usize>, Error<SPI::Error>> {
+        let mut offset = SECTOR_HEADER_SIZE;
+
+        loop {
+            match self.parse(flash, self.active, offset)? {
+                Parsed::Record(record) => offset += record.size(),
+                Parsed::Torn => return Ok(None),
+                Parsed::End => break,
+            }
+        }
+
+        let address = self.sector_address(self.active) + offset as u32;
+        Ok(flash.is_blank(address, W25QXX_SECTOR_SIZE - offset)?.then_some(offset))
+    }
+
+    /// Parses the record at `offset` of `sector`.
+    fn parse<SPI: SpiDevice, D: DelayNs>(&self, flash: &mut W25qxx<SPI, D>, sector: usize, offset: usize) -> Result<Parsed, Error<SPI::Error>> {
+        if offset + RECORD_HEADER_SIZE > W25QXX_SECTOR_SIZE {
+            return Ok(Parsed::End);
+        }
+
+        let address = self.sector_address(sector) + offset as u32;
+        let mut header: [u8; RECORD_HEADER_SIZE] = [0; RECORD_HEADER_SIZE];
+        flash.read(address, &mut header)?;
+        if header == [0xFF; RECORD_HEADER_SIZE] {
+            return Ok(Parsed::End);
+        }
+
+        let key_len = header[0] as usize;
+        let kind = header[1];
+        let value_len = u16::from_le_bytes([header[2], header[3]]) as usize;
+        if key_len == 0
+            || key_len > MAX_KEY_SIZE
+            || (kind != KIND_VALUE && kind != KIND_REMOVED)
+            || offset + record_size(key_len, value_len) > W25QXX_SECTOR_SIZE
+        {
+            return Ok(Parsed::Torn);
+        }
+
+        let mut record = Record {
+            address,
+            kind,
+            key_len,
+            value_len,
+            key: [0; MAX_KEY_SIZE],
+            valid: false,
+        };
+        flash.read(address + RECORD_HEADER_SIZE as u32, &mut record.key[..key_len])?;
+
+        let crc_address = address + (record.size() - 4) as u32;
+        let crc = flash.digest(address, record.size() - 4, Crc32::new())?;
+        let mut stored: [u8; 4] = [0; 4];
+        flash.read(crc_address, &mut stored)?;
+        record.valid = crc == u32::from_le_bytes(stored);
+
+        Ok(Parsed::Record(record))
+    }
+
+    fn value_equals<SPI: SpiDevice, D: DelayNs>(&self, flash: &mut W25qxx<SPI, D>, record: &Record, value: &[u8]) -> Result<bool, Error<SPI::Error>> {
+        let mut chunk: [u8; W25QXX_PAGE_SIZE] = [0; W25QXX_PAGE_SIZE];
+
+        for (index, expected) in value.chunks(W25QXX_PAGE_SIZE).enumerate() {
+            let stored = &mut chunk[..expected.len()];
+            flash.read(record.value_address() + (index * W25QXX_PAGE_SIZE) as u32, stored)?;
+            if stored != expected {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+
+    fn sector_state<SPI: SpiDevice, D: DelayNs>(&self, flash: &mut W25qxx<SPI, D>, sector: usize) -> Result<SectorState, Error<SPI::Error>> {
+        let address = self.sector_address(sector);
+        let mut header: [u8; SECTOR_HEADER_SIZE] = [0; SECTOR_HEADER_SIZE];
+        flash.read(address, &mut header)?;
+
+        if header == [0xFF; SECTOR_HEADER_SIZE] {
+            let erased = flash.is_blank(address, W25QXX_SECTOR_SIZE)?;
+            return Ok(if erased { SectorState::Erased } else { SectorState::Garbage });
+        }
+        if header[..4] != KV_MAGIC.to_le_bytes() {
+            return Ok(SectorState::Garbage);
+        }
+
+        Ok(match (header[IN_USE_OFFSET], header[RETIRED_OFFSET]) {
+            (_, 0x00) => SectorState::Retired,
+            (0xFF, _) => SectorState::Receiving,
+            _ => SectorState::InUse(u32::from_le_bytes([header[4], header[5], header[6], header[7]])),
+        })
+    }
+
+    /// Writes the header of an erased sector, not in use yet.
+    fn start_sector<SPI: SpiDevice, D: DelayNs>(&self, flash: &mut W25qxx<SPI, D>, sector: usize, sequence: u32) -> Result<(), Error<SPI::Error>> {
+        let mut header: [u8; 8] = [0; 8];
+        header[..4].copy_from_slice(&KV_MAGIC.to_le_bytes());
+        header[4..].copy_from_slice(&sequence.to_le_bytes());
+
+        flash.write(self.sector_address(sector), &header)
+    }
+
+    /// Programs the marker byte at `offset` of the sector header.
+    fn mark<SPI: SpiDevice, D: DelayNs>(&self, flash: &mut W25qxx<SPI, D>, sector: usize, offset: usize) -> Result<(), Error<SPI::Error>> {
+        flash.write(self.sector_address(sector) + offset as u32, &[0x00])
+    }
+
+    fn sector_address(&self, sector: usize) -> u32 {
+        self.address + (sector * W25QXX_SECTOR_SIZE) as u32
+    }
+}
+
+fn check_key<E>(key: &[u8]) -> Result<(), Error<E>> {
+    if key.is_empty() || key.len() > MAX_KEY_SIZE {
+        return Err(Error::InvalidArgument);
+    }
+
+    Ok(())
+}
+
+fn record_header(kind: u8, key_len: usize, value_len: usize) -> [u8; RECORD_HEADER_SIZE] {
+    let value_len = (value_len as u16).to_le_bytes();
+    [key_len as u8, kind, value_len[0], value_len[1]]
+}
+
+/// Header, key, value and CRC.
+fn record_size(key_len: usize, value_len: usize) -> usize {
+    RECORD_HEADER_SIZE + key_len + value_len + 4
+}
+
+fn copy<SPI: SpiDevice, D: DelayNs>(flash: &mut W25qxx<SPI, D>, from: u32, to: u32, len: usize) -> Result<(), Error<SPI::Error>> {
+    let mut chunk: [u8; W25QXX_PAGE_SIZE] = [0; W25QXX_PAGE_SIZE];
+    let mut offset: usize = 0;
+
+    while offset < len {
+        let chunk_size = core::cmp::min(len - offset, chunk.len());
+        flash.read(from + offset as u32, &mut chunk[..chunk_size])?;
+        flash.write(to + offset as u32, &chunk[..chunk_size])?;
+        offset += chunk_size;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,112 @@
+//! Driver for the Winbond W25QXX family of SPI NOR flash memories.
+//!
+//! The driver is generic over an `embedded-hal` 1.0 `SpiDevice`, so it can be
+//! used from any HAL. Boards that expose a bare `SpiBus` and drive chip select
+//! from a GPIO can wrap both in a [`SpiBusDevice`]. See `examples/linux.rs` for
+//! a demo running on Linux through `spidev` and sysfs GPIO.
+//!
+//! [`W25qxx::builder`] selects the expected JEDEC ID, read mode, address width,
+//! busy poll interval and write verification before the driver is created.
+//! Dual and quad read modes run through a [`MultiIoTransfer`] on the host's
+//! controller, since `embedded-hal` only describes single-line SPI.
+//!
+//! [`W25qxx`] implements the `embedded-storage` `NorFlash` traits, so it plugs
+//! into bootloaders and filesystems built on them.
+//!
+//! [`W25qxxAsync`] offers the same API on top of `embedded-hal-async` for use
+//! from async executors.
+//!
+//! The `std` feature is enabled by default. Disable default features to build
+//! the driver for bare-metal `#![no_std]` targets.
+//!
+//! The rarely used command groups (`security-registers`, `qpi`, `suspend`,
+//! `individual-locks`) are default features as well, so firmware built
+//! without default features only links the groups it turns back on.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+pub mod asynch;
+pub mod block;
+pub mod builder;
+mod bus;
+pub mod checksum;
+pub mod chip;
+pub mod circular;
+pub mod config;
+pub mod counter;
+pub mod discard;
+pub mod eeprom;
+#[cfg(feature = "encryption")]
+pub mod encrypted;
+pub mod hold_reset;
+pub mod kv;
+pub mod multi_io;
+pub mod partition;
+pub mod protection;
+#[cfg(feature = "security-registers")]
+pub mod provisioning;
+pub mod read_only;
+#[cfg(feature = "security-registers")]
+pub mod rollback;
+pub mod scramble;
+pub mod scratch;
+#[cfg(feature = "settings")]
+pub mod settings;
+pub mod sfdp;
+#[cfg(any(feature = "ed25519", feature = "ecdsa-p256"))]
+pub mod signature;
+#[cfg(feature = "std")]
+pub mod sim;
+pub mod status;
+mod storage;
+pub mod transaction;
+mod w25qxx;
+pub mod wear;
+pub mod write_protect;
+
+pub use asynch::W25qxxAsync;
+pub use block::{BlockDevice, BlockError, FlashBlockDevice};
+pub use builder::{
+    AddressWidth, BurstWrap, BusyPoll, BusyTimeouts, Config, DummyCycles, IdCheck, ReadMode,
+    W25qxxBuilder,
+};
+pub use bus::{DeviceError, SpiBusDevice};
+pub use checksum::{Checksum, Crc32};
+pub use chip::{ChipParams, DeviceInfo, Family, Geometry, Opcodes, QuadEnable, Quirks};
+pub use circular::{CircularLog, MAX_LOG_RECORD_SIZE};
+pub use config::{ConfigBlob, MAX_CONFIG_BLOB_SIZE};
+pub use counter::{MonotonicCounter, COUNTER_SECTOR_INCREMENTS};
+pub use discard::DeferredErase;
+pub use eeprom::Eeprom;
+#[cfg(feature = "encryption")]
+pub use encrypted::EncryptedRegion;
+pub use hold_reset::HoldReset;
+pub use kv::{KvStore, MAX_KEY_SIZE};
+pub use multi_io::{IoOperation, Lines, MultiIoTransfer};
+pub use partition::Partition;
+pub use protection::{BlockProtection, ProtectedRange, ProtectionSource};
+#[cfg(feature = "security-registers")]
+pub use provisioning::Provisioning;
+pub use read_only::ReadOnly;
+#[cfg(feature = "security-registers")]
+pub use rollback::ROLLBACK_COUNTER_MAX;
+pub use scramble::{Scrambled, SectorScrambler};
+pub use scratch::{ScratchPool, ScratchSector};
+#[cfg(feature = "settings")]
+pub use settings::Settings;
+pub use sfdp::Sfdp;
+pub use status::{
+    DriveStrength, Status, StatusProtection, StatusRegister1, StatusRegister2, StatusRegister3,
+};
+pub use transaction::{Journal, Transaction};
+pub use wear::{WearMap, WearTracked};
+pub use write_protect::WriteProtected;
+
+pub use w25qxx::{
+    BusyOperation, ChipCheck, ClockControl, Error, InitError, IntegrityCheck, IntegrityReport,
+    PowerControl, Progress, ProgressHook, ReadConsistency, ReadOnlyUnknown, Ready, Uninit, W25qxx,
+    MAX_PROTECTED_RANGES, W25M_DEVID_VALUE_512, W25QXX_BLOCK32K_SIZE, W25QXX_BLOCK64K_SIZE,
+    W25QXX_CAPACITY_128, W25QXX_CAPACITY_256, W25QXX_DEVID_VALUE_128, W25QXX_MANID_VALUE,
+    W25QXX_MAX_DIES, W25QXX_MEMORY_TYPE, W25QXX_PAGE_SIZE, W25QXX_SECTOR_SIZE,
+    W25QXX_SECURITY_REGISTER_SIZE,
+};
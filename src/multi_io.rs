@@ -0,0 +1,65 @@
+//! Transfers over two or four data lines.
+//!
+//! `embedded-hal` only describes single-line SPI, so the Dual and Quad read
+//! commands cannot go through the [`SpiDevice`](embedded_hal::spi::SpiDevice)
+//! itself. A [`MultiIoTransfer`] registered with
+//! [`W25qxxBuilder::multi_io`](crate::W25qxxBuilder::multi_io) runs them on
+//! the host's (Q)SPI controller instead: it receives the phases of one chip
+//! select cycle, each tagged with the number of lines it uses.
+
+use embedded_hal::spi::ErrorType;
+#[cfg(feature = "qpi")]
+use embedded_hal::spi::Operation;
+
+/// Data lines a phase is clocked on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Lines {
+    /// MOSI out, MISO in.
+    Single,
+    /// IO0 and IO1.
+    Dual,
+    /// IO0 to IO3.
+    Quad,
+}
+
+/// One phase of a multi-line transfer.
+#[derive(Debug, PartialEq, Eq)]
+pub enum IoOperation<'a> {
+    /// Bytes sent on the given lines.
+    Write(Lines, &'a [u8]),
+    /// Bytes received on the given lines.
+    Read(Lines, &'a mut [u8]),
+    /// Clock cycles with the data lines released (dummy cycles).
+    Dummy(u8),
+}
+
+/// Runs the phases of one chip select cycle on the host controller.
+pub type MultiIoTransfer<SPI> = fn(&mut SPI, &mut [IoOperation<'_>]) -> Result<(), <SPI as ErrorType>::Error>;
+
+/// Most phases of one single-line command sent in QPI mode.
+#[cfg(feature = "qpi")]
+const QPI_MAX_PHASES: usize = 4;
+
+/// Runs a single-line command in QPI mode: every phase on four lines.
+#[cfg(feature = "qpi")]
+pub(crate) fn quad_transaction<SPI: ErrorType>(
+    transfer: MultiIoTransfer<SPI>,
+    spi: &mut SPI,
+    operations: &mut [Operation<'_, u8>],
+) -> Result<(), SPI::Error> {
+    let mut phases: [IoOperation<'_>; QPI_MAX_PHASES] = core::array::from_fn(|_| IoOperation::Dummy(0));
+    let mut count = 0;
+
+    for operation in operations.iter_mut().take(QPI_MAX_PHASES) {
+        phases[count] = match operation {
+            Operation::Write(bytes) => IoOperation::Write(Lines::Quad, bytes),
+            Operation::Read(buffer) => IoOperation::Read(Lines::Quad, buffer),
+            /* The driver only issues writes and reads */
+            _ => continue,
+        };
+        count += 1;
+    }
+
+    transfer(spi, &mut phases[..count])
+}
@@ -0,0 +1,181 @@
+//! Partitions of one chip shared by several subsystems.
+//!
+//! A [`Partition`] is a sector-aligned window onto the chip with addresses
+//! starting at 0. It only reaches its own bytes, so a log, a filesystem and
+//! a firmware update slot each handed their partition cannot touch the
+//! others' data. The partitions share the driver through a `RefCell`, the
+//! way `embedded-hal-bus` shares a bus: single-threaded and without locking.
+//! Using two partitions at once from an interrupt and the main loop is not
+//! possible; the second borrow panics.
+
+use crate::w25qxx::{Error, W25qxx, W25QXX_SECTOR_SIZE};
+use core::cell::RefCell;
+use core::ops::Range;
+use embedded_hal::delay::DelayNs;
+use embedded_hal::spi::{ErrorType, SpiDevice};
+use embedded_storage::nor_flash::{self, MultiwriteNorFlash, NorFlash, ReadNorFlash};
+
+/// `len` bytes of the chip from `offset`, addressed from 0.
+pub struct Partition<'a, SPI: ErrorType, D> {
+    flash: &'a RefCell<W25qxx<SPI, D>>,
+    offset: u32,
+    len: usize,
+}
+
+impl<'a, SPI, D> Partition<'a, SPI, D>
+where
+    SPI: SpiDevice,
+    D: DelayNs,
+{
+    /// Partition of `len` bytes at `offset`, both sector aligned.
+    pub fn new(flash: &'a RefCell<W25qxx<SPI, D>>, offset: u32, len: usize) -> Result<Self, Error<SPI::Error>> {
+        Self::check(flash, offset, len)?;
+
+        Ok(Partition { flash, offset, len })
+    }
+
+    /// One partition per range of `layout`, checking that no two of them
+    /// overlap. Fails with [`Error::InvalidArgument`] if some do or if a
+    /// range is reversed.
+    pub fn split<const N: usize>(
+        flash: &'a RefCell<W25qxx<SPI, D>>,
+        layout: [Range<u32>; N],
+    ) -> Result<[Self; N], Error<SPI::Error>> {
+        for (index, range) in layout.iter().enumerate() {
+            if range.start > range.end {
+                return Err(Error::InvalidArgument);
+            }
+            if layout[..index].iter().any(|other| range.start < other.end && other.start < range.end) {
+                return Err(Error::InvalidArgument);
+            }
+            Self::check(flash, range.start, (range.end - range.start) as usize)?;
+        }
+
+        Ok(core::array::from_fn(|index| Partition {
+            flash,
+            offset: layout[index].start,
+            len: (layout[index].end - layout[index].start) as usize,
+        }))
+    }
+
+    /// Fails unless `len` bytes at `offset` are sector aligned and lie on
+    /// the chip.
+    fn check(flash: &RefCell<W25qxx<SPI, D>>, offset: u32, len: usize) -> Result<(), Error<SPI::Error>> {
+        if !offset.is_multiple_of(W25QXX_SECTOR_SIZE as u32) || !len.is_multiple_of(W25QXX_SECTOR_SIZE) {
+            return Err(Error::NotAligned);
+        }
+        if (offset as usize).checked_add(len).is_none_or(|end| end > flash.borrow().params().capacity) {
+            return Err(Error::InvalidAddress);
+        }
+
+        Ok(())
+    }
+
+    /// Chip address of the partition's first byte.
+    pub fn offset(&self) -> u32 {
+        self.offset
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Chip addresses covered.
+    pub fn range(&self) -> Range<u32> {
+        self.offset..self.offset + self.len as u32
+    }
+
+    pub fn read(&mut self, address: u32, buffer: &mut [u8]) -> Result<(), Error<SPI::Error>> {
+        let address = self.chip_address(address, buffer.len())?;
+        self.flash.borrow_mut().read(address, buffer)
+    }
+
+    /// Programs `buffer` at `address`, which has to be erased.
+    pub fn write(&mut self, address: u32, buffer: &[u8]) -> Result<(), Error<SPI::Error>> {
+        let address = self.chip_address(address, buffer.len())?;
+        self.flash.borrow_mut().write(address, buffer)
+    }
+
+    /// Writes `buffer` whatever the partition holds there; see
+    /// [`W25qxx::overwrite`]. The sectors it rewrites lie inside the
+    /// partition, as the partition is sector aligned.
+    pub fn overwrite(&mut self, address: u32, buffer: &[u8]) -> Result<(), Error<SPI::Error>> {
+        let address = self.chip_address(address, buffer.len())?;
+        self.flash.borrow_mut().overwrite(address, buffer)
+    }
+
+    /// Erases `len` bytes at `address`, both sector aligned.
+    pub fn erase(&mut self, address: u32, len: usize) -> Result<(), Error<SPI::Error>> {
+        let address = self.chip_address(address, len)?;
+        self.flash.borrow_mut().erase(address, len)
+    }
+
+    /// Erases the whole partition.
+    pub fn erase_all(&mut self) -> Result<(), Error<SPI::Error>> {
+        self.erase(0, self.len)
+    }
+
+    /// Chip address of partition address `address`, with
+    /// [`Error::InvalidAddress`] unless all `len` bytes lie inside.
+    fn chip_address(&self, address: u32, len: usize) -> Result<u32, Error<SPI::Error>> {
+        match (address as usize).checked_add(len) {
+            Some(end) if end <= self.len => Ok(self.offset + address),
+            _ => Err(Error::InvalidAddress),
+        }
+    }
+}
+
+impl<SPI: SpiDevice, D> nor_flash::ErrorType for Partition<'_, SPI, D> {
+    type Error = Error<SPI::Error>;
+}
+
+impl<SPI, D> ReadNorFlash for Partition<'_, SPI, D>
+where
+    SPI: SpiDevice,
+    D: DelayNs,
+{
+    const READ_SIZE: usize = 1;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        let address = self.chip_address(offset, bytes.len())?;
+        ReadNorFlash::read(&mut *self.flash.borrow_mut(), address, bytes)
+    }
+
+    fn capacity(&self) -> usize {
+        self.len
+    }
+}
+
+impl<SPI, D> NorFlash for Partition<'_, SPI, D>
+where
+    SPI: SpiDevice,
+    D: DelayNs,
+{
+    const WRITE_SIZE: usize = 1;
+    const ERASE_SIZE: usize = W25QXX_SECTOR_SIZE;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        if from > to {
+            return Err(Error::InvalidAddress);
+        }
+
+        let address = self.chip_address(from, (to - from) as usize)?;
+        NorFlash::erase(&mut *self.flash.borrow_mut(), address, address + (to - from))
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        let address = self.chip_address(offset, bytes.len())?;
+        NorFlash::write(&mut *self.flash.borrow_mut(), address, bytes)
+    }
+}
+
+impl<SPI, D> MultiwriteNorFlash for Partition<'_, SPI, D>
+where
+    SPI: SpiDevice,
+    D: DelayNs,
+{
+}
@@ -0,0 +1,146 @@
+//! Block protection through the BP, TB, SEC and CMP status bits.
+//!
+//! Winbond parts protect one contiguous range at the top or the bottom of the
+//! array, or everything but such a range, selected by a handful of status
+//! register bits whose meaning depends on the density. [`BlockProtection`]
+//! holds those bits and knows the range they cover;
+//! [`W25qxx::protect_range`](crate::W25qxx::protect_range) finds the bits for
+//! a range. [`W25qxx::protection_map`](crate::W25qxx::protection_map) lists
+//! what is protected right now, by these bits or by the individual block
+//! locks.
+
+use crate::w25qxx::{W25QXX_BLOCK64K_SIZE, W25QXX_CAPACITY_128, W25QXX_SECTOR_SIZE};
+use core::ops::Range;
+
+/// BP bits of status register 1, from bit 2 up.
+const BP_SHIFT: u8 = 2;
+/// Status register 1 bits owned by the protection scheme (BP, TB, SEC).
+const PROTECTION_MASK: u8 = 0x7C;
+/// TB and SEC of parts up to 16 MiB.
+const TB: u8 = 0x20;
+const SEC: u8 = 0x40;
+/// TB of larger parts, above the fourth BP bit. They have no SEC bit.
+const TB_LARGE: u8 = 0x40;
+/// CMP, status register 2.
+const CMP: u8 = 0x40;
+
+/// Block protect bits of one die.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct BlockProtection {
+    /// BP2..BP0, or BP3..BP0 on parts larger than 16 MiB.
+    pub bp: u8,
+    /// Protect from the bottom instead of the top.
+    pub tb: bool,
+    /// Count in 4 KiB sectors instead of fractions of the array. Parts
+    /// larger than 16 MiB do not have it.
+    pub sec: bool,
+    /// Protect everything except the range the other bits select.
+    pub cmp: bool,
+}
+
+/// What write-protects a [`ProtectedRange`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ProtectionSource {
+    /// BP, TB, SEC and CMP of the die.
+    BlockProtect,
+    /// Individual block locks, selected by WPS in status register 3.
+    IndividualLock,
+}
+
+/// Write-protected addresses reported by
+/// [`W25qxx::protection_map`](crate::W25qxx::protection_map).
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ProtectedRange {
+    pub range: Range<u32>,
+    pub source: ProtectionSource,
+}
+
+impl BlockProtection {
+    /// Nothing protected.
+    pub const NONE: BlockProtection = BlockProtection { bp: 0, tb: false, sec: false, cmp: false };
+
+    /// Range of a die of `die_size` bytes these bits protect, empty when
+    /// nothing is.
+    pub fn range(&self, die_size: usize) -> Range<u32> {
+        let size = self.size(die_size);
+        let end = die_size as u32;
+
+        match (self.cmp, self.tb) {
+            (false, _) if size == 0 => 0..0,
+            (false, true) => 0..size as u32,
+            (false, false) => (die_size - size) as u32..end,
+            /* The complement of a bottom range is the top of the array */
+            (true, true) if size < die_size => size as u32..end,
+            (true, false) if size < die_size => 0..(die_size - size) as u32,
+            (true, _) => 0..0,
+        }
+    }
+
+    /// Bytes selected by BP, TB and SEC, before CMP.
+    fn size(&self, die_size: usize) -> usize {
+        match (self.bp, die_size > W25QXX_CAPACITY_128, self.sec) {
+            (0, _, _) => 0,
+            /* 64 KiB doubling with every step, up to the whole die */
+            (bp, true, _) => W25QXX_BLOCK64K_SIZE.checked_shl(bp as u32 - 1).map_or(die_size, |size| size.min(die_size)),
+            (bp @ 1..=3, false, true) => W25QXX_SECTOR_SIZE << (bp - 1),
+            (4..=6, false, true) => 8 * W25QXX_SECTOR_SIZE,
+            (bp @ 1..=6, false, false) => die_size >> (7 - bp),
+            _ => die_size,
+        }
+    }
+
+    /// Bits matching `range` of a die of `die_size` bytes exactly, preferring
+    /// settings without CMP.
+    pub fn for_range(die_size: usize, range: Range<u32>) -> Option<BlockProtection> {
+        if range.is_empty() {
+            return Some(BlockProtection::NONE);
+        }
+
+        let large = die_size > W25QXX_CAPACITY_128;
+        let bp_values: u8 = if large { 16 } else { 8 };
+
+        [false, true].into_iter().find_map(|cmp| {
+            (0..bp_values).find_map(|bp| {
+                [false, true].into_iter().find_map(|tb| {
+                    [false, !large].into_iter().find_map(|sec| {
+                        let protection = BlockProtection { bp, tb, sec, cmp };
+                        (protection.range(die_size) == range).then_some(protection)
+                    })
+                })
+            })
+        })
+    }
+
+    /// Decodes the bits from status registers 1 and 2.
+    pub(crate) fn from_status(die_size: usize, status1: u8, status2: u8) -> BlockProtection {
+        let large = die_size > W25QXX_CAPACITY_128;
+        let bp_mask = if large { 0x0F } else { 0x07 };
+
+        BlockProtection {
+            bp: (status1 >> BP_SHIFT) & bp_mask,
+            tb: status1 & if large { TB_LARGE } else { TB } != 0,
+            sec: !large && status1 & SEC != 0,
+            cmp: status2 & CMP != 0,
+        }
+    }
+
+    /// Status registers 1 and 2 with these bits in place of the current
+    /// ones.
+    pub(crate) fn to_status(self, die_size: usize, status1: u8, status2: u8) -> (u8, u8) {
+        let large = die_size > W25QXX_CAPACITY_128;
+
+        let mut protection = self.bp << BP_SHIFT;
+        if self.tb {
+            protection |= if large { TB_LARGE } else { TB };
+        }
+        if self.sec && !large {
+            protection |= SEC;
+        }
+
+        let status2 = if self.cmp { status2 | CMP } else { status2 & !CMP };
+        ((status1 & !PROTECTION_MASK) | (protection & PROTECTION_MASK), status2)
+    }
+}
@@ -0,0 +1,123 @@
+//! Factory provisioning record in a security register.
+//!
+//! [`Provisioning`] holds what a device learns once at the end of the line:
+//! its serial number, the hardware revision and the hashes of the keys it
+//! trusts. [`W25qxx::provision`] stores it with a CRC in one of the security
+//! registers and can lock the register afterwards, so the record survives
+//! every erase of the array and can no longer be changed.
+//!
+//! Enabled by the `security-registers` feature.
+
+use crate::checksum::Crc32;
+use crate::w25qxx::{Error, W25qxx, W25QXX_SECURITY_REGISTER_SIZE};
+use embedded_hal::delay::DelayNs;
+use embedded_hal::spi::SpiDevice;
+
+/// Marks a provisioning record ("PROV" in little endian).
+const PROVISIONING_MAGIC: u32 = 0x564F_5250;
+
+/// Layout version of the record.
+const PROVISIONING_VERSION: u8 = 1;
+
+/// Number of key hashes a record holds.
+pub const PROVISIONING_KEY_SLOTS: usize = 4;
+
+/// Size of one key hash, a SHA-256 digest.
+pub const KEY_HASH_SIZE: usize = 32;
+
+/// Magic, version, key mask, hardware revision, serial number, key hashes.
+const RECORD_SIZE: usize = 4 + 1 + 1 + 2 + 16 + PROVISIONING_KEY_SLOTS * KEY_HASH_SIZE;
+
+/// Identity of one device, written once during manufacturing.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Provisioning {
+    /// Serial number, for example ASCII padded with zeros.
+    pub serial_number: [u8; 16],
+    pub hardware_revision: u16,
+    /// Hashes of the keys the device trusts, such as firmware signing keys.
+    pub key_hashes: [Option<[u8; KEY_HASH_SIZE]>; PROVISIONING_KEY_SLOTS],
+}
+
+impl Provisioning {
+    /// Record bytes followed by their CRC32.
+    fn encode(&self) -> [u8; RECORD_SIZE + 4] {
+        let mut record: [u8; RECORD_SIZE + 4] = [0; RECORD_SIZE + 4];
+
+        record[..4].copy_from_slice(&PROVISIONING_MAGIC.to_le_bytes());
+        record[4] = PROVISIONING_VERSION;
+        record[6..8].copy_from_slice(&self.hardware_revision.to_le_bytes());
+        record[8..24].copy_from_slice(&self.serial_number);
+        for (slot, hash) in self.key_hashes.iter().enumerate() {
+            if let Some(hash) = hash {
+                record[5] |= 1 << slot;
+                record[24 + slot * KEY_HASH_SIZE..24 + (slot + 1) * KEY_HASH_SIZE].copy_from_slice(hash);
+            }
+        }
+
+        let crc = Crc32::checksum(&record[..RECORD_SIZE]);
+        record[RECORD_SIZE..].copy_from_slice(&crc.to_le_bytes());
+        record
+    }
+
+    /// Record of `bytes`, `None` when the magic, the version or the CRC do not
+    /// match.
+    fn decode(bytes: &[u8; RECORD_SIZE + 4]) -> Option<Provisioning> {
+        let crc = Crc32::checksum(&bytes[..RECORD_SIZE]);
+        if bytes[..4] != PROVISIONING_MAGIC.to_le_bytes() || bytes[4] != PROVISIONING_VERSION || bytes[RECORD_SIZE..] != crc.to_le_bytes() {
+            return None;
+        }
+
+        let mut provisioning = Provisioning {
+            hardware_revision: u16::from_le_bytes([bytes[6], bytes[7]]),
+            ..Provisioning::default()
+        };
+        provisioning.serial_number.copy_from_slice(&bytes[8..24]);
+        for (slot, hash) in provisioning.key_hashes.iter_mut().enumerate() {
+            if bytes[5] & (1 << slot) != 0 {
+                let mut value: [u8; KEY_HASH_SIZE] = [0; KEY_HASH_SIZE];
+                value.copy_from_slice(&bytes[24 + slot * KEY_HASH_SIZE..24 + (slot + 1) * KEY_HASH_SIZE]);
+                *hash = Some(value);
+            }
+        }
+
+        Some(provisioning)
+    }
+}
+
+impl<SPI, D> W25qxx<SPI, D>
+where
+    SPI: SpiDevice,
+    D: DelayNs,
+{
+    /// Erases security register `register` (1 to 3), writes `provisioning`
+    /// with its CRC, and reads it back, failing with
+    /// [`Error::OtpVerifyFailed`] on a mismatch. With `lock`, the register is
+    /// locked afterwards: the record can then neither be changed nor erased,
+    /// and a second call fails with [`Error::Locked`].
+    pub fn provision(&mut self, register: u8, provisioning: &Provisioning, lock: bool) -> Result<(), Error<SPI::Error>> {
+        const { assert!(RECORD_SIZE + 4 <= W25QXX_SECURITY_REGISTER_SIZE) };
+
+        let record = provisioning.encode();
+        self.otp_erase(register)?;
+        self.otp_program(register, 0, &record)?;
+
+        if self.read_provisioning(register)?.as_ref() != Some(provisioning) {
+            return Err(Error::OtpVerifyFailed { register });
+        }
+        if lock {
+            self.otp_lock(register)?;
+        }
+
+        Ok(())
+    }
+
+    /// Provisioning record stored in security register `register`, `None`
+    /// when it holds none or a corrupted one.
+    pub fn read_provisioning(&mut self, register: u8) -> Result<Option<Provisioning>, Error<SPI::Error>> {
+        let mut record: [u8; RECORD_SIZE + 4] = [0; RECORD_SIZE + 4];
+        self.otp_read(register, 0, &mut record)?;
+
+        Ok(Provisioning::decode(&record))
+    }
+}
@@ -0,0 +1,177 @@
+//! Read-only access to the chip.
+//!
+//! [`ReadOnly`] owns the driver and only passes on the methods that read:
+//! data, checksums, status and protection state, identification. Program,
+//! erase and status register writes are not part of its API, so code handed
+//! a `&mut ReadOnly` (a diagnostic shell, a debug protocol handler) cannot
+//! change the contents or the configuration of the chip. Through
+//! `embedded-storage` it is a [`ReadNorFlash`], not a `NorFlash`.
+
+use crate::checksum::Checksum;
+use crate::protection::{BlockProtection, ProtectedRange};
+use crate::status::{Status, StatusProtection, StatusRegister1, StatusRegister2, StatusRegister3};
+use crate::w25qxx::{ChipCheck, Error, ReadConsistency, W25qxx};
+use core::ops::Range;
+use embedded_hal::delay::DelayNs;
+use embedded_hal::spi::{ErrorType, SpiDevice};
+use embedded_storage::nor_flash::{self, ReadNorFlash};
+
+/// Driver wrapper without write or erase methods.
+///
+/// Unlike the other wrappers it has no `DerefMut`; [`get_ref`](Self::get_ref)
+/// gives the `&self` methods of the driver, none of which touch the chip.
+pub struct ReadOnly<SPI: ErrorType, D> {
+    flash: W25qxx<SPI, D>,
+}
+
+impl<SPI: ErrorType, D> ReadOnly<SPI, D> {
+    pub fn new(flash: W25qxx<SPI, D>) -> Self {
+        ReadOnly { flash }
+    }
+
+    /// The wrapped driver, for its parameters and cached state.
+    pub fn get_ref(&self) -> &W25qxx<SPI, D> {
+        &self.flash
+    }
+
+    /// Gives back the wrapped driver, with full access. Code that must stay
+    /// read-only gets a `&mut ReadOnly`, which cannot call this.
+    pub fn into_inner(self) -> W25qxx<SPI, D> {
+        self.flash
+    }
+}
+
+impl<SPI, D> ReadOnly<SPI, D>
+where
+    SPI: SpiDevice,
+    D: DelayNs,
+{
+    pub fn read(&mut self, address: u32, buffer: &mut [u8]) -> Result<(), Error<SPI::Error>> {
+        self.flash.read(address, buffer)
+    }
+
+    /// See [`W25qxx::read_stable`].
+    pub fn read_stable(&mut self, address: u32, buffer: &mut [u8], attempts: usize) -> Result<ReadConsistency, Error<SPI::Error>> {
+        self.flash.read_stable(address, buffer, attempts)
+    }
+
+    pub fn digest<C: Checksum>(&mut self, address: u32, len: usize, hasher: C) -> Result<C::Output, Error<SPI::Error>> {
+        self.flash.digest(address, len, hasher)
+    }
+
+    pub fn verify<C: Checksum>(&mut self, address: u32, len: usize, hasher: C, expected: &C::Output) -> Result<bool, Error<SPI::Error>> {
+        self.flash.verify(address, len, hasher, expected)
+    }
+
+    pub fn find(&mut self, range: Range<u32>, needle: &[u8]) -> Result<Option<u32>, Error<SPI::Error>> {
+        self.flash.find(range, needle)
+    }
+
+    pub fn rfind(&mut self, range: Range<u32>, needle: &[u8]) -> Result<Option<u32>, Error<SPI::Error>> {
+        self.flash.rfind(range, needle)
+    }
+
+    pub fn status_register(&mut self, reg_num: u8) -> Result<u8, Error<SPI::Error>> {
+        self.flash.status_register(reg_num)
+    }
+
+    pub fn status1(&mut self) -> Result<StatusRegister1, Error<SPI::Error>> {
+        self.flash.status1()
+    }
+
+    pub fn status2(&mut self) -> Result<StatusRegister2, Error<SPI::Error>> {
+        self.flash.status2()
+    }
+
+    pub fn status3(&mut self) -> Result<StatusRegister3, Error<SPI::Error>> {
+        self.flash.status3()
+    }
+
+    pub fn status(&mut self) -> Result<Status, Error<SPI::Error>> {
+        self.flash.status()
+    }
+
+    pub fn status_protection(&mut self) -> Result<StatusProtection, Error<SPI::Error>> {
+        self.flash.status_protection()
+    }
+
+    pub fn quad_enabled(&mut self) -> Result<bool, Error<SPI::Error>> {
+        self.flash.quad_enabled()
+    }
+
+    pub fn block_protection(&mut self) -> Result<BlockProtection, Error<SPI::Error>> {
+        self.flash.block_protection()
+    }
+
+    pub fn protection_map(&mut self, protected: impl FnMut(ProtectedRange)) -> Result<(), Error<SPI::Error>> {
+        self.flash.protection_map(protected)
+    }
+
+    pub fn protected_overlap(&mut self, address: u32, len: usize) -> Result<Option<Range<u32>>, Error<SPI::Error>> {
+        self.flash.protected_overlap(address, len)
+    }
+
+    #[cfg(feature = "individual-locks")]
+    pub fn is_block_locked(&mut self, address: u32) -> Result<bool, Error<SPI::Error>> {
+        self.flash.is_block_locked(address)
+    }
+
+    #[cfg(feature = "security-registers")]
+    pub fn otp_read(&mut self, register: u8, offset: usize, buffer: &mut [u8]) -> Result<(), Error<SPI::Error>> {
+        self.flash.otp_read(register, offset, buffer)
+    }
+
+    #[cfg(feature = "security-registers")]
+    pub fn otp_locked(&mut self, register: u8) -> Result<bool, Error<SPI::Error>> {
+        self.flash.otp_locked(register)
+    }
+
+    #[cfg(feature = "security-registers")]
+    pub fn read_provisioning(&mut self, register: u8) -> Result<Option<crate::Provisioning>, Error<SPI::Error>> {
+        self.flash.read_provisioning(register)
+    }
+
+    #[cfg(feature = "security-registers")]
+    pub fn rollback_counter(&mut self, register: u8) -> Result<u32, Error<SPI::Error>> {
+        self.flash.rollback_counter(register)
+    }
+
+    #[cfg(feature = "suspend")]
+    pub fn is_suspended(&mut self) -> Result<bool, Error<SPI::Error>> {
+        self.flash.is_suspended()
+    }
+
+    /// See [`W25qxx::check_chip`]. Only reports; call
+    /// [`W25qxx::reprobe`] on the full driver to act on the result.
+    pub fn check_chip(&mut self) -> Result<ChipCheck, Error<SPI::Error>> {
+        self.flash.check_chip()
+    }
+
+    pub fn unique_id(&mut self) -> Result<u64, Error<SPI::Error>> {
+        self.flash.unique_id()
+    }
+
+    pub fn read_sfdp(&mut self) -> Result<Option<crate::Sfdp>, Error<SPI::Error>> {
+        self.flash.read_sfdp()
+    }
+}
+
+impl<SPI: SpiDevice, D> nor_flash::ErrorType for ReadOnly<SPI, D> {
+    type Error = Error<SPI::Error>;
+}
+
+impl<SPI, D> ReadNorFlash for ReadOnly<SPI, D>
+where
+    SPI: SpiDevice,
+    D: DelayNs,
+{
+    const READ_SIZE: usize = 1;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        ReadNorFlash::read(&mut self.flash, offset, bytes)
+    }
+
+    fn capacity(&self) -> usize {
+        ReadNorFlash::capacity(&self.flash)
+    }
+}
@@ -0,0 +1,75 @@
+//! Anti-rollback counter in a security register.
+//!
+//! Each increment programs one more bit of the register from 1 to 0. Bits
+//! can only be set back by erasing the register, which the update logic never
+//! does, so the count only grows: firmware whose security version is below it
+//! is refused. A power loss in the middle of an advance can leave the count
+//! between the old and the new value, but never below the old one.
+//!
+//! The register is not locked, as that would freeze the counter; keep it
+//! apart from registers holding other data such as a
+//! [`Provisioning`](crate::Provisioning) record. Enabled by the
+//! `security-registers` feature.
+
+use crate::w25qxx::{Error, W25qxx, W25QXX_SECURITY_REGISTER_SIZE};
+use embedded_hal::delay::DelayNs;
+use embedded_hal::spi::SpiDevice;
+
+/// Highest count a register holds, one per bit.
+pub const ROLLBACK_COUNTER_MAX: u32 = W25QXX_SECURITY_REGISTER_SIZE as u32 * 8;
+
+impl<SPI, D> W25qxx<SPI, D>
+where
+    SPI: SpiDevice,
+    D: DelayNs,
+{
+    /// Current count of the anti-rollback counter in security register
+    /// `register` (1 to 3): the number of programmed bits, 0 on an erased
+    /// register.
+    pub fn rollback_counter(&mut self, register: u8) -> Result<u32, Error<SPI::Error>> {
+        let mut bits: [u8; W25QXX_SECURITY_REGISTER_SIZE] = [0; W25QXX_SECURITY_REGISTER_SIZE];
+        self.otp_read(register, 0, &mut bits)?;
+
+        Ok(bits.iter().map(|byte| byte.count_zeros()).sum())
+    }
+
+    /// Advances the counter in security register `register` to at least
+    /// `count` and returns the new count. A count at or below the current
+    /// one changes nothing; above [`ROLLBACK_COUNTER_MAX`] fails with
+    /// [`Error::InvalidArgument`], and a count that did not stick with
+    /// [`Error::OtpVerifyFailed`].
+    pub fn advance_rollback_counter(&mut self, register: u8, count: u32) -> Result<u32, Error<SPI::Error>> {
+        if count > ROLLBACK_COUNTER_MAX {
+            return Err(Error::InvalidArgument);
+        }
+
+        let mut bits: [u8; W25QXX_SECURITY_REGISTER_SIZE] = [0; W25QXX_SECURITY_REGISTER_SIZE];
+        self.otp_read(register, 0, &mut bits)?;
+        let current: u32 = bits.iter().map(|byte| byte.count_zeros()).sum();
+        if count <= current {
+            return Ok(current);
+        }
+
+        /* Bits are burned from the start of the register, lowest bit of each
+         * byte first. Bits already programmed stay programmed */
+        let mut changed = None;
+        for (index, byte) in bits.iter_mut().enumerate() {
+            let burned = count.saturating_sub(index as u32 * 8).min(8);
+            let target = *byte & !((1u16 << burned) - 1) as u8;
+            if target != *byte {
+                *byte = target;
+                changed = Some(changed.map_or((index, index), |(first, _)| (first, index)));
+            }
+        }
+
+        if let Some((first, last)) = changed {
+            self.otp_program(register, first, &bits[first..=last])?;
+        }
+
+        let advanced = self.rollback_counter(register)?;
+        if advanced < count {
+            return Err(Error::OtpVerifyFailed { register });
+        }
+        Ok(advanced)
+    }
+}
@@ -0,0 +1,208 @@
+//! Keyed logical-to-physical sector permutation.
+//!
+//! [`Scrambled`] wraps any `embedded-storage` [`NorFlash`] and moves every
+//! erase unit to a position chosen by a [`SectorScrambler`], so the physical
+//! layout (where the filesystem metadata, keys or logs sit) cannot be read off
+//! a dumped chip. The permutation is a four round Feistel network over the
+//! sector index, keyed with 128 bits. It hides the layout, not the contents:
+//! stack it on top of an encrypting layer to protect data at rest.
+//!
+//! Addresses inside one erase unit keep their offset, so page programming and
+//! alignment rules of the wrapped flash still apply.
+
+use embedded_storage::nor_flash::{ErrorType, MultiwriteNorFlash, NorFlash, ReadNorFlash};
+
+/// Number of Feistel rounds. Four rounds make the permutation pseudorandom.
+const ROUNDS: usize = 4;
+
+/// Bijection between logical and physical sector indexes.
+#[derive(Clone, Debug)]
+pub struct SectorScrambler {
+    key: [u32; ROUNDS],
+    sectors: u32,
+    half_bits: u32,
+}
+
+impl SectorScrambler {
+    /// Permutation of `sectors` indexes chosen by `key`.
+    pub fn new(key: [u32; ROUNDS], sectors: u32) -> Self {
+        /* Smallest even bit width covering every index */
+        let bits = u32::BITS - sectors.saturating_sub(1).leading_zeros();
+        SectorScrambler { key, sectors, half_bits: bits.div_ceil(2).max(1) }
+    }
+
+    /// Number of sectors covered by the permutation.
+    pub fn sectors(&self) -> u32 {
+        self.sectors
+    }
+
+    /// Physical sector holding logical sector `logical`. Indexes past the last
+    /// sector are returned unchanged.
+    pub fn physical(&self, logical: u32) -> u32 {
+        self.walk(logical, Self::encrypt)
+    }
+
+    /// Logical sector stored in physical sector `physical`.
+    pub fn logical(&self, physical: u32) -> u32 {
+        self.walk(physical, Self::decrypt)
+    }
+
+    /// Cycle walking: the Feistel domain is a power of four, so values landing
+    /// past the last sector are permuted again until they fall inside it.
+    fn walk(&self, index: u32, permute: fn(&Self, u32) -> u32) -> u32 {
+        if index >= self.sectors {
+            return index;
+        }
+
+        let mut value = permute(self, index);
+        while value >= self.sectors {
+            value = permute(self, value);
+        }
+        value
+    }
+
+    fn mask(&self) -> u32 {
+        (1 << self.half_bits) - 1
+    }
+
+    fn encrypt(&self, value: u32) -> u32 {
+        let mut left = value >> self.half_bits;
+        let mut right = value & self.mask();
+
+        for key in self.key {
+            (left, right) = (right, left ^ (round(right, key) & self.mask()));
+        }
+
+        left << self.half_bits | right
+    }
+
+    fn decrypt(&self, value: u32) -> u32 {
+        let mut left = value >> self.half_bits;
+        let mut right = value & self.mask();
+
+        for key in self.key.iter().rev() {
+            (left, right) = (right ^ (round(left, *key) & self.mask()), left);
+        }
+
+        left << self.half_bits | right
+    }
+}
+
+/// Round function: keyed integer mixer (murmur3 finalizer).
+fn round(value: u32, key: u32) -> u32 {
+    let mut mixed = (value ^ key).wrapping_mul(0x9E37_79B9);
+    mixed ^= mixed >> 16;
+    mixed = mixed.wrapping_mul(0x85EB_CA6B);
+    mixed ^= mixed >> 13;
+    mixed = mixed.wrapping_mul(0xC2B2_AE35);
+    mixed ^ mixed >> 16
+}
+
+/// [`NorFlash`] adapter storing each erase unit at a scrambled position.
+///
+/// Addresses past the end of the flash are passed through unchanged, so the
+/// wrapped flash still reports them as out of bounds.
+pub struct Scrambled<F> {
+    flash: F,
+    scrambler: SectorScrambler,
+}
+
+impl<F: NorFlash> Scrambled<F> {
+    /// Scrambles every erase unit of `flash` with `key`. The same key must be
+    /// used every time the flash is opened.
+    pub fn new(flash: F, key: [u32; ROUNDS]) -> Self {
+        let sectors = (flash.capacity() / F::ERASE_SIZE) as u32;
+        Scrambled { flash, scrambler: SectorScrambler::new(key, sectors) }
+    }
+
+    pub fn scrambler(&self) -> &SectorScrambler {
+        &self.scrambler
+    }
+
+    /// Gives back the wrapped flash.
+    pub fn into_inner(self) -> F {
+        self.flash
+    }
+
+    /// Physical address of logical address `address`.
+    pub fn physical_address(&self, address: u32) -> u32 {
+        physical_address(&self.scrambler, F::ERASE_SIZE, address)
+    }
+}
+
+fn physical_address(scrambler: &SectorScrambler, sector_size: usize, address: u32) -> u32 {
+    let sector_size = sector_size as u32;
+    scrambler.physical(address / sector_size) * sector_size + address % sector_size
+}
+
+/// Splits `[offset, offset + len)` at erase unit boundaries and yields the
+/// physical address and buffer range of every piece.
+fn pieces(scrambler: &SectorScrambler, sector_size: usize, offset: u32, len: usize) -> impl Iterator<Item = (u32, usize, usize)> + '_ {
+    let mut done = 0;
+
+    core::iter::from_fn(move || {
+        if done >= len {
+            return None;
+        }
+
+        let address = offset + done as u32;
+        let size = (sector_size - address as usize % sector_size).min(len - done);
+        let piece = (physical_address(scrambler, sector_size, address), done, done + size);
+        done += size;
+        Some(piece)
+    })
+}
+
+impl<F: ErrorType> ErrorType for Scrambled<F> {
+    type Error = F::Error;
+}
+
+impl<F: NorFlash> ReadNorFlash for Scrambled<F> {
+    const READ_SIZE: usize = F::READ_SIZE;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        let Scrambled { flash, scrambler } = self;
+        for (address, start, end) in pieces(scrambler, F::ERASE_SIZE, offset, bytes.len()) {
+            flash.read(address, &mut bytes[start..end])?;
+        }
+
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        self.flash.capacity()
+    }
+}
+
+impl<F: NorFlash> NorFlash for Scrambled<F> {
+    const WRITE_SIZE: usize = F::WRITE_SIZE;
+
+    const ERASE_SIZE: usize = F::ERASE_SIZE;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        let sector_size = F::ERASE_SIZE as u32;
+
+        /* Let the wrapped flash reject ranges it would reject itself */
+        if from > to || !from.is_multiple_of(sector_size) || !to.is_multiple_of(sector_size) {
+            return self.flash.erase(from, to);
+        }
+
+        for sector in from / sector_size..to / sector_size {
+            let address = self.physical_address(sector * sector_size);
+            self.flash.erase(address, address + sector_size)?;
+        }
+
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        let Scrambled { flash, scrambler } = self;
+        for (address, start, end) in pieces(scrambler, F::ERASE_SIZE, offset, bytes.len()) {
+            flash.write(address, &bytes[start..end])?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<F: MultiwriteNorFlash> MultiwriteNorFlash for Scrambled<F> {}
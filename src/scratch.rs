@@ -0,0 +1,84 @@
+//! Pool of temporary scratch sectors spread by wear.
+//!
+//! Copy-swap, OTA staging or health checks all need a sector they can trash
+//! for a moment. A [`ScratchPool`] owns `N` consecutive sectors and hands out
+//! the free one with the fewest erases, so these helpers do not all wear out
+//! the same sector. Erase counts are kept in a [`WearMap`], which can be
+//! seeded with the table a [`WearTracked`](crate::WearTracked) restored.
+
+use crate::w25qxx::{Error, W25qxx, W25QXX_SECTOR_SIZE};
+use crate::wear::WearMap;
+use embedded_hal::delay::DelayNs;
+use embedded_hal::spi::SpiDevice;
+
+/// Sector lent out by [`ScratchPool::acquire`]. Hand it back with
+/// [`ScratchPool::release`].
+#[derive(Debug, PartialEq, Eq)]
+pub struct ScratchSector {
+    address: u32,
+}
+
+impl ScratchSector {
+    /// Start address of the sector.
+    pub fn address(&self) -> u32 {
+        self.address
+    }
+}
+
+/// `N` scratch sectors starting at the base of the wear map.
+pub struct ScratchPool<const N: usize> {
+    map: WearMap<N>,
+    in_use: [bool; N],
+}
+
+impl<const N: usize> ScratchPool<N> {
+    /// Uses the `N` sectors starting at `base`, with no erase history.
+    pub const fn new(base: u32) -> Self {
+        Self::from_map(WearMap::new(base))
+    }
+
+    /// Uses the sectors covered by `map`, starting from its erase counts.
+    pub const fn from_map(map: WearMap<N>) -> Self {
+        ScratchPool { map, in_use: [false; N] }
+    }
+
+    pub fn wear_map(&self) -> &WearMap<N> {
+        &self.map
+    }
+
+    /// Number of sectors currently lent out.
+    pub fn in_use(&self) -> usize {
+        self.in_use.iter().filter(|used| **used).count()
+    }
+
+    /// Erases the least worn free sector and lends it out. Returns `None` when
+    /// every sector is in use.
+    pub fn acquire<SPI: SpiDevice, D: DelayNs>(&mut self, flash: &mut W25qxx<SPI, D>) -> Result<Option<ScratchSector>, Error<SPI::Error>> {
+        let Some((index, _)) = self
+            .map
+            .counts()
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| !self.in_use[*index])
+            .min_by_key(|(_, count)| **count)
+        else {
+            return Ok(None);
+        };
+
+        let address = self.map.base() + (index * W25QXX_SECTOR_SIZE) as u32;
+        flash.erase(address, W25QXX_SECTOR_SIZE)?;
+        self.map.record_erase(address, W25QXX_SECTOR_SIZE);
+
+        self.in_use[index] = true;
+        Ok(Some(ScratchSector { address }))
+    }
+
+    /// Returns a sector to the pool. Its contents are left as they are. A
+    /// sector of another pool is ignored.
+    pub fn release(&mut self, sector: ScratchSector) {
+        let index = sector.address.checked_sub(self.map.base()).map(|offset| offset as usize / W25QXX_SECTOR_SIZE);
+        if let Some(in_use) = index.and_then(|index| self.in_use.get_mut(index)) {
+            *in_use = false;
+        }
+    }
+}
@@ -0,0 +1,101 @@
+//! Typed configuration stored in one flash sector.
+//!
+//! [`Settings`] keeps the current value of a `serde` type in RAM. It is loaded
+//! once at startup, read from RAM afterwards, and every [`Settings::set`]
+//! writes the new value through to its sector before updating the cache.
+//! Values are encoded with `postcard` into at most `N` bytes.
+//!
+//! Enabled by the `settings` feature.
+
+use crate::checksum::Crc32;
+use crate::w25qxx::{Error, W25qxx, W25QXX_SECTOR_SIZE};
+use embedded_hal::delay::DelayNs;
+use embedded_hal::spi::SpiDevice;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// Marks a stored value ("SETT" in little endian).
+const SETTINGS_MAGIC: u32 = 0x5454_4553;
+
+/// Magic + payload length.
+const SETTINGS_HEADER_SIZE: usize = 8;
+
+/// Cached value of type `T` persisted in the sector at `address`, encoded in
+/// at most `N` bytes.
+pub struct Settings<T, const N: usize> {
+    address: u32,
+    value: T,
+}
+
+impl<T, const N: usize> Settings<T, N>
+where
+    T: Serialize + DeserializeOwned,
+{
+    /// Loads the value stored at `address`, falling back to `default` when the
+    /// sector holds no valid record.
+    pub fn load<SPI: SpiDevice, D: DelayNs>(flash: &mut W25qxx<SPI, D>, address: u32, default: T) -> Result<Self, Error<SPI::Error>> {
+        /* Header, payload and CRC must fit in the sector */
+        const { assert!(SETTINGS_HEADER_SIZE + N + 4 <= W25QXX_SECTOR_SIZE) };
+
+        if !address.is_multiple_of(W25QXX_SECTOR_SIZE as u32) {
+            return Err(Error::NotAligned);
+        }
+
+        let value = Self::read_record(flash, address)?.unwrap_or(default);
+        Ok(Settings { address, value })
+    }
+
+    /// Current value, served from RAM.
+    pub fn get(&self) -> &T {
+        &self.value
+    }
+
+    /// Stores `value` on the flash, then makes it the cached value. The cache
+    /// keeps the previous value if the write fails.
+    pub fn set<SPI: SpiDevice, D: DelayNs>(&mut self, flash: &mut W25qxx<SPI, D>, value: T) -> Result<(), Error<SPI::Error>> {
+        let mut record: [u8; W25QXX_SECTOR_SIZE] = [0xFF; W25QXX_SECTOR_SIZE];
+
+        let payload_size = postcard::to_slice(&value, &mut record[SETTINGS_HEADER_SIZE..SETTINGS_HEADER_SIZE + N])
+            .map_err(|_e| Error::InvalidArgument)?
+            .len();
+
+        record[..4].copy_from_slice(&SETTINGS_MAGIC.to_le_bytes());
+        record[4..8].copy_from_slice(&(payload_size as u32).to_le_bytes());
+
+        let record_size = SETTINGS_HEADER_SIZE + payload_size;
+        let crc = Crc32::checksum(&record[..record_size]);
+        record[record_size..record_size + 4].copy_from_slice(&crc.to_le_bytes());
+
+        flash.erase(self.address, W25QXX_SECTOR_SIZE)?;
+        flash.write(self.address, &record[..record_size + 4])?;
+
+        self.value = value;
+        Ok(())
+    }
+
+    /// Gives back the cached value.
+    pub fn into_inner(self) -> T {
+        self.value
+    }
+
+    fn read_record<SPI: SpiDevice, D: DelayNs>(flash: &mut W25qxx<SPI, D>, address: u32) -> Result<Option<T>, Error<SPI::Error>> {
+        let mut record: [u8; W25QXX_SECTOR_SIZE] = [0; W25QXX_SECTOR_SIZE];
+        flash.read(address, &mut record[..SETTINGS_HEADER_SIZE])?;
+
+        let payload_size = u32::from_le_bytes([record[4], record[5], record[6], record[7]]) as usize;
+        if record[..4] != SETTINGS_MAGIC.to_le_bytes() || payload_size > N {
+            return Ok(None);
+        }
+
+        let record_size = SETTINGS_HEADER_SIZE + payload_size;
+        flash.read(address + SETTINGS_HEADER_SIZE as u32, &mut record[SETTINGS_HEADER_SIZE..record_size + 4])?;
+
+        let crc = Crc32::checksum(&record[..record_size]);
+        if record[record_size..record_size + 4] != crc.to_le_bytes() {
+            return Ok(None);
+        }
+
+        /* A type that changed shape since the record was written falls back to the default */
+        Ok(postcard::from_bytes(&record[SETTINGS_HEADER_SIZE..record_size]).ok())
+    }
+}
@@ -0,0 +1,175 @@
+//! Serial Flash Discoverable Parameters (JESD216).
+//!
+//! The chip describes itself in a small table read with Read SFDP (0x5A).
+//! [`Sfdp`] holds what the driver can use from the JEDEC Basic Flash
+//! Parameter table: density, erase granularities, the fast read variants with
+//! their dummy cycles and 4-byte address support. Obtain it with
+//! [`W25qxx::read_sfdp`](crate::W25qxx::read_sfdp).
+
+use crate::builder::DummyCycles;
+use crate::chip::ChipParams;
+use crate::W25QXX_SECTOR_SIZE;
+
+/// "SFDP" in little endian, at address 0 of the SFDP space.
+pub(crate) const SFDP_SIGNATURE: u32 = 0x5044_4653;
+
+/// Size of the SFDP header and of one parameter header.
+pub(crate) const SFDP_HEADER_SIZE: usize = 8;
+
+/// Parameter ID of the JEDEC Basic Flash Parameter table.
+pub(crate) const BFPT_ID: u16 = 0xFF00;
+
+/// Number of BFPT DWORDs the parser looks at (JESD216 rev. A and later).
+pub(crate) const BFPT_DWORDS: usize = 9;
+
+/// Largest density of one die, the 4 GiB a 4-byte address reaches.
+const MAX_DIE_CAPACITY: u64 = 1 << 32;
+
+/// Largest erase unit taken from the BFPT, 64 MiB; a bigger exponent is
+/// garbage.
+const MAX_ERASE_EXPONENT: u32 = 26;
+
+/// Clocks taken by the mode bits byte on two and on four lines.
+const DUAL_IO_MODE_CYCLES: u8 = 4;
+const QUAD_IO_MODE_CYCLES: u8 = 2;
+
+/// One erase command listed in the BFPT.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct EraseType {
+    pub size: usize,
+    pub opcode: u8,
+}
+
+/// Opcode and timing of one fast read variant.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct FastRead {
+    pub opcode: u8,
+    /// Dummy clocks after the address, not counting the mode clocks.
+    pub dummy_cycles: u8,
+    /// Clocks carrying the continuous read mode bits.
+    pub mode_cycles: u8,
+}
+
+/// Address bytes accepted by the part.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SfdpAddressing {
+    ThreeOnly,
+    ThreeOrFour,
+    FourOnly,
+}
+
+/// Basic Flash Parameter table contents.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Sfdp {
+    /// Major and minor revision of the SFDP header.
+    pub revision: (u8, u8),
+    /// Total size in bytes.
+    pub capacity: usize,
+    pub addressing: SfdpAddressing,
+    /// Erase types 1 to 4, smallest first on Winbond parts.
+    pub erase_types: [Option<EraseType>; 4],
+    pub fast_read_1_1_2: Option<FastRead>,
+    pub fast_read_1_2_2: Option<FastRead>,
+    pub fast_read_1_1_4: Option<FastRead>,
+    pub fast_read_1_4_4: Option<FastRead>,
+}
+
+impl Sfdp {
+    /// Decodes the BFPT DWORDs of one die. Returns `None` for a table with a
+    /// density the driver cannot address: less than one sector or more than
+    /// 4 GiB.
+    pub fn parse(revision: (u8, u8), dwords: &[u32; BFPT_DWORDS]) -> Option<Self> {
+        let [first, density, quad, dual, _, _, _, erase12, erase34] = *dwords;
+
+        /* Density in bits: N + 1, or 2^N when bit 31 is set */
+        let bits: u64 = if density & 0x8000_0000 == 0 {
+            density as u64 + 1
+        } else {
+            1u64.checked_shl(density & 0x7FFF_FFFF)?
+        };
+        let bytes = Some(bits / 8).filter(|bytes| (W25QXX_SECTOR_SIZE as u64..=MAX_DIE_CAPACITY).contains(bytes))?;
+        let capacity = usize::try_from(bytes).ok()?;
+
+        let addressing = match (first >> 17) & 0b11 {
+            0b00 => SfdpAddressing::ThreeOnly,
+            0b01 => SfdpAddressing::ThreeOrFour,
+            _ => SfdpAddressing::FourOnly,
+        };
+
+        let erase = |field: u32| {
+            /* An exponent of 0 marks an unsupported type */
+            let size_exponent = field & 0xFF;
+            let size = 1usize.checked_shl(size_exponent).filter(|_| size_exponent != 0 && size_exponent <= MAX_ERASE_EXPONENT)?;
+            Some(EraseType { size, opcode: (field >> 8) as u8 })
+        };
+
+        let supported = |bit: u32| first & (1 << bit) != 0;
+
+        Some(Sfdp {
+            revision,
+            capacity,
+            addressing,
+            erase_types: [erase(erase12 & 0xFFFF), erase(erase12 >> 16), erase(erase34 & 0xFFFF), erase(erase34 >> 16)],
+            fast_read_1_1_2: supported(16).then(|| fast_read(dual & 0xFFFF)),
+            fast_read_1_2_2: supported(20).then(|| fast_read(dual >> 16)),
+            fast_read_1_4_4: supported(21).then(|| fast_read(quad & 0xFFFF)),
+            fast_read_1_1_4: supported(22).then(|| fast_read(quad >> 16)),
+        })
+    }
+
+    /// Erase command for blocks of `size` bytes, if the part has one.
+    pub fn erase_opcode(&self, size: usize) -> Option<u8> {
+        self.erase_types.iter().flatten().find(|erase| erase.size == size).map(|erase| erase.opcode)
+    }
+
+    /// Takes over the capacity and the erase opcodes that match the driver's
+    /// 4K/32K/64K erase units. The table of a stacked-die part describes one
+    /// die.
+    pub fn apply(&self, params: &mut ChipParams) {
+        params.capacity = self.capacity * params.dies.max(1) as usize;
+
+        let opcodes = &mut params.opcodes;
+        if let Some(opcode) = self.erase_opcode(4 * 1024) {
+            opcodes.sector_erase = opcode;
+        }
+        if let Some(opcode) = self.erase_opcode(32 * 1024) {
+            opcodes.block32_erase = opcode;
+        }
+        if let Some(opcode) = self.erase_opcode(64 * 1024) {
+            opcodes.block64_erase = opcode;
+        }
+    }
+
+    /// Takes over the dummy clocks of the dual and quad reads the table
+    /// lists. The driver always sends a whole byte of mode bits in the I/O
+    /// reads, so mode clocks beyond that count as dummy clocks.
+    pub fn apply_dummy_cycles(&self, dummy_cycles: &mut DummyCycles) {
+        let total = |read: &FastRead| read.dummy_cycles + read.mode_cycles;
+
+        if let Some(read) = &self.fast_read_1_1_2 {
+            dummy_cycles.dual_output = total(read);
+        }
+        if let Some(read) = &self.fast_read_1_1_4 {
+            dummy_cycles.quad_output = total(read);
+        }
+        if let Some(read) = &self.fast_read_1_2_2 {
+            dummy_cycles.dual_io = total(read).saturating_sub(DUAL_IO_MODE_CYCLES);
+        }
+        if let Some(read) = &self.fast_read_1_4_4 {
+            dummy_cycles.quad_io = total(read).saturating_sub(QUAD_IO_MODE_CYCLES);
+        }
+    }
+}
+
+/// Fast read field of the BFPT: dummy clocks, mode clocks, opcode.
+fn fast_read(field: u32) -> FastRead {
+    FastRead {
+        opcode: (field >> 8) as u8,
+        dummy_cycles: (field & 0x1F) as u8,
+        mode_cycles: ((field >> 5) & 0x07) as u8,
+    }
+}
@@ -0,0 +1,107 @@
+//! Signature checks of flash regions.
+//!
+//! A bootloader verifies an application image before jumping to it. The
+//! methods here stream the region page by page through the verifier, the
+//! same way [`W25qxx::digest`] does, so an image of any size is checked
+//! without holding it in RAM. The caller supplies the public key, typically
+//! compiled into the bootloader or checked against a
+//! [`Provisioning`](crate::Provisioning) key hash, and the signature, often
+//! stored next to the image.
+//!
+//! Ed25519 is enabled by the `ed25519` feature, ECDSA over P-256 with SHA-256
+//! by the `ecdsa-p256` feature.
+
+use crate::checksum::Checksum;
+use crate::w25qxx::{Error, W25qxx};
+use embedded_hal::delay::DelayNs;
+use embedded_hal::spi::SpiDevice;
+
+/// Ed25519 verifier fed with the region; finalizes to whether the signature
+/// holds.
+#[cfg(feature = "ed25519")]
+struct Ed25519Stream(ed25519_dalek::StreamVerifier);
+
+#[cfg(feature = "ed25519")]
+impl Checksum for Ed25519Stream {
+    type Output = bool;
+
+    fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    fn finalize(self) -> bool {
+        self.0.finalize_and_verify().is_ok()
+    }
+}
+
+/// SHA-256 of the region, checked against the signature when finalized.
+#[cfg(feature = "ecdsa-p256")]
+struct EcdsaP256Stream<'a> {
+    key: p256::ecdsa::VerifyingKey,
+    signature: &'a p256::ecdsa::Signature,
+    hasher: sha2::Sha256,
+}
+
+#[cfg(feature = "ecdsa-p256")]
+impl Checksum for EcdsaP256Stream<'_> {
+    type Output = bool;
+
+    fn update(&mut self, data: &[u8]) {
+        sha2::Digest::update(&mut self.hasher, data);
+    }
+
+    fn finalize(self) -> bool {
+        use p256::ecdsa::signature::DigestVerifier;
+        self.key.verify_digest(self.hasher, self.signature).is_ok()
+    }
+}
+
+impl<SPI, D> W25qxx<SPI, D>
+where
+    SPI: SpiDevice,
+    D: DelayNs,
+{
+    /// Checks the Ed25519 `signature` of the `len` bytes at `address` with
+    /// `public_key`. A key that is no valid curve point fails with
+    /// [`Error::InvalidArgument`]; a wrong or malformed signature returns
+    /// `false`. Checks like `ed25519_dalek::VerifyingKey::verify`, not
+    /// `verify_strict`.
+    #[cfg(feature = "ed25519")]
+    pub fn verify_ed25519(
+        &mut self,
+        address: u32,
+        len: usize,
+        public_key: &[u8; 32],
+        signature: &[u8; 64],
+    ) -> Result<bool, Error<SPI::Error>> {
+        let key = ed25519_dalek::VerifyingKey::from_bytes(public_key).map_err(|_| Error::InvalidArgument)?;
+        let verifier = match key.verify_stream(&ed25519_dalek::Signature::from_bytes(signature)) {
+            Ok(verifier) => verifier,
+            Err(_) => return Ok(false),
+        };
+
+        self.digest(address, len, Ed25519Stream(verifier))
+    }
+
+    /// Checks the ECDSA P-256 `signature` (`r` and `s`, 32 bytes each) over
+    /// the SHA-256 of the `len` bytes at `address` with `public_key`, a
+    /// SEC1 encoded point (compressed or not). A key that does not decode
+    /// fails with [`Error::InvalidArgument`]; a wrong or malformed signature
+    /// returns `false`.
+    #[cfg(feature = "ecdsa-p256")]
+    pub fn verify_ecdsa_p256(
+        &mut self,
+        address: u32,
+        len: usize,
+        public_key: &[u8],
+        signature: &[u8; 64],
+    ) -> Result<bool, Error<SPI::Error>> {
+        let key = p256::ecdsa::VerifyingKey::from_sec1_bytes(public_key).map_err(|_| Error::InvalidArgument)?;
+        let signature = match p256::ecdsa::Signature::from_slice(signature) {
+            Ok(signature) => signature,
+            Err(_) => return Ok(false),
+        };
+
+        self.digest(address, len, EcdsaP256Stream { key, signature: &signature, hasher: sha2::Digest::new() })
+    }
+}
@@ -0,0 +1,556 @@
+//! Emulated W25QXX for running the driver on a host without hardware.
+//!
+//! [`SimFlash`] is a [`SpiDevice`] that decodes the commands of a
+//! [`ChipParams`] opcode table and applies them to an in-memory image,
+//! optionally mirrored to an image file so the contents survive between runs.
+//! Program only clears bits and erase sets whole units back to 0xFF, as on
+//! the real part. Stacked-die parts answer Software Die Select and address
+//! the selected die.
+//!
+//! After a program or erase the die stays busy for a few Read Status
+//! Register 1 polls (see [`SimFlash::set_busy_polls`]). Meanwhile it ignores
+//! every command but the status reads, Suspend, Reset and Die Select, and
+//! array reads return the complement of the contents, so a driver reading
+//! before the operation finished sees wrong data.
+//!
+//! Requires the `std` feature.
+
+use crate::chip::ChipParams;
+use crate::multi_io::IoOperation;
+use crate::w25qxx::{
+    StatusRegister, W25QXX_BLOCK32K_SIZE, W25QXX_BLOCK64K_SIZE, W25QXX_PAGE_SIZE, W25QXX_SECTOR_SIZE,
+    W25QXX_SECURITY_REGISTER_SIZE,
+};
+use embedded_hal::spi::{ErrorKind, ErrorType, Operation, SpiDevice};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::vec;
+use std::vec::Vec;
+
+/// Serial number returned by Read Unique ID.
+const SIM_UNIQUE_ID: u64 = 0xD565_A458_0B2D_362F;
+
+/// Read Status Register 1 polls a program or erase stays busy for.
+const SIM_BUSY_POLLS: u8 = 2;
+
+/// Header, one parameter header and nine BFPT DWORDs.
+const SIM_SFDP_SIZE: usize = 16 + 9 * 4;
+
+/// Emulated chip behind a [`SpiDevice`].
+pub struct SimFlash {
+    params: ChipParams,
+    memory: Vec<u8>,
+    file: Option<File>,
+    write_enabled: bool,
+    /// Write Enable for Volatile Status Register came before this command.
+    volatile_write_enabled: bool,
+    /// Writable bits of status registers 1 to 3, as last written.
+    status: [u8; 3],
+    four_byte_address: bool,
+    die: u8,
+    /// Line length Fast Read Quad I/O wraps in, after Set Burst with Wrap.
+    burst_wrap: Option<usize>,
+    /// I/O read whose address the chip takes next, in continuous read mode.
+    continuous_read: Option<u8>,
+    /// Individual block lock bit of every sector, all set at power-up. They
+    /// are reported but do not block programs and erases.
+    locks: Vec<bool>,
+    /// In Deep Power-Down: only Release is decoded.
+    powered_down: bool,
+    /// Security registers 1 to 3, shared by all dies.
+    security: [[u8; W25QXX_SECURITY_REGISTER_SIZE]; 3],
+    /// Polls each die still reports BUSY for.
+    busy: Vec<u8>,
+    busy_polls: u8,
+}
+
+impl SimFlash {
+    /// Erased chip held in RAM only.
+    pub fn new(params: ChipParams) -> Self {
+        SimFlash {
+            params,
+            memory: vec![0xFF; params.capacity],
+            file: None,
+            write_enabled: false,
+            volatile_write_enabled: false,
+            status: [0; 3],
+            four_byte_address: false,
+            die: 0,
+            burst_wrap: None,
+            continuous_read: None,
+            locks: vec![true; params.capacity / W25QXX_SECTOR_SIZE],
+            powered_down: false,
+            security: [[0xFF; W25QXX_SECURITY_REGISTER_SIZE]; 3],
+            busy: vec![0; params.dies.max(1) as usize],
+            busy_polls: SIM_BUSY_POLLS,
+        }
+    }
+
+    /// Chip backed by the image file at `path`. A missing file is created and
+    /// a short one is padded with erased bytes up to the chip capacity.
+    pub fn open<P: AsRef<Path>>(path: P, params: ChipParams) -> io::Result<Self> {
+        let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)?;
+
+        let mut memory = Vec::with_capacity(params.capacity);
+        file.read_to_end(&mut memory)?;
+        memory.truncate(params.capacity);
+
+        if memory.len() < params.capacity {
+            let stored = memory.len();
+            memory.resize(params.capacity, 0xFF);
+            file.seek(SeekFrom::Start(stored as u64))?;
+            file.write_all(&memory[stored..])?;
+        }
+
+        let mut flash = SimFlash::new(params);
+        flash.memory = memory;
+        flash.file = Some(file);
+        Ok(flash)
+    }
+
+    /// Current contents of the emulated chip.
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    /// Read Status Register 1 polls a program or erase reports BUSY for; 0
+    /// finishes them at once.
+    pub fn set_busy_polls(&mut self, polls: u8) {
+        self.busy_polls = polls;
+    }
+
+    /// Starts a program or erase on the selected die.
+    fn start_busy(&mut self) {
+        self.busy[self.die as usize] = self.busy_polls;
+    }
+
+    /// Answers a command sent to the selected die while it is busy.
+    fn busy_transaction(&mut self, frame: &[u8], data: &mut [Operation<'_, u8>]) {
+        let opcodes = self.params.opcodes;
+        let opcode = frame[0];
+        let die = self.die as usize;
+
+        if opcode == opcodes.read_status1 {
+            self.busy[die] -= 1;
+            let status = self.status1() | StatusRegister::Busy as u8;
+            for operation in data.iter_mut() {
+                if let Operation::Read(buffer) = operation {
+                    buffer.fill(status);
+                }
+            }
+            return;
+        }
+        /* Suspend lets the operation finish right away, as if it ended just before */
+        if opcode == opcodes.suspend {
+            self.busy[die] = 0;
+            return;
+        }
+
+        let mut address = self.address(frame);
+        let is_read = self.is_read_opcode(opcode);
+        for operation in data.iter_mut() {
+            if let Operation::Read(buffer) = operation {
+                for byte in buffer.iter_mut() {
+                    *byte = if is_read { !self.memory[address] } else { 0xFF };
+                    address = (address + 1) % self.memory.len();
+                }
+            }
+        }
+    }
+
+    fn status1(&self) -> u8 {
+        if self.write_enabled {
+            self.status[0] | StatusRegister::WriteEnable as u8
+        } else {
+            self.status[0]
+        }
+    }
+
+    /// SFDP space: header, one parameter header and a Basic Flash Parameter
+    /// table as found on a W25Q..JV, with the density of one die.
+    fn sfdp(&self) -> [u8; SIM_SFDP_SIZE] {
+        let density = (self.params.die_size() as u32 * 8) - 1;
+        let dwords: [u32; 9] = [
+            0xFFF9_20E5, density, 0x6B08_EB44, 0xBB42_3B08, 0xFFFF_FFFE, 0xFF00_FFFF, 0xFF00_FFFF, 0x520F_200C, 0x0000_D810,
+        ];
+
+        let mut sfdp = [0xFF; SIM_SFDP_SIZE];
+        sfdp[..8].copy_from_slice(&[b'S', b'F', b'D', b'P', 0x06, 0x01, 0x00, 0xFF]);
+        sfdp[8..16].copy_from_slice(&[0x00, 0x06, 0x01, 0x09, 0x10, 0x00, 0x00, 0xFF]);
+        for (bytes, dword) in sfdp[16..].chunks_exact_mut(4).zip(dwords) {
+            bytes.copy_from_slice(&dword.to_le_bytes());
+        }
+        sfdp
+    }
+
+    /// Sectors sharing the lock bit of `address`: one sector in the first
+    /// and last 64 KiB block of a die, the whole block elsewhere.
+    fn lock_unit(&self, address: usize) -> std::ops::Range<usize> {
+        let die_address = address % self.params.die_size();
+        let sector = address / W25QXX_SECTOR_SIZE;
+
+        if die_address < W25QXX_BLOCK64K_SIZE || die_address >= self.params.die_size() - W25QXX_BLOCK64K_SIZE {
+            sector..sector + 1
+        } else {
+            let per_block = W25QXX_BLOCK64K_SIZE / W25QXX_SECTOR_SIZE;
+            let first = sector - sector % per_block;
+            first..first + per_block
+        }
+    }
+
+    /// Address bytes after `opcode`.
+    fn address_width(&self, opcode: u8) -> usize {
+        if self.four_byte_address || self.is_four_byte_opcode(opcode) {
+            4
+        } else {
+            3
+        }
+    }
+
+    /// Address carried after the opcode, wrapped to the die size and moved
+    /// into the selected die.
+    fn address(&self, frame: &[u8]) -> usize {
+        let width = self.address_width(frame[0]);
+        let address = frame.iter().skip(1).take(width).fold(0usize, |acc, byte| acc << 8 | *byte as usize);
+        self.die as usize * self.params.die_size() + address % self.params.die_size()
+    }
+
+    /// Security register (0 to 2) and offset an address selects, if any, and
+    /// whether its LB bit locks it.
+    fn security_register(&self, address: usize) -> Option<(usize, usize, bool)> {
+        let address = address % self.params.die_size();
+        let register = (address >> 12).checked_sub(1).filter(|register| *register < 3)?;
+        let locked = self.status[1] & (0x08 << register) != 0;
+        Some((register, address % W25QXX_SECURITY_REGISTER_SIZE, locked))
+    }
+
+    /// Commands that take a 4-byte address in 3-byte address mode.
+    fn is_four_byte_opcode(&self, opcode: u8) -> bool {
+        let opcodes = self.params.opcodes;
+        [
+            opcodes.read_data_4b,
+            opcodes.fast_read_4b,
+            opcodes.fast_read_dual_output_4b,
+            opcodes.fast_read_quad_output_4b,
+            opcodes.fast_read_dual_io_4b,
+            opcodes.fast_read_quad_io_4b,
+            opcodes.page_program_4b,
+            opcodes.sector_erase_4b,
+            opcodes.block64_erase_4b,
+        ]
+        .contains(&opcode)
+    }
+
+    /// Commands returning array data.
+    fn is_read_opcode(&self, opcode: u8) -> bool {
+        let opcodes = self.params.opcodes;
+        [
+            opcodes.read_data,
+            opcodes.fast_read,
+            opcodes.fast_read_dual_output,
+            opcodes.fast_read_quad_output,
+            opcodes.fast_read_dual_io,
+            opcodes.fast_read_quad_io,
+            opcodes.read_data_4b,
+            opcodes.fast_read_4b,
+            opcodes.fast_read_dual_output_4b,
+            opcodes.fast_read_quad_output_4b,
+            opcodes.fast_read_dual_io_4b,
+            opcodes.fast_read_quad_io_4b,
+        ]
+        .contains(&opcode)
+    }
+
+    /// [`MultiIoTransfer`](crate::MultiIoTransfer) for the emulated chip.
+    /// The number of lines makes no difference; in a read, the bytes sent
+    /// before the data make up the command frame. QPI mode is accepted and
+    /// ignored.
+    pub fn multi_io_transfer(&mut self, operations: &mut [IoOperation<'_>]) -> Result<(), ErrorKind> {
+        let is_read = operations.iter().any(|operation| matches!(operation, IoOperation::Read(..)));
+        let mut frame = Vec::new();
+        let mut phases = Vec::new();
+
+        for operation in operations.iter_mut() {
+            match operation {
+                IoOperation::Write(_, bytes) if phases.is_empty() && (is_read || frame.is_empty()) => {
+                    frame.extend_from_slice(bytes)
+                }
+                IoOperation::Write(_, bytes) => phases.push(Operation::Write(bytes)),
+                IoOperation::Read(_, buffer) => phases.push(Operation::Read(buffer)),
+                IoOperation::Dummy(_) => {}
+            }
+        }
+
+        let mut transaction = vec![Operation::Write(&frame)];
+        transaction.extend(phases);
+        self.transaction(&mut transaction)
+    }
+
+    fn program(&mut self, address: usize, data: &[u8]) -> io::Result<()> {
+        /* The address wraps inside the page, as on the real chip */
+        let page = address - address % W25QXX_PAGE_SIZE;
+        for (index, byte) in data.iter().enumerate() {
+            let target = page + (address % W25QXX_PAGE_SIZE + index) % W25QXX_PAGE_SIZE;
+            self.memory[target] &= byte;
+        }
+        self.start_busy();
+
+        self.persist(page, W25QXX_PAGE_SIZE)
+    }
+
+    fn erase(&mut self, address: usize, size: usize) -> io::Result<()> {
+        let start = address - address % size;
+        self.memory[start..start + size].fill(0xFF);
+        self.start_busy();
+        self.persist(start, size)
+    }
+
+    fn persist(&mut self, start: usize, len: usize) -> io::Result<()> {
+        if let Some(file) = self.file.as_mut() {
+            file.seek(SeekFrom::Start(start as u64))?;
+            file.write_all(&self.memory[start..start + len])?;
+        }
+
+        Ok(())
+    }
+
+    /// Runs a command that has no data phase.
+    fn execute(&mut self, frame: &[u8]) -> io::Result<()> {
+        let opcodes = self.params.opcodes;
+        let opcode = frame[0];
+        let address = self.address(frame);
+
+        let volatile_write_enabled = core::mem::take(&mut self.volatile_write_enabled);
+
+        if opcode == opcodes.write_enable {
+            self.write_enabled = true;
+            return Ok(());
+        }
+        if opcode == opcodes.volatile_write_enable {
+            self.volatile_write_enabled = true;
+            return Ok(());
+        }
+        /* Status register writes need either write enable and clear WEL. BUSY, WEL,
+         * SUS and ADS are read-only */
+        let writes = [(opcodes.write_status1, 0xFC), (opcodes.write_status2, 0x7F), (opcodes.write_status3, 0xFE)];
+        if let Some(register) = writes.iter().position(|(write, _)| *write == opcode) {
+            if let (true, Some(value)) = (self.write_enabled || volatile_write_enabled, frame.get(1)) {
+                /* The LB bits are one-time programmable */
+                let sticky = if register == 1 { self.status[1] & 0x38 } else { 0 };
+                self.status[register] = (value & writes[register].1) | sticky;
+            }
+            self.write_enabled = false;
+            return Ok(());
+        }
+        if opcode == opcodes.enable_reset || opcode == opcodes.continuous_read_reset {
+            return Ok(());
+        }
+        if opcode == opcodes.reset {
+            self.write_enabled = false;
+            self.four_byte_address = false;
+            self.burst_wrap = None;
+            return Ok(());
+        }
+        if opcode == opcodes.power_down {
+            self.powered_down = true;
+            return Ok(());
+        }
+        if opcode == opcodes.enter_4byte_address || opcode == opcodes.exit_4byte_address {
+            self.four_byte_address = opcode == opcodes.enter_4byte_address;
+            return Ok(());
+        }
+        /* Lock and unlock need the latch and clear it */
+        if opcode == opcodes.individual_lock || opcode == opcodes.individual_unlock {
+            if self.write_enabled {
+                let unit = self.lock_unit(address);
+                self.locks[unit].fill(opcode == opcodes.individual_lock);
+            }
+            self.write_enabled = false;
+            return Ok(());
+        }
+        if opcode == opcodes.global_lock || opcode == opcodes.global_unlock {
+            if self.write_enabled {
+                let die = self.die as usize * self.params.die_size() / W25QXX_SECTOR_SIZE;
+                let sectors = self.params.die_size() / W25QXX_SECTOR_SIZE;
+                self.locks[die..die + sectors].fill(opcode == opcodes.global_lock);
+            }
+            self.write_enabled = false;
+            return Ok(());
+        }
+        if opcode == opcodes.erase_security {
+            if let (true, Some((register, _, false))) = (self.write_enabled, self.security_register(address)) {
+                self.security[register].fill(0xFF);
+                self.start_busy();
+            }
+            self.write_enabled = false;
+            return Ok(());
+        }
+        if opcode == opcodes.die_select && self.params.dies > 1 {
+            if let Some(&die) = frame.get(1).filter(|die| **die < self.params.dies) {
+                self.die = die;
+            }
+            return Ok(());
+        }
+
+        let erase_size = if opcode == opcodes.sector_erase || opcode == opcodes.sector_erase_4b {
+            W25QXX_SECTOR_SIZE
+        } else if opcode == opcodes.block32_erase {
+            W25QXX_BLOCK32K_SIZE
+        } else if opcode == opcodes.block64_erase || opcode == opcodes.block64_erase_4b {
+            W25QXX_BLOCK64K_SIZE
+        } else if opcode == opcodes.chip_erase {
+            self.params.die_size()
+        } else {
+            return Ok(());
+        };
+
+        /* Erase needs the write enable latch and clears it */
+        if self.write_enabled {
+            self.write_enabled = false;
+            self.erase(address, erase_size)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl ErrorType for SimFlash {
+    type Error = ErrorKind;
+}
+
+impl SpiDevice for SimFlash {
+    fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), Self::Error> {
+        let opcodes = self.params.opcodes;
+
+        let Some((Operation::Write(frame), data)) = operations.split_first_mut() else {
+            return Ok(());
+        };
+        if frame.is_empty() {
+            return Ok(());
+        }
+
+        /* Deep power-down ignores everything but Release, and MISO floats */
+        if self.powered_down {
+            self.powered_down = frame[0] != opcodes.release_power_down;
+            for operation in data.iter_mut() {
+                if let Operation::Read(buffer) = operation {
+                    buffer.fill(0xFF);
+                }
+            }
+            return Ok(());
+        }
+
+        /* Reset aborts the operation. Status reads, Die Select and Enable
+         * Reset go through while busy */
+        if frame[0] == opcodes.reset {
+            self.busy[self.die as usize] = 0;
+        }
+        let passes = [opcodes.read_status2, opcodes.read_status3, opcodes.die_select, opcodes.enable_reset];
+        if self.busy[self.die as usize] > 0 && !passes.contains(&frame[0]) {
+            let frame = frame.to_vec();
+            self.busy_transaction(&frame, data);
+            return Ok(());
+        }
+
+        /* In continuous read mode the opcode is implied. 0xFF clocks end it
+         * as mode bits that do not continue */
+        let frame: Vec<u8> = match self.continuous_read.take() {
+            Some(opcode) => [&[opcode], &frame[..]].concat(),
+            None => frame.to_vec(),
+        };
+        let frame = &frame[..];
+        let opcode = frame[0];
+        let mut address = self.address(frame);
+
+        let io_read = [opcodes.fast_read_dual_io, opcodes.fast_read_quad_io, opcodes.fast_read_dual_io_4b, opcodes.fast_read_quad_io_4b];
+        if io_read.contains(&opcode) {
+            let mode_bits = frame.get(1 + self.address_width(opcode)).copied().unwrap_or(0);
+            self.continuous_read = (mode_bits & 0x30 == 0x20).then_some(opcode);
+        }
+
+        if data.is_empty() {
+            return self.execute(frame).map_err(|_e| ErrorKind::Other);
+        }
+
+        let die_start = self.die as usize * self.params.die_size();
+        let wrap = self.burst_wrap.filter(|_| opcode == opcodes.fast_read_quad_io || opcode == opcodes.fast_read_quad_io_4b);
+
+        for operation in data.iter_mut() {
+            match operation {
+                Operation::Read(buffer) if opcode == opcodes.read_id => {
+                    let [memory_type, capacity_id] = self.params.device_id.to_be_bytes();
+                    let id = [self.params.manufacturer_id, memory_type, capacity_id];
+                    for (byte, id) in buffer.iter_mut().zip(id.iter().cycle()) {
+                        *byte = *id;
+                    }
+                }
+                Operation::Read(buffer) if opcode == opcodes.read_sfdp => {
+                    /* Always a 3-byte address */
+                    let sfdp = self.sfdp();
+                    let start = frame.iter().skip(1).take(3).fold(0usize, |acc, byte| acc << 8 | *byte as usize);
+                    for (index, byte) in buffer.iter_mut().enumerate() {
+                        *byte = sfdp.get(start + index).copied().unwrap_or(0xFF);
+                    }
+                }
+                Operation::Read(buffer) if opcode == opcodes.read_security => {
+                    if let Some((register, offset, _)) = self.security_register(address) {
+                        for (index, byte) in buffer.iter_mut().enumerate() {
+                            *byte = self.security[register][(offset + index) % W25QXX_SECURITY_REGISTER_SIZE];
+                        }
+                    }
+                }
+                Operation::Read(buffer) => {
+                    for byte in buffer.iter_mut() {
+                        *byte = if opcode == opcodes.read_unique_id {
+                            SIM_UNIQUE_ID.to_be_bytes()[address % 8]
+                        } else if opcode == opcodes.read_status1 {
+                            self.status1()
+                        } else if opcode == opcodes.read_status2 {
+                            self.status[1]
+                        } else if opcode == opcodes.read_status3 {
+                            self.status[2] | self.four_byte_address as u8
+                        } else if opcode == opcodes.read_block_lock {
+                            self.locks[address / W25QXX_SECTOR_SIZE] as u8
+                        } else if self.is_read_opcode(opcode) {
+                            self.memory[address]
+                        } else {
+                            0xFF
+                        };
+                        /* Sequential reads wrap inside the die, or inside the burst wrap line */
+                        address = match wrap {
+                            Some(line) => address - address % line + (address + 1) % line,
+                            None => die_start + (address + 1 - die_start) % self.params.die_size(),
+                        };
+                    }
+                }
+                /* Three dummy bytes, then W6-W5 select the line and W4 clears the wrap */
+                Operation::Write(bytes) if opcode == opcodes.set_burst_with_wrap => {
+                    if let Some(&wrap_bits) = bytes.get(3) {
+                        self.burst_wrap = (wrap_bits & 0x10 == 0).then(|| 8 << ((wrap_bits >> 5) & 0b11));
+                    }
+                }
+                /* Program needs the write enable latch and clears it */
+                Operation::Write(bytes)
+                    if (opcode == opcodes.page_program || opcode == opcodes.page_program_4b) && self.write_enabled =>
+                {
+                    self.write_enabled = false;
+                    self.program(address, bytes).map_err(|_e| ErrorKind::Other)?;
+                }
+                Operation::Write(bytes) if opcode == opcodes.program_security && self.write_enabled => {
+                    self.write_enabled = false;
+                    if let Some((register, offset, false)) = self.security_register(address) {
+                        for (index, byte) in bytes.iter().enumerate() {
+                            self.security[register][(offset + index) % W25QXX_SECURITY_REGISTER_SIZE] &= byte;
+                        }
+                        self.start_busy();
+                    }
+                }
+                /* Dummy bytes of fast reads, program without WEL */
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,223 @@
+//! Typed views of the status registers.
+//!
+//! [`W25qxx::status_register`](crate::W25qxx::status_register) and
+//! [`W25qxx::write_status_register`](crate::W25qxx::write_status_register)
+//! work on raw bytes. The types here decode the bits that configure the chip
+//! so they can be changed without remembering the datasheet layout; the
+//! layout is the Winbond one. [`W25qxx::status`](crate::W25qxx::status)
+//! reads all three registers at once.
+
+use crate::protection::BlockProtection;
+
+/// BUSY and WEL, status register 1.
+const STATUS1_BUSY: u8 = 0x01;
+const STATUS1_WEL: u8 = 0x02;
+/// SRP, status register 1: WP# guards the status registers.
+pub(crate) const STATUS1_SRP: u8 = 0x80;
+/// SRL, status register 2: status registers locked until power-down.
+pub(crate) const STATUS2_SRL: u8 = 0x01;
+/// QE, status register 2.
+const STATUS2_QE: u8 = 0x02;
+/// LB1 to LB3, status register 2: security registers locked for good.
+pub(crate) const STATUS2_LB_SHIFT: u8 = 3;
+/// CMP and SUS, status register 2.
+const STATUS2_CMP: u8 = 0x40;
+const STATUS2_SUS: u8 = 0x80;
+
+/// WPS: protection by the individual block locks instead of the BP bits.
+const STATUS3_WPS: u8 = 0x04;
+/// ADP: 4-byte address mode after power-up (parts above 16 MiB).
+const STATUS3_ADP: u8 = 0x02;
+/// ADS: 4-byte address mode now. Read-only.
+pub(crate) const STATUS3_ADS: u8 = 0x01;
+/// DRV1 and DRV0.
+const STATUS3_DRV_SHIFT: u8 = 5;
+const STATUS3_DRV_MASK: u8 = 0x60;
+/// HOLD/RST: the /HOLD pin acts as /RESET (on parts that have the bit).
+const STATUS3_HOLD_RST: u8 = 0x80;
+
+/// Who may write the status registers, from SRP (status register 1) and
+/// SRL (status register 2).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum StatusProtection {
+    /// Writable after Write Enable, whatever the WP# pin does.
+    Software,
+    /// Writable only while WP# is high.
+    Hardware,
+    /// Not writable until the next power cycle.
+    PowerSupplyLockDown,
+}
+
+/// Output driver strength, DRV1/DRV0 of status register 3.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DriveStrength {
+    Percent100 = 0,
+    Percent75 = 1,
+    Percent50 = 2,
+    /// Factory default.
+    Percent25 = 3,
+}
+
+/// Status register 1 (read 0x05, write 0x01).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct StatusRegister1 {
+    /// A program, erase or status register write is running.
+    pub busy: bool,
+    pub write_enable_latch: bool,
+    /// BP, TB and SEC. CMP comes from status register 2 and stays clear
+    /// here; [`Status::block_protection`] combines both.
+    pub block_protection: BlockProtection,
+    /// Status Register Protect: WP# guards the status registers.
+    pub srp: bool,
+}
+
+impl StatusRegister1 {
+    /// Decodes the register of a die of `die_size` bytes, whose size decides
+    /// where TB sits.
+    pub fn from_bits(bits: u8, die_size: usize) -> Self {
+        StatusRegister1 {
+            busy: bits & STATUS1_BUSY != 0,
+            write_enable_latch: bits & STATUS1_WEL != 0,
+            block_protection: BlockProtection::from_status(die_size, bits, 0),
+            srp: bits & STATUS1_SRP != 0,
+        }
+    }
+
+    /// Register value for a die of `die_size` bytes. BUSY and WEL are
+    /// read-only and left out.
+    pub fn to_bits(&self, die_size: usize) -> u8 {
+        let (bits, _) = self.block_protection.to_status(die_size, 0, 0);
+        if self.srp {
+            bits | STATUS1_SRP
+        } else {
+            bits
+        }
+    }
+}
+
+/// Status register 2 (read 0x35, write 0x31).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct StatusRegister2 {
+    /// Status Register Lock.
+    pub srl: bool,
+    /// Quad Enable: IO2 and IO3 are data lines instead of WP# and HOLD#.
+    pub quad_enable: bool,
+    /// LB1 to LB3: security registers 1 to 3 are locked for good (OTP).
+    pub security_locks: [bool; 3],
+    /// Complement the range of the block protect bits.
+    pub cmp: bool,
+    /// A program or erase is suspended. Read-only.
+    pub suspended: bool,
+}
+
+impl From<u8> for StatusRegister2 {
+    fn from(bits: u8) -> Self {
+        StatusRegister2 {
+            srl: bits & STATUS2_SRL != 0,
+            quad_enable: bits & STATUS2_QE != 0,
+            security_locks: core::array::from_fn(|index| bits & (1 << (STATUS2_LB_SHIFT + index as u8)) != 0),
+            cmp: bits & STATUS2_CMP != 0,
+            suspended: bits & STATUS2_SUS != 0,
+        }
+    }
+}
+
+impl From<StatusRegister2> for u8 {
+    fn from(status: StatusRegister2) -> u8 {
+        let mut bits = 0;
+        if status.srl {
+            bits |= STATUS2_SRL;
+        }
+        if status.quad_enable {
+            bits |= STATUS2_QE;
+        }
+        for (index, locked) in status.security_locks.iter().enumerate() {
+            if *locked {
+                bits |= 1 << (STATUS2_LB_SHIFT + index as u8);
+            }
+        }
+        if status.cmp {
+            bits |= STATUS2_CMP;
+        }
+        bits
+    }
+}
+
+/// Status register 3 (read 0x15, write 0x11).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct StatusRegister3 {
+    /// Write Protect Selection: the individual block locks protect the array
+    /// instead of the BP/TB/SEC bits.
+    pub wps: bool,
+    /// Power up in 4-byte address mode.
+    pub adp: bool,
+    /// In 4-byte address mode. Read-only.
+    pub ads: bool,
+    pub drive_strength: DriveStrength,
+    /// /HOLD works as /RESET. Reserved on parts without that pin function.
+    pub hold_reset: bool,
+}
+
+impl From<u8> for StatusRegister3 {
+    fn from(bits: u8) -> Self {
+        let drive_strength = match (bits & STATUS3_DRV_MASK) >> STATUS3_DRV_SHIFT {
+            0 => DriveStrength::Percent100,
+            1 => DriveStrength::Percent75,
+            2 => DriveStrength::Percent50,
+            _ => DriveStrength::Percent25,
+        };
+
+        StatusRegister3 {
+            wps: bits & STATUS3_WPS != 0,
+            adp: bits & STATUS3_ADP != 0,
+            ads: bits & STATUS3_ADS != 0,
+            drive_strength,
+            hold_reset: bits & STATUS3_HOLD_RST != 0,
+        }
+    }
+}
+
+impl From<StatusRegister3> for u8 {
+    fn from(status: StatusRegister3) -> u8 {
+        let mut bits = (status.drive_strength as u8) << STATUS3_DRV_SHIFT;
+        if status.wps {
+            bits |= STATUS3_WPS;
+        }
+        if status.adp {
+            bits |= STATUS3_ADP;
+        }
+        if status.hold_reset {
+            bits |= STATUS3_HOLD_RST;
+        }
+        bits
+    }
+}
+
+/// All three status registers, read one after the other.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Status {
+    pub status1: StatusRegister1,
+    pub status2: StatusRegister2,
+    pub status3: StatusRegister3,
+}
+
+impl Status {
+    /// Block protect bits including CMP.
+    pub fn block_protection(&self) -> BlockProtection {
+        BlockProtection { cmp: self.status2.cmp, ..self.status1.block_protection }
+    }
+
+    pub fn status_protection(&self) -> StatusProtection {
+        match (self.status2.srl, self.status1.srp) {
+            (true, _) => StatusProtection::PowerSupplyLockDown,
+            (false, true) => StatusProtection::Hardware,
+            (false, false) => StatusProtection::Software,
+        }
+    }
+}
@@ -0,0 +1,168 @@
+//! `embedded-storage` NOR flash traits for both drivers.
+//!
+//! Bootloaders and filesystems written against
+//! [`embedded_storage::nor_flash`] can use [`W25qxx`] directly, and async
+//! ones written against [`embedded_storage_async::nor_flash`] (such as
+//! embassy-boot) can use [`W25qxxAsync`].
+
+use crate::asynch::W25qxxAsync;
+use crate::w25qxx::{Error, Ready, W25qxx, W25QXX_SECTOR_SIZE};
+use core::fmt::Debug;
+use embedded_hal::delay::DelayNs as BlockingDelayNs;
+use embedded_hal::spi::SpiDevice;
+use embedded_hal_async::delay::DelayNs;
+use embedded_hal_async::spi::SpiDevice as AsyncSpiDevice;
+use embedded_storage_async::nor_flash as async_nor_flash;
+use embedded_storage::nor_flash::{
+    ErrorType, MultiwriteNorFlash, NorFlash, NorFlashError, NorFlashErrorKind, ReadNorFlash,
+};
+
+/// Rejects accesses that run past the end of the chip.
+fn check_bounds<E>(capacity: usize, offset: u32, len: usize) -> Result<(), Error<E>> {
+    if offset as usize > capacity || len > capacity - offset as usize {
+        return Err(Error::InvalidAddress);
+    }
+
+    Ok(())
+}
+
+/// Rejects erase ranges that are reversed, out of bounds or not sector aligned.
+fn check_erase_range<E>(capacity: usize, from: u32, to: u32) -> Result<(), Error<E>> {
+    if from > to {
+        return Err(Error::InvalidArgument);
+    }
+    if !from.is_multiple_of(W25QXX_SECTOR_SIZE as u32) || !to.is_multiple_of(W25QXX_SECTOR_SIZE as u32) {
+        return Err(Error::NotAligned);
+    }
+
+    check_bounds(capacity, from, (to - from) as usize)
+}
+
+impl<E: Debug> NorFlashError for Error<E> {
+    fn kind(&self) -> NorFlashErrorKind {
+        match self {
+            Error::NotAligned => NorFlashErrorKind::NotAligned,
+            Error::InvalidAddress => NorFlashErrorKind::OutOfBounds,
+            _ => NorFlashErrorKind::Other,
+        }
+    }
+}
+
+impl<SPI, D> ErrorType for W25qxx<SPI, D>
+where
+    SPI: SpiDevice,
+    D: BlockingDelayNs,
+{
+    type Error = Error<SPI::Error>;
+}
+
+impl<SPI, D> ReadNorFlash for W25qxx<SPI, D>
+where
+    SPI: SpiDevice,
+    D: BlockingDelayNs,
+{
+    const READ_SIZE: usize = 1;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        check_bounds(self.params().capacity, offset, bytes.len())?;
+
+        /* Zero-length reads are valid for the trait but rejected by the chip path */
+        if bytes.is_empty() {
+            return Ok(());
+        }
+
+        W25qxx::<SPI, D, Ready>::read(self, offset, bytes)
+    }
+
+    fn capacity(&self) -> usize {
+        self.params().capacity
+    }
+}
+
+impl<SPI, D> NorFlash for W25qxx<SPI, D>
+where
+    SPI: SpiDevice,
+    D: BlockingDelayNs,
+{
+    const WRITE_SIZE: usize = 1;
+    const ERASE_SIZE: usize = W25QXX_SECTOR_SIZE;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        check_erase_range(self.params().capacity, from, to)?;
+
+        W25qxx::erase(self, from, (to - from) as usize)
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        check_bounds(self.params().capacity, offset, bytes.len())?;
+
+        W25qxx::write(self, offset, bytes)
+    }
+}
+
+/// NOR cells can be programmed again as long as bits only go from 1 to 0.
+impl<SPI, D> MultiwriteNorFlash for W25qxx<SPI, D>
+where
+    SPI: SpiDevice,
+    D: BlockingDelayNs,
+{
+}
+
+impl<SPI, D> ErrorType for W25qxxAsync<SPI, D>
+where
+    SPI: AsyncSpiDevice,
+    D: DelayNs,
+{
+    type Error = Error<SPI::Error>;
+}
+
+impl<SPI, D> async_nor_flash::ReadNorFlash for W25qxxAsync<SPI, D>
+where
+    SPI: AsyncSpiDevice,
+    D: DelayNs,
+{
+    const READ_SIZE: usize = 1;
+
+    async fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        check_bounds(self.params().capacity, offset, bytes.len())?;
+
+        /* Zero-length reads are valid for the trait but rejected by the chip path */
+        if bytes.is_empty() {
+            return Ok(());
+        }
+
+        W25qxxAsync::read(self, offset, bytes).await
+    }
+
+    fn capacity(&self) -> usize {
+        self.params().capacity
+    }
+}
+
+impl<SPI, D> async_nor_flash::NorFlash for W25qxxAsync<SPI, D>
+where
+    SPI: AsyncSpiDevice,
+    D: DelayNs,
+{
+    const WRITE_SIZE: usize = 1;
+    const ERASE_SIZE: usize = W25QXX_SECTOR_SIZE;
+
+    async fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        check_erase_range(self.params().capacity, from, to)?;
+
+        W25qxxAsync::erase(self, from, (to - from) as usize).await
+    }
+
+    async fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        check_bounds(self.params().capacity, offset, bytes.len())?;
+
+        W25qxxAsync::write(self, offset, bytes).await
+    }
+}
+
+impl<SPI, D> async_nor_flash::MultiwriteNorFlash for W25qxxAsync<SPI, D>
+where
+    SPI: AsyncSpiDevice,
+    D: DelayNs,
+{
+}
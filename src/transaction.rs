@@ -0,0 +1,279 @@
+//! All-or-nothing updates spanning several flash regions.
+//!
+//! A [`Journal`] reserves a sector-aligned area of the flash. Its first sector
+//! holds the commit record, the rest stages the data of the running
+//! [`Transaction`]. Nothing outside the journal is touched until
+//! [`Transaction::commit`] has written the commit record; the staged data is
+//! then copied to its destinations and the record is marked as applied.
+//!
+//! Call [`Journal::recover`] once at startup, right after `init()`:
+//!
+//! * a transaction that never reached its commit record is discarded, so the
+//!   destinations keep their previous contents;
+//! * a committed transaction that was interrupted while being applied is
+//!   replayed from the staged copy.
+//!
+//! Every destination region must start on a sector boundary and owns all the
+//! sectors it covers: they are erased before the new data is programmed, so
+//! no two regions may share a sector.
+
+use crate::checksum::Crc32;
+use crate::w25qxx::{Error, W25qxx, W25QXX_PAGE_SIZE, W25QXX_SECTOR_SIZE};
+use embedded_hal::delay::DelayNs;
+use embedded_hal::spi::{ErrorType, SpiDevice};
+
+/// Maximum number of destination regions in one transaction.
+pub const MAX_TRANSACTION_REGIONS: usize = 8;
+
+/// Marks a commit record ("TXN1" in little endian).
+const COMMIT_MAGIC: u32 = 0x314E_5854;
+
+/// Magic + region count.
+const RECORD_HEADER_SIZE: usize = 8;
+
+/// Destination address, length and offset inside the staging area.
+const RECORD_ENTRY_SIZE: usize = 12;
+
+/// Value of the applied marker once the copy finished.
+const APPLIED: u32 = 0;
+
+#[derive(Clone, Copy, Default)]
+struct Region {
+    target: u32,
+    len: u32,
+    staged: u32,
+}
+
+/// Location of the journal on the flash.
+#[derive(Clone, Copy, Debug)]
+pub struct Journal {
+    address: u32,
+    len: usize,
+}
+
+/// Outcome of [`Journal::recover`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Recovery {
+    /// No committed transaction was pending.
+    Clean,
+    /// A committed transaction was interrupted and has been replayed.
+    Replayed,
+}
+
+impl Journal {
+    /// Uses `len` bytes at `address` as journal. Both must be sector aligned
+    /// and leave room for at least one staging sector.
+    pub fn new<E>(address: u32, len: usize) -> Result<Journal, Error<E>> {
+        if !address.is_multiple_of(W25QXX_SECTOR_SIZE as u32) || !len.is_multiple_of(W25QXX_SECTOR_SIZE) {
+            return Err(Error::NotAligned);
+        }
+        if len < 2 * W25QXX_SECTOR_SIZE {
+            return Err(Error::InvalidArgument);
+        }
+
+        Ok(Journal { address, len })
+    }
+
+    /// Bytes available for staged data.
+    pub fn capacity(&self) -> usize {
+        self.len - W25QXX_SECTOR_SIZE
+    }
+
+    /// Finishes or discards whatever an interrupted transaction left behind.
+    pub fn recover<SPI: SpiDevice, D: DelayNs>(&self, flash: &mut W25qxx<SPI, D>) -> Result<Recovery, Error<SPI::Error>> {
+        let mut regions = [Region::default(); MAX_TRANSACTION_REGIONS];
+
+        let Some(count) = self.read_record(flash, &mut regions)? else {
+            return Ok(Recovery::Clean);
+        };
+
+        if self.read_applied(flash, count)? == APPLIED {
+            return Ok(Recovery::Clean);
+        }
+
+        self.apply(flash, &regions[..count])?;
+        Ok(Recovery::Replayed)
+    }
+
+    /// Starts a new transaction, wiping the journal.
+    pub fn begin<'a, SPI: SpiDevice, D: DelayNs>(&self, flash: &'a mut W25qxx<SPI, D>) -> Result<Transaction<'a, SPI, D>, Error<SPI::Error>> {
+        flash.erase(self.address, self.len)?;
+
+        Ok(Transaction {
+            flash,
+            journal: *self,
+            regions: [Region::default(); MAX_TRANSACTION_REGIONS],
+            count: 0,
+            staged: 0,
+        })
+    }
+
+    fn staging_address(&self) -> u32 {
+        self.address + W25QXX_SECTOR_SIZE as u32
+    }
+
+    fn overlaps(&self, address: u32, len: usize) -> bool {
+        (address as u64) < self.address as u64 + self.len as u64
+            && (self.address as u64) < address as u64 + len as u64
+    }
+
+    fn read_record<SPI: SpiDevice, D: DelayNs>(&self, flash: &mut W25qxx<SPI, D>, regions: &mut [Region; MAX_TRANSACTION_REGIONS]) -> Result<Option<usize>, Error<SPI::Error>> {
+        let mut header: [u8; RECORD_HEADER_SIZE] = [0; RECORD_HEADER_SIZE];
+        flash.read(self.address, &mut header)?;
+
+        let count = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize;
+        if header[..4] != COMMIT_MAGIC.to_le_bytes() || count == 0 || count > MAX_TRANSACTION_REGIONS {
+            return Ok(None);
+        }
+
+        let record_size = RECORD_HEADER_SIZE + count * RECORD_ENTRY_SIZE;
+        let crc = flash.digest(self.address, record_size, Crc32::new())?;
+        let mut stored: [u8; 4] = [0; 4];
+        flash.read(self.address + record_size as u32, &mut stored)?;
+        if crc != u32::from_le_bytes(stored) {
+            return Ok(None);
+        }
+
+        let mut entry: [u8; RECORD_ENTRY_SIZE] = [0; RECORD_ENTRY_SIZE];
+        for (index, region) in regions.iter_mut().take(count).enumerate() {
+            flash.read(self.address + (RECORD_HEADER_SIZE + index * RECORD_ENTRY_SIZE) as u32, &mut entry)?;
+            region.target = u32::from_le_bytes([entry[0], entry[1], entry[2], entry[3]]);
+            region.len = u32::from_le_bytes([entry[4], entry[5], entry[6], entry[7]]);
+            region.staged = u32::from_le_bytes([entry[8], entry[9], entry[10], entry[11]]);
+        }
+
+        Ok(Some(count))
+    }
+
+    /// The applied marker follows the record CRC.
+    fn applied_address(&self, count: usize) -> u32 {
+        self.address + (RECORD_HEADER_SIZE + count * RECORD_ENTRY_SIZE + 4) as u32
+    }
+
+    fn read_applied<SPI: SpiDevice, D: DelayNs>(&self, flash: &mut W25qxx<SPI, D>, count: usize) -> Result<u32, Error<SPI::Error>> {
+        let mut marker: [u8; 4] = [0; 4];
+        flash.read(self.applied_address(count), &mut marker)?;
+        Ok(u32::from_le_bytes(marker))
+    }
+
+    fn apply<SPI: SpiDevice, D: DelayNs>(&self, flash: &mut W25qxx<SPI, D>, regions: &[Region]) -> Result<(), Error<SPI::Error>> {
+        let mut chunk: [u8; W25QXX_PAGE_SIZE] = [0; W25QXX_PAGE_SIZE];
+
+        for region in regions {
+            let len = region.len as usize;
+            flash.erase(region.target, len.div_ceil(W25QXX_SECTOR_SIZE) * W25QXX_SECTOR_SIZE)?;
+
+            let mut offset: usize = 0;
+            while offset < len {
+                let chunk_size = core::cmp::min(len - offset, chunk.len());
+                flash.read(self.staging_address() + region.staged + offset as u32, &mut chunk[..chunk_size])?;
+                flash.write(region.target + offset as u32, &chunk[..chunk_size])?;
+                offset += chunk_size;
+            }
+        }
+
+        flash.write(self.applied_address(regions.len()), &APPLIED.to_le_bytes())
+    }
+}
+
+/// Writes staged in the journal until [`commit`](Transaction::commit).
+///
+/// Dropping a transaction without committing leaves the destinations
+/// untouched.
+pub struct Transaction<'a, SPI: ErrorType, D> {
+    flash: &'a mut W25qxx<SPI, D>,
+    journal: Journal,
+    regions: [Region; MAX_TRANSACTION_REGIONS],
+    count: usize,
+    staged: usize,
+}
+
+impl<SPI, D> Transaction<'_, SPI, D>
+where
+    SPI: SpiDevice,
+    D: DelayNs,
+{
+    /// Stages `data` for `address`. Consecutive writes that continue the
+    /// previous one extend the same region. Data reaching past the chip or
+    /// into the journal or the sectors of another region fails with
+    /// [`Error::InvalidAddress`].
+    pub fn write(&mut self, address: u32, data: &[u8]) -> Result<(), Error<SPI::Error>> {
+        if data.is_empty() {
+            return Ok(());
+        }
+        if self.staged + data.len() > self.journal.capacity() {
+            return Err(Error::InvalidArgument);
+        }
+        if address as u64 + data.len() as u64 > self.flash.capacity() as u64 || self.journal.overlaps(address, data.len()) {
+            return Err(Error::InvalidAddress);
+        }
+
+        let extends_last = self.count > 0 && {
+            let last = &self.regions[self.count - 1];
+            last.target + last.len == address
+        };
+        if self.overlaps_region(address, data.len(), extends_last.then(|| self.count - 1)) {
+            return Err(Error::InvalidAddress);
+        }
+
+        if extends_last {
+            self.regions[self.count - 1].len += data.len() as u32;
+        } else {
+            if self.count == MAX_TRANSACTION_REGIONS {
+                return Err(Error::InvalidArgument);
+            }
+            if !address.is_multiple_of(W25QXX_SECTOR_SIZE as u32) {
+                return Err(Error::NotAligned);
+            }
+            self.regions[self.count] = Region { target: address, len: data.len() as u32, staged: self.staged as u32 };
+            self.count += 1;
+        }
+
+        self.flash.write(self.journal.staging_address() + self.staged as u32, data)?;
+        self.staged += data.len();
+        Ok(())
+    }
+
+    /// Whether `len` bytes at `address` reach into the sectors of a region
+    /// other than `skip`: applying it erases all of them.
+    fn overlaps_region(&self, address: u32, len: usize, skip: Option<usize>) -> bool {
+        let sector_end = |end: u64| end.next_multiple_of(W25QXX_SECTOR_SIZE as u64);
+        let end = sector_end(address as u64 + len as u64);
+
+        self.regions[..self.count]
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| Some(*index) != skip)
+            .any(|(_, region)| (address as u64) < sector_end(region.target as u64 + region.len as u64) && (region.target as u64) < end)
+    }
+
+    /// Writes the commit record, then copies every staged region to its
+    /// destination.
+    pub fn commit(self) -> Result<(), Error<SPI::Error>> {
+        if self.count == 0 {
+            return Ok(());
+        }
+
+        let regions = &self.regions[..self.count];
+        let mut record: [u8; RECORD_HEADER_SIZE + MAX_TRANSACTION_REGIONS * RECORD_ENTRY_SIZE + 4] =
+            [0; RECORD_HEADER_SIZE + MAX_TRANSACTION_REGIONS * RECORD_ENTRY_SIZE + 4];
+
+        record[..4].copy_from_slice(&COMMIT_MAGIC.to_le_bytes());
+        record[4..8].copy_from_slice(&(self.count as u32).to_le_bytes());
+        for (entry, region) in record[RECORD_HEADER_SIZE..].chunks_exact_mut(RECORD_ENTRY_SIZE).zip(regions) {
+            entry[0..4].copy_from_slice(&region.target.to_le_bytes());
+            entry[4..8].copy_from_slice(&region.len.to_le_bytes());
+            entry[8..12].copy_from_slice(&region.staged.to_le_bytes());
+        }
+
+        let record_size = RECORD_HEADER_SIZE + self.count * RECORD_ENTRY_SIZE;
+        let crc = Crc32::checksum(&record[..record_size]);
+        record[record_size..record_size + 4].copy_from_slice(&crc.to_le_bytes());
+
+        /* From here on the transaction survives a power loss */
+        self.flash.write(self.journal.address, &record[..record_size + 4])?;
+
+        self.journal.apply(self.flash, regions)
+    }
+}
@@ -1,83 +1,856 @@
-use embedded_hal::digital::v2::OutputPin;
-use std::io::{Read, Write};
-use std::time::Duration;
-use std::thread;
+use crate::builder::{AddressWidth, BurstWrap, BusyPoll, MAX_FAST_READ_DUMMY_CYCLES, READ_HEADER_MAX_SIZE, Config, IdCheck, ReadMode, W25qxxBuilder};
+use crate::checksum::{Checksum, Crc32};
+use crate::chip::{capacity_from_id, ChipParams, DeviceInfo, Family, Geometry, Opcodes, QuadEnable, Quirks};
+#[cfg(feature = "qpi")]
+use crate::multi_io::quad_transaction;
+use crate::multi_io::{IoOperation, Lines, MultiIoTransfer};
+use crate::protection::{BlockProtection, ProtectedRange, ProtectionSource};
+use crate::sfdp::{Sfdp, BFPT_DWORDS, BFPT_ID, SFDP_HEADER_SIZE, SFDP_SIGNATURE};
+use crate::status::{Status, StatusProtection, StatusRegister1, StatusRegister2, StatusRegister3, STATUS1_SRP, STATUS2_SRL, STATUS3_ADS};
+use core::fmt;
+use core::marker::PhantomData;
+use core::ops::{ControlFlow, Range};
+use embedded_hal::delay::DelayNs;
+use embedded_hal::spi::{ErrorType, Operation, SpiDevice};
+
+/// Diagnostics go to the `log` crate and/or to defmt, depending on the
+/// enabled features, at the given level. Without either they are dropped.
+macro_rules! diag {
+    ($level:ident, $($arg:tt)*) => {
+        #[cfg(feature = "log")]
+        log::$level!($($arg)*);
+        #[cfg(feature = "defmt")]
+        defmt::$level!($($arg)*);
+    };
+}
+
+/// W25QXX driver on top of an `embedded-hal` [`SpiDevice`].
+///
+/// The SPI device owns the chip select line. Use
+/// [`SpiBusDevice`](crate::SpiBusDevice) (or `embedded-hal-bus`) to drive the
+/// flash from a bare `SpiBus` plus an `OutputPin`. Every wait goes through
+/// the [`DelayNs`] implementation handed to the constructor.
+///
+/// The driver starts in the [`Uninit`] state; only [`W25qxx::init`] turns it
+/// into a [`Ready`] driver on which read/write/erase are available.
+pub struct W25qxx<SPI: ErrorType, D, S = Ready> {
+    spi: SPI,
+    delay: D,
+    params: ChipParams,
+    config: Config,
+    /// Last command that may keep each die busy.
+    pending: [BusyOperation; W25QXX_MAX_DIES],
+    /// Die the commands go to on stacked-die parts.
+    die: u8,
+    /// Opcode of the I/O read the chip stays in continuous read mode for.
+    continuous_read: Option<u8>,
+    /// Whether the chip takes its commands in QPI mode.
+    #[cfg(feature = "qpi")]
+    qpi: bool,
+    /// Deep Power-Down was entered; only Release is sent until it ends.
+    powered_down: bool,
+    /// A command went out since the last [`W25qxx::poll_idle`], and the
+    /// timestamp from which the chip counts as idle.
+    active: bool,
+    idle_since_ms: u32,
+    /// Dies with a suspended program or erase.
+    #[cfg(feature = "suspend")]
+    suspended: [bool; W25QXX_MAX_DIES],
+    /// Die address of the last erase started on each die.
+    #[cfg(feature = "suspend")]
+    erase_address: [u32; W25QXX_MAX_DIES],
+    integrity_check: Option<IntegrityCheck>,
+    integrity_hook: Option<fn(&IntegrityReport)>,
+    progress_hook: Option<ProgressHook>,
+    power_control: Option<PowerControl<D>>,
+    clock_control: Option<ClockControl<SPI>>,
+    /// SPI clock last set through the [`ClockControl`] hook.
+    spi_clock_hz: Option<u32>,
+    multi_io: Option<MultiIoTransfer<SPI>>,
+    integrity_report: Option<IntegrityReport>,
+    /// Ranges registered with [`W25qxx::add_protected_range`].
+    protected_ranges: [Option<Range<u32>>; MAX_PROTECTED_RANGES],
+    state: PhantomData<S>,
+}
+
+/// Power cycles the flash, for example by switching its load switch off and
+/// on again. Gets the driver's delay to hold the rail off long enough.
+pub type PowerControl<D> = fn(&mut D);
+
+/// Sets the clock of the SPI device to the given frequency in Hz, on
+/// backends that can change it at runtime such as spidev.
+pub type ClockControl<SPI> = fn(&mut SPI, u32) -> Result<(), <SPI as ErrorType>::Error>;
+
+/// Wait after power comes back before the chip accepts commands (tVSL, with
+/// margin).
+const POWER_UP_DELAY_US: u32 = 5_000;
+
+/// From Power-Down (0xB9) to the low standby current (tDP), and from
+/// Release Power-Down (0xAB) to the next command (tRES1).
+const POWER_DOWN_DELAY_US: u32 = 3;
+const RELEASE_POWER_DOWN_DELAY_US: u32 = 3;
+
+/// Shortest RESET# low pulse (tRESET), and time from the end of a reset
+/// until the chip takes commands (tRST).
+const RESET_PULSE_US: u32 = 1;
+pub(crate) const RESET_DELAY_US: u32 = 30;
+
+/// Longest time from Suspend to the chip being ready for reads (tSUS),
+/// also the shortest time from Resume to the next Suspend.
+#[cfg(feature = "suspend")]
+const SUSPEND_LATENCY_US: u32 = 20;
+
+/// Driver state before the chip was identified and reset.
+pub struct Uninit;
+
+/// Driver state after a successful [`W25qxx::init`].
+pub struct Ready;
+
+/// Driver state for a chip that could not be identified, obtained from
+/// [`InitError::into_read_only`]: data and status can be read, program and
+/// erase are not available.
+pub struct ReadOnlyUnknown;
+
+/// Failed [`W25qxx::init`]. Gives the uninitialized driver back so the bus is
+/// not lost.
+pub struct InitError<SPI: ErrorType, D> {
+    pub flash: W25qxx<SPI, D, Uninit>,
+    pub error: Error<SPI::Error>,
+}
+
+impl<SPI, D> InitError<SPI, D>
+where
+    SPI: SpiDevice,
+    D: DelayNs,
+{
+    /// Keeps the unidentified chip in a read-only mode, so recovery tooling
+    /// can still dump data off an unknown or half-dead part. Reads use the
+    /// configured read mode and address width; the capacity is not known, so
+    /// addresses are not checked.
+    pub fn into_read_only(self) -> W25qxx<SPI, D, ReadOnlyUnknown> {
+        diag!(warn, "W25QXX - Unidentified chip, continuing read-only");
+
+        self.flash.into_state()
+    }
+}
+
+impl<SPI: ErrorType, D> fmt::Debug for InitError<SPI, D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("InitError").field("error", &self.error).finish_non_exhaustive()
+    }
+}
+
+/// Region hashed by [`W25qxx::init`] and the CRC32 it must match.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct IntegrityCheck {
+    pub address: u32,
+    pub len: usize,
+    pub expected: u32,
+}
+
+/// Outcome of the startup integrity check.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct IntegrityReport {
+    pub check: IntegrityCheck,
+    /// CRC32 actually computed over the region.
+    pub found: u32,
+}
+
+impl IntegrityReport {
+    pub fn is_ok(&self) -> bool {
+        self.found == self.check.expected
+    }
+}
+
+/// How far a [`W25qxx::write`] or [`W25qxx::erase`] got, passed to the
+/// [`on_progress`](W25qxx::on_progress) hook after each page or erase unit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Progress {
+    /// Command just issued for the unit at `address`.
+    pub operation: BusyOperation,
+    pub address: u32,
+    /// Bytes handled so far, this unit included.
+    pub done: usize,
+    pub total: usize,
+}
+
+/// Called with the [`Progress`] of long writes and erases; `Break` cancels
+/// them.
+pub type ProgressHook = fn(&Progress) -> ControlFlow<()>;
+
+/// What [`W25qxx::check_chip`] found on the bus.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ChipCheck {
+    /// The identified part, set up as the driver left it.
+    Unchanged,
+    /// Status registers read as all 0x00 or all 0xFF: nothing drives MISO,
+    /// the chip is missing or unpowered.
+    NoResponse,
+    /// A part with another JEDEC ID answers: it was swapped.
+    Replaced { manufacturer_id: u8, device_id: u16 },
+    /// The same part lost its volatile setup (4-byte address mode, Quad
+    /// Enable): it was power-cycled or reset behind the driver's back.
+    SetupLost,
+}
 
-pub struct W25qxx<Spidev, CS> {
-    spi: Spidev,
-    cs: CS,
+/// Most ranges [`W25qxx::add_protected_range`] keeps.
+pub const MAX_PROTECTED_RANGES: usize = 4;
+
+/// Erases [`W25qxx::secure_erase`] and [`W25qxx::sanitize`] give a sector
+/// that fails its blank check before reporting it.
+const SECURE_ERASE_ATTEMPTS: usize = 3;
+
+/// Bytes read per step by [`W25qxx::find`] and [`W25qxx::rfind`].
+const SEARCH_CHUNK_SIZE: usize = 2 * W25QXX_PAGE_SIZE;
+
+/// Bytes that did not read back the same in every pass of
+/// [`W25qxx::read_stable`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ReadConsistency {
+    /// Number of bytes that changed between passes.
+    pub fluctuating: usize,
+    /// Address of the first and the last fluctuating byte.
+    pub first: Option<u32>,
+    pub last: Option<u32>,
 }
-const W25QXX_MANID_VALUE: u8  = 0xEF;
 
-/** Device ID */
-const W25QXX_DEVID_VALUE_128: u8 = 0x17; /* 128Mbit */
+impl ReadConsistency {
+    pub fn is_stable(&self) -> bool {
+        self.fluctuating == 0
+    }
+}
+
+/// Manufacturer ID reported by Winbond parts.
+pub const W25QXX_MANID_VALUE: u8 = 0xEF;
+
+/// Memory type byte of the JEDEC ID of the W25Q..JV-IQ parts.
+pub const W25QXX_MEMORY_TYPE: u8 = 0x40;
+
+/** Device ID: memory type and capacity bytes of the JEDEC ID */
+pub const W25QXX_DEVID_VALUE_128: u16 = 0x4018; /* 128Mbit */
+
+/// Capacity of the 128Mbit part.
+pub const W25QXX_CAPACITY_128: usize = 16 * 1024 * 1024; /* 16M */
+
+/// Capacity of the 256Mbit part, and of each W25M512JV die.
+pub const W25QXX_CAPACITY_256: usize = 32 * 1024 * 1024; /* 32M */
+
+/// Device ID of the W25M512JV. The capacity byte is the size of one die.
+pub const W25M_DEVID_VALUE_512: u16 = 0x7119;
 
-const W25QXX_PAGE_SIZE: usize = 256;
+/// Most dies a stacked part may have.
+pub const W25QXX_MAX_DIES: usize = 4;
+
+pub const W25QXX_PAGE_SIZE: usize = 256;
+
+/// Size of each of the three security registers.
+pub const W25QXX_SECURITY_REGISTER_SIZE: usize = 256;
 
 /* Constants */
-const W25QXX_SECTOR_SIZE: usize = 4 * 1024; /* 4K */
-const W25QXX_BLOCK32K_SIZE: usize = 32 * 1024; /* 32K */
-const W25QXX_BLOCK64K_SIZE: usize = 64 * 1024; /* 64K */
+pub const W25QXX_SECTOR_SIZE: usize = 4 * 1024; /* 4K */
+pub const W25QXX_BLOCK32K_SIZE: usize = 32 * 1024; /* 32K */
+pub const W25QXX_BLOCK64K_SIZE: usize = 64 * 1024; /* 64K */
 
 /// Easily readable representation of the command bytes used by the flash chip.
 #[repr(u8)]
-enum Command {
-    Jedec = 0x90,
+#[derive(Clone, Copy)]
+pub(crate) enum Command {
+    JedecId = 0x9F,
     PageProgram = 0x02,
     ReadData = 0x03,
     FastRead = 0x0B,
     ReadStatusRegister1 = 0x05,
     ReadStatusRegister2 = 0x35,
+    ReadStatusRegister3 = 0x15,
     WriteEnable = 0x06,
+    VolatileWriteEnable = 0x50,
     SectorErase = 0x20,
     Block32Erase = 0x52,
     Block64Erase = 0xD8,
     ChipErase = 0xC7,
     EnableReset = 0x66,
     Reset = 0x99,
+    ContinuousReadReset = 0xFF,
+    EnterQpi = 0x38,
+    SetReadParameters = 0xC0,
+    SetBurstWithWrap = 0x77,
+    Enter4ByteAddressMode = 0xB7,
+    Exit4ByteAddressMode = 0xE9,
+    ReadData4ByteAddress = 0x13,
+    FastRead4ByteAddress = 0x0C,
+    FastReadDualOutput = 0x3B,
+    FastReadDualOutput4ByteAddress = 0x3C,
+    FastReadQuadOutput = 0x6B,
+    FastReadQuadOutput4ByteAddress = 0x6C,
+    FastReadDualIo = 0xBB,
+    FastReadDualIo4ByteAddress = 0xBC,
+    FastReadQuadIo = 0xEB,
+    FastReadQuadIo4ByteAddress = 0xEC,
+    WriteStatusRegister1 = 0x01,
+    WriteStatusRegister2 = 0x31,
+    WriteStatusRegister3 = 0x11,
+    PageProgram4ByteAddress = 0x12,
+    SectorErase4ByteAddress = 0x21,
+    Block64Erase4ByteAddress = 0xDC,
+    ReadSfdp = 0x5A,
+    ReadUniqueId = 0x4B,
+    DieSelect = 0xC2,
+    Suspend = 0x75,
+    Resume = 0x7A,
+    IndividualBlockLock = 0x36,
+    IndividualBlockUnlock = 0x39,
+    ReadBlockLock = 0x3D,
+    GlobalBlockLock = 0x7E,
+    GlobalBlockUnlock = 0x98,
+    PowerDown = 0xB9,
+    ReleasePowerDown = 0xAB,
+    EraseSecurityRegister = 0x44,
+    ProgramSecurityRegister = 0x42,
+    ReadSecurityRegister = 0x48,
 }
 
-enum StatusRegister {
+pub(crate) enum StatusRegister {
     Busy = 0x01,
     WriteEnable = 0x02,
+    /// Status register 2: a program or erase is suspended.
+    Suspend = 0x80,
+}
+
+/// Mode bits sent after the address of Dual/Quad I/O reads. Anything but
+/// 0bxx10xxxx ends continuous read mode with the command.
+const MODE_BITS: u8 = 0x00;
+
+/// Mode bits keeping the chip in continuous read mode: the next command is
+/// taken as the address of another read of the same kind.
+const CONTINUOUS_READ_MODE_BITS: u8 = 0x20;
+
+/// Set Read Parameters value: two dummy clocks (one dummy byte on four
+/// lines), no wrap. Used while the SPI clock is unknown.
+#[cfg(feature = "qpi")]
+const QPI_READ_PARAMETERS: u8 = 0x00;
+
+/// Fastest SPI clock of Fast Read in QPI mode with 2, 4, 6 and 8 dummy
+/// clocks, Set Read Parameters P5-P4 = 0 to 3.
+#[cfg(feature = "qpi")]
+const QPI_DUMMY_CLOCK_LIMITS_HZ: [u32; 4] = [33_000_000, 55_000_000, 80_000_000, 104_000_000];
+
+/// Fastest SPI clock of Read Data (0x03), which has no dummy clocks.
+const READ_DATA_MAX_HZ: u32 = 50_000_000;
+
+/// Wrap bits with W4 set: linear Quad I/O reads, the power-up state.
+const BURST_WRAP_DISABLED: u8 = 0x10;
+
+/// Dummy bytes of Fast Read in QPI mode, two clocks on four lines, while
+/// the SPI clock is unknown.
+#[cfg(feature = "qpi")]
+const QPI_FAST_READ_DUMMY_BYTES: usize = 1;
+
+/// Quad Enable bit in status register 2 (Winbond, GigaDevice).
+const QE_STATUS2: u8 = 0x02;
+
+/// Quad Enable bit in status register 1 (Macronix, ISSI).
+const QE_STATUS1: u8 = 0x40;
+
+/// Command that keeps the chip busy for a while after it was sent.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum BusyOperation {
+    /// Nothing was sent yet: after power-up the chip may still be finishing
+    /// anything, so the longest timeout applies.
+    Unknown,
+    PageProgram,
+    SectorErase,
+    Block32Erase,
+    Block64Erase,
+    ChipErase,
+    WriteStatusRegister,
+}
+
+impl BusyOperation {
+    /// Typical duration from the W25Q128JV datasheet.
+    pub fn typical_us(&self) -> u32 {
+        match self {
+            BusyOperation::PageProgram => 400,
+            BusyOperation::SectorErase => 45_000,
+            BusyOperation::Block32Erase => 120_000,
+            BusyOperation::Block64Erase => 150_000,
+            BusyOperation::WriteStatusRegister => 10_000,
+            BusyOperation::ChipErase | BusyOperation::Unknown => 40_000_000,
+        }
+    }
 }
 
+/// Driver errors. `E` is the error type of the SPI device, kept so the cause
+/// of a bus failure is not lost.
 #[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum Error<E> {
+    /// The SPI transfer failed.
     SPIError(E),
+    /// A length, count or option that can never be valid (empty buffer,
+    /// region too large, ...).
+    InvalidArgument,
+    /// The access runs past the end of the chip or of the area it targets.
+    InvalidAddress,
+    /// Address or length is not a multiple of the required unit.
+    NotAligned,
+    /// The chip did not finish `operation` within its
+    /// [`BusyTimeouts`](crate::BusyTimeouts) limit.
+    Timeout { operation: BusyOperation },
+    /// The Write Enable Latch did not set after Write Enable.
+    WriteEnableFailed,
+    /// The configured mode needs a capability that is missing, such as a
+    /// [`MultiIoTransfer`] for dual and quad reads.
+    Unsupported,
+    /// A program or erase is suspended on this die: resume it before
+    /// programming, erasing or writing status registers.
+    Suspended,
+    /// The [`ProgressHook`] cancelled a write or erase after `done` bytes.
+    /// Starting the same operation again `done` bytes further on completes
+    /// it.
+    Cancelled { done: usize },
+    /// The chip is in Deep Power-Down: call
+    /// [`W25qxx::release_power_down`] before any other command, or set
+    /// [`Config::idle_power_down_ms`] to wake it automatically.
+    PoweredDown,
+    /// A GPIO the driver controls (WP#, HOLD#/RESET#) could not be set.
+    PinFailed,
+    /// The Quad Enable bit did not read back as set after writing it: the
+    /// status registers are protected (SRP with WP# low, or SRL).
+    QuadEnableFailed,
+    /// The security register is locked (its LB bit is set): it can no
+    /// longer be programmed or erased.
+    Locked,
+    /// The write or erase overlaps a range registered with
+    /// [`W25qxx::add_protected_range`]; nothing was sent to the chip.
+    Protected,
+    /// A store has no room left for the record, even after reclaiming the
+    /// space of outdated ones.
+    NoSpace,
+    /// Data read back after programming differs from what was written.
+    VerifyFailed { address: u32 },
+    /// Security register `register` did not read back as programmed: its LB
+    /// bit, record or counter did not stick.
+    OtpVerifyFailed { register: u8 },
+    /// The sector at `address` still held programmed bits after
+    /// [`W25qxx::secure_erase`] erased it repeatedly: the cells are worn out.
+    EraseFailed { address: u32 },
+    /// The identification read only returned 0x00 or 0xFF: nothing drives
+    /// MISO. Check the wiring, the chip select line and the power supply.
+    NoResponse,
+    /// A chip answered, but with another ID than the configured part.
+    UnexpectedId { manufacturer_id: u8, device_id: u16 },
 }
 
-impl<Spidev, CS> W25qxx<Spidev, CS>
+impl<E> Error<E> {
+    /// Errors a wedged or glitched chip can cause, as opposed to bad arguments
+    /// or data.
+    pub(crate) fn is_recoverable(&self) -> bool {
+        matches!(
+            self,
+            Error::SPIError(_) | Error::Timeout { .. } | Error::WriteEnableFailed | Error::NoResponse | Error::UnexpectedId { .. }
+        )
+    }
+}
+
+impl<SPI, D> W25qxx<SPI, D, Uninit>
 where
-    Spidev: Write + Read,
-    CS: OutputPin,
+    SPI: SpiDevice,
+    D: DelayNs,
 {
-    pub fn new(spi: Spidev, cs: CS) -> Result<W25qxx<Spidev, CS> , Error<()>> {
-        let mut flash = W25qxx { spi, cs };
-        
-        let _ = flash.cs.set_high();
+    pub fn new(spi: SPI, delay: D) -> Result<W25qxx<SPI, D, Uninit>, Error<SPI::Error>> {
+        Self::with_params(spi, delay, ChipParams::default())
+    }
+
+    /// Creates a driver for a part described by `params` instead of the
+    /// default W25Q128.
+    pub fn with_params(spi: SPI, delay: D, params: ChipParams) -> Result<W25qxx<SPI, D, Uninit>, Error<SPI::Error>> {
+        W25qxxBuilder::new(spi, delay).params(params).build()
+    }
+
+    /// Starts a [`W25qxxBuilder`] to pick the expected ID, read mode, address
+    /// width, busy poll interval and write verification.
+    pub fn builder(spi: SPI, delay: D) -> W25qxxBuilder<SPI, D> {
+        W25qxxBuilder::new(spi, delay)
+    }
+
+    pub(crate) fn from_parts(
+        spi: SPI,
+        delay: D,
+        params: ChipParams,
+        config: Config,
+        multi_io: Option<MultiIoTransfer<SPI>>,
+    ) -> W25qxx<SPI, D, Uninit> {
+        W25qxx {
+            spi,
+            delay,
+            params,
+            pending: [BusyOperation::Unknown; W25QXX_MAX_DIES],
+            die: 0,
+            continuous_read: None,
+            #[cfg(feature = "qpi")]
+            qpi: false,
+            powered_down: false,
+            active: false,
+            idle_since_ms: 0,
+            #[cfg(feature = "suspend")]
+            suspended: [false; W25QXX_MAX_DIES],
+            #[cfg(feature = "suspend")]
+            erase_address: [0; W25QXX_MAX_DIES],
+            config,
+            integrity_check: None,
+            integrity_hook: None,
+            progress_hook: None,
+            power_control: None,
+            clock_control: None,
+            spi_clock_hz: None,
+            multi_io,
+            integrity_report: None,
+            protected_ranges: [const { None }; MAX_PROTECTED_RANGES],
+            state: PhantomData,
+        }
+    }
+
+    /// Makes [`init`](Self::init) check that `len` bytes at `address` hash to
+    /// `expected` (CRC32), so corrupted critical data is detected at boot.
+    pub fn verify_on_init(&mut self, address: u32, len: usize, expected: u32) {
+        self.integrity_check = Some(IntegrityCheck { address, len, expected });
+    }
+
+    /// Registers a hook called with the result of the startup integrity
+    /// check, whether it passed or not.
+    pub fn on_integrity_report(&mut self, hook: fn(&IntegrityReport)) {
+        self.integrity_hook = Some(hook);
+    }
+
+    /// Identifies and resets the chip, then runs the integrity check
+    /// registered with [`verify_on_init`](Self::verify_on_init).
+    ///
+    /// A failed integrity check does not fail `init()`: the driver is ready
+    /// and the outcome is available from
+    /// [`integrity_report`](W25qxx::integrity_report).
+    /* The error hands the whole driver back, it is large by design */
+    #[allow(clippy::result_large_err)]
+    pub fn init(mut self) -> Result<W25qxx<SPI, D, Ready>, InitError<SPI, D>> {
+        /* A chip still in continuous read mode takes the Read ID for an address */
+        if let Err(error) = self.reset_continuous_read() {
+            return Err(InitError { flash: self, error });
+        }
+
+        /* and one left in deep power-down by the previous run ignores it */
+        if let Err(error) = self.release_power_down() {
+            return Err(InitError { flash: self, error });
+        }
+
+        if let Err(error) = self.with_recovery(|flash| flash.read_jedec_register()) {
+            return Err(InitError { flash: self, error });
+        }
+
+        diag!(info, "W25QXX - Identification OK");
+
+        if self.config.use_sfdp {
+            match self.read_sfdp() {
+                Ok(Some(sfdp)) => {
+                    /* A capacity code the ID decodes to wins over a disagreeing table */
+                    let dies = self.params.dies.max(1) as usize;
+                    let jedec = capacity_from_id(self.params.device_id as u8).map(|capacity| capacity * dies);
+                    sfdp.apply(&mut self.params);
+                    if let Some(capacity) = jedec.filter(|capacity| *capacity != self.params.capacity) {
+                        diag!(warn, "W25QXX - SFDP density {} bytes disagrees with the ID, keeping {} bytes", self.params.capacity, capacity);
+                        self.params.capacity = capacity;
+                    }
+                    sfdp.apply_dummy_cycles(&mut self.config.dummy_cycles);
+                }
+                Ok(None) => {
+                    diag!(warn, "W25QXX - No SFDP table, keeping the configured parameters");
+                }
+                Err(error) => return Err(InitError { flash: self, error }),
+            }
+        }
+
+        if let Err(error) = self.reset_when_idle(true) {
+            return Err(InitError { flash: self, error });
+        }
+
+        diag!(debug, "W25QXX - Reset OK");
+
+        if let Err(error) = self.enter_address_mode() {
+            return Err(InitError { flash: self, error });
+        }
+
+        if self.config.read_mode.data_lines() == Lines::Quad {
+            if let Err(error) = self.enable_quad() {
+                return Err(InitError { flash: self, error });
+            }
+        }
+
+        if let Some(wrap) = self.config.burst_wrap {
+            if let Err(error) = self.burst_wrap_command(Some(wrap)) {
+                return Err(InitError { flash: self, error });
+            }
+        }
+
+        #[cfg(feature = "qpi")]
+        if self.config.qpi {
+            if let Err(error) = self.enter_qpi() {
+                return Err(InitError { flash: self, error });
+            }
+        }
+
+        let mut flash: W25qxx<SPI, D, Ready> = self.into_state();
+
+        if let Some(check) = flash.integrity_check {
+            let found = match flash.digest(check.address, check.len, Crc32::new()) {
+                Ok(found) => found,
+                Err(error) => return Err(InitError { flash: flash.into_state(), error }),
+            };
+            let report = IntegrityReport { check, found };
+
+            if let Some(hook) = flash.integrity_hook {
+                hook(&report);
+            }
+            if report.is_ok() {
+                diag!(info, "W25QXX - Integrity check OK");
+            } else {
+                diag!(warn, "W25QXX - Integrity check FAILED at 0x{:08X}", check.address);
+            }
+
+            flash.integrity_report = Some(report);
+        }
+
+        diag!(info, "W25QXX - Initialized OK");
 
         Ok(flash)
     }
+}
 
-    pub fn init(&mut self) -> Result<(), Error<()>> {
-        self.read_jedec_register()?;
+impl<SPI, D> W25qxx<SPI, D, Ready>
+where
+    SPI: SpiDevice,
+    D: DelayNs,
+{
+    /// Outcome of the startup integrity check, if one was registered.
+    pub fn integrity_report(&self) -> Option<&IntegrityReport> {
+        self.integrity_report.as_ref()
+    }
+
+    /// Turns [`Config::verify_after_write`] on or off, for instance only
+    /// around the programming of a firmware image.
+    pub fn set_verify_after_write(&mut self, enabled: bool) {
+        self.config.verify_after_write = enabled;
+    }
 
-        println!("W25QXX - Identification OK");
+    /// Changes the address width, entering or leaving 4-byte address mode
+    /// (0xB7/0xE9) as needed. Leaving it hands the chip over in the mode a
+    /// boot ROM expects.
+    pub fn set_address_width(&mut self, width: AddressWidth) -> Result<(), Error<SPI::Error>> {
+        let was_four_byte_mode = self.four_byte_mode();
+        self.config.address_width = width;
+
+        match (was_four_byte_mode, self.four_byte_mode()) {
+            (false, true) => self.address_mode_command(self.params.opcodes.enter_4byte_address),
+            (true, false) => self.address_mode_command(self.params.opcodes.exit_4byte_address),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn read(&mut self, address: u32, buffer: &mut [u8]) -> Result<(), Error<SPI::Error>> {
+        diag!(trace, "W25QXX - read 0x{:08X} len {}", address, buffer.len());
+
+        self.with_recovery(|flash| flash.read_once(address, buffer))
+    }
+
+    /// Switches the command `read()` uses. Dual and quad modes need a
+    /// [`MultiIoTransfer`]; the quad ones set the Quad Enable bit first. In
+    /// QPI mode the choice takes effect after [`exit_qpi`](Self::exit_qpi).
+    pub fn set_read_mode(&mut self, mode: ReadMode) -> Result<(), Error<SPI::Error>> {
+        if mode.data_lines() != Lines::Single && self.multi_io.is_none() {
+            return Err(Error::Unsupported);
+        }
+        if mode.data_lines() == Lines::Quad && self.config.read_mode.data_lines() != Lines::Quad {
+            self.enable_quad()?;
+        }
 
-        self.reset()?;
+        self.config.read_mode = mode;
+        Ok(())
+    }
+
+    /// Read Data (0x03) whatever the read mode: no dummy byte, for SPI
+    /// clocks below about 33 MHz. Not available in QPI mode.
+    pub fn slow_read(&mut self, address: u32, buffer: &mut [u8]) -> Result<(), Error<SPI::Error>> {
+        #[cfg(feature = "qpi")]
+        if self.qpi {
+            return Err(Error::Unsupported);
+        }
 
-        println!("W25QXX - Reset OK");
-        println!("W25QXX - Initialized OK");
+        diag!(trace, "W25QXX - slow read 0x{:08X} len {}", address, buffer.len());
+
+        self.with_recovery(|flash| flash.read_in_mode(ReadMode::Normal, address, buffer))
+    }
+
+    /// Sets or clears the burst wrap of Fast Read Quad I/O (Set Burst with
+    /// Wrap, 0x77). Needs [`ReadMode::QuadIo`]; not available in QPI mode.
+    pub fn set_burst_wrap(&mut self, wrap: Option<BurstWrap>) -> Result<(), Error<SPI::Error>> {
+        if self.read_mode() != ReadMode::QuadIo {
+            return Err(Error::Unsupported);
+        }
 
+        self.burst_wrap_command(wrap)?;
+        self.config.burst_wrap = wrap;
         Ok(())
     }
 
-    pub fn read(&mut self, address: u32, buffer: &mut [u8]) -> Result<(), Error<()>> {
-        self.fast_read(address, buffer)
+    /// Cache line fill: one Fast Read Quad I/O returning the bytes of the
+    /// wrap-aligned line holding `address`, starting at `address` and
+    /// wrapping to the start of the line. `buffer` holds at most one line.
+    pub fn read_wrapped(&mut self, address: u32, buffer: &mut [u8]) -> Result<(), Error<SPI::Error>> {
+        let Some(wrap) = self.config.burst_wrap.filter(|_| self.read_mode() == ReadMode::QuadIo) else {
+            return Err(Error::Unsupported);
+        };
+        if buffer.is_empty() || buffer.len() > wrap.line_size() {
+            return Err(Error::InvalidArgument);
+        }
+        if address as usize >= self.params.capacity {
+            return Err(Error::InvalidAddress);
+        }
+
+        diag!(trace, "W25QXX - wrapped read 0x{:08X} len {}", address, buffer.len());
+
+        self.with_recovery(|flash| {
+            let die_addr = flash.select_die_at(address)?;
+            flash.busy_wait()?;
+            flash.quad_io_read(die_addr, buffer)
+        })
     }
+
     
-    pub fn write(&mut self, address: u32, buffer: &[u8]) -> Result<(), Error<()>> {
+    /// Streams `len` bytes starting at `address` through `hasher` and returns
+    /// the resulting digest.
+    pub fn digest<C: Checksum>(&mut self, address: u32, len: usize, mut hasher: C) -> Result<C::Output, Error<SPI::Error>> {
+        let mut chunk: [u8; W25QXX_PAGE_SIZE] = [0; W25QXX_PAGE_SIZE];
+        let mut offset: usize = 0;
+
+        while offset < len {
+            let chunk_size = core::cmp::min(len - offset, chunk.len());
+            self.read(address + offset as u32, &mut chunk[..chunk_size])?;
+            hasher.update(&chunk[..chunk_size]);
+            offset += chunk_size;
+        }
+
+        Ok(hasher.finalize())
+    }
+
+    /// Checks the digest of a region against `expected`.
+    pub fn verify<C: Checksum>(&mut self, address: u32, len: usize, hasher: C, expected: &C::Output) -> Result<bool, Error<SPI::Error>> {
+        Ok(self.digest(address, len, hasher)? == *expected)
+    }
+
+    /// Address of the first occurrence of `needle` inside `range`, scanning
+    /// the flash in chunks. `needle` may be up to one page long.
+    pub fn find(&mut self, range: Range<u32>, needle: &[u8]) -> Result<Option<u32>, Error<SPI::Error>> {
+        if needle.is_empty() || needle.len() > W25QXX_PAGE_SIZE {
+            return Err(Error::InvalidArgument);
+        }
+
+        let mut chunk: [u8; SEARCH_CHUNK_SIZE] = [0; SEARCH_CHUNK_SIZE];
+        let mut start = range.start;
+
+        while range.end > start && (range.end - start) as usize >= needle.len() {
+            let chunk_size = core::cmp::min(SEARCH_CHUNK_SIZE, (range.end - start) as usize);
+            self.read(start, &mut chunk[..chunk_size])?;
+
+            if let Some(offset) = chunk[..chunk_size].windows(needle.len()).position(|window| window == needle) {
+                return Ok(Some(start + offset as u32));
+            }
+
+            /* Keep the tail so a match straddling two chunks is still found */
+            start += (chunk_size - (needle.len() - 1)) as u32;
+        }
+
+        Ok(None)
+    }
+
+    /// Address of the last occurrence of `needle` inside `range`, scanning
+    /// backwards from the end of the range.
+    pub fn rfind(&mut self, range: Range<u32>, needle: &[u8]) -> Result<Option<u32>, Error<SPI::Error>> {
+        if needle.is_empty() || needle.len() > W25QXX_PAGE_SIZE {
+            return Err(Error::InvalidArgument);
+        }
+
+        let mut chunk: [u8; SEARCH_CHUNK_SIZE] = [0; SEARCH_CHUNK_SIZE];
+        let mut end = range.end;
+
+        while end > range.start && (end - range.start) as usize >= needle.len() {
+            let chunk_size = core::cmp::min(SEARCH_CHUNK_SIZE, (end - range.start) as usize);
+            let start = end - chunk_size as u32;
+            self.read(start, &mut chunk[..chunk_size])?;
+
+            if let Some(offset) = chunk[..chunk_size].windows(needle.len()).rposition(|window| window == needle) {
+                return Ok(Some(start + offset as u32));
+            }
+
+            end = start + (needle.len() - 1) as u32;
+            if start == range.start {
+                break;
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Reads the region `attempts` times and reports the bytes that differed
+    /// between passes. `buffer` holds the first pass. Fluctuating data points
+    /// at signal integrity problems rather than at what was programmed.
+    pub fn read_stable(&mut self, address: u32, buffer: &mut [u8], attempts: usize) -> Result<ReadConsistency, Error<SPI::Error>> {
+        if attempts < 2 {
+            return Err(Error::InvalidArgument);
+        }
+
+        self.read(address, buffer)?;
+
+        let mut consistency = ReadConsistency::default();
+        let mut chunk: [u8; W25QXX_PAGE_SIZE] = [0; W25QXX_PAGE_SIZE];
+
+        for (index, first_pass) in buffer.chunks(W25QXX_PAGE_SIZE).enumerate() {
+            let chunk_address = address + (index * W25QXX_PAGE_SIZE) as u32;
+            let mut changed: [bool; W25QXX_PAGE_SIZE] = [false; W25QXX_PAGE_SIZE];
+
+            /* Every chunk is re-read by all passes so a byte is only counted once */
+            for _ in 1..attempts {
+                self.read(chunk_address, &mut chunk[..first_pass.len()])?;
+                for ((flag, old), new) in changed.iter_mut().zip(first_pass).zip(chunk.iter()) {
+                    *flag |= old != new;
+                }
+            }
+
+            for (offset, _) in changed.iter().enumerate().filter(|(_, flag)| **flag) {
+                let byte_address = chunk_address + offset as u32;
+                consistency.fluctuating += 1;
+                consistency.first.get_or_insert(byte_address);
+                consistency.last = Some(byte_address);
+            }
+        }
+
+        Ok(consistency)
+    }
+
+    pub fn write(&mut self, address: u32, buffer: &[u8]) -> Result<(), Error<SPI::Error>> {
+        diag!(trace, "W25QXX - write 0x{:08X} len {}", address, buffer.len());
+        self.check_protected(address, buffer.len())?;
+
+        self.with_recovery(|flash| flash.write_once(address, buffer, 0, buffer.len()))
+    }
+
+    /// Programs `buffer` page by page. `done` and `total` place it in a
+    /// longer operation for the progress hook.
+    fn write_once(&mut self, address: u32, buffer: &[u8], done: usize, total: usize) -> Result<(), Error<SPI::Error>> {
+        self.check_bounds(address, buffer.len())?;
+
         /* Write size 1 Page */
         let page_size: usize = W25QXX_PAGE_SIZE; /* 256 Bytes */
         let mut size = buffer.len();
@@ -92,258 +865,2569 @@ where
             if size < write_size {
                 write_size = size;
             }
-            /* 3.- Execute write command */
-            self.busy_wait();
+            /* 3.- Select the die of the page and wait until it is idle */
+            let die_addr = self.select_die_at(addr)?;
+            self.busy_wait()?;
 
             /* 4.- Execute write command */
-            let _= self.page_program(addr, &buffer[offset..(offset + write_size)]);
+            self.page_program(die_addr, &buffer[offset..(offset + write_size)])?;
+
+            if self.config.verify_after_write {
+                self.verify_page(addr, &buffer[offset..(offset + write_size)])?;
+            }
 
             /* 5.- Update the offset and the remaining size */
             offset += write_size;
             size -= write_size;
+            self.report_progress(BusyOperation::PageProgram, addr, done + offset, total)?;
             addr += write_size as u32;
         }
 
         Ok(())
     }
     
-    pub fn erase(&mut self, address: u32, len: usize) -> Result<(), Error<()>>  {
-        let u_end:u32 = address + len as u32;
-        let mut size:usize = len;
-        let mut addr:u32 = address;
-
-        /* Check alignment to 512 */
-        if ((addr % W25QXX_SECTOR_SIZE as u32) != 0) || ((len % W25QXX_SECTOR_SIZE) != 0) {
-            return Err(Error::SPIError(()));
+    /// Programs `len` bytes at `address` with `pattern` repeated over and
+    /// over. Page buffers are generated on the fly, so no full-size source
+    /// buffer is needed. The region must be erased beforehand.
+    pub fn fill(&mut self, address: u32, len: usize, pattern: &[u8]) -> Result<(), Error<SPI::Error>> {
+        if pattern.is_empty() {
+            return Err(Error::InvalidArgument);
         }
-    
-        /* Loop until everything is erased  */
-        while addr < u_end {
-            let bytes_erase = size;
-
-            /* Erase 64K (64K Block) */
-            if ((addr % W25QXX_BLOCK64K_SIZE as u32) == 0) && (bytes_erase >= W25QXX_BLOCK64K_SIZE) {
-                self.busy_wait();
-                self.erase_cmd(addr, Command::Block64Erase as u8)?;
-                size -= W25QXX_BLOCK64K_SIZE;
-                addr += W25QXX_BLOCK64K_SIZE as u32;
-            }
-            /* Erase 32K (32K Block) */
-            else if ((addr % W25QXX_BLOCK32K_SIZE as u32) == 0) && (bytes_erase >= W25QXX_BLOCK32K_SIZE) {
-                self.busy_wait();
-                self.erase_cmd(addr, Command::Block32Erase as u8)?;
-                size -= W25QXX_BLOCK32K_SIZE;
-                addr += W25QXX_BLOCK32K_SIZE as u32;
-            }
-            /* Erase 4K (Sector) */
-            else if ((addr % W25QXX_SECTOR_SIZE as u32) == 0) && (bytes_erase >= W25QXX_SECTOR_SIZE) {
-                self.busy_wait();
-                self.erase_cmd(addr, Command::SectorErase as u8)?;
-                size -= W25QXX_SECTOR_SIZE;
-                addr += W25QXX_SECTOR_SIZE as u32;
-            } else {
-                /* Error, not aligned erase (we should never reach this point) */
-                return Err(Error::SPIError(()));
+        self.check_bounds(address, len)?;
+        self.check_protected(address, len)?;
+
+        let mut page: [u8; W25QXX_PAGE_SIZE] = [0; W25QXX_PAGE_SIZE];
+        let mut offset: usize = 0;
+
+        while offset < len {
+            let addr = address + offset as u32;
+            /* Stop each chunk at a page boundary so every write is a single program */
+            let chunk_size = core::cmp::min(W25QXX_PAGE_SIZE - (addr as usize % W25QXX_PAGE_SIZE), len - offset);
+
+            for (index, byte) in page[..chunk_size].iter_mut().enumerate() {
+                *byte = pattern[(offset + index) % pattern.len()];
             }
+
+            let chunk = &page[..chunk_size];
+            self.with_recovery(|flash| flash.write_once(addr, chunk, offset, len))?;
+            offset += chunk_size;
         }
-    
+
         Ok(())
     }
 
-    #[allow(dead_code)]
-    pub fn chip_erase(&mut self) -> Result<(), Error<()>> {
-        /* Check the BUSY bit and the SUS bit in Status Register
-        * before issuing the Reset command sequence */
-        /* Note: Not checking suspend, as it will not be used within this driver */
-        self.busy_wait();
-
-        /* Before Erase, write enable latch */
-        self.write_enable()?;
+    /// Writes `buffer` at `address` whatever the flash holds there, with
+    /// byte-level semantics. A sector whose current bytes can take the new
+    /// ones by clearing bits alone is programmed in place; any other touched
+    /// sector is read, merged, erased and programmed again. Uses a
+    /// sector-sized buffer on the stack. Fails with [`Error::Protected`] when
+    /// a touched sector overlaps a protected range, as it may be erased.
+    pub fn overwrite(&mut self, address: u32, buffer: &[u8]) -> Result<(), Error<SPI::Error>> {
+        diag!(trace, "W25QXX - overwrite 0x{:08X} len {}", address, buffer.len());
+        self.check_bounds(address, buffer.len())?;
+        if !buffer.is_empty() {
+            let start = address - address % W25QXX_SECTOR_SIZE as u32;
+            let end = (address as usize + buffer.len()).next_multiple_of(W25QXX_SECTOR_SIZE);
+            self.check_protected(start, end - start as usize)?;
+        }
 
-        let mut tx_cmd: [u8; 1] = [Command::ChipErase as u8];
+        let mut sector: [u8; W25QXX_SECTOR_SIZE] = [0; W25QXX_SECTOR_SIZE];
+        let mut offset: usize = 0;
 
-        self.spi_transmit_and_receive(&mut tx_cmd, &mut [], 0)
-    }
+        while offset < buffer.len() {
+            let addr = address + offset as u32;
+            let sector_address = addr - addr % W25QXX_SECTOR_SIZE as u32;
+            let start = (addr - sector_address) as usize;
+            let size = core::cmp::min(W25QXX_SECTOR_SIZE - start, buffer.len() - offset);
+            let new = &buffer[offset..offset + size];
 
-    fn read_jedec_register(&mut self) -> Result<(), Error<()>> {
-        let mut tx_cmd: [u8; 4] = [0; 4];
-        let mut rx_buffer: [u8; 2] = [0; 2];
+            let current = &mut sector[start..start + size];
+            self.read(addr, current)?;
 
-        tx_cmd[0] = Command::Jedec as u8;
+            if current.iter().zip(new).all(|(old, new)| old & new == *new) {
+                /* Only bits going 1->0: program in place, if anything changes */
+                if current != new {
+                    self.write(addr, new)?;
+                }
+            } else {
+                if start > 0 {
+                    self.read(sector_address, &mut sector[..start])?;
+                }
+                if start + size < W25QXX_SECTOR_SIZE {
+                    self.read(addr + size as u32, &mut sector[start + size..])?;
+                }
+                sector[start..start + size].copy_from_slice(new);
 
-        let _ = self.spi_transmit_and_receive(&mut tx_cmd, &mut rx_buffer, 0);
+                self.rewrite_sector(sector_address, &sector)?;
+            }
 
-        if rx_buffer[0] != W25QXX_MANID_VALUE || rx_buffer[1] != W25QXX_DEVID_VALUE_128 {
-            return Err(Error::SPIError(()));
+            offset += size;
         }
 
-        println!("W25QXX - Manufacture ID: 0x{:02X}", rx_buffer[0]);
-        println!("W25QXX - Device ID: 0x{:02X}", rx_buffer[1]);
-
         Ok(())
     }
 
-    fn reset(&mut self) -> Result<(), Error<()>> {
-        self.busy_wait();
-        self.spi.write(&[Command::EnableReset as u8]).unwrap();
-        self.spi.write(&[Command::Reset as u8]).unwrap();
-        Ok(())
-    }
+    /// Erases the whole sectors covering `[address, address + len)` and
+    /// returns the range actually erased. With `preserve`, the bytes of the
+    /// first and last sector outside the requested range are buffered and
+    /// programmed back, so only the requested bytes end up erased.
+    pub fn erase_unaligned(&mut self, address: u32, len: usize, preserve: bool) -> Result<Range<u32>, Error<SPI::Error>> {
+        let end = address.checked_add(len as u32).filter(|end| *end as usize <= self.params.capacity).ok_or(Error::InvalidAddress)?;
+        if len == 0 {
+            return Ok(address..address);
+        }
 
-    fn read_status_register(&mut self, reg_num: u8) -> Result<u8, Error<()>> {
-        let mut tx_cmd: [u8; 1] = [0; 1];
-        let mut rx_buffer: [u8; 1] = [0; 1];
-        if reg_num == 1 {
-            tx_cmd[0] = Command::ReadStatusRegister1 as u8;
-        } else if reg_num == 2 {
-            tx_cmd[0] = Command::ReadStatusRegister2 as u8;
-        } else {
-            return Err(Error::SPIError(()));
+        let sector_size = W25QXX_SECTOR_SIZE as u32;
+        let start = address - address % sector_size;
+        let stop = end.div_ceil(sector_size) * sector_size;
+        self.check_protected(start, (stop - start) as usize)?;
+
+        if !preserve {
+            self.erase(start, (stop - start) as usize)?;
+            return Ok(start..stop);
         }
 
-        self.spi_transmit_and_receive(&mut tx_cmd, &mut rx_buffer, 0)?;
+        let mut whole = start..stop;
 
-        Ok(rx_buffer[0])
+        /* The partial sectors at either end keep their other bytes */
+        if address != start {
+            let keep_end = core::cmp::min(end, start + sector_size);
+            self.erase_within_sector(start, (address - start) as usize..(keep_end - start) as usize)?;
+            whole.start = start + sector_size;
+        }
+        if end != stop && stop - sector_size >= whole.start {
+            self.erase_within_sector(stop - sector_size, 0..(end - (stop - sector_size)) as usize)?;
+            whole.end = stop - sector_size;
+        }
+        if whole.end > whole.start {
+            self.erase(whole.start, (whole.end - whole.start) as usize)?;
+        }
+
+        Ok(start..stop)
     }
 
-    fn is_busy(&mut self) -> Result<bool, Error<()>> {
-        Ok((self.read_status_register(1).unwrap() & StatusRegister::Busy as u8) != 0)
+    /// Erases `range` (offsets inside the sector) and keeps the rest of the
+    /// sector.
+    fn erase_within_sector(&mut self, sector_address: u32, range: Range<usize>) -> Result<(), Error<SPI::Error>> {
+        let mut sector: [u8; W25QXX_SECTOR_SIZE] = [0; W25QXX_SECTOR_SIZE];
+
+        self.read(sector_address, &mut sector)?;
+        sector[range].fill(0xFF);
+
+        self.rewrite_sector(sector_address, &sector)
     }
 
-    fn busy_wait(&mut self)  {
-        while self.is_busy().expect("Error read status register") {
-            thread::sleep(Duration::from_millis(1));
+    /// Erases the sector and programs `contents` into it, skipping the
+    /// pages that stay erased.
+    fn rewrite_sector(&mut self, sector_address: u32, contents: &[u8; W25QXX_SECTOR_SIZE]) -> Result<(), Error<SPI::Error>> {
+        self.erase(sector_address, W25QXX_SECTOR_SIZE)?;
+
+        for (index, page) in contents.chunks(W25QXX_PAGE_SIZE).enumerate() {
+            if page.iter().any(|byte| *byte != 0xFF) {
+                self.write(sector_address + (index * W25QXX_PAGE_SIZE) as u32, page)?;
+            }
         }
+
+        Ok(())
     }
 
-    fn is_write_enable(&mut self) -> bool {
-        // Leer el registro de estado
-        let status: u8 = self.read_status_register(1).unwrap();
+    /// Erases `len` bytes at `address`, both sector aligned, with the
+    /// largest erase units that fit. A range covering the whole chip is
+    /// erased with Chip Erase (0xC7).
+    pub fn erase(&mut self, address: u32, len: usize) -> Result<(), Error<SPI::Error>>  {
+        diag!(trace, "W25QXX - erase 0x{:08X} len {}", address, len);
+        self.check_protected(address, len)?;
 
-        // Comprobar si el bit de Write Enable está establecido
-        (status & StatusRegister::WriteEnable as u8) != 0
+        self.with_recovery(|flash| flash.erase_once(address, len))
     }
 
-    fn write_enable(&mut self) -> Result<(), Error<()>> {
-        let mut tx_cmd: [u8; 1] = [Command::WriteEnable as u8];
+    /// Erases `len` bytes at `address` like [`erase`](Self::erase), then
+    /// reads every byte back. Sectors that are not blank are erased again,
+    /// up to three times in all; the remaining sectors are still erased and
+    /// checked before the first one that stayed programmed is reported as
+    /// [`Error::EraseFailed`]. For data sanitization, where an erase
+    /// silently failing on a worn sector must not go unnoticed.
+    pub fn secure_erase(&mut self, address: u32, len: usize) -> Result<(), Error<SPI::Error>> {
+        self.erase(address, len)?;
+
+        let mut failed = None;
+        for sector in (address..address + len as u32).step_by(W25QXX_SECTOR_SIZE) {
+            if !self.retry_erase(sector)? {
+                failed.get_or_insert(sector);
+            }
+        }
 
-        self.spi_transmit_and_receive(&mut tx_cmd, &mut [], 0)?;
+        match failed {
+            Some(address) => Err(Error::EraseFailed { address }),
+            None => Ok(()),
+        }
+    }
 
-        if !self.is_write_enable() {
-            return Err(Error::SPIError(()));
+    /// Sanitizes `len` bytes at `address`, both sector aligned: every sector
+    /// is programmed with data from `fill` (a random number generator), then
+    /// erased and blank-checked like [`secure_erase`](Self::secure_erase)
+    /// does. Stricter destruction policies ask for the overwrite, so old
+    /// contents cannot be told from an erase that left charge behind.
+    ///
+    /// The [`on_progress`](Self::on_progress) hook is called once per sector
+    /// with [`BusyOperation::SectorErase`] and the bytes sanitized so far;
+    /// after `Break`, [`Error::Cancelled`] tells where to continue. Sectors
+    /// that would not erase are reported as for `secure_erase`.
+    pub fn sanitize(&mut self, address: u32, len: usize, mut fill: impl FnMut(&mut [u8])) -> Result<(), Error<SPI::Error>> {
+        if !address.is_multiple_of(W25QXX_SECTOR_SIZE as u32) || !len.is_multiple_of(W25QXX_SECTOR_SIZE) {
+            return Err(Error::NotAligned);
         }
+        address.checked_add(len as u32).filter(|end| *end as usize <= self.params.capacity).ok_or(Error::InvalidAddress)?;
+        self.check_protected(address, len)?;
 
-        Ok(())
+        /* Progress is reported per sector, not by the write and erase below */
+        let hook = self.progress_hook.take();
+        let result = self.sanitize_sectors(hook, address, len, &mut fill);
+        self.progress_hook = hook;
+
+        match result? {
+            Some(address) => Err(Error::EraseFailed { address }),
+            None => Ok(()),
+        }
     }
 
-    fn spi_transmit(&mut self, cmd: u8, address: u32, tx_buffer: &[u8]) -> Result<(), Error<()>> {
-        let mut tx_cmd: [u8; 4] = [0; 4];
+    /// Programs, erases and checks each sector of the range, reporting
+    /// progress through `hook`. The first sector that would not erase.
+    fn sanitize_sectors(
+        &mut self,
+        hook: Option<ProgressHook>,
+        address: u32,
+        len: usize,
+        fill: &mut impl FnMut(&mut [u8]),
+    ) -> Result<Option<u32>, Error<SPI::Error>> {
+        let mut sector_data: [u8; W25QXX_SECTOR_SIZE] = [0; W25QXX_SECTOR_SIZE];
+        let mut failed = None;
+
+        for sector in (address..address + len as u32).step_by(W25QXX_SECTOR_SIZE) {
+            fill(&mut sector_data);
+            self.write(sector, &sector_data)?;
+            self.erase(sector, W25QXX_SECTOR_SIZE)?;
+            if !self.retry_erase(sector)? {
+                failed.get_or_insert(sector);
+            }
 
-        tx_cmd[0] = cmd;
-        tx_cmd[1] = ((address >> 16) & 0xFF) as u8;
-        tx_cmd[2] = ((address >> 8) & 0xFF) as u8;
-        tx_cmd[3] = ((address) & 0xFF) as u8;
+            self.progress_hook = hook;
+            let reported = self.report_progress(BusyOperation::SectorErase, sector, (sector - address) as usize + W25QXX_SECTOR_SIZE, len);
+            self.progress_hook = None;
+            reported?;
+        }
 
-        /* Chip select low */
-        let _ = self.cs.set_low();
+        Ok(failed)
+    }
 
-        /* Send Command */
-        let write_result = self.spi.write(&mut tx_cmd);
+    /// Erases the freshly erased sector at `sector` again until it reads
+    /// blank, [`SECURE_ERASE_ATTEMPTS`] erases in all. Whether it did.
+    fn retry_erase(&mut self, sector: u32) -> Result<bool, Error<SPI::Error>> {
+        let mut attempts = 1;
 
-        /* Send Bytes */
-        match write_result {
-            Ok(size) => {
-                // Comprobar si se escribió algún byte
-                if size > 0 && !tx_buffer.is_empty() {
-                    // Solo escribir el buffer si tiene tamaño
-                    self.spi.write(tx_buffer).unwrap();
-                }
-            }
-            Err(_e) => {
-                return Err(Error::SPIError(()));
+        while !self.is_blank(sector, W25QXX_SECTOR_SIZE)? {
+            if attempts == SECURE_ERASE_ATTEMPTS {
+                diag!(warn, "W25QXX - sector 0x{:08X} does not erase", sector);
+                return Ok(false);
             }
+            self.erase(sector, W25QXX_SECTOR_SIZE)?;
+            attempts += 1;
         }
 
-        /* Chip select high */
-        let _ = self.cs.set_high();
-
-        Ok(())
+        Ok(true)
     }
 
-    fn spi_transmit_and_receive(&mut self, tx_buffer: &mut [u8], rx_buffer: &mut [u8], dummy_bytes: usize) -> Result<(), Error<()>> {
-        /* Chip select low */
-        let _ = self.cs.set_low();
+    /// Whether all `len` bytes at `address` read as 0xFF.
+    pub(crate) fn is_blank(&mut self, address: u32, len: usize) -> Result<bool, Error<SPI::Error>> {
+        let mut chunk: [u8; W25QXX_PAGE_SIZE] = [0; W25QXX_PAGE_SIZE];
 
-        /* Send Bytes */
-        if !tx_buffer.is_empty() {
-            self.spi.write(tx_buffer).unwrap();
+        for offset in (0..len).step_by(W25QXX_PAGE_SIZE) {
+            let chunk = &mut chunk[..W25QXX_PAGE_SIZE.min(len - offset)];
+            self.read(address + offset as u32, chunk)?;
+            if chunk.iter().any(|byte| *byte != 0xFF) {
+                return Ok(false);
+            }
         }
 
-        /* Send Bytes */
-        if dummy_bytes > 0 {
-            let dummy_buffer: [u8; 1] = [0x00; 1];
-            self.spi.write(&dummy_buffer).unwrap();
-        }
+        Ok(true)
+    }
 
-        // Receive bytes
-        if !rx_buffer.is_empty() {
-            self.spi.read(rx_buffer).unwrap();
+    fn erase_once(&mut self, address: u32, len: usize) -> Result<(), Error<SPI::Error>> {
+        /* The whole chip: one Chip Erase beats thousands of block erases */
+        if address == 0 && len == self.params.capacity {
+            return self.chip_erase_once();
         }
+        self.check_bounds(address, len)?;
 
-        /* Chip select high */
-        let _ = self.cs.set_high();
+        let u_end:u32 = address + len as u32;
+        let mut size:usize = len;
+        let mut addr:u32 = address;
 
+        /* Check alignment to 512 */
+        if !addr.is_multiple_of(W25QXX_SECTOR_SIZE as u32) || !len.is_multiple_of(W25QXX_SECTOR_SIZE) {
+            return Err(Error::NotAligned);
+        }
+    
+        /* Loop until everything is erased  */
+        while addr < u_end {
+            /* Erase 64K, 32K or 4K depending on alignment and remaining size */
+            let Some((cmd, erase_size)) = erase_step(&self.opcodes(), addr, size, !self.config.four_byte_opcodes) else {
+                /* Error, not aligned erase (we should never reach this point) */
+                return Err(Error::NotAligned);
+            };
+
+            /* Erase units never straddle a die */
+            let die_addr = self.select_die_at(addr)?;
+            self.busy_wait()?;
+            self.erase_cmd(die_addr, cmd)?;
+            size -= erase_size;
+            self.report_progress(self.pending[self.die as usize], addr, len - size, len)?;
+            addr += erase_size as u32;
+        }
+    
         Ok(())
     }
 
-    fn page_program(&mut self, address: u32, tx_buffer: &[u8]) -> Result<(), Error<()>> {
-        /* Argument check */
-        if tx_buffer.is_empty() || tx_buffer.len() == 0 || tx_buffer.len() > W25QXX_PAGE_SIZE {
-            return Err(Error::SPIError(()));
-        }
+    /// Reads back a freshly programmed page and compares it with `expected`.
+    fn verify_page(&mut self, address: u32, expected: &[u8]) -> Result<(), Error<SPI::Error>> {
+        let mut readback: [u8; W25QXX_PAGE_SIZE] = [0; W25QXX_PAGE_SIZE];
 
-        /* Before program enable write enable latch */
-        self.write_enable()?;
+        self.busy_wait()?;
+        self.read_once(address, &mut readback[..expected.len()])?;
+
+        if let Some(offset) = readback.iter().zip(expected).position(|(read, written)| read != written) {
+            return Err(Error::VerifyFailed { address: address + offset as u32 });
+        }
 
-        self.spi_transmit(Command::PageProgram as u8, address, tx_buffer)
+        Ok(())
     }
 
     #[allow(dead_code)]
-    fn slow_read(&mut self, address: u32, rx_buffer: &mut [u8]) -> Result<(), Error<()>> {
-        let mut tx_cmd: [u8; 4] = [0; 4];
+    pub fn chip_erase(&mut self) -> Result<(), Error<SPI::Error>> {
+        diag!(trace, "W25QXX - chip erase");
+        self.check_protected(0, self.params.capacity)?;
 
-        if rx_buffer.is_empty() || rx_buffer.len() == 0 {
-            return Err(Error::SPIError(()));
-        }
+        self.with_recovery(|flash| flash.chip_erase_once())
+    }
 
-        tx_cmd[0] = Command::ReadData as u8;
-        tx_cmd[1] = ((address >> 16) & 0xFF) as u8;
-        tx_cmd[2] = ((address >> 8) & 0xFF) as u8;
-        tx_cmd[3] = ((address) & 0xFF) as u8;
+    /// Stacked dies only erase themselves: every die is started in turn and
+    /// they erase in parallel.
+    fn chip_erase_once(&mut self) -> Result<(), Error<SPI::Error>> {
+        self.for_each_die(|flash| {
+            /* A suspended operation makes the write enable below fail */
+            flash.busy_wait()?;
 
-        self.spi_transmit_and_receive(&mut tx_cmd, rx_buffer, 0)
+            /* Before Erase, write enable latch */
+            flash.write_enable()?;
+
+            let mut tx_cmd: [u8; 1] = [flash.params.opcodes.chip_erase];
+
+            flash.pending[flash.die as usize] = BusyOperation::ChipErase;
+            flash.spi_transmit_and_receive(&mut tx_cmd, &mut [], 0)
+        })
+    }
+
+    /// Value of status register `reg_num` (1 to 3). Stacked-die parts
+    /// answer for die 0.
+    pub fn status_register(&mut self, reg_num: u8) -> Result<u8, Error<SPI::Error>> {
+        self.die_select(0)?;
+        self.read_status_register(reg_num)
+    }
+
+    /// Write Status Register 1, 2 or 3 (0x01, 0x31, 0x11) with `value` on
+    /// every die, with Write Enable before and waiting for the write cycle
+    /// after. The bits are non-volatile; read-only bits such as BUSY, WEL
+    /// and SUS ignore what is written.
+    pub fn write_status_register(&mut self, reg_num: u8, value: u8) -> Result<(), Error<SPI::Error>> {
+        diag!(debug, "W25QXX - write status register {} 0x{:02X}", reg_num, value);
+
+        self.with_recovery(|flash| flash.for_each_die(|flash| flash.write_status(reg_num, value, false)))
+    }
+
+    /// Like [`write_status_register`](Self::write_status_register), but
+    /// preceded by Write Enable for Volatile Status Register (0x50): the new
+    /// value only lasts until the next power cycle and does not wear the
+    /// non-volatile cells, for QE or protection bits changed at every boot.
+    pub fn write_status_register_volatile(&mut self, reg_num: u8, value: u8) -> Result<(), Error<SPI::Error>> {
+        diag!(debug, "W25QXX - write volatile status register {} 0x{:02X}", reg_num, value);
+
+        self.with_recovery(|flash| flash.for_each_die(|flash| flash.write_status(reg_num, value, true)))
+    }
+
+    /// Status register protection, from SRP and SRL of die 0.
+    pub fn status_protection(&mut self) -> Result<StatusProtection, Error<SPI::Error>> {
+        self.status().map(|status| status.status_protection())
+    }
+
+    /// Sets SRP on every die for [`StatusProtection::Software`] and
+    /// [`StatusProtection::Hardware`]. [`StatusProtection::PowerSupplyLockDown`]
+    /// sets SRL with a volatile write, so the lock ends at the next power
+    /// cycle and can never become permanent.
+    ///
+    /// In hardware protection the chip ignores status register writes while
+    /// WP# is low; [`WriteProtected`](crate::WriteProtected) drives the pin.
+    pub fn set_status_protection(&mut self, protection: StatusProtection) -> Result<(), Error<SPI::Error>> {
+        diag!(debug, "W25QXX - status protection {:?}", protection);
+
+        self.with_recovery(|flash| {
+            flash.for_each_die(|flash| match protection {
+                StatusProtection::PowerSupplyLockDown => {
+                    let status2 = flash.read_status_register(2)?;
+                    flash.write_status(2, status2 | STATUS2_SRL, true)
+                }
+                StatusProtection::Software | StatusProtection::Hardware => {
+                    let status1 = flash.read_status_register(1)?;
+                    let srp = if protection == StatusProtection::Hardware { STATUS1_SRP } else { 0 };
+                    flash.write_status(1, (status1 & !STATUS1_SRP) | srp, false)
+                }
+            })
+        })
+    }
+
+    /// Whether the Quad Enable bit is set on die 0. While it is, WP# and
+    /// HOLD# are data lines and their protection functions are off.
+    pub fn quad_enabled(&mut self) -> Result<bool, Error<SPI::Error>> {
+        let (reg_num, qe) = self.quad_enable_bit();
+        Ok(self.status_register(reg_num)? & qe != 0)
+    }
+
+    /// Status register 1: busy, write enable latch, block protection.
+    pub fn status1(&mut self) -> Result<StatusRegister1, Error<SPI::Error>> {
+        let die_size = self.params.die_size();
+        self.status_register(1).map(|bits| StatusRegister1::from_bits(bits, die_size))
+    }
+
+    /// Status register 2: Quad Enable, security register locks, CMP, SUS.
+    pub fn status2(&mut self) -> Result<StatusRegister2, Error<SPI::Error>> {
+        self.status_register(2).map(StatusRegister2::from)
+    }
+
+    /// Status register 3: protection scheme, drive strength and the
+    /// power-up address mode.
+    pub fn status3(&mut self) -> Result<StatusRegister3, Error<SPI::Error>> {
+        self.status_register(3).map(StatusRegister3::from)
+    }
+
+    /// Snapshot of all three status registers of die 0.
+    pub fn status(&mut self) -> Result<Status, Error<SPI::Error>> {
+        Ok(Status { status1: self.status1()?, status2: self.status2()?, status3: self.status3()? })
+    }
+
+    /// Writes status register 3 on every die. Change one field of what
+    /// [`status3`](Self::status3) returns to keep the others.
+    pub fn write_status3(&mut self, status: StatusRegister3) -> Result<(), Error<SPI::Error>> {
+        self.write_status_register(3, status.into())
+    }
+
+    /// Forbids writes and erases overlapping `range`, whatever the block
+    /// protect bits say: they fail with [`Error::Protected`] before any
+    /// command is sent. Meant for areas such as the bootloader that the
+    /// application never changes. Held by the driver, not the chip.
+    /// [`Error::InvalidArgument`] for an empty range or when
+    /// [`MAX_PROTECTED_RANGES`] are registered already.
+    pub fn add_protected_range(&mut self, range: Range<u32>) -> Result<(), Error<SPI::Error>> {
+        if range.is_empty() {
+            return Err(Error::InvalidArgument);
+        }
+
+        let slot = self.protected_ranges.iter_mut().find(|slot| slot.is_none()).ok_or(Error::InvalidArgument)?;
+        *slot = Some(range);
+        Ok(())
+    }
+
+    /// Drops every range registered with
+    /// [`add_protected_range`](Self::add_protected_range).
+    pub fn clear_protected_ranges(&mut self) {
+        self.protected_ranges = [const { None }; MAX_PROTECTED_RANGES];
+    }
+
+    pub fn protected_ranges(&self) -> impl Iterator<Item = Range<u32>> + '_ {
+        self.protected_ranges.iter().flatten().cloned()
+    }
+
+    /// Fails with [`Error::Protected`] when `[address, address + len)`
+    /// overlaps a registered range.
+    fn check_protected(&self, address: u32, len: usize) -> Result<(), Error<SPI::Error>> {
+        let end = address as u64 + len as u64;
+        if len > 0 && self.protected_ranges().any(|range| (range.start as u64) < end && address < range.end) {
+            return Err(Error::Protected);
+        }
+
+        Ok(())
+    }
+
+    /// Write-protects exactly `[address, address + len)` with the block
+    /// protect bits, and unprotects everything else. The range has to be one
+    /// the BP/TB/SEC/CMP matrix of the part can express: an upper or lower
+    /// fraction of the array (or of one die), a few sectors at either end,
+    /// or everything but those. Other ranges fail with
+    /// [`Error::InvalidArgument`].
+    ///
+    /// Only Winbond parts are supported. The bits are ignored while WPS (in
+    /// [`StatusRegister3`]) selects the individual block locks.
+    pub fn protect_range(&mut self, address: u32, len: usize) -> Result<(), Error<SPI::Error>> {
+        if !matches!(self.device_info().family, Family::W25q | Family::W25m) {
+            return Err(Error::Unsupported);
+        }
+        if address as usize + len > self.params.capacity {
+            return Err(Error::InvalidAddress);
+        }
+
+        let die_size = self.params.die_size();
+        let die = (address as usize / die_size) as u8;
+        let die_address = address % die_size as u32;
+        let range = die_address..die_address + len as u32;
+        if range.end as usize > die_size {
+            return Err(Error::InvalidArgument);
+        }
+
+        let protection = BlockProtection::for_range(die_size, range).ok_or(Error::InvalidArgument)?;
+        diag!(debug, "W25QXX - protect 0x{:08X} len {}: {:?}", address, len, protection);
+
+        self.with_recovery(|flash| {
+            flash.for_each_die(|flash| {
+                let bits = if flash.die == die || len == 0 { protection } else { BlockProtection::NONE };
+                flash.write_block_protection(bits)
+            })
+        })
+    }
+
+    /// Clears the block protect bits on every die.
+    pub fn unprotect_all(&mut self) -> Result<(), Error<SPI::Error>> {
+        self.protect_range(0, 0)
+    }
+
+    /// Block protect bits currently set, of die 0 on stacked-die parts.
+    /// [`BlockProtection::range`] turns them into the protected range.
+    pub fn block_protection(&mut self) -> Result<BlockProtection, Error<SPI::Error>> {
+        let status1 = self.status_register(1)?;
+        let status2 = self.read_status_register(2)?;
+
+        Ok(BlockProtection::from_status(self.params.die_size(), status1, status2))
+    }
+
+    /// Calls `protected` with every write-protected range of the array, in
+    /// ascending order and with adjacent ranges of the same source merged.
+    /// Each die is decoded from its own status registers: the block protect
+    /// bits, or the individual lock of every lock unit while WPS is set.
+    ///
+    /// Reading the individual locks needs the `individual-locks` feature;
+    /// without it, a die with WPS set fails with [`Error::Unsupported`].
+    pub fn protection_map(&mut self, mut protected: impl FnMut(ProtectedRange)) -> Result<(), Error<SPI::Error>> {
+        let die_size = self.params.die_size();
+        let mut current: Option<ProtectedRange> = None;
+        let mut add = |range: Range<u32>, source: ProtectionSource| match current.as_mut() {
+            Some(last) if last.source == source && last.range.end == range.start => last.range.end = range.end,
+            _ => {
+                if let Some(last) = current.replace(ProtectedRange { range, source }) {
+                    protected(last);
+                }
+            }
+        };
+
+        for die in 0..self.params.dies.max(1) {
+            let base = die as u32 * die_size as u32;
+            self.die_select(die)?;
+            let status1 = self.read_status_register(1)?;
+            let status2 = self.read_status_register(2)?;
+            let status3 = StatusRegister3::from(self.read_status_register(3)?);
+
+            if !status3.wps {
+                let range = BlockProtection::from_status(die_size, status1, status2).range(die_size);
+                if !range.is_empty() {
+                    add(base + range.start..base + range.end, ProtectionSource::BlockProtect);
+                }
+                continue;
+            }
+
+            #[cfg(feature = "individual-locks")]
+            {
+                let mut unit = base;
+                while unit < base + die_size as u32 {
+                    let range = self.lock_unit(unit);
+                    if self.is_block_locked(unit)? {
+                        add(range.clone(), ProtectionSource::IndividualLock);
+                    }
+                    unit = range.end;
+                }
+            }
+            #[cfg(not(feature = "individual-locks"))]
+            return Err(Error::Unsupported);
+        }
+
+        if let Some(last) = current {
+            protected(last);
+        }
+        self.die_select(0)
+    }
+
+    /// First write-protected range overlapping `[address, address + len)`,
+    /// clipped to it, or `None` when the whole range can be programmed and
+    /// erased.
+    pub fn protected_overlap(&mut self, address: u32, len: usize) -> Result<Option<Range<u32>>, Error<SPI::Error>> {
+        if address as usize + len > self.params.capacity {
+            return Err(Error::InvalidAddress);
+        }
+
+        let end = address + len as u32;
+        let mut overlap = None;
+        self.protection_map(|protected| {
+            let start = protected.range.start.max(address);
+            let stop = protected.range.end.min(end);
+            if overlap.is_none() && start < stop {
+                overlap = Some(start..stop);
+            }
+        })?;
+
+        Ok(overlap)
+    }
+
+    /// Lock unit holding `address` for the individual block locks: a 4 KiB
+    /// sector in the lowest and the highest 64 KiB block of each die, a
+    /// 64 KiB block elsewhere.
+    #[cfg(feature = "individual-locks")]
+    pub fn lock_unit(&self, address: u32) -> Range<u32> {
+        let die_size = self.params.die_size() as u32;
+        let die_address = address % die_size;
+
+        let size = if die_address < W25QXX_BLOCK64K_SIZE as u32 || die_address >= die_size - W25QXX_BLOCK64K_SIZE as u32 {
+            W25QXX_SECTOR_SIZE as u32
+        } else {
+            W25QXX_BLOCK64K_SIZE as u32
+        };
+
+        let start = address - address % size;
+        start..start + size
+    }
+
+    /// Individual Block Lock (0x36) of the [`lock_unit`](Self::lock_unit)
+    /// holding `address`. The locks only protect while WPS is set in
+    /// [`StatusRegister3`]; they are volatile and all set again at power-up.
+    #[cfg(feature = "individual-locks")]
+    pub fn lock_block(&mut self, address: u32) -> Result<(), Error<SPI::Error>> {
+        let opcode = self.params.opcodes.individual_lock;
+        self.with_recovery(|flash| flash.block_lock_command(opcode, address))
+    }
+
+    /// Individual Block Unlock (0x39) of the unit holding `address`.
+    #[cfg(feature = "individual-locks")]
+    pub fn unlock_block(&mut self, address: u32) -> Result<(), Error<SPI::Error>> {
+        let opcode = self.params.opcodes.individual_unlock;
+        self.with_recovery(|flash| flash.block_lock_command(opcode, address))
+    }
+
+    /// Locks every lock unit overlapping `[address, address + len)`, for
+    /// example a bootloader region that has to stay as it is while the rest
+    /// of the array is written.
+    #[cfg(feature = "individual-locks")]
+    pub fn lock_range(&mut self, address: u32, len: usize) -> Result<(), Error<SPI::Error>> {
+        let opcode = self.params.opcodes.individual_lock;
+        self.for_each_lock_unit(address, len, |flash, unit| flash.block_lock_command(opcode, unit))
+    }
+
+    /// Unlocks every lock unit overlapping `[address, address + len)`.
+    #[cfg(feature = "individual-locks")]
+    pub fn unlock_range(&mut self, address: u32, len: usize) -> Result<(), Error<SPI::Error>> {
+        let opcode = self.params.opcodes.individual_unlock;
+        self.for_each_lock_unit(address, len, |flash, unit| flash.block_lock_command(opcode, unit))
+    }
+
+    /// Global Block Lock (0x7E): sets the individual lock of every unit on
+    /// every die in one command.
+    #[cfg(feature = "individual-locks")]
+    pub fn lock_all(&mut self) -> Result<(), Error<SPI::Error>> {
+        let opcode = self.params.opcodes.global_lock;
+        self.with_recovery(|flash| flash.for_each_die(|flash| flash.global_lock_command(opcode)))
+    }
+
+    /// Global Block Unlock (0x98): clears every individual lock on every
+    /// die.
+    #[cfg(feature = "individual-locks")]
+    pub fn unlock_all(&mut self) -> Result<(), Error<SPI::Error>> {
+        let opcode = self.params.opcodes.global_unlock;
+        self.with_recovery(|flash| flash.for_each_die(|flash| flash.global_lock_command(opcode)))
+    }
+
+    /// Read Block Lock (0x3D): whether the unit holding `address` is locked.
+    #[cfg(feature = "individual-locks")]
+    pub fn is_block_locked(&mut self, address: u32) -> Result<bool, Error<SPI::Error>> {
+        self.with_recovery(|flash| {
+            let (mut tx_cmd, cmd_len) = flash.lock_frame(flash.params.opcodes.read_block_lock, address)?;
+            let mut lock: [u8; 1] = [0; 1];
+
+            flash.spi_transmit_and_receive(&mut tx_cmd[..cmd_len], &mut lock, 0)?;
+            Ok(lock[0] & 0x01 != 0)
+        })
+    }
+
+    /// Runs `command` with the start of every lock unit overlapping
+    /// `[address, address + len)`.
+    #[cfg(feature = "individual-locks")]
+    fn for_each_lock_unit(
+        &mut self,
+        address: u32,
+        len: usize,
+        mut command: impl FnMut(&mut Self, u32) -> Result<(), Error<SPI::Error>>,
+    ) -> Result<(), Error<SPI::Error>> {
+        if address as usize + len > self.params.capacity {
+            return Err(Error::InvalidAddress);
+        }
+
+        let end = address + len as u32;
+        let mut unit = address;
+        while unit < end {
+            let range = self.lock_unit(unit);
+            self.with_recovery(|flash| command(flash, range.start))?;
+            unit = range.end;
+        }
+
+        Ok(())
+    }
+
+    /// Selects the die of `address` and builds the header of a lock
+    /// command. These have no 4-byte opcodes, so they take the address width
+    /// of the mode the chip is in.
+    #[cfg(feature = "individual-locks")]
+    fn lock_frame(&mut self, opcode: u8, address: u32) -> Result<([u8; 5], usize), Error<SPI::Error>> {
+        if address as usize >= self.params.capacity {
+            return Err(Error::InvalidAddress);
+        }
+
+        let die_addr = self.select_die_at(address)?;
+        let width = if self.four_byte_mode() { AddressWidth::Four } else { AddressWidth::Three };
+        if width == AddressWidth::Three && die_addr > 0x00FF_FFFF {
+            return Err(Error::Unsupported);
+        }
+
+        Ok(wide_address_frame(opcode, die_addr, width))
+    }
+
+    /// Global Block Lock or Unlock on the selected die.
+    #[cfg(feature = "individual-locks")]
+    fn global_lock_command(&mut self, opcode: u8) -> Result<(), Error<SPI::Error>> {
+        let mut tx_cmd: [u8; 1] = [opcode];
+
+        self.busy_wait()?;
+        self.write_enable()?;
+        self.spi_transmit_and_receive(&mut tx_cmd, &mut [], 0)
+    }
+
+    /// Individual Block Lock or Unlock of the unit at `address`.
+    #[cfg(feature = "individual-locks")]
+    fn block_lock_command(&mut self, opcode: u8, address: u32) -> Result<(), Error<SPI::Error>> {
+        let (mut tx_cmd, cmd_len) = self.lock_frame(opcode, address)?;
+
+        self.busy_wait()?;
+        self.write_enable()?;
+        self.spi_transmit_and_receive(&mut tx_cmd[..cmd_len], &mut [], 0)
+    }
+
+    /// Read Security Registers (0x48): `buffer.len()` bytes of security
+    /// register `register` (1 to 3) from `offset`. Stacked-die parts answer
+    /// with the registers of die 0.
+    #[cfg(feature = "security-registers")]
+    pub fn otp_read(&mut self, register: u8, offset: usize, buffer: &mut [u8]) -> Result<(), Error<SPI::Error>> {
+        let opcode = self.params.opcodes.read_security;
+
+        self.with_recovery(|flash| {
+            let (mut tx_cmd, cmd_len) = flash.security_frame(opcode, register, offset, buffer.len())?;
+
+            flash.busy_wait()?;
+            flash.spi_transmit_and_receive(&mut tx_cmd[..cmd_len], buffer, 1)
+        })
+    }
+
+    /// Program Security Registers (0x42): writes `data` into security
+    /// register `register` at `offset`. Like a page program it only clears
+    /// bits, so the register has to be erased before. Fails with
+    /// [`Error::Locked`] once the register is locked.
+    #[cfg(feature = "security-registers")]
+    pub fn otp_program(&mut self, register: u8, offset: usize, data: &[u8]) -> Result<(), Error<SPI::Error>> {
+        let opcode = self.params.opcodes.program_security;
+
+        self.with_recovery(|flash| {
+            let (tx_cmd, cmd_len) = flash.security_frame(opcode, register, offset, data.len())?;
+            if flash.otp_locked(register)? {
+                return Err(Error::Locked);
+            }
+
+            flash.busy_wait()?;
+            flash.write_enable()?;
+            flash.pending[flash.die as usize] = BusyOperation::PageProgram;
+            flash.spi_transmit_frame(&tx_cmd[..cmd_len], data)?;
+            flash.busy_wait()?;
+
+            if flash.config.verify_after_write {
+                let mut readback: [u8; W25QXX_SECURITY_REGISTER_SIZE] = [0; W25QXX_SECURITY_REGISTER_SIZE];
+                let (mut tx_cmd, cmd_len) = flash.security_frame(flash.params.opcodes.read_security, register, offset, data.len())?;
+                flash.spi_transmit_and_receive(&mut tx_cmd[..cmd_len], &mut readback[..data.len()], 1)?;
+                if let Some(index) = readback.iter().zip(data).position(|(read, written)| read != written) {
+                    return Err(Error::VerifyFailed { address: (offset + index) as u32 });
+                }
+            }
+
+            Ok(())
+        })
+    }
+
+    /// Erase Security Registers (0x44): sets all 256 bytes of `register`
+    /// back to 0xFF. Fails with [`Error::Locked`] once it is locked.
+    #[cfg(feature = "security-registers")]
+    pub fn otp_erase(&mut self, register: u8) -> Result<(), Error<SPI::Error>> {
+        let opcode = self.params.opcodes.erase_security;
+
+        self.with_recovery(|flash| {
+            let (mut tx_cmd, cmd_len) = flash.security_frame(opcode, register, 0, W25QXX_SECURITY_REGISTER_SIZE)?;
+            if flash.otp_locked(register)? {
+                return Err(Error::Locked);
+            }
+
+            flash.busy_wait()?;
+            flash.write_enable()?;
+            flash.pending[flash.die as usize] = BusyOperation::SectorErase;
+            flash.spi_transmit_and_receive(&mut tx_cmd[..cmd_len], &mut [], 0)?;
+            flash.busy_wait()
+        })
+    }
+
+    /// Sets the LB bit of `register` in status register 2. This is one-time
+    /// programmable: the register is read-only for good afterwards, for
+    /// factory calibration or serial data. Fails with
+    /// [`Error::OtpVerifyFailed`] when the bit did not stick, as with
+    /// protected status registers.
+    #[cfg(feature = "security-registers")]
+    pub fn otp_lock(&mut self, register: u8) -> Result<(), Error<SPI::Error>> {
+        let lock = security_lock_bit(register)?;
+        diag!(warn, "W25QXX - Locking security register {} for good", register);
+
+        self.with_recovery(|flash| {
+            flash.die_select(0)?;
+            let status2 = flash.read_status_register(2)?;
+            if status2 & lock != 0 {
+                return Ok(());
+            }
+
+            flash.write_status(2, status2 | lock, false)?;
+            if flash.read_status_register(2)? & lock == 0 {
+                return Err(Error::OtpVerifyFailed { register });
+            }
+
+            Ok(())
+        })
+    }
+
+    /// Whether security register `register` is locked.
+    #[cfg(feature = "security-registers")]
+    pub fn otp_locked(&mut self, register: u8) -> Result<bool, Error<SPI::Error>> {
+        let lock = security_lock_bit(register)?;
+        Ok(self.status_register(2)? & lock != 0)
+    }
+
+    /// Selects die 0 and builds the header of a security register command
+    /// for `len` bytes at `offset`. Register `n` sits at address `n << 12`;
+    /// the commands take the address width of the mode the chip is in.
+    #[cfg(feature = "security-registers")]
+    fn security_frame(&mut self, opcode: u8, register: u8, offset: usize, len: usize) -> Result<([u8; 5], usize), Error<SPI::Error>> {
+        security_lock_bit(register)?;
+        if len == 0 {
+            return Err(Error::InvalidArgument);
+        }
+        if offset + len > W25QXX_SECURITY_REGISTER_SIZE {
+            return Err(Error::InvalidAddress);
+        }
+
+        self.die_select(0)?;
+        let width = if self.four_byte_mode() { AddressWidth::Four } else { AddressWidth::Three };
+        Ok(wide_address_frame(opcode, (register as u32) << 12 | offset as u32, width))
+    }
+
+    /// Program/Erase Suspend (0x75): pauses the page program, sector or
+    /// block erase running on any die, so the array can be read in the
+    /// meantime. Chip erases and status register writes cannot be
+    /// suspended. Returns whether an operation was suspended.
+    ///
+    /// Until [`resume`](Self::resume), programs, erases and status register
+    /// writes on a suspended die fail with [`Error::Suspended`].
+    #[cfg(feature = "suspend")]
+    pub fn suspend(&mut self) -> Result<bool, Error<SPI::Error>> {
+        let mut any = false;
+
+        self.for_each_die(|flash| {
+            any |= flash.suspend_die()?;
+            Ok(())
+        })?;
+
+        if any {
+            diag!(debug, "W25QXX - Suspended");
+        }
+
+        Ok(any)
+    }
+
+    /// Program/Erase Resume (0x7A) on every die whose SUS bit is set, also
+    /// one suspended before a reset of the MCU. Returns whether anything was
+    /// resumed; the chip is busy again until the operation completes.
+    #[cfg(feature = "suspend")]
+    pub fn resume(&mut self) -> Result<bool, Error<SPI::Error>> {
+        let mut any = false;
+
+        self.for_each_die(|flash| {
+            any |= flash.resume_die()?;
+            Ok(())
+        })?;
+
+        if any {
+            diag!(debug, "W25QXX - Resumed");
+        }
+
+        Ok(any)
+    }
+
+    /// Whether the SUS bit (status register 2) is set on any die.
+    #[cfg(feature = "suspend")]
+    pub fn is_suspended(&mut self) -> Result<bool, Error<SPI::Error>> {
+        let mut any = false;
+
+        self.for_each_die(|flash| {
+            let suspended = flash.read_status_register(2)? & StatusRegister::Suspend as u8 != 0;
+            flash.suspended[flash.die as usize] = suspended;
+            any |= suspended;
+            Ok(())
+        })?;
+
+        Ok(any)
+    }
+}
+
+impl<SPI, D> W25qxx<SPI, D, ReadOnlyUnknown>
+where
+    SPI: SpiDevice,
+    D: DelayNs,
+{
+    pub fn read(&mut self, address: u32, buffer: &mut [u8]) -> Result<(), Error<SPI::Error>> {
+        diag!(trace, "W25QXX - read-only read 0x{:08X} len {}", address, buffer.len());
+
+        self.read_once(address, buffer)
+    }
+
+    /// Raw JEDEC ID bytes (manufacturer, memory type, capacity), unchecked.
+    pub fn read_id(&mut self) -> Result<[u8; 3], Error<SPI::Error>> {
+        let mut tx_cmd: [u8; 1] = [self.params.opcodes.read_id];
+        let mut rx_buffer: [u8; 3] = [0; 3];
+
+        self.spi_transmit_and_receive(&mut tx_cmd, &mut rx_buffer, 0)?;
+
+        Ok(rx_buffer)
+    }
+
+    /// Raw value of status register `reg_num` (1 to 3).
+    pub fn status_register(&mut self, reg_num: u8) -> Result<u8, Error<SPI::Error>> {
+        self.read_status_register(reg_num)
+    }
+}
+
+impl<SPI, D, S> W25qxx<SPI, D, S>
+where
+    SPI: SpiDevice,
+    D: DelayNs,
+{
+    /// Parameters of the part being driven.
+    pub fn params(&self) -> &ChipParams {
+        &self.params
+    }
+
+    /// Identifies the chip: reads its JEDEC ID, checks it against the
+    /// configured part (adopting the detected capacity when detection is on)
+    /// and returns what was found.
+    pub fn probe(&mut self) -> Result<DeviceInfo, Error<SPI::Error>> {
+        self.read_jedec_register()?;
+
+        Ok(self.device_info())
+    }
+
+    /// Checks whether the chip on the bus is still the one the driver set
+    /// up, without changing anything: status registers of die 0, the JEDEC
+    /// ID against the identified part, then the 4-byte address mode and the
+    /// Quad Enable bit the configuration needs. Anything but
+    /// [`ChipCheck::Unchanged`] calls for [`reprobe`](Self::reprobe).
+    pub fn check_chip(&mut self) -> Result<ChipCheck, Error<SPI::Error>> {
+        self.die_select(0)?;
+        let status = [self.read_status_register(1)?, self.read_status_register(2)?, self.read_status_register(3)?];
+        if status == [0x00; 3] || status == [0xFF; 3] {
+            return Ok(ChipCheck::NoResponse);
+        }
+
+        let mut tx_cmd: [u8; 1] = [self.params.opcodes.read_id];
+        let mut id: [u8; 3] = [0; 3];
+        self.spi_transmit_and_receive(&mut tx_cmd, &mut id, 0)?;
+        let [manufacturer_id, memory_type, capacity_id] = id;
+        let device_id = u16::from_be_bytes([memory_type, capacity_id]);
+        if self.config.id_check != IdCheck::Skip && (manufacturer_id, device_id) != (self.params.manufacturer_id, self.params.device_id) {
+            return Ok(ChipCheck::Replaced { manufacturer_id, device_id });
+        }
+
+        let (qe_reg, qe) = self.quad_enable_bit();
+        let quad_lost = self.config.read_mode.data_lines() == Lines::Quad && status[qe_reg as usize - 1] & qe == 0;
+        let address_mode_lost = self.four_byte_mode() && status[2] & STATUS3_ADS == 0;
+        if quad_lost || address_mode_lost {
+            return Ok(ChipCheck::SetupLost);
+        }
+
+        Ok(ChipCheck::Unchanged)
+    }
+
+    /// Starts over with a chip that was swapped or power-cycled: forgets
+    /// what the driver knew about it (pending operations, selected die,
+    /// continuous read, QPI, suspend and power-down state), identifies it
+    /// again, adopting the detected capacity when detection is on, and sets
+    /// up address mode, Quad Enable, burst wrap and QPI as configured.
+    pub fn reprobe(&mut self) -> Result<DeviceInfo, Error<SPI::Error>> {
+        diag!(info, "W25QXX - Probing the chip again");
+
+        self.forget_chip_state();
+        self.read_jedec_register()?;
+        self.restore_modes()?;
+
+        Ok(self.device_info())
+    }
+
+    /// Identification data: what `init()` or [`probe`](Self::probe) found,
+    /// or the configured part before either ran.
+    pub fn device_info(&self) -> DeviceInfo {
+        self.params.device_info()
+    }
+
+    /// Size, page, sector and block sizes of the chip, as detected by
+    /// `init()` when capacity detection is on.
+    pub fn geometry(&self) -> Geometry {
+        self.params.geometry()
+    }
+
+    /// Total size of the chip in bytes.
+    pub fn capacity(&self) -> usize {
+        self.params.capacity
+    }
+
+    /// Runtime options chosen with the [`W25qxxBuilder`].
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Deinitializes the driver and gives back the SPI device and the delay so
+    /// the bus can be reused. A [`SpiBusDevice`](crate::SpiBusDevice) can in
+    /// turn be split into its bus and chip select pin with
+    /// [`SpiBusDevice::release`](crate::SpiBusDevice::release).
+    pub fn release(self) -> (SPI, D) {
+        (self.spi, self.delay)
+    }
+
+    /// Brings the bus and the chip back to a known command state after an
+    /// aborted transfer: chip select is released between transactions, the
+    /// mode bits of a pending continuous read are cleared by clocking 0xFF
+    /// bytes, and the chip must answer a status register read.
+    ///
+    /// Called automatically whenever a transfer fails.
+    pub fn resynchronize(&mut self) -> Result<(), Error<SPI::Error>> {
+        self.wake()?;
+        self.reset_continuous_read()?;
+
+        /* A chip that answers with a floating bus is not back yet */
+        let mut status: [u8; 1] = [0; 1];
+        let read_status1 = self.params.opcodes.read_status1;
+        self.transfer(&mut [Operation::Write(&[read_status1]), Operation::Read(&mut status)])
+            .map_err(Error::SPIError)?;
+
+        if status[0] == 0xFF {
+            return Err(Error::NoResponse);
+        }
+
+        Ok(())
+    }
+
+    /// Continuous Read Mode Reset: 16 clocks of 0xFF end a Dual or Quad I/O
+    /// continuous read, including one left open by a previous run of the
+    /// firmware, and are ignored otherwise. `init()` starts with it.
+    pub fn reset_continuous_read(&mut self) -> Result<(), Error<SPI::Error>> {
+        self.wake()?;
+        self.continuous_read = None;
+
+        let reset = self.params.opcodes.continuous_read_reset;
+        self.transfer(&mut [Operation::Write(&[reset, reset])]).map_err(Error::SPIError)?;
+
+        /* They leave QPI as well; go back to it without recursing into resynchronize */
+        #[cfg(feature = "qpi")]
+        if self.qpi {
+            self.qpi = false;
+            self.qpi_commands().map_err(Error::SPIError)?;
+        }
+
+        Ok(())
+    }
+
+    /// Runs the bus sanity checks of `init()` on demand: a floating or
+    /// shorted MISO is reported as [`Error::NoResponse`], another chip as
+    /// [`Error::UnexpectedId`]. The status register must not read as
+    /// all ones either.
+    pub fn diagnose(&mut self) -> Result<(), Error<SPI::Error>> {
+        self.read_jedec_register()?;
+
+        if self.read_status_register(1)? == 0xFF {
+            return Err(Error::NoResponse);
+        }
+
+        Ok(())
+    }
+
+    /// Runs `operation`, and when it fails with an error a stuck chip could
+    /// cause, resets and re-probes the chip before trying again, up to
+    /// [`Config::recovery_retries`] times.
+    fn with_recovery<T>(&mut self, mut operation: impl FnMut(&mut Self) -> Result<T, Error<SPI::Error>>) -> Result<T, Error<SPI::Error>> {
+        let mut retries = self.config.recovery_retries;
+
+        loop {
+            match operation(self) {
+                Err(error) if retries > 0 && error.is_recoverable() => {
+                    retries -= 1;
+                    diag!(warn, "W25QXX - Operation failed, resetting the chip");
+
+                    /* A failed recovery is retried as well, until the budget is spent */
+                    let _ = self.recover();
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Factory-programmed 64-bit serial number (Read Unique ID, 0x4B),
+    /// unique to each device.
+    pub fn unique_id(&mut self) -> Result<u64, Error<SPI::Error>> {
+        /* Read Unique ID has no QPI form */
+        #[cfg(feature = "qpi")]
+        if self.qpi {
+            return Err(Error::Unsupported);
+        }
+
+        /* Opcode and four dummy bytes, one more in 4-byte address mode */
+        let mut tx_cmd: [u8; 6] = [self.params.opcodes.read_unique_id, 0, 0, 0, 0, 0];
+        let cmd_len = if self.four_byte_mode() { 6 } else { 5 };
+        let mut rx_buffer: [u8; 8] = [0; 8];
+
+        self.spi_transmit_and_receive(&mut tx_cmd[..cmd_len], &mut rx_buffer, 0)?;
+
+        Ok(u64::from_be_bytes(rx_buffer))
+    }
+
+    /// Reads the JEDEC Basic Flash Parameter table. Returns `None` when the
+    /// chip has no SFDP signature or no usable basic table.
+    pub fn read_sfdp(&mut self) -> Result<Option<Sfdp>, Error<SPI::Error>> {
+        let mut header: [u8; SFDP_HEADER_SIZE] = [0; SFDP_HEADER_SIZE];
+        self.sfdp_read(0, &mut header)?;
+
+        if header[..4] != SFDP_SIGNATURE.to_le_bytes() {
+            return Ok(None);
+        }
+
+        let revision = (header[5], header[4]);
+        let headers = header[6] as usize + 1;
+
+        /* Parameter headers follow the SFDP header */
+        let mut table = None;
+        for index in 1..=headers {
+            let mut parameter: [u8; SFDP_HEADER_SIZE] = [0; SFDP_HEADER_SIZE];
+            self.sfdp_read((index * SFDP_HEADER_SIZE) as u32, &mut parameter)?;
+
+            /* Length is in DWORDs; JESD216 rev. 1.0 tables already have nine */
+            if u16::from_le_bytes([parameter[0], parameter[7]]) == BFPT_ID && parameter[3] as usize >= BFPT_DWORDS {
+                table = Some(u32::from_le_bytes([parameter[4], parameter[5], parameter[6], 0]));
+                break;
+            }
+        }
+
+        let Some(pointer) = table else {
+            return Ok(None);
+        };
+
+        let mut raw: [u8; BFPT_DWORDS * 4] = [0; BFPT_DWORDS * 4];
+        self.sfdp_read(pointer, &mut raw)?;
+
+        let mut dwords: [u32; BFPT_DWORDS] = [0; BFPT_DWORDS];
+        for (dword, bytes) in dwords.iter_mut().zip(raw.chunks_exact(4)) {
+            *dword = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+
+        Ok(Sfdp::parse(revision, &dwords))
+    }
+
+    /// Read SFDP: 3-byte address and one dummy byte, in every address mode.
+    fn sfdp_read(&mut self, address: u32, rx_buffer: &mut [u8]) -> Result<(), Error<SPI::Error>> {
+        let mut tx_cmd = address_frame(self.params.opcodes.read_sfdp, address);
+
+        self.spi_transmit_and_receive(&mut tx_cmd, rx_buffer, 1)
+    }
+
+    /// Registers a [`PowerControl`] hook that [`recover`](Self::recover)
+    /// uses as a last resort: some lock-up states are only cleared by a
+    /// power-on reset.
+    pub fn on_power_cycle(&mut self, hook: PowerControl<D>) {
+        self.power_control = Some(hook);
+    }
+
+    /// Registers the [`ClockControl`] hook
+    /// [`set_spi_clock`](Self::set_spi_clock) changes the SPI clock with.
+    pub fn on_clock_change(&mut self, hook: ClockControl<SPI>) {
+        self.clock_control = Some(hook);
+    }
+
+    /// Switches the SPI clock to `hz` through the [`ClockControl`] hook, for
+    /// example to identify the chip slowly and read in bulk fast, or to slow
+    /// down for long wires. The reads follow the clock: in QPI mode the
+    /// Fast Read dummy clocks are set up for it (Set Read Parameters, sent
+    /// before the change), and [`ReadMode::Normal`] reads go out as Fast Read
+    /// above the 50 MHz limit of Read Data. Fails with [`Error::Unsupported`]
+    /// without a hook.
+    pub fn set_spi_clock(&mut self, hz: u32) -> Result<(), Error<SPI::Error>> {
+        let clock_control = self.clock_control.ok_or(Error::Unsupported)?;
+        if hz == 0 {
+            return Err(Error::InvalidArgument);
+        }
+
+        #[cfg(feature = "qpi")]
+        if self.qpi {
+            let (parameters, _) = qpi_read_parameters(Some(hz));
+            let mut tx_cmd: [u8; 2] = [self.params.opcodes.set_read_parameters, parameters];
+            self.spi_transmit_and_receive(&mut tx_cmd, &mut [], 0)?;
+        }
+
+        clock_control(&mut self.spi, hz).map_err(Error::SPIError)?;
+        self.spi_clock_hz = Some(hz);
+        diag!(debug, "W25QXX - SPI clock {} Hz", hz);
+
+        Ok(())
+    }
+
+    /// SPI clock last set with [`set_spi_clock`](Self::set_spi_clock).
+    pub fn spi_clock(&self) -> Option<u32> {
+        self.spi_clock_hz
+    }
+
+    /// Registers a hook called after every page programmed by `write()` and
+    /// `fill()` and every unit erased by `erase()`, for progress bars on long
+    /// operations. A write or erase retried by the recovery starts over from
+    /// zero.
+    ///
+    /// Returning `ControlFlow::Break` cancels the operation before its next
+    /// unit with [`Error::Cancelled`].
+    pub fn on_progress(&mut self, hook: ProgressHook) {
+        self.progress_hook = Some(hook);
+    }
+
+    /// Software reset (Enable Reset 0x66, Reset 0x99) of every die, as the
+    /// datasheet asks for it: waits for a running program or erase first and
+    /// fails with [`Error::Suspended`] while one is suspended, since the
+    /// reset would drop it half done. After tRST the chip is identified
+    /// again and address mode, Quad Enable, burst wrap and QPI are set up as
+    /// configured.
+    pub fn reset(&mut self) -> Result<(), Error<SPI::Error>> {
+        self.reset_when_idle(false)?;
+        self.read_jedec_register()?;
+        self.restore_modes()
+    }
+
+    /// Like [`reset`](Self::reset), without checking BUSY and SUS: aborts a
+    /// program or erase in progress, or a suspended one, whose unit is left
+    /// with undefined contents.
+    pub fn force_reset(&mut self) -> Result<(), Error<SPI::Error>> {
+        self.for_each_die(|flash| flash.software_reset())?;
+        self.read_jedec_register()?;
+        self.restore_modes()
+    }
+
+    /// Enable Reset/Reset, then checks the ID and restores the address mode.
+    /// The reset is not held back by a busy chip: a wedged one never gets idle.
+    ///
+    /// When the chip still does not answer and a [`PowerControl`] hook is
+    /// registered, its power is cycled and the identification run again.
+    pub fn recover(&mut self) -> Result<(), Error<SPI::Error>> {
+        let result = self
+            .release_power_down()
+            .and_then(|_| self.for_each_die(|flash| flash.software_reset()))
+            .and_then(|_| self.read_jedec_register());
+
+        match (result, self.power_control) {
+            (Err(_), Some(power_cycle)) => {
+                diag!(warn, "W25QXX - Chip not responding, cycling its power");
+
+                power_cycle(&mut self.delay);
+                self.delay.delay_us(POWER_UP_DELAY_US);
+                self.forget_chip_state();
+
+                self.read_jedec_register()?;
+            }
+            (result, _) => result?,
+        }
+
+        self.restore_modes()
+    }
+
+    /// Hardware reset through the RESET# pin: `set_reset(true)` drives it
+    /// active (low) for tRESET, `set_reset(false)` releases it, and after
+    /// tRST the chip is identified and its modes set up again as after
+    /// [`recover`](Self::recover).
+    pub(crate) fn pin_reset(
+        &mut self,
+        mut set_reset: impl FnMut(bool) -> Result<(), Error<SPI::Error>>,
+    ) -> Result<(), Error<SPI::Error>> {
+        set_reset(true)?;
+        self.delay.delay_us(RESET_PULSE_US);
+        set_reset(false)?;
+        self.delay.delay_us(RESET_DELAY_US);
+
+        self.forget_chip_state();
+        self.read_jedec_register()?;
+        self.restore_modes()
+    }
+
+    /// What the driver knew about the chip, after a power cycle or a
+    /// hardware reset cleared it.
+    fn forget_chip_state(&mut self) {
+        self.pending = [BusyOperation::Unknown; W25QXX_MAX_DIES];
+        self.die = 0;
+        self.continuous_read = None;
+        self.powered_down = false;
+        #[cfg(feature = "qpi")]
+        {
+            self.qpi = false;
+        }
+        #[cfg(feature = "suspend")]
+        {
+            self.suspended = [false; W25QXX_MAX_DIES];
+        }
+    }
+
+    /// Address mode, Quad Enable, burst wrap and QPI as configured, after a
+    /// reset returned them to their power-up state.
+    fn restore_modes(&mut self) -> Result<(), Error<SPI::Error>> {
+        self.enter_address_mode()?;
+
+        /* The reset reloaded a volatile Quad Enable from the non-volatile bit */
+        if self.config.read_mode.data_lines() == Lines::Quad {
+            self.enable_quad()?;
+        }
+
+        /* The reset turned the burst wrap off */
+        if let Some(wrap) = self.config.burst_wrap {
+            self.burst_wrap_command(Some(wrap))?;
+        }
+
+        #[cfg(feature = "qpi")]
+        if self.config.qpi {
+            self.enter_qpi()?;
+        }
+
+        Ok(())
+    }
+
+    /// Deep Power-Down (0xB9) on every die, after waiting for the program or
+    /// erase in progress: the chip drops to its power-down current (about
+    /// 1 µA) until [`release_power_down`](Self::release_power_down). Until
+    /// then every other command fails with [`Error::PoweredDown`] without
+    /// reaching the bus, unless [`Config::idle_power_down_ms`] is set: then
+    /// the chip is released before the next command. A suspended operation
+    /// has to be resumed first.
+    pub fn power_down(&mut self) -> Result<(), Error<SPI::Error>> {
+        if self.powered_down {
+            return Ok(());
+        }
+        #[cfg(feature = "suspend")]
+        if self.suspended.iter().any(|suspended| *suspended) {
+            return Err(Error::Suspended);
+        }
+
+        /* All dies idle before the first sleeps: an asleep chip reads as busy (0xFF) */
+        let opcode = self.params.opcodes.power_down;
+        self.for_each_die(|flash| flash.busy_wait())?;
+        self.for_each_die(|flash| flash.spi_transmit_and_receive(&mut [opcode], &mut [], 0))?;
+
+        self.powered_down = true;
+        self.delay.delay_us(POWER_DOWN_DELAY_US);
+        diag!(debug, "W25QXX - Deep power-down");
+
+        Ok(())
+    }
+
+    /// Release Power-Down (0xAB) on every die and waits until the chip takes
+    /// commands again. Harmless on a chip that is not powered down, so
+    /// `init()` and [`recover`](Self::recover) start with it.
+    pub fn release_power_down(&mut self) -> Result<(), Error<SPI::Error>> {
+        let opcode = self.params.opcodes.release_power_down;
+
+        self.powered_down = false;
+        self.for_each_die(|flash| flash.spi_transmit_and_receive(&mut [opcode], &mut [], 0))?;
+        self.delay.delay_us(RELEASE_POWER_DOWN_DELAY_US);
+
+        Ok(())
+    }
+
+    /// Whether [`power_down`](Self::power_down) put the chip to sleep.
+    pub fn is_powered_down(&self) -> bool {
+        self.powered_down
+    }
+
+    /// Idle power-down policy of [`Config::idle_power_down_ms`]: call it
+    /// regularly with a wrapping millisecond timestamp. Powers the chip down
+    /// once no command went out between calls for the configured time, and
+    /// returns whether it did. A program or erase still running, or a
+    /// suspended one, keeps the chip awake.
+    ///
+    /// The idle time is counted from the first call that saw no activity,
+    /// so the chip sleeps up to one call period later than the timeout.
+    pub fn poll_idle(&mut self, now_ms: u32) -> Result<bool, Error<SPI::Error>> {
+        let Some(timeout_ms) = self.config.idle_power_down_ms else {
+            return Ok(false);
+        };
+        if self.powered_down {
+            return Ok(false);
+        }
+        if core::mem::take(&mut self.active) {
+            self.idle_since_ms = now_ms;
+            return Ok(false);
+        }
+        if now_ms.wrapping_sub(self.idle_since_ms) < timeout_ms {
+            return Ok(false);
+        }
+        #[cfg(feature = "suspend")]
+        if self.suspended.iter().any(|suspended| *suspended) {
+            return Ok(false);
+        }
+
+        let mut busy = false;
+        self.for_each_die(|flash| {
+            busy |= flash.is_busy()?;
+            Ok(())
+        })?;
+        /* The busy poll itself is no activity */
+        self.active = false;
+        if busy {
+            self.idle_since_ms = now_ms;
+            return Ok(false);
+        }
+
+        diag!(debug, "W25QXX - Idle for {} ms", now_ms.wrapping_sub(self.idle_since_ms));
+        self.power_down()?;
+        self.active = false;
+
+        Ok(true)
+    }
+
+    /// Switches the chip to QPI: from then on, opcodes, addresses and data
+    /// are all clocked on four lines through the [`MultiIoTransfer`], and
+    /// reads use Fast Read (0x0B) with two dummy clocks. Sets the Quad
+    /// Enable bit first. Not available on stacked-die parts.
+    #[cfg(feature = "qpi")]
+    pub fn enter_qpi(&mut self) -> Result<(), Error<SPI::Error>> {
+        if self.multi_io.is_none() || self.params.dies > 1 {
+            return Err(Error::Unsupported);
+        }
+        if self.qpi {
+            return Ok(());
+        }
+
+        self.enable_quad()?;
+        self.busy_wait()?;
+        self.qpi_commands().map_err(Error::SPIError)
+    }
+
+    /// Returns the chip to single-line SPI (0xFF in QPI mode).
+    #[cfg(feature = "qpi")]
+    pub fn exit_qpi(&mut self) -> Result<(), Error<SPI::Error>> {
+        if !self.qpi {
+            return Ok(());
+        }
+
+        self.busy_wait()?;
+
+        let mut tx_cmd: [u8; 1] = [self.params.opcodes.exit_qpi];
+        self.spi_transmit_and_receive(&mut tx_cmd, &mut [], 0)?;
+        self.qpi = false;
+
+        Ok(())
+    }
+
+    /// Whether the chip currently takes its commands in QPI mode.
+    #[cfg(feature = "qpi")]
+    pub fn is_qpi(&self) -> bool {
+        self.qpi
+    }
+
+    /// Enter QPI on one line, then Set Read Parameters on four.
+    #[cfg(feature = "qpi")]
+    fn qpi_commands(&mut self) -> Result<(), SPI::Error> {
+        self.transfer(&mut [Operation::Write(&[self.params.opcodes.enter_qpi])])?;
+        self.qpi = true;
+
+        let set_read_parameters = self.params.opcodes.set_read_parameters;
+        let (parameters, _) = qpi_read_parameters(self.spi_clock_hz);
+        self.transfer(&mut [Operation::Write(&[set_read_parameters, parameters])])
+    }
+
+    /// Address width in use: the configured one, with
+    /// [`AddressWidth::Auto`] resolved against the size of one die.
+    pub fn address_width(&self) -> AddressWidth {
+        self.config.address_width.resolve(self.params.die_size())
+    }
+
+    /// Whether the chip is kept in 4-byte address mode, as opposed to 3-byte
+    /// addresses or 4-byte opcodes.
+    fn four_byte_mode(&self) -> bool {
+        self.address_width() == AddressWidth::Four && !self.config.four_byte_opcodes
+    }
+
+    /// Commands for the address width in use.
+    fn opcodes(&self) -> Opcodes {
+        if self.address_width() == AddressWidth::Four && self.config.four_byte_opcodes {
+            self.params.opcodes.four_byte()
+        } else {
+            self.params.opcodes
+        }
+    }
+
+    /// Switches every die to 4-byte addresses when configured. A reset puts
+    /// it back into 3-byte mode.
+    fn enter_address_mode(&mut self) -> Result<(), Error<SPI::Error>> {
+        if self.four_byte_mode() {
+            self.address_mode_command(self.params.opcodes.enter_4byte_address)?;
+        }
+
+        Ok(())
+    }
+
+    /// Sends Enter or Exit 4-Byte Address Mode to every die once it is idle:
+    /// a busy die ignores both.
+    fn address_mode_command(&mut self, opcode: u8) -> Result<(), Error<SPI::Error>> {
+        self.for_each_die(|flash| {
+            flash.busy_wait()?;
+
+            let mut tx_cmd: [u8; 1] = [opcode];
+            flash.spi_transmit_and_receive(&mut tx_cmd, &mut [], 0)
+        })
+    }
+
+    /// Fails with [`Error::InvalidAddress`] unless `[address, address + len)`
+    /// lies on the chip.
+    fn check_bounds(&self, address: u32, len: usize) -> Result<(), Error<SPI::Error>> {
+        if address as u64 + len as u64 > self.params.capacity as u64 {
+            return Err(Error::InvalidAddress);
+        }
+
+        Ok(())
+    }
+
+    /// Reads are split at die boundaries: a read command never continues
+    /// into the next die.
+    fn read_once(&mut self, address: u32, buffer: &mut [u8]) -> Result<(), Error<SPI::Error>> {
+        self.read_in_mode(self.read_mode(), address, buffer)
+    }
+
+    fn read_in_mode(&mut self, mode: ReadMode, address: u32, buffer: &mut [u8]) -> Result<(), Error<SPI::Error>> {
+        self.check_bounds(address, buffer.len())?;
+
+        let mut addr = address;
+        let mut rest = buffer;
+
+        loop {
+            let chunk_len = rest.len().min(self.read_chunk_size()).min(self.die_remaining(addr)).min(self.wrap_remaining(addr));
+            let (chunk, tail) = rest.split_at_mut(chunk_len);
+            let die_addr = self.select_die_at(addr)?;
+            #[cfg(feature = "suspend")]
+            let suspended = self.suspend_for_read(die_addr, chunk.len())?;
+            #[cfg(not(feature = "suspend"))]
+            let suspended = false;
+
+            /* The array only reads back once the die is idle or its operation suspended */
+            if !suspended {
+                self.busy_wait()?;
+            }
+
+            match mode {
+                ReadMode::Normal => self.read_data(die_addr, chunk)?,
+                ReadMode::Fast => self.fast_read(die_addr, chunk)?,
+                ReadMode::DualOutput => self.dual_output_read(die_addr, chunk)?,
+                ReadMode::QuadOutput => self.quad_output_read(die_addr, chunk)?,
+                ReadMode::DualIo => self.dual_io_read(die_addr, chunk)?,
+                ReadMode::QuadIo => self.quad_io_read(die_addr, chunk)?,
+            }
+
+            if suspended {
+                #[cfg(feature = "suspend")]
+                self.resume_die()?;
+            }
+
+            if tail.is_empty() {
+                return Ok(());
+            }
+            addr += chunk.len() as u32;
+            rest = tail;
+        }
+    }
+
+    /// Most data bytes one read command returns: `read()` splits longer
+    /// reads so that no transfer exceeds [`Config::max_transfer_size`].
+    pub fn read_chunk_size(&self) -> usize {
+        self.config.max_transfer_size - READ_HEADER_MAX_SIZE
+    }
+
+    /// Read command `read()` uses: QPI mode only knows Fast Read among the
+    /// modes, and Read Data is too slow for an SPI clock above 50 MHz.
+    pub fn read_mode(&self) -> ReadMode {
+        #[cfg(feature = "qpi")]
+        if self.qpi {
+            return ReadMode::Fast;
+        }
+
+        match (self.config.read_mode, self.spi_clock_hz) {
+            (ReadMode::Normal, Some(hz)) if hz > READ_DATA_MAX_HZ => ReadMode::Fast,
+            (mode, _) => mode,
+        }
+    }
+
+    /// Bytes from `address` to the end of its wrap line, when Quad I/O reads
+    /// wrap: a linear read must not run past it.
+    fn wrap_remaining(&self, address: u32) -> usize {
+        match self.config.burst_wrap {
+            Some(wrap) if self.read_mode() == ReadMode::QuadIo => wrap.line_size() - address as usize % wrap.line_size(),
+            _ => usize::MAX,
+        }
+    }
+
+    /// Set Burst with Wrap on every die: opcode on one line, then three
+    /// dummy bytes and the wrap bits on four.
+    fn burst_wrap_command(&mut self, wrap: Option<BurstWrap>) -> Result<(), Error<SPI::Error>> {
+        let opcode = [self.params.opcodes.set_burst_with_wrap];
+        let wrap_bits = [0, 0, 0, wrap.map_or(BURST_WRAP_DISABLED, |wrap| wrap.wrap_bits())];
+
+        self.for_each_die(|flash| {
+            flash.multi_io_transfer(&mut [IoOperation::Write(Lines::Single, &opcode), IoOperation::Write(Lines::Quad, &wrap_bits)])
+        })
+    }
+
+    /// Suspends the program or erase running on the selected die. Returns
+    /// whether the chip took the suspend.
+    #[cfg(feature = "suspend")]
+    fn suspend_die(&mut self) -> Result<bool, Error<SPI::Error>> {
+        let die = self.die as usize;
+        let suspendable = !matches!(self.pending[die], BusyOperation::ChipErase | BusyOperation::WriteStatusRegister);
+        if self.suspended[die] || !suspendable || !self.is_busy()? {
+            return Ok(false);
+        }
+
+        let mut tx_cmd: [u8; 1] = [self.params.opcodes.suspend];
+        self.spi_transmit_and_receive(&mut tx_cmd, &mut [], 0)?;
+        self.delay.delay_us(SUSPEND_LATENCY_US);
+
+        if self.is_busy()? {
+            return Err(Error::Timeout { operation: self.pending[die] });
+        }
+
+        /* The operation may have finished just before the command */
+        self.suspended[die] = self.read_status_register(2)? & StatusRegister::Suspend as u8 != 0;
+        Ok(self.suspended[die])
+    }
+
+    /// Resumes the selected die if its SUS bit is set.
+    #[cfg(feature = "suspend")]
+    fn resume_die(&mut self) -> Result<bool, Error<SPI::Error>> {
+        self.suspended[self.die as usize] = false;
+
+        if self.read_status_register(2)? & StatusRegister::Suspend as u8 == 0 {
+            return Ok(false);
+        }
+
+        let mut tx_cmd: [u8; 1] = [self.params.opcodes.resume];
+        self.spi_transmit_and_receive(&mut tx_cmd, &mut [], 0)?;
+        /* A new Suspend is only accepted after tSUS */
+        self.delay.delay_us(SUSPEND_LATENCY_US);
+
+        Ok(true)
+    }
+
+    /// Makes the array readable at `[address, address + len)` of the
+    /// selected die for [`Config::suspend_for_reads`]: an erase elsewhere is
+    /// suspended (returns true), anything else is waited for.
+    #[cfg(feature = "suspend")]
+    fn suspend_for_read(&mut self, address: u32, len: usize) -> Result<bool, Error<SPI::Error>> {
+        let die = self.die as usize;
+        if !self.config.suspend_for_reads || self.suspended[die] {
+            return Ok(false);
+        }
+
+        let unit = match self.pending[die] {
+            BusyOperation::SectorErase => W25QXX_SECTOR_SIZE,
+            BusyOperation::Block32Erase => W25QXX_BLOCK32K_SIZE,
+            BusyOperation::Block64Erase => W25QXX_BLOCK64K_SIZE,
+            _ => 0,
+        } as u32;
+        let erase_start = self.erase_address[die] - self.erase_address[die] % unit.max(1);
+        let overlaps = address < erase_start + unit && address + len as u32 > erase_start;
+
+        if unit == 0 || overlaps {
+            self.busy_wait()?;
+            return Ok(false);
+        }
+
+        self.suspend_die()
     }
 
-    fn fast_read(&mut self, address: u32, rx_buffer: &mut [u8]) -> Result<(), Error<()>> {
+    /// Software Die Select (0xC2). Does nothing on monolithic parts.
+    fn die_select(&mut self, die: u8) -> Result<(), Error<SPI::Error>> {
+        if self.params.dies > 1 {
+            let mut tx_cmd: [u8; 2] = [self.params.opcodes.die_select, die];
+            self.spi_transmit_and_receive(&mut tx_cmd, &mut [], 0)?;
+            self.die = die;
+        }
+
+        Ok(())
+    }
+
+    /// Calls the progress hook, turning a `Break` before the last unit into
+    /// [`Error::Cancelled`].
+    fn report_progress(&self, operation: BusyOperation, address: u32, done: usize, total: usize) -> Result<(), Error<SPI::Error>> {
+        let Some(hook) = self.progress_hook else {
+            return Ok(());
+        };
+
+        match hook(&Progress { operation, address, done, total }) {
+            ControlFlow::Break(()) if done < total => Err(Error::Cancelled { done }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Selects the die holding `address` unless it is active already, and
+    /// returns the address inside that die.
+    fn select_die_at(&mut self, address: u32) -> Result<u32, Error<SPI::Error>> {
+        if self.params.dies <= 1 {
+            return Ok(address);
+        }
+
+        let die_size = self.params.die_size() as u32;
+        let die = address / die_size;
+        if die >= self.params.dies as u32 {
+            return Err(Error::InvalidAddress);
+        }
+        if die as u8 != self.die {
+            self.die_select(die as u8)?;
+        }
+
+        Ok(address % die_size)
+    }
+
+    /// Bytes from `address` to the end of its die.
+    fn die_remaining(&self, address: u32) -> usize {
+        if self.params.dies <= 1 {
+            return usize::MAX;
+        }
+
+        let die_size = self.params.die_size();
+        die_size - address as usize % die_size
+    }
+
+    /// Runs `command` on every die, highest first, so die 0 is selected
+    /// afterwards. Die select is always sent: after a reset of the MCU the
+    /// chip may have any die active.
+    fn for_each_die(&mut self, mut command: impl FnMut(&mut Self) -> Result<(), Error<SPI::Error>>) -> Result<(), Error<SPI::Error>> {
+        for die in (0..self.params.dies.max(1)).rev() {
+            self.die_select(die)?;
+            command(self)?;
+        }
+
+        Ok(())
+    }
+
+    fn into_state<T>(self) -> W25qxx<SPI, D, T> {
+        W25qxx {
+            spi: self.spi,
+            delay: self.delay,
+            params: self.params,
+            config: self.config,
+            pending: self.pending,
+            die: self.die,
+            continuous_read: self.continuous_read,
+            #[cfg(feature = "qpi")]
+            qpi: self.qpi,
+            powered_down: self.powered_down,
+            active: self.active,
+            idle_since_ms: self.idle_since_ms,
+            #[cfg(feature = "suspend")]
+            suspended: self.suspended,
+            #[cfg(feature = "suspend")]
+            erase_address: self.erase_address,
+            integrity_check: self.integrity_check,
+            integrity_hook: self.integrity_hook,
+            progress_hook: self.progress_hook,
+            power_control: self.power_control,
+            clock_control: self.clock_control,
+            spi_clock_hz: self.spi_clock_hz,
+            multi_io: self.multi_io,
+            integrity_report: self.integrity_report,
+            protected_ranges: self.protected_ranges,
+            state: PhantomData,
+        }
+    }
+
+    /// Reads the JEDEC ID (0x9F) and checks it as configured by
+    /// [`Config::id_check`], taking over the detected capacity when
+    /// `params.detect_capacity` is set.
+    fn read_jedec_register(&mut self) -> Result<(), Error<SPI::Error>> {
+        if self.config.id_check == IdCheck::Skip {
+            diag!(warn, "W25QXX - Identification skipped, assuming device ID 0x{:04X}", self.params.device_id);
+            return Ok(());
+        }
+
+        let mut tx_cmd: [u8; 1] = [self.params.opcodes.read_id];
+        let mut rx_buffer: [u8; 3] = [0; 3];
+
+        self.spi_transmit_and_receive(&mut tx_cmd, &mut rx_buffer, 0)?;
+
+        check_id(&mut self.params, self.config.id_check, rx_buffer)?;
+
+        diag!(debug, "W25QXX - Manufacture ID: 0x{:02X}", rx_buffer[0]);
+        diag!(debug, "W25QXX - Device ID: 0x{:04X}", self.params.device_id);
+        diag!(debug, "W25QXX - Capacity: {} bytes", self.params.capacity);
+
+        Ok(())
+    }
+
+    /// Enable Reset/Reset on every die once it is idle. A die with a
+    /// suspended operation fails with [`Error::Suspended`], unless
+    /// `finish_suspended` resumes the operation and waits for it: `init()`
+    /// does that for one left behind by the previous run.
+    fn reset_when_idle(&mut self, finish_suspended: bool) -> Result<(), Error<SPI::Error>> {
+        self.for_each_die(|flash| {
+            flash.busy_wait()?;
+
+            if flash.read_status_register(2)? & StatusRegister::Suspend as u8 != 0 {
+                if !finish_suspended {
+                    return Err(Error::Suspended);
+                }
+                diag!(warn, "W25QXX - Finishing a suspended operation before the reset");
+                let mut tx_cmd: [u8; 1] = [flash.params.opcodes.resume];
+                flash.spi_transmit_and_receive(&mut tx_cmd, &mut [], 0)?;
+                flash.pending[flash.die as usize] = BusyOperation::Unknown;
+                flash.busy_wait()?;
+            }
+
+            flash.software_reset()
+        })
+    }
+
+    /// Enable Reset/Reset without waiting for the chip, which also aborts a
+    /// stuck program or erase, then the reset time tRST.
+    fn software_reset(&mut self) -> Result<(), Error<SPI::Error>> {
+        let mut tx_cmd: [u8; 1] = [self.params.opcodes.enable_reset];
+        self.spi_transmit_and_receive(&mut tx_cmd, &mut [], 0)?;
+
+        tx_cmd[0] = self.params.opcodes.reset;
+        self.spi_transmit_and_receive(&mut tx_cmd, &mut [], 0)?;
+        self.delay.delay_us(RESET_DELAY_US);
+
+        /* The reset also returns the chip to single-line SPI */
+        #[cfg(feature = "qpi")]
+        {
+            self.qpi = false;
+        }
+        /* and drops a suspended operation */
+        #[cfg(feature = "suspend")]
+        {
+            self.suspended[self.die as usize] = false;
+        }
+
+        Ok(())
+    }
+
+    fn read_status_register(&mut self, reg_num: u8) -> Result<u8, Error<SPI::Error>> {
+        let mut tx_cmd: [u8; 1] = [0; 1];
+        let mut rx_buffer: [u8; 1] = [0; 1];
+        if reg_num == 1 {
+            tx_cmd[0] = self.params.opcodes.read_status1;
+        } else if reg_num == 2 {
+            tx_cmd[0] = self.params.opcodes.read_status2;
+        } else if reg_num == 3 {
+            tx_cmd[0] = self.params.opcodes.read_status3;
+        } else {
+            return Err(Error::InvalidArgument);
+        }
+
+        self.spi_transmit_and_receive(&mut tx_cmd, &mut rx_buffer, 0)?;
+
+        Ok(rx_buffer[0])
+    }
+
+    fn is_busy(&mut self) -> Result<bool, Error<SPI::Error>> {
+        Ok((self.read_status_register(1)? & StatusRegister::Busy as u8) != 0)
+    }
+
+    /// Polls until the selected die is idle, for at most the timeout of its
+    /// pending operation. Only the poll delays are counted, so the real wait
+    /// is a bit longer than the timeout, never shorter.
+    fn busy_wait(&mut self) -> Result<(), Error<SPI::Error>> {
+        let pending = self.pending[self.die as usize];
+        let timeout_us = self.config.busy_timeouts.for_operation(pending);
+        let typical_us = pending.typical_us();
+        let mut interval_us = match self.config.busy_poll {
+            BusyPoll::Fixed => self.config.busy_poll_interval_us,
+            BusyPoll::Backoff => (typical_us / 16).max(1),
+        };
+        let mut waited_us: u32 = 0;
+
+        while self.is_busy()? {
+            if waited_us >= timeout_us {
+                diag!(error, "W25QXX - {:?} still busy after {} us", pending, waited_us);
+                return Err(Error::Timeout { operation: pending });
+            }
+
+            self.delay.delay_us(interval_us);
+            waited_us = waited_us.saturating_add(interval_us);
+
+            if self.config.busy_poll == BusyPoll::Backoff {
+                interval_us = interval_us.saturating_mul(2).min((typical_us / 4).max(1));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn is_write_enable(&mut self) -> Result<bool, Error<SPI::Error>> {
+        // Leer el registro de estado
+        let status: u8 = self.read_status_register(1)?;
+
+        // Comprobar si el bit de Write Enable está establecido
+        Ok((status & StatusRegister::WriteEnable as u8) != 0)
+    }
+
+    fn write_enable(&mut self) -> Result<(), Error<SPI::Error>> {
+        /* Nothing but reads may interrupt a suspended operation here */
+        #[cfg(feature = "suspend")]
+        if self.suspended[self.die as usize] {
+            return Err(Error::Suspended);
+        }
+
+        let mut tx_cmd: [u8; 1] = [self.params.opcodes.write_enable];
+
+        self.spi_transmit_and_receive(&mut tx_cmd, &mut [], 0)?;
+
+        if !self.is_write_enable()? {
+            return Err(Error::WriteEnableFailed);
+        }
+
+        Ok(())
+    }
+
+    /// Write Enable for Volatile Status Register (0x50). Does not set WEL,
+    /// so there is nothing to check afterwards.
+    fn volatile_write_enable(&mut self) -> Result<(), Error<SPI::Error>> {
+        #[cfg(feature = "suspend")]
+        if self.suspended[self.die as usize] {
+            return Err(Error::Suspended);
+        }
+
+        let mut tx_cmd: [u8; 1] = [self.params.opcodes.volatile_write_enable];
+
+        self.spi_transmit_and_receive(&mut tx_cmd, &mut [], 0)
+    }
+
+    /// Runs one chip select cycle and dumps its bytes under `trace-bytes`.
+    /// Every command goes through here, so the deselect delay applies to all
+    /// of them.
+    fn transfer(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), SPI::Error> {
+        self.leave_continuous_read()?;
+
+        #[cfg(feature = "qpi")]
+        let result = match (self.qpi, self.multi_io) {
+            (true, Some(multi_io)) => quad_transaction(multi_io, &mut self.spi, operations),
+            _ => self.spi.transaction(operations),
+        };
+        #[cfg(not(feature = "qpi"))]
+        let result = self.spi.transaction(operations);
+        trace_transaction(operations);
+        trace_command(&self.params.opcodes, operations);
+
+        if self.config.cs_deselect_delay_ns > 0 {
+            self.delay.delay_ns(self.config.cs_deselect_delay_ns);
+        }
+
+        result
+    }
+
+    fn spi_transmit(&mut self, cmd: u8, address: u32, tx_buffer: &[u8]) -> Result<(), Error<SPI::Error>> {
+        let (tx_cmd, cmd_len) = wide_address_frame(cmd, address, self.address_width());
+
+        self.spi_transmit_frame(&tx_cmd[..cmd_len], tx_buffer)
+    }
+
+    /// Sends a command header, then the bytes within the same chip select
+    /// cycle.
+    fn spi_transmit_frame(&mut self, tx_cmd: &[u8], tx_buffer: &[u8]) -> Result<(), Error<SPI::Error>> {
+        self.wake()?;
+
+        let result = if tx_buffer.is_empty() {
+            self.transfer(&mut [Operation::Write(tx_cmd)])
+        } else {
+            self.transfer(&mut [Operation::Write(tx_cmd), Operation::Write(tx_buffer)])
+        };
+
+        result.map_err(|e| {
+            let _ = self.resynchronize();
+            Error::SPIError(e)
+        })
+    }
+
+    /// Runs one chip select cycle through the registered
+    /// [`MultiIoTransfer`].
+    fn multi_io_transfer(&mut self, operations: &mut [IoOperation<'_>]) -> Result<(), Error<SPI::Error>> {
+        self.wake()?;
+        let transfer = self.multi_io.ok_or(Error::Unsupported)?;
+        let result = self.leave_continuous_read().and_then(|_| transfer(&mut self.spi, operations));
+        trace_io_transaction(operations);
+        trace_io_command(operations);
+
+        if self.config.cs_deselect_delay_ns > 0 {
+            self.delay.delay_ns(self.config.cs_deselect_delay_ns);
+        }
+
+        result.map_err(|e| {
+            let _ = self.resynchronize();
+            Error::SPIError(e)
+        })
+    }
+
+    /// Keeps commands away from a chip in Deep Power-Down, which would
+    /// ignore them and leave MISO floating: releases it first under the idle
+    /// power-down policy, fails otherwise. Every command counts as activity
+    /// for [`poll_idle`](Self::poll_idle).
+    fn wake(&mut self) -> Result<(), Error<SPI::Error>> {
+        self.active = true;
+
+        if self.powered_down {
+            if self.config.idle_power_down_ms.is_none() {
+                return Err(Error::PoweredDown);
+            }
+            self.release_power_down()?;
+        }
+
+        Ok(())
+    }
+
+    /// Ends the continuous read left open by the last I/O read before any
+    /// other command goes out.
+    fn leave_continuous_read(&mut self) -> Result<(), SPI::Error> {
+        if self.continuous_read.take().is_some() {
+            let reset = self.params.opcodes.continuous_read_reset;
+            self.spi.transaction(&mut [Operation::Write(&[reset, reset])])?;
+
+            if self.config.cs_deselect_delay_ns > 0 {
+                self.delay.delay_ns(self.config.cs_deselect_delay_ns);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn spi_transmit_and_receive(&mut self, tx_buffer: &mut [u8], rx_buffer: &mut [u8], dummy_bytes: usize) -> Result<(), Error<SPI::Error>> {
+        self.wake()?;
+        let dummy_buffer: [u8; MAX_FAST_READ_DUMMY_CYCLES as usize / 8] = [0x00; MAX_FAST_READ_DUMMY_CYCLES as usize / 8];
+
+        /* Send Bytes, the dummy bytes and receive bytes within one chip select cycle */
+        let result = match (dummy_bytes > 0, rx_buffer.is_empty()) {
+            (false, true) => self.transfer(&mut [Operation::Write(tx_buffer)]),
+            (false, false) => self.transfer(&mut [Operation::Write(tx_buffer), Operation::Read(rx_buffer)]),
+            (true, _) => self.transfer(&mut [
+                Operation::Write(tx_buffer),
+                Operation::Write(&dummy_buffer[..dummy_bytes]),
+                Operation::Read(rx_buffer),
+            ]),
+        };
+
+        result.map_err(|e| {
+            let _ = self.resynchronize();
+            Error::SPIError(e)
+        })
+    }
+
+    fn page_program(&mut self, address: u32, tx_buffer: &[u8]) -> Result<(), Error<SPI::Error>> {
         /* Argument check */
-        let mut tx_cmd: [u8; 4] = [0; 4];
+        if tx_buffer.is_empty() || tx_buffer.len() > W25QXX_PAGE_SIZE {
+            return Err(Error::InvalidArgument);
+        }
+
+        /* Before program enable write enable latch */
+        self.write_enable()?;
+
+        self.pending[self.die as usize] = BusyOperation::PageProgram;
+        self.spi_transmit(self.opcodes().page_program, address, tx_buffer)
+    }
 
-        if rx_buffer.is_empty() || rx_buffer.len() == 0 {
-            return Err(Error::SPIError(()));
+    fn read_data(&mut self, address: u32, rx_buffer: &mut [u8]) -> Result<(), Error<SPI::Error>> {
+        if rx_buffer.is_empty() {
+            return Err(Error::InvalidArgument);
         }
 
-        tx_cmd[0] = Command::FastRead as u8;
-        tx_cmd[1] = ((address >> 16) & 0xFF) as u8;
-        tx_cmd[2] = ((address >> 8) & 0xFF) as u8;
-        tx_cmd[3] = ((address) & 0xFF) as u8;
+        let (mut tx_cmd, cmd_len) = wide_address_frame(self.opcodes().read_data, address, self.address_width());
 
-        self.spi_transmit_and_receive(&mut tx_cmd, rx_buffer, 1)
+        self.spi_transmit_and_receive(&mut tx_cmd[..cmd_len], rx_buffer, 0)
+    }
+
+    fn fast_read(&mut self, address: u32, rx_buffer: &mut [u8]) -> Result<(), Error<SPI::Error>> {
+        /* Argument check */
+        if rx_buffer.is_empty() {
+            return Err(Error::InvalidArgument);
+        }
+
+        let (mut tx_cmd, cmd_len) = wide_address_frame(self.opcodes().fast_read, address, self.address_width());
+
+        /* Set Read Parameters fixed the QPI dummy clocks */
+        #[cfg(feature = "qpi")]
+        let dummy_bytes = if self.qpi { qpi_read_parameters(self.spi_clock_hz).1 } else { self.config.dummy_cycles.fast as usize / 8 };
+        #[cfg(not(feature = "qpi"))]
+        let dummy_bytes = self.config.dummy_cycles.fast as usize / 8;
+
+        self.spi_transmit_and_receive(&mut tx_cmd[..cmd_len], rx_buffer, dummy_bytes)
+    }
+
+    /// Fast Read Dual Output: opcode and address on one line, dummy clocks,
+    /// data on two lines.
+    fn dual_output_read(&mut self, address: u32, rx_buffer: &mut [u8]) -> Result<(), Error<SPI::Error>> {
+        if rx_buffer.is_empty() {
+            return Err(Error::InvalidArgument);
+        }
+
+        let (tx_cmd, cmd_len) = wide_address_frame(self.opcodes().fast_read_dual_output, address, self.address_width());
+
+        self.multi_io_transfer(&mut [
+            IoOperation::Write(Lines::Single, &tx_cmd[..cmd_len]),
+            IoOperation::Dummy(self.config.dummy_cycles.dual_output),
+            IoOperation::Read(Lines::Dual, rx_buffer),
+        ])
+    }
+
+    /// Fast Read Quad Output: opcode and address on one line, dummy clocks,
+    /// data on four lines. Needs the Quad Enable bit.
+    fn quad_output_read(&mut self, address: u32, rx_buffer: &mut [u8]) -> Result<(), Error<SPI::Error>> {
+        if rx_buffer.is_empty() {
+            return Err(Error::InvalidArgument);
+        }
+
+        let (tx_cmd, cmd_len) = wide_address_frame(self.opcodes().fast_read_quad_output, address, self.address_width());
+
+        self.multi_io_transfer(&mut [
+            IoOperation::Write(Lines::Single, &tx_cmd[..cmd_len]),
+            IoOperation::Dummy(self.config.dummy_cycles.quad_output),
+            IoOperation::Read(Lines::Quad, rx_buffer),
+        ])
+    }
+
+    /// Fast Read Dual I/O: opcode on one line, then address and mode bits on
+    /// two lines, which take the place of the dummy clocks on the W25Q, any
+    /// further dummy clocks and data on two lines.
+    fn dual_io_read(&mut self, address: u32, rx_buffer: &mut [u8]) -> Result<(), Error<SPI::Error>> {
+        let opcode = self.opcodes().fast_read_dual_io;
+        self.io_read(opcode, Lines::Dual, self.config.dummy_cycles.dual_io, address, rx_buffer)
+    }
+
+    /// Fast Read Quad I/O: opcode on one line, then address and mode bits on
+    /// four lines, dummy clocks and data on four lines. Needs the Quad
+    /// Enable bit.
+    fn quad_io_read(&mut self, address: u32, rx_buffer: &mut [u8]) -> Result<(), Error<SPI::Error>> {
+        let opcode = self.opcodes().fast_read_quad_io;
+        self.io_read(opcode, Lines::Quad, self.config.dummy_cycles.quad_io, address, rx_buffer)
+    }
+
+    /// I/O read with the address and mode bits on `lines`. In continuous
+    /// read mode the opcode is left out when the chip still waits for an
+    /// address of this command, and the chip is left waiting again.
+    fn io_read(&mut self, opcode: u8, lines: Lines, dummy_cycles: u8, address: u32, rx_buffer: &mut [u8]) -> Result<(), Error<SPI::Error>> {
+        if rx_buffer.is_empty() {
+            return Err(Error::InvalidArgument);
+        }
+
+        let (frame, frame_len) = wide_address_frame(opcode, address, self.address_width());
+        let mode_bits = if self.config.continuous_read { CONTINUOUS_READ_MODE_BITS } else { MODE_BITS };
+        let mut address_and_mode: [u8; 5] = [mode_bits; 5];
+        address_and_mode[..frame_len - 1].copy_from_slice(&frame[1..frame_len]);
+
+        let continuing = self.continuous_read == Some(opcode);
+        if continuing {
+            self.continuous_read = None;
+        }
+
+        let mut operations = [
+            IoOperation::Write(Lines::Single, &frame[..1]),
+            IoOperation::Write(lines, &address_and_mode[..frame_len]),
+            IoOperation::Dummy(dummy_cycles),
+            IoOperation::Read(lines, rx_buffer),
+        ];
+        self.multi_io_transfer(&mut operations[continuing as usize..])?;
+
+        self.continuous_read = self.config.continuous_read.then_some(opcode);
+        Ok(())
+    }
+
+    /// Sets the Quad Enable bit, wherever the part keeps it, on every die,
+    /// and checks that it took. Non-volatile unless
+    /// [`Config::volatile_quad_enable`] is set. Quad reads return garbage without it: IO2 and IO3 are
+    /// still /WP and /HOLD.
+    fn enable_quad(&mut self) -> Result<(), Error<SPI::Error>> {
+        let (reg_num, qe) = self.quad_enable_bit();
+
+        self.for_each_die(|flash| {
+            flash.busy_wait()?;
+
+            let status = flash.read_status_register(reg_num)?;
+            if status & qe != 0 {
+                return Ok(());
+            }
+
+            flash.write_status(reg_num, status | qe, flash.config.volatile_quad_enable)?;
+
+            /* Protected status registers ignore the write */
+            if flash.read_status_register(reg_num)? & qe == 0 {
+                return Err(Error::QuadEnableFailed);
+            }
+
+            Ok(())
+        })
+    }
+
+    /// Status register and mask of the Quad Enable bit.
+    fn quad_enable_bit(&self) -> (u8, u8) {
+        match self.params.quad_enable {
+            QuadEnable::Status2Bit1 => (2, QE_STATUS2),
+            QuadEnable::Status1Bit6 => (1, QE_STATUS1),
+        }
+    }
+
+    /// Replaces the block protect bits of the selected die, writing only the
+    /// status registers that change.
+    fn write_block_protection(&mut self, protection: BlockProtection) -> Result<(), Error<SPI::Error>> {
+        let die_size = self.params.die_size();
+        let status1 = self.read_status_register(1)?;
+        let status2 = self.read_status_register(2)?;
+        let (new_status1, new_status2) = protection.to_status(die_size, status1, status2);
+
+        if new_status1 != status1 {
+            self.write_status(1, new_status1, false)?;
+        }
+        if new_status2 != status2 {
+            self.write_status(2, new_status2, false)?;
+        }
+
+        Ok(())
+    }
+
+    /// Write Status Register `reg_num` on the selected die, once it is idle,
+    /// and waits for the write cycle. A `volatile` write only changes the
+    /// register until power is removed.
+    fn write_status(&mut self, reg_num: u8, value: u8, volatile: bool) -> Result<(), Error<SPI::Error>> {
+        let opcode = match reg_num {
+            1 => self.params.opcodes.write_status1,
+            2 => self.params.opcodes.write_status2,
+            3 => self.params.opcodes.write_status3,
+            _ => return Err(Error::InvalidArgument),
+        };
+
+        self.busy_wait()?;
+        if volatile {
+            self.volatile_write_enable()?;
+        } else {
+            self.write_enable()?;
+        }
+
+        let mut tx_cmd: [u8; 2] = [opcode, value];
+        self.pending[self.die as usize] = BusyOperation::WriteStatusRegister;
+        self.spi_transmit_and_receive(&mut tx_cmd, &mut [], 0)?;
+        self.busy_wait()
     }
 
-    fn erase_cmd(&mut self, address: u32, cmd: u8) -> Result<(), Error<()>>  {
+    fn erase_cmd(&mut self, address: u32, cmd: u8) -> Result<(), Error<SPI::Error>>  {
         /* Before Erase enable write enable latch */
         self.write_enable()?;
 
+        self.pending[self.die as usize] = erase_operation(&self.opcodes(), cmd);
+        #[cfg(feature = "suspend")]
+        {
+            self.erase_address[self.die as usize] = address;
+        }
         self.spi_transmit(cmd, address, &[])
     }
 }
+
+/// Largest number of bytes dumped per operation by `trace-bytes`.
+#[cfg(feature = "trace-bytes")]
+const TRACE_MAX_BYTES: usize = 16;
+
+/// Logs the bytes of a finished transaction on one line: `>` for bytes sent,
+/// `<` for bytes received. Long payloads are cut after `TRACE_MAX_BYTES`.
+#[cfg(feature = "trace-bytes")]
+pub(crate) fn trace_transaction(operations: &[Operation<'_, u8>]) {
+    use std::fmt::Write as _;
+
+    let mut line = std::string::String::from("W25QXX SPI");
+    for operation in operations {
+        let (direction, bytes): (&str, &[u8]) = match operation {
+            Operation::Write(bytes) => (">", bytes),
+            Operation::Read(bytes) | Operation::TransferInPlace(bytes) => ("<", bytes),
+            Operation::Transfer(read, _) => ("<", read),
+            Operation::DelayNs(_) => continue,
+        };
+
+        let _ = write!(line, " {}", direction);
+        trace_bytes(&mut line, bytes);
+    }
+
+    log::trace!("{}", line);
+}
+
+#[cfg(not(feature = "trace-bytes"))]
+pub(crate) fn trace_transaction(_operations: &[Operation<'_, u8>]) {}
+
+/// Logs the bytes of a finished multi-line transfer like
+/// [`trace_transaction`], with the lines of every phase: `>4` for bytes sent
+/// on four lines, `<2` for bytes received on two, `~8` for eight dummy
+/// clocks.
+#[cfg(feature = "trace-bytes")]
+fn trace_io_transaction(operations: &[IoOperation<'_>]) {
+    use std::fmt::Write as _;
+
+    let mut line = std::string::String::from("W25QXX SPI");
+    for operation in operations {
+        let (direction, lines, bytes): (&str, Lines, &[u8]) = match operation {
+            IoOperation::Write(lines, bytes) => (">", *lines, bytes),
+            IoOperation::Read(lines, bytes) => ("<", *lines, bytes),
+            IoOperation::Dummy(cycles) => {
+                let _ = write!(line, " ~{}", cycles);
+                continue;
+            }
+        };
+
+        let width = match lines {
+            Lines::Single => "",
+            Lines::Dual => "2",
+            Lines::Quad => "4",
+        };
+        let _ = write!(line, " {}{}", direction, width);
+        trace_bytes(&mut line, bytes);
+    }
+
+    log::trace!("{}", line);
+}
+
+#[cfg(not(feature = "trace-bytes"))]
+fn trace_io_transaction(_operations: &[IoOperation<'_>]) {}
+
+/// Appends `bytes` in hex, cut after `TRACE_MAX_BYTES`.
+#[cfg(feature = "trace-bytes")]
+fn trace_bytes(line: &mut std::string::String, bytes: &[u8]) {
+    use std::fmt::Write as _;
+
+    for byte in bytes.iter().take(TRACE_MAX_BYTES) {
+        let _ = write!(line, " {:02X}", byte);
+    }
+    if bytes.len() > TRACE_MAX_BYTES {
+        let _ = write!(line, " ..(+{})", bytes.len() - TRACE_MAX_BYTES);
+    }
+}
+
+/// Logs a decoded view of a finished transaction: opcode, address and data
+/// length, or the value read for status register commands.
+#[cfg(feature = "trace-commands")]
+pub(crate) fn trace_command(opcodes: &Opcodes, operations: &[Operation<'_, u8>]) {
+    let Some((Operation::Write(frame), data)) = operations.split_first() else {
+        return;
+    };
+    let Some(&opcode) = frame.first() else {
+        return;
+    };
+
+    if [opcodes.read_status1, opcodes.read_status2, opcodes.read_status3].contains(&opcode) {
+        if let Some(Operation::Read([status, ..])) = data.first() {
+            log::trace!("W25QXX - cmd 0x{:02X} status 0x{:02X}", opcode, status);
+        }
+        return;
+    }
+
+    let len: usize = data
+        .iter()
+        .map(|operation| match operation {
+            Operation::Write(bytes) => bytes.len(),
+            Operation::Read(bytes) | Operation::TransferInPlace(bytes) => bytes.len(),
+            Operation::Transfer(read, write) => core::cmp::max(read.len(), write.len()),
+            Operation::DelayNs(_) => 0,
+        })
+        .sum();
+
+    /* Commands with an address carry 3 or 4 address bytes after the opcode */
+    if let [_, address @ ..] = &frame[..] {
+        if address.len() >= 3 {
+            let address = address.iter().take(4).fold(0u32, |acc, byte| acc << 8 | *byte as u32);
+            log::trace!("W25QXX - cmd 0x{:02X} addr 0x{:08X} len {}", opcode, address, len);
+            return;
+        }
+    }
+
+    log::trace!("W25QXX - cmd 0x{:02X} len {}", opcode, len);
+}
+
+#[cfg(not(feature = "trace-commands"))]
+pub(crate) fn trace_command(_opcodes: &Opcodes, _operations: &[Operation<'_, u8>]) {}
+
+/// Logs a decoded view of a finished multi-line transfer: opcode, address,
+/// data length and the lines of the data. An I/O read sends its address with
+/// the mode bits after the opcode, and without the opcode (`--`) when it
+/// continues a continuous read.
+#[cfg(feature = "trace-commands")]
+fn trace_io_command(operations: &[IoOperation<'_>]) {
+    let reads = operations.iter().any(|operation| matches!(operation, IoOperation::Read(..)));
+    let (opcode, address, data): (Option<u8>, &[u8], &[IoOperation<'_>]) = match operations {
+        [IoOperation::Write(Lines::Single, [opcode, address @ ..]), data @ ..] if address.len() >= 3 => (Some(*opcode), address, data),
+        [IoOperation::Write(Lines::Single, [opcode]), IoOperation::Write(_, [address @ .., _mode]), data @ ..] if reads => (Some(*opcode), address, data),
+        [IoOperation::Write(Lines::Single, [opcode]), data @ ..] => (Some(*opcode), &[], data),
+        [IoOperation::Write(_, [address @ .., _mode]), data @ ..] if reads => (None, address, data),
+        _ => return,
+    };
+
+    let mut len: usize = 0;
+    let mut lines = Lines::Single;
+    for operation in data {
+        let (data_lines, bytes_len) = match operation {
+            IoOperation::Write(data_lines, bytes) => (data_lines, bytes.len()),
+            IoOperation::Read(data_lines, bytes) => (data_lines, bytes.len()),
+            IoOperation::Dummy(_) => continue,
+        };
+        len += bytes_len;
+        lines = *data_lines;
+    }
+
+    let address = (address.len() >= 3).then(|| address.iter().take(4).fold(0u32, |acc, byte| acc << 8 | *byte as u32));
+    match (opcode, address) {
+        (Some(opcode), Some(address)) => log::trace!("W25QXX - cmd 0x{:02X} addr 0x{:08X} len {} on {:?}", opcode, address, len, lines),
+        (Some(opcode), None) => log::trace!("W25QXX - cmd 0x{:02X} len {} on {:?}", opcode, len, lines),
+        (None, Some(address)) => log::trace!("W25QXX - cmd -- addr 0x{:08X} len {} on {:?}", address, len, lines),
+        (None, None) => {}
+    }
+}
+
+#[cfg(not(feature = "trace-commands"))]
+fn trace_io_command(_operations: &[IoOperation<'_>]) {}
+
+/// LB bit of security register `register` (1 to 3) in status register 2.
+#[cfg(feature = "security-registers")]
+fn security_lock_bit<E>(register: u8) -> Result<u8, Error<E>> {
+    match register {
+        1..=3 => Ok(1 << (crate::status::STATUS2_LB_SHIFT + register - 1)),
+        _ => Err(Error::InvalidArgument),
+    }
+}
+
+/// Set Read Parameters value for an SPI clock of `hz`, with the dummy bytes
+/// it gives Fast Read in QPI mode (two clocks per byte on four lines).
+#[cfg(feature = "qpi")]
+fn qpi_read_parameters(hz: Option<u32>) -> (u8, usize) {
+    let Some(hz) = hz else {
+        return (QPI_READ_PARAMETERS, QPI_FAST_READ_DUMMY_BYTES);
+    };
+
+    let step = QPI_DUMMY_CLOCK_LIMITS_HZ.iter().position(|limit| hz <= *limit).unwrap_or(QPI_DUMMY_CLOCK_LIMITS_HZ.len() - 1);
+    ((step as u8) << 4, step + 1)
+}
+
+/// Builds the opcode + 24-bit address header shared by most commands.
+pub(crate) fn address_frame(cmd: u8, address: u32) -> [u8; 4] {
+    [cmd, ((address >> 16) & 0xFF) as u8, ((address >> 8) & 0xFF) as u8, (address & 0xFF) as u8]
+}
+
+/// Builds the opcode + address header for the resolved address width,
+/// returning it together with its length.
+pub(crate) fn wide_address_frame(cmd: u8, address: u32, width: AddressWidth) -> ([u8; 5], usize) {
+    match width {
+        AddressWidth::Three | AddressWidth::Auto => {
+            let [cmd, a2, a1, a0] = address_frame(cmd, address);
+            ([cmd, a2, a1, a0, 0], 4)
+        }
+        AddressWidth::Four => {
+            let [a3, a2, a1, a0] = address.to_be_bytes();
+            ([cmd, a3, a2, a1, a0], 5)
+        }
+    }
+}
+
+/// Compares the JEDEC ID bytes (manufacturer, memory type, capacity) with the
+/// expected part, telling a silent bus apart from a wrong chip. With
+/// `detect_capacity` or an [`IdCheck::AnyOf`] list, any accepted part with a
+/// known capacity code updates `params` to match it.
+pub(crate) fn check_id<E>(params: &mut ChipParams, id_check: IdCheck, found: [u8; 3]) -> Result<(), Error<E>> {
+    if found == [0x00; 3] || found == [0xFF; 3] {
+        return Err(Error::NoResponse);
+    }
+
+    let [manufacturer_id, memory_type, capacity_id] = found;
+    let device_id = u16::from_be_bytes([memory_type, capacity_id]);
+
+    match id_check {
+        IdCheck::Params => {}
+        IdCheck::AnyOf(ids) => {
+            if !ids.contains(&(manufacturer_id, device_id)) {
+                return Err(Error::UnexpectedId { manufacturer_id, device_id });
+            }
+
+            params.manufacturer_id = manufacturer_id;
+            params.device_id = device_id;
+            if let Some(quirks) = Quirks::lookup(manufacturer_id, device_id) {
+                quirks.apply(params);
+            }
+            if let Some(capacity) = capacity_from_id(capacity_id) {
+                params.capacity = capacity * params.dies as usize;
+            }
+            return Ok(());
+        }
+        IdCheck::Skip => return Ok(()),
+    }
+
+    if params.detect_capacity {
+        if let (Some(quirks), Some(capacity)) = (Quirks::lookup(manufacturer_id, device_id), capacity_from_id(capacity_id)) {
+            params.manufacturer_id = manufacturer_id;
+            params.device_id = device_id;
+            quirks.apply(params);
+            params.capacity = capacity * params.dies as usize;
+            return Ok(());
+        }
+    }
+
+    if manufacturer_id != params.manufacturer_id || device_id != params.device_id {
+        return Err(Error::UnexpectedId { manufacturer_id, device_id });
+    }
+
+    Ok(())
+}
+
+/// Operation started by the erase opcode `cmd`.
+pub(crate) fn erase_operation(opcodes: &Opcodes, cmd: u8) -> BusyOperation {
+    if cmd == opcodes.sector_erase {
+        BusyOperation::SectorErase
+    } else if cmd == opcodes.block32_erase {
+        BusyOperation::Block32Erase
+    } else {
+        BusyOperation::Block64Erase
+    }
+}
+
+/// Picks the largest erase command usable at `addr` with `size` bytes left,
+/// returning it together with the number of bytes it erases. Without
+/// `block32`, 32K blocks are left to sector erases.
+pub(crate) fn erase_step(opcodes: &Opcodes, addr: u32, size: usize, block32: bool) -> Option<(u8, usize)> {
+    [
+        (opcodes.block64_erase, W25QXX_BLOCK64K_SIZE),
+        (opcodes.block32_erase, W25QXX_BLOCK32K_SIZE),
+        (opcodes.sector_erase, W25QXX_SECTOR_SIZE),
+    ]
+    .into_iter()
+    .filter(|(_, erase_size)| block32 || *erase_size != W25QXX_BLOCK32K_SIZE)
+    .find(|(_, erase_size)| addr.is_multiple_of(*erase_size as u32) && size >= *erase_size)
+}
@@ -0,0 +1,258 @@
+//! Per-sector erase accounting for wear estimation.
+//!
+//! [`WearTracked`] wraps the driver and counts every sector erase that falls
+//! inside a tracked window of `N` sectors. The table lives in RAM and can be
+//! persisted to (and restored from) two dedicated flash sectors so the counts
+//! survive reboots. Like [`ConfigBlob`](crate::ConfigBlob), each save goes to
+//! the copy that does not hold the latest table, so a power loss during a save
+//! keeps the previous one.
+
+use crate::checksum::{Checksum, Crc32};
+use crate::w25qxx::{Error, W25qxx, W25QXX_PAGE_SIZE, W25QXX_SECTOR_SIZE};
+use core::ops::Deref;
+use embedded_hal::delay::DelayNs;
+use embedded_hal::spi::{ErrorType, SpiDevice};
+
+/// Typical erase/program endurance of a W25QXX sector (datasheet minimum).
+pub const W25QXX_SECTOR_ENDURANCE: u32 = 100_000;
+
+/// Marks a persisted wear table ("WEAR" in little endian).
+const WEAR_MAGIC: u32 = 0x5241_4557;
+
+/// Magic, sector count and sequence number.
+const WEAR_HEADER_SIZE: usize = 12;
+
+/// Erase counters for `N` consecutive sectors starting at `base`.
+#[derive(Clone, Debug)]
+pub struct WearMap<const N: usize> {
+    base: u32,
+    counts: [u32; N],
+}
+
+impl<const N: usize> WearMap<N> {
+    pub const fn new(base: u32) -> Self {
+        WearMap { base, counts: [0; N] }
+    }
+
+    /// Address of the first tracked sector.
+    pub fn base(&self) -> u32 {
+        self.base
+    }
+
+    /// Raw erase counters, one per sector.
+    pub fn counts(&self) -> &[u32; N] {
+        &self.counts
+    }
+
+    /// Erase count of the sector containing `address`, if it is tracked.
+    pub fn erase_count(&self, address: u32) -> Option<u32> {
+        self.index(address).map(|index| self.counts[index])
+    }
+
+    /// Estimated erase cycles left for the sector containing `address`.
+    pub fn remaining_endurance(&self, address: u32) -> Option<u32> {
+        self.erase_count(address)
+            .map(|count| W25QXX_SECTOR_ENDURANCE.saturating_sub(count))
+    }
+
+    /// Address and erase count of the most worn sector.
+    pub fn hottest(&self) -> Option<(u32, u32)> {
+        let (index, count) = self.counts.iter().enumerate().max_by_key(|(_, count)| **count)?;
+        Some((self.base + (index * W25QXX_SECTOR_SIZE) as u32, *count))
+    }
+
+    /// Total number of sector erases recorded.
+    pub fn total(&self) -> u64 {
+        self.counts.iter().map(|count| *count as u64).sum()
+    }
+
+    /// Records an erase of `len` bytes starting at `address`. Sectors outside
+    /// the tracked window are ignored.
+    pub fn record_erase(&mut self, address: u32, len: usize) {
+        let mut addr = address;
+        let end = address as u64 + len as u64;
+
+        while (addr as u64) < end {
+            if let Some(index) = self.index(addr) {
+                self.counts[index] = self.counts[index].saturating_add(1);
+            }
+            addr += W25QXX_SECTOR_SIZE as u32;
+        }
+    }
+
+    /// Records an erase of every tracked sector (chip erase).
+    pub fn record_all(&mut self) {
+        for count in self.counts.iter_mut() {
+            *count = count.saturating_add(1);
+        }
+    }
+
+    fn index(&self, address: u32) -> Option<usize> {
+        let index = address.checked_sub(self.base)? as usize / W25QXX_SECTOR_SIZE;
+        (index < N).then_some(index)
+    }
+}
+
+/// Driver wrapper that keeps a [`WearMap`] up to date on every erase.
+///
+/// Unlike most wrappers it has no `DerefMut`: driver methods that erase
+/// would bypass the counting. [`read`](Self::read), [`write`](Self::write)
+/// and [`digest`](Self::digest) are passed on, the `&self` methods of the
+/// driver are reachable through `Deref`.
+pub struct WearTracked<SPI: ErrorType, D, const N: usize> {
+    flash: W25qxx<SPI, D>,
+    map: WearMap<N>,
+}
+
+impl<SPI: ErrorType, D, const N: usize> WearTracked<SPI, D, N> {
+    /// Tracks `N` sectors starting at `base`.
+    pub fn new(flash: W25qxx<SPI, D>, base: u32) -> Self {
+        WearTracked { flash, map: WearMap::new(base) }
+    }
+
+    pub fn wear_map(&self) -> &WearMap<N> {
+        &self.map
+    }
+
+    /// Gives back the wrapped driver.
+    pub fn into_inner(self) -> W25qxx<SPI, D> {
+        self.flash
+    }
+}
+
+impl<SPI, D, const N: usize> WearTracked<SPI, D, N>
+where
+    SPI: SpiDevice,
+    D: DelayNs,
+{
+    /// Header and counters, followed by their CRC.
+    const TABLE_SIZE: usize = WEAR_HEADER_SIZE + N * 4;
+
+    pub fn read(&mut self, address: u32, buffer: &mut [u8]) -> Result<(), Error<SPI::Error>> {
+        self.flash.read(address, buffer)
+    }
+
+    pub fn write(&mut self, address: u32, buffer: &[u8]) -> Result<(), Error<SPI::Error>> {
+        self.flash.write(address, buffer)
+    }
+
+    pub fn digest<C: Checksum>(&mut self, address: u32, len: usize, hasher: C) -> Result<C::Output, Error<SPI::Error>> {
+        self.flash.digest(address, len, hasher)
+    }
+
+    pub fn erase(&mut self, address: u32, len: usize) -> Result<(), Error<SPI::Error>> {
+        self.flash.erase(address, len)?;
+        self.map.record_erase(address, len);
+        Ok(())
+    }
+
+    pub fn chip_erase(&mut self) -> Result<(), Error<SPI::Error>> {
+        self.flash.chip_erase()?;
+        self.map.record_all();
+        Ok(())
+    }
+
+    /// Saves the table to the two sectors at `address`, erasing the copy
+    /// that does not hold the latest table. The header goes last, so the copy
+    /// only counts once it is complete.
+    pub fn persist(&mut self, address: u32) -> Result<(), Error<SPI::Error>> {
+        if Self::TABLE_SIZE + 4 > W25QXX_SECTOR_SIZE {
+            return Err(Error::InvalidArgument);
+        }
+
+        let (index, sequence) = match self.latest_copy(address)? {
+            Some((index, sequence)) => (1 - index, sequence.wrapping_add(1)),
+            None => (0, 0),
+        };
+        let copy = Self::copy_address(address, index);
+        self.erase(copy, W25QXX_SECTOR_SIZE)?;
+
+        let mut header: [u8; WEAR_HEADER_SIZE] = [0; WEAR_HEADER_SIZE];
+        header[..4].copy_from_slice(&WEAR_MAGIC.to_le_bytes());
+        header[4..8].copy_from_slice(&(N as u32).to_le_bytes());
+        header[8..].copy_from_slice(&sequence.to_le_bytes());
+
+        let mut crc = Crc32::new();
+        crc.update(&header);
+
+        /* Stream the counters one page at a time */
+        let mut page: [u8; W25QXX_PAGE_SIZE] = [0; W25QXX_PAGE_SIZE];
+        let mut addr = copy + WEAR_HEADER_SIZE as u32;
+        for counters in self.map.counts.chunks(W25QXX_PAGE_SIZE / 4) {
+            let size = counters.len() * 4;
+            for (bytes, count) in page.chunks_exact_mut(4).zip(counters) {
+                bytes.copy_from_slice(&count.to_le_bytes());
+            }
+            crc.update(&page[..size]);
+            self.flash.write(addr, &page[..size])?;
+            addr += size as u32;
+        }
+
+        self.flash.write(addr, &crc.finalize().to_le_bytes())?;
+        self.flash.write(copy, &header)
+    }
+
+    /// Restores the latest table saved with [`persist`](Self::persist) to
+    /// the two sectors at `address`. Returns `Ok(false)` and leaves the RAM
+    /// table untouched when neither holds a valid table for `N` sectors.
+    pub fn load(&mut self, address: u32) -> Result<bool, Error<SPI::Error>> {
+        let Some((index, _)) = self.latest_copy(address)? else {
+            return Ok(false);
+        };
+
+        let mut page: [u8; W25QXX_PAGE_SIZE] = [0; W25QXX_PAGE_SIZE];
+        let mut addr = Self::copy_address(address, index) + WEAR_HEADER_SIZE as u32;
+        for counters in self.map.counts.chunks_mut(W25QXX_PAGE_SIZE / 4) {
+            let size = counters.len() * 4;
+            self.flash.read(addr, &mut page[..size])?;
+            for (count, bytes) in counters.iter_mut().zip(page.chunks_exact(4)) {
+                *count = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+            }
+            addr += size as u32;
+        }
+
+        Ok(true)
+    }
+
+    /// Copy holding the newest valid table, and its sequence number.
+    fn latest_copy(&mut self, address: u32) -> Result<Option<(usize, u32)>, Error<SPI::Error>> {
+        let first = self.read_copy(Self::copy_address(address, 0))?;
+        let second = self.read_copy(Self::copy_address(address, 1))?;
+
+        Ok(match (first, second) {
+            /* Sequence numbers of the two copies differ by one, even across a wrap */
+            (Some(first), Some(second)) if (second.wrapping_sub(first) as i32) > 0 => Some((1, second)),
+            (Some(first), _) => Some((0, first)),
+            (None, Some(second)) => Some((1, second)),
+            (None, None) => None,
+        })
+    }
+
+    /// Sequence number of the table at `address` if its CRC matches.
+    fn read_copy(&mut self, address: u32) -> Result<Option<u32>, Error<SPI::Error>> {
+        let mut header: [u8; WEAR_HEADER_SIZE] = [0; WEAR_HEADER_SIZE];
+        self.flash.read(address, &mut header)?;
+
+        if header[..4] != WEAR_MAGIC.to_le_bytes() || header[4..8] != (N as u32).to_le_bytes() {
+            return Ok(None);
+        }
+
+        let crc = self.flash.digest(address, Self::TABLE_SIZE, Crc32::new())?;
+        let mut stored: [u8; 4] = [0; 4];
+        self.flash.read(address + Self::TABLE_SIZE as u32, &mut stored)?;
+
+        Ok((crc == u32::from_le_bytes(stored)).then_some(u32::from_le_bytes([header[8], header[9], header[10], header[11]])))
+    }
+
+    fn copy_address(address: u32, index: usize) -> u32 {
+        address + (index * W25QXX_SECTOR_SIZE) as u32
+    }
+}
+
+impl<SPI: ErrorType, D, const N: usize> Deref for WearTracked<SPI, D, N> {
+    type Target = W25qxx<SPI, D>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.flash
+    }
+}
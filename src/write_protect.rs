@@ -0,0 +1,92 @@
+//! Status register protection through the WP# pin.
+//!
+//! With SRP set ([`StatusProtection::Hardware`]) the chip only accepts status
+//! register writes while WP# is high. [`WriteProtected`] owns the GPIO driving
+//! WP#, keeps it low so that stray writes cannot change the protection bits,
+//! and raises it around the status register writes it makes itself.
+
+use crate::status::StatusProtection;
+use crate::w25qxx::{Error, W25qxx};
+use core::ops::{Deref, DerefMut};
+use embedded_hal::delay::DelayNs;
+use embedded_hal::digital::OutputPin;
+use embedded_hal::spi::{ErrorType, SpiDevice};
+
+/// Driver wrapper owning the WP# pin.
+///
+/// All other driver methods are reachable through `Deref`; status register
+/// writes that bypass [`WriteProtected::write_status_register`] are ignored by
+/// the chip while it is in hardware protection.
+pub struct WriteProtected<SPI: ErrorType, D, WP> {
+    flash: W25qxx<SPI, D>,
+    wp: WP,
+}
+
+impl<SPI: ErrorType, D, WP> WriteProtected<SPI, D, WP> {
+    /// Gives back the wrapped driver and the pin, as it was left.
+    pub fn into_inner(self) -> (W25qxx<SPI, D>, WP) {
+        (self.flash, self.wp)
+    }
+}
+
+impl<SPI, D, WP> WriteProtected<SPI, D, WP>
+where
+    SPI: SpiDevice,
+    D: DelayNs,
+    WP: OutputPin,
+{
+    /// Takes the driver and the pin, and drives WP# low.
+    pub fn new(flash: W25qxx<SPI, D>, mut wp: WP) -> Result<Self, Error<SPI::Error>> {
+        wp.set_low().map_err(|_| Error::PinFailed)?;
+        Ok(WriteProtected { flash, wp })
+    }
+
+    /// Sets SRP, so the status registers (and with them the block
+    /// protection) are frozen while WP# stays low. WP# has no protection
+    /// function while Quad Enable is set: fails with
+    /// [`Error::Unsupported`] then.
+    pub fn enter_hardware_protection(&mut self) -> Result<(), Error<SPI::Error>> {
+        if self.flash.quad_enabled()? {
+            return Err(Error::Unsupported);
+        }
+
+        self.unguarded(|flash| flash.set_status_protection(StatusProtection::Hardware))
+    }
+
+    /// Clears SRP: the status registers are writable again whatever WP#
+    /// does.
+    pub fn leave_hardware_protection(&mut self) -> Result<(), Error<SPI::Error>> {
+        self.unguarded(|flash| flash.set_status_protection(StatusProtection::Software))
+    }
+
+    /// Raises WP# for one status register write.
+    pub fn write_status_register(&mut self, reg_num: u8, value: u8) -> Result<(), Error<SPI::Error>> {
+        self.unguarded(|flash| flash.write_status_register(reg_num, value))
+    }
+
+    /// Runs `operation` with WP# high and drives it low again, also when the
+    /// operation fails.
+    fn unguarded<T>(
+        &mut self,
+        operation: impl FnOnce(&mut W25qxx<SPI, D>) -> Result<T, Error<SPI::Error>>,
+    ) -> Result<T, Error<SPI::Error>> {
+        self.wp.set_high().map_err(|_| Error::PinFailed)?;
+        let result = operation(&mut self.flash);
+        self.wp.set_low().map_err(|_| Error::PinFailed)?;
+        result
+    }
+}
+
+impl<SPI: ErrorType, D, WP> Deref for WriteProtected<SPI, D, WP> {
+    type Target = W25qxx<SPI, D>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.flash
+    }
+}
+
+impl<SPI: ErrorType, D, WP> DerefMut for WriteProtected<SPI, D, WP> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.flash
+    }
+}
@@ -0,0 +1,64 @@
+//! Address framing: 3 bytes up to 16 MiB, 4 bytes beyond, through 4-byte
+//! mode or the 4-byte opcodes.
+
+mod common;
+
+use common::{Board, NoDelay};
+use w25qxx::{AddressWidth, ChipParams, W25qxx, W25QXX_CAPACITY_256};
+
+const W25Q256: ChipParams = ChipParams { device_id: 0x4019, capacity: W25QXX_CAPACITY_256, ..ChipParams::W25Q128 };
+
+/// Command frames with the opcode `opcode`.
+fn frames(board: &Board, opcode: u8) -> Vec<Vec<u8>> {
+    board.take_commands().into_iter().filter(|frame| frame[0] == opcode).collect()
+}
+
+#[test]
+fn w25q128_uses_3_byte_addresses() {
+    let board = Board::new();
+    let mut flash = board.flash();
+    assert_eq!(flash.address_width(), AddressWidth::Three);
+
+    flash.write(0xFF_FF00, &[0x5A; 4]).unwrap();
+    assert_eq!(frames(&board, 0x02), [vec![0x02, 0xFF, 0xFF, 0x00]]);
+}
+
+#[test]
+fn w25q256_enters_4_byte_mode() {
+    let board = Board::with_params(W25Q256);
+    let mut flash = board.flash();
+    assert_eq!(flash.address_width(), AddressWidth::Four);
+    assert_eq!(frames(&board, W25Q256.opcodes.enter_4byte_address).len(), 1);
+
+    /* Above 16 MiB, where a 3-byte address would wrap to the bottom */
+    flash.write(0x123_4500, &[0x5A; 4]).unwrap();
+    assert_eq!(frames(&board, 0x02), [vec![0x02, 0x01, 0x23, 0x45, 0x00]]);
+    assert_eq!(board.memory(0x123_4500, 4), [0x5A; 4]);
+    assert_eq!(board.memory(0x23_4500, 4), [0xFF; 4]);
+
+    let mut buffer = [0u8; 4];
+    flash.read(0x123_4500, &mut buffer).unwrap();
+    assert_eq!(buffer, [0x5A; 4]);
+    assert_eq!(frames(&board, 0x0B), [vec![0x0B, 0x01, 0x23, 0x45, 0x00]]);
+
+    flash.erase(0x123_4000, 0x1000).unwrap();
+    assert_eq!(frames(&board, 0x20), [vec![0x20, 0x01, 0x23, 0x40, 0x00]]);
+    assert_eq!(board.memory(0x123_4500, 4), [0xFF; 4]);
+}
+
+#[test]
+fn w25q256_with_4_byte_opcodes_stays_in_3_byte_mode() {
+    let board = Board::with_params(W25Q256);
+    board.power_up();
+    let mut flash = W25qxx::builder(board.clone(), NoDelay).params(W25Q256).four_byte_opcodes(true).build().unwrap().init().unwrap();
+    let opcodes = W25Q256.opcodes;
+    assert!(frames(&board, opcodes.enter_4byte_address).is_empty());
+
+    flash.write(0x123_4500, &[0x5A; 4]).unwrap();
+    assert_eq!(frames(&board, opcodes.page_program_4b), [vec![opcodes.page_program_4b, 0x01, 0x23, 0x45, 0x00]]);
+    assert_eq!(board.memory(0x123_4500, 4), [0x5A; 4]);
+
+    flash.erase(0x123_4000, 0x1000).unwrap();
+    assert_eq!(frames(&board, opcodes.sector_erase_4b), [vec![opcodes.sector_erase_4b, 0x01, 0x23, 0x40, 0x00]]);
+    assert_eq!(board.memory(0x123_4500, 4), [0xFF; 4]);
+}
@@ -0,0 +1,56 @@
+//! Async driver against the simulated chip.
+
+mod common;
+
+use common::{Board, NoDelay};
+use embedded_hal::spi::{Operation, SpiDevice};
+use w25qxx::{BusyOperation, BusyTimeouts, ChipParams, Config, Error, W25qxxAsync};
+
+#[tokio::test]
+async fn init_selects_die_0_of_stacked_parts() {
+    let board = Board::with_params(ChipParams::W25M512);
+    /* Left on die 1 by the firmware before a restart */
+    let die_select = ChipParams::W25M512.opcodes.die_select;
+    board.sim.borrow_mut().transaction(&mut [Operation::Write(&[die_select, 1])]).unwrap();
+
+    let mut flash = W25qxxAsync::with_params(board.clone(), NoDelay, ChipParams::W25M512, Config::default()).unwrap();
+    flash.init().await.unwrap();
+    flash.write(0x100, &[0x42; 4]).await.unwrap();
+
+    assert_eq!(board.memory(0x100, 4), [0x42; 4]);
+}
+
+#[tokio::test]
+async fn accesses_past_the_end_are_rejected() {
+    let board = Board::new();
+    let capacity = ChipParams::default().capacity as u32;
+    let mut flash = W25qxxAsync::new(board.clone(), NoDelay).unwrap();
+    flash.init().await.unwrap();
+    let mut buffer = [0u8; 4];
+
+    assert!(matches!(flash.read(capacity - 2, &mut buffer).await, Err(Error::InvalidAddress)));
+    assert!(matches!(flash.write(capacity - 2, &[0x00; 4]).await, Err(Error::InvalidAddress)));
+    assert!(matches!(flash.erase(capacity, 4096).await, Err(Error::InvalidAddress)));
+    assert!(matches!(flash.erase(0xFFFF_F000, 0x2000).await, Err(Error::InvalidAddress)));
+
+    /* Nothing wrapped around to address 0 */
+    assert_eq!(board.memory(0, 4), [0xFF; 4]);
+}
+
+#[tokio::test]
+async fn busy_wait_honours_configured_timeouts() {
+    let board = Board::new();
+    let config = Config {
+        busy_poll_interval_us: 1,
+        busy_timeouts: BusyTimeouts { page_program_us: 2, ..BusyTimeouts::default() },
+        ..Config::default()
+    };
+    let mut flash = W25qxxAsync::with_params(board.clone(), NoDelay, ChipParams::default(), config).unwrap();
+    flash.init().await.unwrap();
+    board.sim.borrow_mut().set_busy_polls(10);
+
+    flash.write(0, &[0x00; 4]).await.unwrap();
+    let result = flash.write(0x100, &[0x00; 4]).await;
+
+    assert!(matches!(result, Err(Error::Timeout { operation: BusyOperation::PageProgram })));
+}
@@ -0,0 +1,64 @@
+//! Block device view and MBR helpers on the simulated chip.
+
+mod common;
+
+use common::Board;
+use w25qxx::block::{read_mbr, write_mbr, PartitionEntry, BLOCK_SIZE};
+use w25qxx::{BlockDevice, BlockError, FlashBlockDevice};
+
+const ENTRIES: [PartitionEntry; 4] = [
+    PartitionEntry { status: 0x80, kind: 0x0C, start_lba: 8, num_blocks: 2048 },
+    PartitionEntry { status: 0x00, kind: 0x83, start_lba: 2056, num_blocks: 4096 },
+    PartitionEntry { status: 0, kind: 0, start_lba: 0, num_blocks: 0 },
+    PartitionEntry { status: 0, kind: 0, start_lba: 0, num_blocks: 0 },
+];
+
+#[test]
+fn mbr_round_trips_on_a_blank_chip() {
+    let board = Board::new();
+    let mut device = FlashBlockDevice::new(board.flash());
+
+    assert_eq!(read_mbr(&mut device).unwrap(), None);
+    write_mbr(&mut device, &ENTRIES).unwrap();
+
+    assert_eq!(read_mbr(&mut device).unwrap(), Some(ENTRIES));
+    let mbr = board.memory(0, BLOCK_SIZE);
+    assert!(mbr[..0x1BE].iter().all(|byte| *byte == 0x00));
+    assert_eq!(mbr[BLOCK_SIZE - 2..], [0x55, 0xAA]);
+}
+
+#[test]
+fn write_mbr_keeps_the_boot_code() {
+    let board = Board::new();
+    let mut device = FlashBlockDevice::new(board.flash());
+
+    let mut block = [0u8; BLOCK_SIZE];
+    for (index, byte) in block[..0x1BE].iter_mut().enumerate() {
+        *byte = index as u8;
+    }
+    block[BLOCK_SIZE - 2..].copy_from_slice(&[0x55, 0xAA]);
+    device.write_blocks(0, &[block]).unwrap();
+
+    /* Rewriting the table clears bits, so the sector goes through an erase */
+    write_mbr(&mut device, &ENTRIES).unwrap();
+    write_mbr(&mut device, &[ENTRIES[1], ENTRIES[0], ENTRIES[2], ENTRIES[3]]).unwrap();
+
+    assert_eq!(read_mbr(&mut device).unwrap(), Some([ENTRIES[1], ENTRIES[0], ENTRIES[2], ENTRIES[3]]));
+    assert_eq!(board.memory(0, 0x1BE), block[..0x1BE]);
+}
+
+#[test]
+fn blocks_past_the_end_are_rejected() {
+    let board = Board::new();
+    let mut device = FlashBlockDevice::new(board.flash());
+    let last = device.num_blocks() - 1;
+    let mut blocks = [[0u8; BLOCK_SIZE]; 2];
+
+    assert!(matches!(device.read_blocks(last, &mut blocks), Err(BlockError::OutOfBounds)));
+    assert!(matches!(device.write_blocks(last, &blocks), Err(BlockError::OutOfBounds)));
+    /* Block indexes whose byte address no longer fits 32 bits */
+    assert!(matches!(device.read_blocks(1 << 23, &mut blocks[..1]), Err(BlockError::OutOfBounds)));
+    assert!(matches!(device.write_blocks(u32::MAX, &blocks[..1]), Err(BlockError::OutOfBounds)));
+
+    device.read_blocks(last, &mut blocks[..1]).unwrap();
+}
@@ -0,0 +1,25 @@
+//! Accesses past the end of the chip.
+
+mod common;
+
+use common::Board;
+use w25qxx::{Error, W25QXX_SECTOR_SIZE};
+
+#[test]
+fn accesses_past_the_chip_fail() {
+    let board = Board::new();
+    let mut flash = board.flash();
+    let end = flash.capacity() as u32;
+    let mut buffer = [0u8; 16];
+
+    assert!(matches!(flash.read(end - 8, &mut buffer), Err(Error::InvalidAddress)));
+    assert!(matches!(flash.write(end - 8, &buffer), Err(Error::InvalidAddress)));
+    assert!(matches!(flash.fill(end - 8, 16, &[0x00]), Err(Error::InvalidAddress)));
+    assert!(matches!(flash.overwrite(end - 8, &buffer), Err(Error::InvalidAddress)));
+    assert!(matches!(flash.erase(end, W25QXX_SECTOR_SIZE), Err(Error::InvalidAddress)));
+    assert!(matches!(flash.erase(u32::MAX - 0xFFF, 2 * W25QXX_SECTOR_SIZE), Err(Error::InvalidAddress)));
+
+    flash.read(end - 16, &mut buffer).unwrap();
+    flash.erase(end - W25QXX_SECTOR_SIZE as u32, W25QXX_SECTOR_SIZE).unwrap();
+    flash.write(end - 16, &buffer).unwrap();
+}
@@ -0,0 +1,92 @@
+//! Circular log against the simulated chip, with power cuts before each
+//! program and erase.
+
+mod common;
+
+use common::{Board, Flash};
+use w25qxx::{CircularLog, Error, MAX_LOG_RECORD_SIZE, W25QXX_SECTOR_SIZE};
+
+const AREA: u32 = 0x40000;
+
+fn records(log: &CircularLog, flash: &mut Flash) -> Vec<Vec<u8>> {
+    let mut records = Vec::new();
+    let mut buffer = [0u8; MAX_LOG_RECORD_SIZE];
+    log.iter(flash, &mut buffer, |record| records.push(record.to_vec())).unwrap();
+    records
+}
+
+/// Record `index`: its number, then filler of varying length.
+fn record(index: u32) -> Vec<u8> {
+    let mut record = index.to_le_bytes().to_vec();
+    record.resize(4 + (index as usize * 13) % 60, index as u8);
+    record
+}
+
+#[test]
+fn keeps_the_latest_records_oldest_first() {
+    let board = Board::new();
+    let mut flash = board.flash();
+    let mut log = CircularLog::open(&mut flash, AREA, 3 * W25QXX_SECTOR_SIZE).unwrap();
+
+    for index in 0..1000 {
+        log.append(&mut flash, &record(index)).unwrap();
+    }
+
+    let found = records(&log, &mut flash);
+    let first = u32::from_le_bytes(found[0][..4].try_into().unwrap());
+    assert!(found.iter().map(Vec::len).sum::<usize>() >= log.capacity() / 2);
+    assert_eq!(found, (first..1000).map(record).collect::<Vec<_>>());
+
+    let mut flash = board.flash();
+    let log = CircularLog::open(&mut flash, AREA, 3 * W25QXX_SECTOR_SIZE).unwrap();
+    assert_eq!(records(&log, &mut flash), found);
+}
+
+#[test]
+fn clear_and_oversized_records() {
+    let board = Board::new();
+    let mut flash = board.flash();
+    let mut log = CircularLog::open(&mut flash, AREA, 2 * W25QXX_SECTOR_SIZE).unwrap();
+
+    log.append(&mut flash, b"boot").unwrap();
+    assert!(matches!(log.append(&mut flash, &[0; MAX_LOG_RECORD_SIZE + 1]), Err(Error::InvalidArgument)));
+    assert_eq!(records(&log, &mut flash), [b"boot".to_vec()]);
+
+    log.clear(&mut flash).unwrap();
+    assert!(records(&log, &mut flash).is_empty());
+}
+
+/// Appends until the log wraps around, cutting the power before each
+/// program and erase. The log must keep an unbroken run of records ending
+/// with the last or the interrupted one, and take new records.
+#[test]
+fn power_cuts_leave_an_unbroken_log() {
+    let board = Board::new();
+    let appends = 300;
+
+    for cut in 0.. {
+        let mut flash = board.flash();
+        flash.erase(AREA, 2 * W25QXX_SECTOR_SIZE).unwrap();
+        let mut log = CircularLog::open(&mut flash, AREA, 2 * W25QXX_SECTOR_SIZE).unwrap();
+
+        board.cut_after(cut);
+        let done = (0..appends).take_while(|index| log.append(&mut flash, &record(*index)).is_ok()).count() as u32;
+        if done == appends {
+            break;
+        }
+
+        let mut flash = board.flash();
+        let mut log = CircularLog::open(&mut flash, AREA, 2 * W25QXX_SECTOR_SIZE).unwrap();
+        let found = records(&log, &mut flash);
+        if let Some(last) = found.last() {
+            let last = u32::from_le_bytes(last[..4].try_into().unwrap());
+            let first = last + 1 - found.len() as u32;
+            assert!(last + 1 == done || last == done, "cut after {cut} writes");
+            assert_eq!(found, (first..=last).map(record).collect::<Vec<_>>(), "cut after {cut} writes");
+        } else {
+            assert!(done <= 1, "cut after {cut} writes");
+        }
+        log.append(&mut flash, b"after").unwrap();
+        assert_eq!(records(&log, &mut flash).last().unwrap(), b"after");
+    }
+}
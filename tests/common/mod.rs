@@ -0,0 +1,145 @@
+//! Helpers shared by the integration tests: a [`Board`] wiring the driver to
+//! a [`SimFlash`] the test keeps a handle on, to cut the power or make a
+//! sector fail to erase.
+
+#![allow(dead_code)]
+
+use embedded_hal::delay::DelayNs;
+use embedded_hal::spi::{ErrorKind, ErrorType, Operation, SpiDevice};
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use w25qxx::sim::SimFlash;
+use w25qxx::{ChipParams, W25qxx};
+
+/// Delay returning at once: the simulated chip counts polls, not time.
+pub struct NoDelay;
+
+impl DelayNs for NoDelay {
+    fn delay_ns(&mut self, _ns: u32) {}
+}
+
+impl embedded_hal_async::delay::DelayNs for NoDelay {
+    async fn delay_ns(&mut self, _ns: u32) {}
+}
+
+pub type Flash = W25qxx<Board, NoDelay>;
+
+/// Simulated chip behind a cloneable [`SpiDevice`].
+#[derive(Clone)]
+pub struct Board {
+    pub sim: Rc<RefCell<SimFlash>>,
+    /// Programs and erases left before the power goes, `None` while it
+    /// stays on.
+    budget: Rc<Cell<Option<usize>>>,
+    /// Sector whose erases program a zero byte instead.
+    stuck: Rc<Cell<Option<u32>>>,
+    /// Command frame (first write) of every transaction so far.
+    commands: Rc<RefCell<Vec<Vec<u8>>>>,
+}
+
+impl Board {
+    pub fn new() -> Board {
+        Board::with_params(ChipParams::default())
+    }
+
+    pub fn with_params(params: ChipParams) -> Board {
+        Board {
+            sim: Rc::new(RefCell::new(SimFlash::new(params))),
+            budget: Rc::new(Cell::new(None)),
+            stuck: Rc::new(Cell::new(None)),
+            commands: Rc::new(RefCell::new(Vec::new())),
+        }
+    }
+
+    /// Driver for the chip, initialized after a power-up.
+    pub fn flash(&self) -> Flash {
+        self.power_up();
+        W25qxx::builder(self.clone(), NoDelay).build().unwrap().init().unwrap()
+    }
+
+    /// Cuts the power once `writes` more programs or erases went through:
+    /// the next one and every transaction after it fail.
+    pub fn cut_after(&self, writes: usize) {
+        self.budget.set(Some(writes));
+    }
+
+    /// Powers the chip up again: the operation in progress ends and the
+    /// volatile state is reset.
+    pub fn power_up(&self) {
+        self.budget.set(None);
+        let mut sim = self.sim.borrow_mut();
+        sim.transaction(&mut [Operation::Write(&[0x66])]).unwrap();
+        sim.transaction(&mut [Operation::Write(&[0x99])]).unwrap();
+    }
+
+    /// Makes the sector at `address` wear out: its erases leave a
+    /// programmed byte behind while keeping the chip busy as long.
+    pub fn stick_sector(&self, address: u32) {
+        self.stuck.set(Some(address));
+    }
+
+    /// Command frames sent since the last call, oldest first.
+    pub fn take_commands(&self) -> Vec<Vec<u8>> {
+        self.commands.take()
+    }
+
+    /// Bytes of the chip at `address`.
+    pub fn memory(&self, address: u32, len: usize) -> Vec<u8> {
+        self.sim.borrow().memory()[address as usize..address as usize + len].to_vec()
+    }
+}
+
+/// Whether `opcode` programs or erases the array.
+fn is_write(opcode: u8) -> bool {
+    let opcodes = ChipParams::default().opcodes;
+    [
+        opcodes.page_program,
+        opcodes.page_program_4b,
+        opcodes.sector_erase,
+        opcodes.sector_erase_4b,
+        opcodes.block32_erase,
+        opcodes.block64_erase,
+        opcodes.block64_erase_4b,
+        opcodes.chip_erase,
+    ]
+    .contains(&opcode)
+}
+
+impl ErrorType for Board {
+    type Error = ErrorKind;
+}
+
+impl SpiDevice for Board {
+    fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), ErrorKind> {
+        if let [Operation::Write(frame), ..] = &*operations {
+            self.commands.borrow_mut().push(frame.to_vec());
+        }
+
+        let writes = match &*operations {
+            [Operation::Write(frame), ..] => frame.first().is_some_and(|opcode| is_write(*opcode)),
+            _ => false,
+        };
+        match self.budget.get() {
+            Some(0) => return Err(ErrorKind::Other),
+            Some(budget) if writes => self.budget.set(Some(budget - 1)),
+            _ => {}
+        }
+
+        if let (Some(stuck), [Operation::Write(frame)]) = (self.stuck.get(), &*operations) {
+            let address = frame[1..].iter().fold(0u32, |address, byte| address << 8 | *byte as u32);
+            if frame[0] == ChipParams::default().opcodes.sector_erase && frame.len() > 1 && address == stuck {
+                let program = [&[ChipParams::default().opcodes.page_program], &frame[1..]].concat();
+                return self.sim.borrow_mut().transaction(&mut [Operation::Write(&program), Operation::Write(&[0x00])]);
+            }
+        }
+
+        self.sim.borrow_mut().transaction(operations)
+    }
+}
+
+/// The simulated chip answers at once, so the async driver runs on it too.
+impl embedded_hal_async::spi::SpiDevice for Board {
+    async fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), ErrorKind> {
+        SpiDevice::transaction(self, operations)
+    }
+}
@@ -0,0 +1,62 @@
+//! Dual-copy configuration blob against the simulated chip, which stays
+//! busy after every program and erase.
+
+mod common;
+
+use common::Board;
+use w25qxx::{ConfigBlob, Error, MAX_CONFIG_BLOB_SIZE};
+
+fn load(blob: &ConfigBlob, flash: &mut common::Flash) -> Option<Vec<u8>> {
+    let mut buffer = [0u8; MAX_CONFIG_BLOB_SIZE];
+    blob.load(flash, &mut buffer).unwrap().map(|len| buffer[..len].to_vec())
+}
+
+#[test]
+fn save_verifies_on_a_busy_chip() {
+    let board = Board::new();
+    let mut flash = board.flash();
+
+    let mut blob = ConfigBlob::open(&mut flash, 0x8000).unwrap();
+    assert_eq!(load(&blob, &mut flash), None);
+    blob.save(&mut flash, b"first").unwrap();
+    blob.save(&mut flash, b"second").unwrap();
+    assert_eq!(blob.sequence(), Some(1));
+
+    let blob = ConfigBlob::open(&mut flash, 0x8000).unwrap();
+    assert_eq!(load(&blob, &mut flash).as_deref(), Some(&b"second"[..]));
+    assert_eq!(blob.sequence(), Some(1));
+}
+
+#[test]
+fn save_rejects_oversized_blob() {
+    let board = Board::new();
+    let mut flash = board.flash();
+    let mut blob = ConfigBlob::open(&mut flash, 0x8000).unwrap();
+
+    assert!(matches!(blob.save(&mut flash, &[0; MAX_CONFIG_BLOB_SIZE + 1]), Err(Error::InvalidArgument)));
+}
+
+#[test]
+fn power_cut_during_save_keeps_a_configuration() {
+    let board = Board::new();
+    let mut flash = board.flash();
+    ConfigBlob::open(&mut flash, 0x8000).unwrap().save(&mut flash, b"initial").unwrap();
+
+    for cut in 0..8 {
+        let mut flash = board.flash();
+        let mut blob = ConfigBlob::open(&mut flash, 0x8000).unwrap();
+        let before = load(&blob, &mut flash).unwrap();
+        let new = format!("configuration {cut}").into_bytes();
+
+        board.cut_after(cut);
+        let saved = blob.save(&mut flash, &new);
+
+        let mut flash = board.flash();
+        let blob = ConfigBlob::open(&mut flash, 0x8000).unwrap();
+        let after = load(&blob, &mut flash).unwrap();
+        match saved {
+            Ok(()) => assert_eq!(after, new, "cut after {cut}"),
+            Err(_) => assert!(after == before || after == new, "cut after {cut}"),
+        }
+    }
+}
@@ -0,0 +1,67 @@
+//! Monotonic counter against the simulated chip, with power cuts before
+//! each program and erase.
+
+mod common;
+
+use common::{Board, Flash};
+use w25qxx::{MonotonicCounter, COUNTER_SECTOR_INCREMENTS, W25QXX_SECTOR_SIZE};
+
+const AREA: u32 = 0x80000;
+const AREA_SIZE: usize = 2 * W25QXX_SECTOR_SIZE;
+
+fn value(flash: &mut Flash) -> u64 {
+    MonotonicCounter::open(flash, AREA, AREA_SIZE).unwrap().value()
+}
+
+#[test]
+fn counts_across_sector_rotations() {
+    let board = Board::new();
+    let mut flash = board.flash();
+    /* Stray data: the counter starts over in a freshly erased area */
+    flash.write(AREA, &[0x00; 8]).unwrap();
+    let mut counter = MonotonicCounter::open(&mut flash, AREA, AREA_SIZE).unwrap();
+    assert_eq!(counter.value(), 0);
+
+    let total = 2 * COUNTER_SECTOR_INCREMENTS + 10;
+    for expected in 1..=total {
+        assert_eq!(counter.increment(&mut flash).unwrap(), expected);
+    }
+
+    let mut flash = board.flash();
+    assert_eq!(value(&mut flash), total);
+}
+
+/// Cuts the power before each program and erase of the increments around
+/// a sector rotation. The count must stay or go up by one.
+#[test]
+fn power_cuts_never_lose_or_skip_counts() {
+    let board = Board::new();
+    let mut flash = board.flash();
+    let mut counter = MonotonicCounter::open(&mut flash, AREA, AREA_SIZE).unwrap();
+    while counter.value() < COUNTER_SECTOR_INCREMENTS - 2 {
+        counter.increment(&mut flash).unwrap();
+    }
+
+    for _ in 0..4 {
+        for cut in 0.. {
+            let mut flash = board.flash();
+            let before = value(&mut flash);
+            let mut counter = MonotonicCounter::open(&mut flash, AREA, AREA_SIZE).unwrap();
+            board.cut_after(cut);
+            let incremented = counter.increment(&mut flash);
+
+            let mut flash = board.flash();
+            let after = value(&mut flash);
+            match incremented {
+                Ok(count) => {
+                    assert_eq!((count, after), (before + 1, before + 1));
+                    break;
+                }
+                Err(_) => assert!(after == before || after == before + 1, "cut after {cut} writes"),
+            }
+        }
+    }
+
+    let mut flash = board.flash();
+    assert_eq!(value(&mut flash), COUNTER_SECTOR_INCREMENTS + 2);
+}
@@ -0,0 +1,70 @@
+//! EEPROM emulation against the simulated chip, with power cuts before each
+//! program and erase.
+
+mod common;
+
+use common::Board;
+use w25qxx::{Eeprom, Error, W25QXX_SECTOR_SIZE};
+
+const AREA: u32 = 0x60000;
+const AREA_SIZE: usize = 2 * W25QXX_SECTOR_SIZE;
+
+#[test]
+fn writes_survive_reopening() {
+    let board = Board::new();
+    let mut flash = board.flash();
+    let mut eeprom = Eeprom::<300>::open(&mut flash, AREA, AREA_SIZE).unwrap();
+    assert_eq!(eeprom.read(296, 4), Some(&[0xFF; 4][..]));
+    assert_eq!(eeprom.read(297, 4), None);
+
+    /* Enough writes to erase each sector a few times */
+    for index in 0..100u32 {
+        eeprom.write(&mut flash, index as usize * 7 % 296, &index.to_le_bytes()).unwrap();
+    }
+    assert!(matches!(eeprom.write(&mut flash, 299, &[0; 2]), Err(Error::InvalidAddress)));
+
+    let mut flash = board.flash();
+    let reopened = Eeprom::<300>::open(&mut flash, AREA, AREA_SIZE).unwrap();
+    assert_eq!(reopened.as_bytes(), eeprom.as_bytes());
+    assert_eq!(reopened.version(), 100);
+}
+
+/// Cuts the power before each program and erase of the writes around a
+/// sector change. The EEPROM must come back with the contents from before
+/// or after the interrupted write.
+#[test]
+fn power_cuts_keep_old_or_new_contents() {
+    let board = Board::new();
+    let mut flash = board.flash();
+    let mut eeprom = Eeprom::<300>::open(&mut flash, AREA, AREA_SIZE).unwrap();
+    let mut cuts = 0;
+
+    for index in 0..40 {
+        let offset = index * 11 % 290;
+        let data = [index as u8; 10];
+        let before = *eeprom.as_bytes();
+        let mut after = before;
+        after[offset..offset + 10].copy_from_slice(&data);
+
+        for cut in 0.. {
+            let mut flash = board.flash();
+            eeprom = Eeprom::<300>::open(&mut flash, AREA, AREA_SIZE).unwrap();
+            board.cut_after(cut);
+            let written = eeprom.write(&mut flash, offset, &data);
+
+            let mut flash = board.flash();
+            eeprom = Eeprom::<300>::open(&mut flash, AREA, AREA_SIZE).unwrap();
+            if written.is_ok() {
+                assert_eq!(eeprom.as_bytes(), &after);
+                break;
+            }
+            cuts += 1;
+            assert!(eeprom.as_bytes() == &before || eeprom.as_bytes() == &after, "write {index}, cut after {cut} writes");
+            if eeprom.as_bytes() == &after {
+                break;
+            }
+        }
+    }
+
+    assert!(cuts > 40);
+}
@@ -0,0 +1,70 @@
+//! Erases checked against the simulated chip, which stays busy after every
+//! program and erase.
+
+mod common;
+
+use common::Board;
+use w25qxx::{Error, W25QXX_SECTOR_SIZE};
+
+#[test]
+fn secure_erase_checks_sectors_once_erased() {
+    let board = Board::new();
+    let mut flash = board.flash();
+
+    flash.write(0x1000, &[0x5A; 2 * W25QXX_SECTOR_SIZE]).unwrap();
+    flash.secure_erase(0x1000, 2 * W25QXX_SECTOR_SIZE).unwrap();
+
+    assert!(board.memory(0x1000, 2 * W25QXX_SECTOR_SIZE).iter().all(|byte| *byte == 0xFF));
+}
+
+#[test]
+fn secure_erase_reports_sector_that_stays_programmed() {
+    let board = Board::new();
+    let mut flash = board.flash();
+    board.stick_sector(0x2000);
+
+    let result = flash.secure_erase(0x1000, 3 * W25QXX_SECTOR_SIZE);
+
+    assert!(matches!(result, Err(Error::EraseFailed { address: 0x2000 })));
+    assert!(board.memory(0x1000, W25QXX_SECTOR_SIZE).iter().all(|byte| *byte == 0xFF));
+    assert!(board.memory(0x3000, W25QXX_SECTOR_SIZE).iter().all(|byte| *byte == 0xFF));
+}
+
+#[test]
+fn sanitize_overwrites_and_checks_every_sector() {
+    let board = Board::new();
+    let mut flash = board.flash();
+    board.stick_sector(0x5000);
+    let mut fills = 0;
+
+    let result = flash.sanitize(0x4000, 3 * W25QXX_SECTOR_SIZE, |data| {
+        data.fill(0xA5);
+        fills += 1;
+    });
+
+    assert!(matches!(result, Err(Error::EraseFailed { address: 0x5000 })));
+    assert_eq!(fills, 3);
+    assert!(board.memory(0x4000, W25QXX_SECTOR_SIZE).iter().all(|byte| *byte == 0xFF));
+    assert!(board.memory(0x6000, W25QXX_SECTOR_SIZE).iter().all(|byte| *byte == 0xFF));
+}
+
+#[test]
+fn erase_unaligned_keeps_bytes_outside_the_range() {
+    let board = Board::new();
+    let mut flash = board.flash();
+    flash.write(0x8000, &[0x00; 4 * W25QXX_SECTOR_SIZE]).unwrap();
+
+    /* Partial head and tail sectors around two whole ones */
+    let erased = flash.erase_unaligned(0x8100, 3 * W25QXX_SECTOR_SIZE, true).unwrap();
+    assert_eq!(erased, 0x8000..0xC000);
+    assert_eq!(board.memory(0x8000, 0x100), [0x00; 0x100]);
+    assert!(board.memory(0x8100, 3 * W25QXX_SECTOR_SIZE).iter().all(|byte| *byte == 0xFF));
+    assert_eq!(board.memory(0xB100, 0xF00), [0x00; 0xF00]);
+
+    /* Head and tail in the same sector */
+    flash.write(0x8000, &[0x00; W25QXX_SECTOR_SIZE]).unwrap();
+    flash.erase_unaligned(0x8100, 0x100, true).unwrap();
+    assert_eq!(board.memory(0x8000, 0x100), [0x00; 0x100]);
+    assert!(board.memory(0x8100, 0x100).iter().all(|byte| *byte == 0xFF));
+    assert_eq!(board.memory(0x8200, 0xE00), [0x00; 0xE00]);
+}
@@ -0,0 +1,97 @@
+//! Identification: JEDEC ID checks, capacity detection and the quirks of
+//! other vendors' parts.
+
+mod common;
+
+use common::{Board, Flash, NoDelay};
+use embedded_hal::spi::ErrorKind;
+use w25qxx::{ChipParams, Error, Family, QuadEnable, W25qxx, W25qxxBuilder, W25QXX_CAPACITY_128, W25QXX_CAPACITY_256};
+
+const W25Q256: ChipParams = ChipParams { device_id: 0x4019, capacity: W25QXX_CAPACITY_256, ..ChipParams::W25Q128 };
+
+type Builder = W25qxxBuilder<Board, NoDelay>;
+
+/// Runs `init()` with `builder` on a chip described by `chip`.
+fn init(chip: ChipParams, builder: impl FnOnce(Builder) -> Builder) -> (Board, Result<Flash, Error<ErrorKind>>) {
+    let board = Board::with_params(chip);
+    board.power_up();
+    let result = builder(W25qxx::builder(board.clone(), NoDelay)).build().unwrap().init().map_err(|error| error.error);
+    (board, result)
+}
+
+#[test]
+fn capacity_is_detected_from_the_id() {
+    for (chip, capacity, dies, family) in [
+        (ChipParams::W25Q128, W25QXX_CAPACITY_128, 1, Family::W25q),
+        (W25Q256, W25QXX_CAPACITY_256, 1, Family::W25q),
+        (ChipParams::W25M512, 2 * W25QXX_CAPACITY_256, 2, Family::W25m),
+    ] {
+        let (_, flash) = init(chip, |builder| builder.params(ChipParams::W25QXX));
+        let flash = flash.unwrap();
+
+        assert_eq!(flash.params().capacity, capacity);
+        assert_eq!(flash.params().dies, dies);
+        assert_eq!(flash.device_info().family, family);
+    }
+}
+
+#[test]
+fn quirks_of_other_vendors_are_applied() {
+    /* Macronix: 0x35 would enter QPI mode, the configuration register is 0x15 */
+    let mut mx25l = ChipParams { manufacturer_id: 0xC2, device_id: 0x2018, ..ChipParams::W25Q128 };
+    mx25l.opcodes.read_status2 = 0x15;
+    let (board, flash) = init(mx25l, |builder| builder.params(ChipParams::W25QXX));
+    let mut flash = flash.unwrap();
+
+    assert_eq!(flash.device_info().family, Family::Mx25l);
+    assert_eq!(flash.params().quad_enable, QuadEnable::Status1Bit6);
+    board.take_commands();
+    flash.status_register(2).unwrap();
+    assert_eq!(board.take_commands(), [vec![0x15]]);
+
+    let gd25q = ChipParams { manufacturer_id: 0xC8, device_id: 0x4017, ..ChipParams::W25Q128 };
+    let (_, flash) = init(gd25q, |builder| builder.params(ChipParams::W25QXX));
+    let flash = flash.unwrap();
+    assert_eq!(flash.device_info().family, Family::Gd25q);
+    assert_eq!(flash.params().capacity, 8 * 1024 * 1024);
+}
+
+#[test]
+fn wrong_or_missing_chips_are_told_apart() {
+    let (_, flash) = init(W25Q256, |builder| builder.params(ChipParams::W25Q128));
+    assert!(matches!(flash, Err(Error::UnexpectedId { manufacturer_id: 0xEF, device_id: 0x4019 })));
+
+    /* Nothing drives MISO */
+    let floating = ChipParams { manufacturer_id: 0xFF, device_id: 0xFFFF, ..ChipParams::W25Q128 };
+    let (_, flash) = init(floating, |builder| builder.params(ChipParams::W25QXX));
+    assert!(matches!(flash, Err(Error::NoResponse)));
+}
+
+#[test]
+fn any_of_accepts_listed_ids_only() {
+    static IDS: [(u8, u16); 2] = [(0xC8, 0x4018), (0xEF, 0x4019)];
+
+    let (_, flash) = init(W25Q256, |builder| builder.expected_ids(&IDS));
+    let flash = flash.unwrap();
+    assert_eq!(flash.params().device_id, 0x4019);
+    assert_eq!(flash.params().capacity, W25QXX_CAPACITY_256);
+
+    let gd25q = ChipParams { manufacturer_id: 0xC8, device_id: 0x4018, ..ChipParams::W25Q128 };
+    let (_, flash) = init(gd25q, |builder| builder.expected_ids(&IDS));
+    assert_eq!(flash.unwrap().device_info().family, Family::Gd25q);
+
+    let (_, flash) = init(ChipParams::W25Q128, |builder| builder.expected_ids(&IDS));
+    assert!(matches!(flash, Err(Error::UnexpectedId { manufacturer_id: 0xEF, device_id: 0x4018 })));
+}
+
+#[test]
+fn skip_trusts_the_parameters() {
+    let unknown = ChipParams { manufacturer_id: 0x1F, device_id: 0x1234, ..ChipParams::W25Q128 };
+    let (board, flash) = init(unknown, |builder| builder.params(ChipParams::W25Q128).skip_id_check());
+    let mut flash = flash.unwrap();
+
+    assert!(board.take_commands().iter().all(|frame| frame[0] != 0x9F));
+    assert_eq!(flash.params().device_id, ChipParams::W25Q128.device_id);
+    flash.write(0x100, &[0x42; 4]).unwrap();
+    assert_eq!(board.memory(0x100, 4), [0x42; 4]);
+}
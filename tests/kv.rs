@@ -0,0 +1,144 @@
+//! Key-value store against the simulated chip, with power cuts before each
+//! program and erase.
+
+mod common;
+
+use common::{Board, Flash};
+use std::collections::BTreeMap;
+use w25qxx::{Error, KvStore, MAX_KEY_SIZE, W25QXX_SECTOR_SIZE};
+
+const AREA: u32 = 0x20000;
+const AREA_SIZE: usize = 3 * W25QXX_SECTOR_SIZE;
+
+type Contents = BTreeMap<Vec<u8>, Vec<u8>>;
+
+/// Operation `index` of the workload: sets of seven keys with values of
+/// varying length, every fifth one a removal.
+fn operation(index: usize) -> (Vec<u8>, Option<Vec<u8>>) {
+    let key = format!("key{}", index % 7).into_bytes();
+    match index % 5 {
+        4 => (key, None),
+        _ => (key, Some(vec![index as u8; 10 + index * 37 % 200])),
+    }
+}
+
+fn run(store: &mut KvStore, flash: &mut Flash, index: usize) -> Result<(), Error<embedded_hal::spi::ErrorKind>> {
+    match operation(index) {
+        (key, Some(value)) => store.set(flash, &key, &value),
+        (key, None) => store.remove(flash, &key).map(|_| ()),
+    }
+}
+
+fn model(contents: &mut Contents, index: usize) {
+    match operation(index) {
+        (key, Some(value)) => contents.insert(key, value),
+        (key, None) => contents.remove(&key),
+    };
+}
+
+fn contents(store: &KvStore, flash: &mut Flash) -> Contents {
+    let mut contents = Contents::new();
+    let mut buffer = [0u8; 256];
+    store
+        .iter(flash, &mut buffer, |key, value| {
+            assert!(contents.insert(key.to_vec(), value.to_vec()).is_none());
+        })
+        .unwrap();
+    contents
+}
+
+#[test]
+fn set_get_and_remove() {
+    let board = Board::new();
+    let mut flash = board.flash();
+    let mut store = KvStore::open(&mut flash, AREA, AREA_SIZE).unwrap();
+    let mut buffer = [0u8; 8];
+
+    store.set(&mut flash, b"name", b"sensor").unwrap();
+    assert_eq!(store.get(&mut flash, b"name", &mut buffer).unwrap(), Some(6));
+    assert_eq!(&buffer[..6], b"sensor");
+    assert!(store.remove(&mut flash, b"name").unwrap());
+    assert!(!store.remove(&mut flash, b"name").unwrap());
+    assert_eq!(store.get(&mut flash, b"name", &mut buffer).unwrap(), None);
+
+    assert!(matches!(store.set(&mut flash, &[b'k'; MAX_KEY_SIZE + 1], b""), Err(Error::InvalidArgument)));
+    assert!(matches!(KvStore::open(&mut flash, AREA + 0x100, AREA_SIZE), Err(Error::NotAligned)));
+    assert!(matches!(KvStore::open(&mut flash, AREA, W25QXX_SECTOR_SIZE), Err(Error::InvalidArgument)));
+}
+
+#[test]
+fn reclaims_sectors_and_reopens() {
+    let board = Board::new();
+    let mut flash = board.flash();
+    let mut store = KvStore::open(&mut flash, AREA, AREA_SIZE).unwrap();
+    let mut expected = Contents::new();
+
+    for index in 0..400 {
+        run(&mut store, &mut flash, index).unwrap();
+        model(&mut expected, index);
+    }
+
+    assert_eq!(contents(&store, &mut flash), expected);
+    let mut flash = board.flash();
+    let store = KvStore::open(&mut flash, AREA, AREA_SIZE).unwrap();
+    assert_eq!(contents(&store, &mut flash), expected);
+}
+
+#[test]
+fn set_fails_once_live_records_fill_the_area() {
+    let board = Board::new();
+    let mut flash = board.flash();
+    let mut store = KvStore::open(&mut flash, AREA, 2 * W25QXX_SECTOR_SIZE).unwrap();
+
+    let mut keys = 0;
+    let error = loop {
+        match store.set(&mut flash, format!("key{keys}").as_bytes(), &[0x5A; 200]) {
+            Ok(()) => keys += 1,
+            Err(error) => break error,
+        }
+    };
+
+    assert!(matches!(error, Error::NoSpace));
+    assert!(keys > 0);
+}
+
+/// Cuts the power before each program and erase of a workload that
+/// reclaims sectors, then once more during the first writes of the next
+/// open. The store must hold the contents before or after the interrupted
+/// operation and take new records.
+#[test]
+fn power_cuts_keep_old_or_new_value() {
+    let board = Board::new();
+    let operations = 80;
+
+    for cut in 0.. {
+        let mut flash = board.flash();
+        flash.erase(AREA, AREA_SIZE).unwrap();
+        let mut store = KvStore::open(&mut flash, AREA, AREA_SIZE).unwrap();
+        let mut expected = Contents::new();
+
+        board.cut_after(cut);
+        let done = (0..operations).take_while(|index| run(&mut store, &mut flash, *index).is_ok()).count();
+        if done == operations {
+            assert!(cut > 100);
+            break;
+        }
+        (0..done).for_each(|index| model(&mut expected, index));
+        let mut next = expected.clone();
+        model(&mut next, done);
+
+        for open_cut in 0..2 {
+            let mut flash = board.flash();
+            board.cut_after(open_cut);
+            let _ = KvStore::open(&mut flash, AREA, AREA_SIZE);
+        }
+
+        let mut flash = board.flash();
+        let mut store = KvStore::open(&mut flash, AREA, AREA_SIZE).unwrap();
+        let found = contents(&store, &mut flash);
+        assert!(found == expected || found == next, "cut after {cut} writes");
+        store.set(&mut flash, b"after", b"cut").unwrap();
+        let mut buffer = [0u8; 3];
+        assert_eq!(store.get(&mut flash, b"after", &mut buffer).unwrap(), Some(3));
+    }
+}
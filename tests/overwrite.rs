@@ -0,0 +1,26 @@
+//! Byte-level overwrites against the simulated chip, which stays busy after
+//! every program and erase.
+
+mod common;
+
+use common::Board;
+use w25qxx::W25QXX_SECTOR_SIZE;
+
+#[test]
+fn overwrite_keeps_the_rest_of_each_sector() {
+    let board = Board::new();
+    let mut flash = board.flash();
+    flash.write(0x1000, &[0x0F; 2 * W25QXX_SECTOR_SIZE]).unwrap();
+
+    /* Bits going 0->1 across a sector boundary: both sectors are rewritten */
+    flash.overwrite(0x1FF0, &[0xF0; 32]).unwrap();
+    /* Bits going 1->0 only: programmed in place */
+    flash.overwrite(0x1FF0, &[0x00; 4]).unwrap();
+
+    let mut expected = vec![0x0F; 2 * W25QXX_SECTOR_SIZE];
+    expected[0xFF0..0x1010].fill(0xF0);
+    expected[0xFF0..0xFF4].fill(0x00);
+    let mut found = vec![0u8; 2 * W25QXX_SECTOR_SIZE];
+    flash.read(0x1000, &mut found).unwrap();
+    assert_eq!(found, expected);
+}
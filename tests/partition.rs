@@ -0,0 +1,37 @@
+//! Splitting the chip into partitions.
+
+mod common;
+
+use common::Board;
+use core::cell::RefCell;
+use w25qxx::{Error, Partition, W25QXX_CAPACITY_128};
+
+#[test]
+fn split_hands_out_one_partition_per_range() {
+    let board = Board::new();
+    let flash = RefCell::new(board.flash());
+
+    let [mut log, mut slot] = Partition::split(&flash, [0x0000..0x4000, 0x4000..0x10000]).unwrap();
+    assert_eq!((log.offset(), log.len()), (0x0000, 0x4000));
+    assert_eq!((slot.offset(), slot.len()), (0x4000, 0xC000));
+
+    log.write(0, &[0x11; 4]).unwrap();
+    slot.write(0, &[0x22; 4]).unwrap();
+    assert_eq!(board.memory(0x0000, 4), [0x11; 4]);
+    assert_eq!(board.memory(0x4000, 4), [0x22; 4]);
+    assert!(matches!(log.write(0x4000, &[0x00]), Err(Error::InvalidAddress)));
+}
+
+#[test]
+fn split_rejects_bad_layouts() {
+    let board = Board::new();
+    let flash = RefCell::new(board.flash());
+    let end = W25QXX_CAPACITY_128 as u32;
+
+    assert!(matches!(Partition::split(&flash, [0x0000..0x2000, 0x1000..0x3000]), Err(Error::InvalidArgument)));
+    #[allow(clippy::reversed_empty_ranges)]
+    let reversed = [0x0000..0x1000, 0x3000..0x2000];
+    assert!(matches!(Partition::split(&flash, reversed), Err(Error::InvalidArgument)));
+    assert!(matches!(Partition::split(&flash, [0x0000..0x1000, 0x1000..0x2800]), Err(Error::NotAligned)));
+    assert!(matches!(Partition::split(&flash, [0x0000..0x1000, end - 0x1000..end + 0x1000]), Err(Error::InvalidAddress)));
+}
@@ -0,0 +1,156 @@
+//! Block protect bits against the datasheet tables of the W25Q128JV and the
+//! W25Q256JV.
+
+mod common;
+
+use common::Board;
+use w25qxx::{BlockProtection, ChipParams, Error, W25QXX_CAPACITY_128, W25QXX_CAPACITY_256};
+
+/// BP, TB, SEC, CMP, protected range, and whether `for_range` picks these
+/// bits for the range (the first match without CMP wins).
+type Row = (u8, bool, bool, bool, u32, u32, bool);
+
+const W25Q128_ROWS: [Row; 36] = [
+    (0, false, false, false, 0, 0, true),
+    /* Upper and lower fractions, 1/64 up to 1/2 */
+    (1, false, false, false, 0xFC_0000, 0x100_0000, true),
+    (2, false, false, false, 0xF8_0000, 0x100_0000, true),
+    (3, false, false, false, 0xF0_0000, 0x100_0000, true),
+    (4, false, false, false, 0xE0_0000, 0x100_0000, true),
+    (5, false, false, false, 0xC0_0000, 0x100_0000, true),
+    (6, false, false, false, 0x80_0000, 0x100_0000, true),
+    (7, false, false, false, 0, 0x100_0000, true),
+    (1, true, false, false, 0, 0x04_0000, true),
+    (2, true, false, false, 0, 0x08_0000, true),
+    (3, true, false, false, 0, 0x10_0000, true),
+    (4, true, false, false, 0, 0x20_0000, true),
+    (5, true, false, false, 0, 0x40_0000, true),
+    (6, true, false, false, 0, 0x80_0000, true),
+    (7, true, true, false, 0, 0x100_0000, false),
+    /* 4 KiB up to 32 KiB at either end */
+    (1, false, true, false, 0xFF_F000, 0x100_0000, true),
+    (2, false, true, false, 0xFF_E000, 0x100_0000, true),
+    (3, false, true, false, 0xFF_C000, 0x100_0000, true),
+    (4, false, true, false, 0xFF_8000, 0x100_0000, true),
+    (6, false, true, false, 0xFF_8000, 0x100_0000, false),
+    (1, true, true, false, 0, 0x1000, true),
+    (4, true, true, false, 0, 0x8000, true),
+    (5, true, true, false, 0, 0x8000, false),
+    /* Complements */
+    (0, false, false, true, 0, 0x100_0000, false),
+    (1, false, false, true, 0, 0xFC_0000, true),
+    (2, false, false, true, 0, 0xF8_0000, true),
+    (3, false, false, true, 0, 0xF0_0000, true),
+    (4, false, false, true, 0, 0xE0_0000, true),
+    (5, false, false, true, 0, 0xC0_0000, true),
+    (6, false, false, true, 0, 0x80_0000, false),
+    (7, false, false, true, 0, 0, false),
+    (1, true, false, true, 0x04_0000, 0x100_0000, true),
+    (6, true, false, true, 0x80_0000, 0x100_0000, false),
+    (1, false, true, true, 0, 0xFF_F000, true),
+    (4, false, true, true, 0, 0xFF_8000, true),
+    (3, true, true, true, 0x4000, 0x100_0000, true),
+];
+
+const W25Q256_ROWS: [Row; 20] = [
+    (0, false, false, false, 0, 0, true),
+    /* 64 KiB doubling up to the upper or lower half, then the whole die */
+    (1, false, false, false, 0x1FF_0000, 0x200_0000, true),
+    (2, false, false, false, 0x1FE_0000, 0x200_0000, true),
+    (3, false, false, false, 0x1FC_0000, 0x200_0000, true),
+    (4, false, false, false, 0x1F8_0000, 0x200_0000, true),
+    (5, false, false, false, 0x1F0_0000, 0x200_0000, true),
+    (6, false, false, false, 0x1E0_0000, 0x200_0000, true),
+    (7, false, false, false, 0x1C0_0000, 0x200_0000, true),
+    (8, false, false, false, 0x180_0000, 0x200_0000, true),
+    (9, false, false, false, 0x100_0000, 0x200_0000, true),
+    (10, false, false, false, 0, 0x200_0000, true),
+    (15, false, false, false, 0, 0x200_0000, false),
+    (1, true, false, false, 0, 0x1_0000, true),
+    (9, true, false, false, 0, 0x100_0000, true),
+    /* No SEC bit: it changes nothing */
+    (1, false, true, false, 0x1FF_0000, 0x200_0000, false),
+    /* Complements */
+    (0, false, false, true, 0, 0x200_0000, false),
+    (1, false, false, true, 0, 0x1FF_0000, true),
+    (8, false, false, true, 0, 0x180_0000, true),
+    (1, true, false, true, 0x1_0000, 0x200_0000, true),
+    (10, false, false, true, 0, 0, false),
+];
+
+fn check_rows(die_size: usize, rows: &[Row]) {
+    for &(bp, tb, sec, cmp, start, end, canonical) in rows {
+        let protection = BlockProtection { bp, tb, sec, cmp };
+        assert_eq!(protection.range(die_size), start..end, "{protection:?}");
+
+        let found = BlockProtection::for_range(die_size, start..end).unwrap();
+        assert_eq!(found.range(die_size), start..end, "{protection:?}");
+        if canonical && start != end {
+            assert_eq!(found, protection);
+        }
+    }
+}
+
+#[test]
+fn w25q128_ranges_match_the_datasheet() {
+    check_rows(W25QXX_CAPACITY_128, &W25Q128_ROWS);
+}
+
+#[test]
+fn w25q256_ranges_match_the_datasheet() {
+    check_rows(W25QXX_CAPACITY_256, &W25Q256_ROWS);
+}
+
+#[test]
+fn for_range_inverts_range() {
+    for (die_size, bp_values) in [(W25QXX_CAPACITY_128, 8), (W25QXX_CAPACITY_256, 16)] {
+        for bp in 0..bp_values {
+            for (tb, sec, cmp) in (0..8).map(|bits| (bits & 1 != 0, bits & 2 != 0, bits & 4 != 0)) {
+                let range = BlockProtection { bp, tb, sec, cmp }.range(die_size);
+                let found = BlockProtection::for_range(die_size, range.clone()).unwrap();
+                assert_eq!(found.range(die_size), range);
+                assert_eq!(BlockProtection::for_range(die_size, found.range(die_size)), Some(found));
+            }
+        }
+    }
+}
+
+#[test]
+fn unrepresentable_ranges_are_rejected() {
+    for range in [0x1000..0x3000, 0..0x3000, 0xFF_D000..0x100_0000, 0x10_0000..0x20_0000, 0..0xFF_0000] {
+        assert_eq!(BlockProtection::for_range(W25QXX_CAPACITY_128, range), None);
+    }
+    /* No sector granularity on the larger parts */
+    for range in [0..0x1000, 0x1FF_F000..0x200_0000, 0..0x3_0000] {
+        assert_eq!(BlockProtection::for_range(W25QXX_CAPACITY_256, range), None);
+    }
+
+    let mut flash = Board::new().flash();
+    assert!(matches!(flash.protect_range(0x1000, 0x2000), Err(Error::InvalidArgument)));
+}
+
+/// Status register 1 and 2 values `protect_range` writes, against the bit
+/// positions of the datasheet: SEC TB BP2..BP0 on the W25Q128, TB BP3..BP0
+/// on the W25Q256, CMP in bit 6 of status register 2.
+#[test]
+fn bits_land_where_the_datasheet_puts_them() {
+    let w25q256 = ChipParams { device_id: 0x4019, capacity: W25QXX_CAPACITY_256, ..ChipParams::W25Q128 };
+    let cases = [
+        (ChipParams::W25Q128, 0xFC_0000, 0x04_0000, 0x04, false),
+        (ChipParams::W25Q128, 0, 0x1000, 0x64, false),
+        (ChipParams::W25Q128, 0xFF_8000, 0x8000, 0x50, false),
+        (ChipParams::W25Q128, 0, 0xFC_0000, 0x04, true),
+        (w25q256, 0, 0x1_0000, 0x44, false),
+        (w25q256, 0x100_0000, 0x100_0000, 0x24, false),
+        (w25q256, 0x1_0000, 0x1FF_0000, 0x44, true),
+    ];
+
+    for (params, address, len, status1, cmp) in cases {
+        let mut flash = Board::with_params(params).flash();
+        flash.protect_range(address, len).unwrap();
+
+        assert_eq!(flash.status_register(1).unwrap() & 0x7C, status1, "0x{address:08X} len 0x{len:X}");
+        assert_eq!(flash.status_register(2).unwrap() & 0x40 != 0, cmp, "0x{address:08X} len 0x{len:X}");
+        assert_eq!(flash.block_protection().unwrap().range(params.die_size()), address..address + len as u32);
+    }
+}
@@ -0,0 +1,60 @@
+//! Sector scrambling, alone and on top of the encrypted region.
+
+mod common;
+
+use w25qxx::SectorScrambler;
+
+const KEY: [u32; 4] = [0x0123_4567, 0x89AB_CDEF, 0xFEDC_BA98, 0x7654_3210];
+
+#[test]
+fn scrambler_is_a_bijection_for_any_sector_count() {
+    /* Powers of four fill the Feistel domain, the others need cycle walking */
+    for sectors in [1, 2, 3, 5, 12, 16, 100, 1000, 4097] {
+        let scrambler = SectorScrambler::new(KEY, sectors);
+
+        let mut physical: Vec<u32> = (0..sectors).map(|logical| scrambler.physical(logical)).collect();
+        for (logical, sector) in physical.iter().enumerate() {
+            assert_eq!(scrambler.logical(*sector), logical as u32, "{sectors} sectors");
+        }
+
+        physical.sort_unstable();
+        assert_eq!(physical, (0..sectors).collect::<Vec<_>>(), "{sectors} sectors");
+        assert_eq!(scrambler.physical(sectors), sectors);
+    }
+
+    let scrambler = SectorScrambler::new(KEY, 1000);
+    assert!((0..1000).any(|logical| scrambler.physical(logical) != logical));
+}
+
+#[cfg(feature = "encryption")]
+#[test]
+fn scrambled_encrypted_region_round_trips() {
+    use common::Board;
+    use embedded_storage::nor_flash::{NorFlash, ReadNorFlash};
+    use w25qxx::{EncryptedRegion, Scrambled, W25QXX_SECTOR_SIZE};
+
+    let board = Board::new();
+    let key: [u8; 32] = core::array::from_fn(|index| index as u8);
+    /* Twelve sectors, not a power of four */
+    let region = EncryptedRegion::new(board.flash(), 0x10000..0x1C000, &key).unwrap();
+    let mut flash = Scrambled::new(region, KEY);
+    assert_eq!(flash.capacity(), 12 * W25QXX_SECTOR_SIZE);
+
+    let data: Vec<u8> = (0..64).map(|index| index as u8).collect();
+    flash.erase(0, 2 * W25QXX_SECTOR_SIZE as u32).unwrap();
+    flash.write(W25QXX_SECTOR_SIZE as u32 - 32, &data).unwrap();
+
+    let mut buffer = [0u8; 64];
+    flash.read(W25QXX_SECTOR_SIZE as u32 - 32, &mut buffer).unwrap();
+    assert_eq!(buffer[..], data[..]);
+
+    /* Both halves landed, encrypted, in the scrambled sectors of the range */
+    for (logical, half) in [(W25QXX_SECTOR_SIZE as u32 - 32, &data[..32]), (W25QXX_SECTOR_SIZE as u32, &data[32..])] {
+        let chip = board.memory(0x10000 + flash.physical_address(logical), 32);
+        assert_ne!(chip, half);
+        assert!(chip.iter().any(|byte| *byte != 0xFF));
+    }
+
+    /* The region's write unit still applies through the scrambler */
+    assert!(flash.write(0x100, &[0x00; 8]).is_err());
+}
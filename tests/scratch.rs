@@ -0,0 +1,31 @@
+//! Scratch sector pool against the simulated chip.
+
+mod common;
+
+use common::Board;
+use w25qxx::{ScratchPool, W25QXX_SECTOR_SIZE};
+
+#[test]
+fn lends_the_least_worn_sector_and_ignores_foreign_ones() {
+    let board = Board::new();
+    let mut flash = board.flash();
+    let mut pool = ScratchPool::<2>::new(0x10000);
+    let mut other = ScratchPool::<2>::new(0x20000);
+
+    let first = pool.acquire(&mut flash).unwrap().unwrap();
+    pool.release(first);
+    let first = pool.acquire(&mut flash).unwrap().unwrap();
+    assert_eq!(first.address(), 0x10000 + W25QXX_SECTOR_SIZE as u32);
+    let second = pool.acquire(&mut flash).unwrap().unwrap();
+    assert!(pool.acquire(&mut flash).unwrap().is_none());
+
+    /* Below and above the pool */
+    pool.release(other.acquire(&mut flash).unwrap().unwrap());
+    let mut low = ScratchPool::<1>::new(0);
+    pool.release(low.acquire(&mut flash).unwrap().unwrap());
+    assert_eq!(pool.in_use(), 2);
+
+    pool.release(first);
+    pool.release(second);
+    assert_eq!(pool.in_use(), 0);
+}
@@ -0,0 +1,63 @@
+//! `find` and `rfind` at the edges of their chunks and ranges.
+
+mod common;
+
+use common::Board;
+use w25qxx::{Error, W25QXX_PAGE_SIZE};
+
+const NEEDLE: [u8; 4] = [0xDE, 0xAD, 0xBE, 0xEF];
+
+#[test]
+fn needle_straddling_a_chunk_boundary_is_found() {
+    let board = Board::new();
+    let mut flash = board.flash();
+
+    /* Chunks are two pages long, counted from the start or the end of the range */
+    flash.write(0x1000 + 2 * W25QXX_PAGE_SIZE as u32 - 2, &NEEDLE).unwrap();
+    assert_eq!(flash.find(0x1000..0x2000, &NEEDLE).unwrap(), Some(0x11FE));
+
+    flash.write(0x4000 - 2 * W25QXX_PAGE_SIZE as u32 - 2, &NEEDLE).unwrap();
+    assert_eq!(flash.rfind(0x3000..0x4000, &NEEDLE).unwrap(), Some(0x3DFE));
+}
+
+#[test]
+fn needle_at_the_end_of_the_range_is_found() {
+    let board = Board::new();
+    let mut flash = board.flash();
+    flash.write(0x1FFC, &NEEDLE).unwrap();
+
+    assert_eq!(flash.find(0x1000..0x2000, &NEEDLE).unwrap(), Some(0x1FFC));
+    assert_eq!(flash.rfind(0x1000..0x2000, &NEEDLE).unwrap(), Some(0x1FFC));
+    /* The last byte alone */
+    assert_eq!(flash.find(0x1000..0x2000, &NEEDLE[3..]).unwrap(), Some(0x1FFF));
+    assert_eq!(flash.rfind(0x1000..0x2000, &NEEDLE[3..]).unwrap(), Some(0x1FFF));
+
+    /* One byte short of the needle */
+    assert_eq!(flash.find(0x1000..0x1FFF, &NEEDLE).unwrap(), None);
+    assert_eq!(flash.rfind(0x1FFD..0x2000, &NEEDLE).unwrap(), None);
+}
+
+#[test]
+fn first_and_last_match_are_told_apart() {
+    let board = Board::new();
+    let mut flash = board.flash();
+    flash.write(0x1010, &NEEDLE).unwrap();
+    flash.write(0x1800, &NEEDLE).unwrap();
+
+    assert_eq!(flash.find(0x1000..0x2000, &NEEDLE).unwrap(), Some(0x1010));
+    assert_eq!(flash.rfind(0x1000..0x2000, &NEEDLE).unwrap(), Some(0x1800));
+    assert_eq!(flash.find(0x1011..0x2000, &NEEDLE).unwrap(), Some(0x1800));
+    assert_eq!(flash.rfind(0x1000..0x1803, &NEEDLE).unwrap(), Some(0x1010));
+}
+
+#[test]
+fn empty_or_oversized_needles_are_rejected() {
+    let mut flash = Board::new().flash();
+
+    assert!(matches!(flash.find(0..0x1000, &[]), Err(Error::InvalidArgument)));
+    assert!(matches!(flash.rfind(0..0x1000, &[]), Err(Error::InvalidArgument)));
+    assert!(matches!(flash.find(0..0x1000, &[0x00; W25QXX_PAGE_SIZE + 1]), Err(Error::InvalidArgument)));
+    /* An empty range holds nothing */
+    assert_eq!(flash.find(0x1000..0x1000, &NEEDLE).unwrap(), None);
+    assert_eq!(flash.rfind(0x1000..0x1000, &NEEDLE).unwrap(), None);
+}
@@ -0,0 +1,56 @@
+//! Basic Flash Parameter table decoding.
+
+use w25qxx::sfdp::{EraseType, Sfdp};
+
+/// BFPT of a W25Q128, with `density` and the erase types replaced.
+fn table(density: u32, erase12: u32, erase34: u32) -> [u32; 9] {
+    [0xFFF9_20E5, density, 0x6B08_EB44, 0x3B08_BB42, 0xFFFF_FFFE, 0xFF00_FFFF, 0xEB40_FFFF, erase12, erase34]
+}
+
+fn w25q128(density: u32) -> Option<Sfdp> {
+    Sfdp::parse((1, 6), &table(density, 0x520F_200C, 0xFF00_D810))
+}
+
+#[test]
+fn density_in_bits_minus_one() {
+    assert_eq!(w25q128(0x07FF_FFFF).unwrap().capacity, 16 * 1024 * 1024);
+    /* One sector is the smallest part the driver handles */
+    assert_eq!(w25q128(0x0000_7FFF).unwrap().capacity, 4096);
+}
+
+#[test]
+fn density_as_power_of_two() {
+    assert_eq!(w25q128(0x8000_0000 | 27).unwrap().capacity, 16 * 1024 * 1024);
+    assert_eq!(w25q128(0x8000_0000 | 29).unwrap().capacity, 64 * 1024 * 1024);
+}
+
+#[test]
+fn unaddressable_densities_are_rejected() {
+    /* Zero bytes and less than one sector */
+    assert_eq!(w25q128(0), None);
+    assert_eq!(w25q128(0x0000_3FFF), None);
+    /* More than 4 GiB per die, and exponents no integer holds */
+    assert_eq!(w25q128(0x8000_0000 | 36), None);
+    assert_eq!(w25q128(0x8000_0000 | 64), None);
+    assert_eq!(w25q128(0xFFFF_FFFF), None);
+}
+
+#[test]
+fn erase_types_with_implausible_sizes_are_unsupported() {
+    let sfdp = w25q128(0x07FF_FFFF).unwrap();
+    assert_eq!(
+        sfdp.erase_types,
+        [
+            Some(EraseType { size: 4096, opcode: 0x20 }),
+            Some(EraseType { size: 32 * 1024, opcode: 0x52 }),
+            Some(EraseType { size: 64 * 1024, opcode: 0xD8 }),
+            /* Exponent 0xFF */
+            None,
+        ]
+    );
+
+    /* Exponent 0 marks an unused type, 26 (64 MiB) is the largest taken */
+    let sfdp = Sfdp::parse((1, 6), &table(0x07FF_FFFF, 0x521A_2000, 0xDC1B_D800)).unwrap();
+    assert_eq!(sfdp.erase_types, [None, Some(EraseType { size: 64 * 1024 * 1024, opcode: 0x52 }), None, None]);
+    assert_eq!(sfdp.erase_opcode(4096), None);
+}
@@ -0,0 +1,72 @@
+//! Stacked-die W25M512JV: accesses crossing from die 0 to die 1.
+
+mod common;
+
+use common::Board;
+use w25qxx::{ChipParams, W25QXX_CAPACITY_256, W25QXX_SECTOR_SIZE};
+
+const DIE_SELECT: u8 = ChipParams::W25M512.opcodes.die_select;
+
+/// Die selects and array commands among `commands`, with their addresses.
+fn die_traffic(commands: Vec<Vec<u8>>, opcode: u8) -> Vec<Vec<u8>> {
+    commands.into_iter().filter(|frame| frame[0] == DIE_SELECT || frame[0] == opcode).collect()
+}
+
+#[test]
+fn accesses_cross_the_die_boundary() {
+    let board = Board::with_params(ChipParams::W25M512);
+    let mut flash = board.flash();
+    let boundary = W25QXX_CAPACITY_256 as u32;
+    let data: Vec<u8> = (0..0x200).map(|index| index as u8).collect();
+    board.take_commands();
+
+    /* Page program, 4-byte addresses relative to each die */
+    flash.write(boundary - 0x100, &data).unwrap();
+    assert_eq!(
+        die_traffic(board.take_commands(), 0x02),
+        [vec![0x02, 0x01, 0xFF, 0xFF, 0x00], vec![DIE_SELECT, 1], vec![0x02, 0x00, 0x00, 0x00, 0x00]]
+    );
+    assert_eq!(board.memory(boundary - 0x100, 0x100), data[..0x100]);
+    assert_eq!(board.memory(boundary, 0x100), data[0x100..]);
+
+    let mut buffer = [0u8; 0x200];
+    flash.read(boundary - 0x100, &mut buffer).unwrap();
+    assert_eq!(buffer[..], data[..]);
+    assert_eq!(
+        die_traffic(board.take_commands(), 0x0B),
+        [vec![DIE_SELECT, 0], vec![0x0B, 0x01, 0xFF, 0xFF, 0x00], vec![DIE_SELECT, 1], vec![0x0B, 0x00, 0x00, 0x00, 0x00]]
+    );
+
+    /* Sector erase of the last sector of die 0 and the first of die 1 only */
+    flash.write(boundary - 0x2000, &[0x00; 0x10]).unwrap();
+    flash.write(boundary + 0x1000, &[0x00; 0x10]).unwrap();
+    board.take_commands();
+    flash.erase(boundary - W25QXX_SECTOR_SIZE as u32, 2 * W25QXX_SECTOR_SIZE).unwrap();
+    assert_eq!(
+        die_traffic(board.take_commands(), 0x20),
+        [vec![DIE_SELECT, 0], vec![0x20, 0x01, 0xFF, 0xF0, 0x00], vec![DIE_SELECT, 1], vec![0x20, 0x00, 0x00, 0x00, 0x00]]
+    );
+    assert!(board.memory(boundary - 0x1000, 0x2000).iter().all(|byte| *byte == 0xFF));
+    assert_eq!(board.memory(boundary - 0x2000, 0x10), [0x00; 0x10]);
+    assert_eq!(board.memory(boundary + 0x1000, 0x10), [0x00; 0x10]);
+}
+
+#[test]
+fn init_puts_both_dies_in_4_byte_mode() {
+    let board = Board::with_params(ChipParams::W25M512);
+    board.flash();
+
+    /* Die selected whenever Enter 4-Byte Address Mode goes out */
+    let enter = ChipParams::W25M512.opcodes.enter_4byte_address;
+    let mut die = 0;
+    let mut entered = Vec::new();
+    for frame in board.take_commands() {
+        match frame[..] {
+            [DIE_SELECT, selected] => die = selected,
+            [opcode] if opcode == enter => entered.push(die),
+            _ => {}
+        }
+    }
+    entered.sort_unstable();
+    assert_eq!(entered, [0, 1]);
+}
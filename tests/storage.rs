@@ -0,0 +1,40 @@
+//! `embedded-storage` traits against the simulated chip, which stays busy
+//! after every program and erase.
+
+mod common;
+
+use common::{Board, NoDelay};
+use embedded_storage::nor_flash::{NorFlash, ReadNorFlash};
+use embedded_storage_async::nor_flash as async_nor_flash;
+use w25qxx::{W25qxxAsync, W25QXX_SECTOR_SIZE};
+
+#[test]
+fn reads_return_data_just_written_or_erased() {
+    let board = Board::new();
+    let mut flash = board.flash();
+    let mut buffer = [0u8; 16];
+
+    NorFlash::write(&mut flash, 0x1010, &[0x3C; 16]).unwrap();
+    ReadNorFlash::read(&mut flash, 0x1010, &mut buffer).unwrap();
+    assert_eq!(buffer, [0x3C; 16]);
+
+    NorFlash::erase(&mut flash, 0x1000, 0x1000 + W25QXX_SECTOR_SIZE as u32).unwrap();
+    ReadNorFlash::read(&mut flash, 0x1010, &mut buffer).unwrap();
+    assert_eq!(buffer, [0xFF; 16]);
+}
+
+#[tokio::test]
+async fn async_reads_return_data_just_written_or_erased() {
+    let board = Board::new();
+    let mut flash = W25qxxAsync::new(board.clone(), NoDelay).unwrap();
+    flash.init().await.unwrap();
+    let mut buffer = [0u8; 16];
+
+    async_nor_flash::NorFlash::write(&mut flash, 0x1010, &[0x3C; 16]).await.unwrap();
+    async_nor_flash::ReadNorFlash::read(&mut flash, 0x1010, &mut buffer).await.unwrap();
+    assert_eq!(buffer, [0x3C; 16]);
+
+    async_nor_flash::NorFlash::erase(&mut flash, 0x1000, 0x1000 + W25QXX_SECTOR_SIZE as u32).await.unwrap();
+    async_nor_flash::ReadNorFlash::read(&mut flash, 0x1010, &mut buffer).await.unwrap();
+    assert_eq!(buffer, [0xFF; 16]);
+}
@@ -0,0 +1,96 @@
+//! Program/Erase Suspend, by hand and for `suspend_for_reads`, while the
+//! simulated chip is still erasing.
+
+#![cfg(feature = "suspend")]
+
+mod common;
+
+use common::{Board, Flash, NoDelay};
+use w25qxx::{ChipParams, W25qxx, W25QXX_SECTOR_SIZE};
+
+const SUSPEND: u8 = ChipParams::W25Q128.opcodes.suspend;
+
+fn flash(board: &Board, suspend_for_reads: bool) -> Flash {
+    board.power_up();
+    W25qxx::builder(board.clone(), NoDelay).suspend_for_reads(suspend_for_reads).build().unwrap().init().unwrap()
+}
+
+#[test]
+fn suspend_only_goes_out_while_busy() {
+    let board = Board::new();
+    let mut flash = board.flash();
+    flash.write(0x8000, &[0x5A; 16]).unwrap();
+    let mut buffer = [0u8; 16];
+    flash.read(0x8000, &mut buffer).unwrap();
+
+    /* Idle: nothing to suspend */
+    board.take_commands();
+    assert!(!flash.suspend().unwrap());
+    assert!(!board.take_commands().iter().any(|frame| frame[..] == [SUSPEND]));
+
+    /* The simulated erase ends at the suspend, leaving nothing to resume */
+    board.sim.borrow_mut().set_busy_polls(100);
+    flash.erase(0x1000, W25QXX_SECTOR_SIZE).unwrap();
+    assert!(!flash.suspend().unwrap());
+    assert!(board.take_commands().iter().any(|frame| frame[..] == [SUSPEND]));
+    assert!(!flash.is_suspended().unwrap());
+    assert!(!flash.resume().unwrap());
+
+    flash.read(0x8000, &mut buffer).unwrap();
+    assert_eq!(buffer, [0x5A; 16]);
+    assert!(board.memory(0x1000, W25QXX_SECTOR_SIZE).iter().all(|byte| *byte == 0xFF));
+}
+
+#[test]
+fn read_elsewhere_suspends_the_erase() {
+    let board = Board::new();
+    let mut flash = flash(&board, true);
+    flash.write(0x8000, &[0x5A; 16]).unwrap();
+    flash.write(0x1000, &[0x00; 16]).unwrap();
+    board.sim.borrow_mut().set_busy_polls(100);
+
+    flash.erase(0x1000, W25QXX_SECTOR_SIZE).unwrap();
+    board.take_commands();
+    let mut buffer = [0u8; 16];
+    flash.read(0x8000, &mut buffer).unwrap();
+
+    /* Read while busy, the chip would return the complement */
+    assert_eq!(buffer, [0x5A; 16]);
+    let commands = board.take_commands();
+    let suspend = commands.iter().position(|frame| frame[..] == [SUSPEND]).unwrap();
+    let read = commands.iter().position(|frame| frame[0] == 0x0B).unwrap();
+    assert!(suspend < read);
+    assert!(board.memory(0x1000, 16).iter().all(|byte| *byte == 0xFF));
+}
+
+#[test]
+fn read_inside_the_erased_sector_waits() {
+    let board = Board::new();
+    let mut flash = flash(&board, true);
+    flash.write(0x1000, &[0x00; 16]).unwrap();
+    board.sim.borrow_mut().set_busy_polls(5);
+
+    flash.erase(0x1000, W25QXX_SECTOR_SIZE).unwrap();
+    board.take_commands();
+    let mut buffer = [0u8; 16];
+    flash.read(0x1008, &mut buffer).unwrap();
+
+    assert_eq!(buffer, [0xFF; 16]);
+    let commands = board.take_commands();
+    assert!(!commands.iter().any(|frame| frame[..] == [SUSPEND]));
+    assert!(commands.iter().filter(|frame| frame[..] == [0x05]).count() >= 5);
+}
+
+#[test]
+fn reads_without_the_option_never_suspend() {
+    let board = Board::new();
+    let mut flash = flash(&board, false);
+    flash.write(0x8000, &[0x5A; 16]).unwrap();
+
+    flash.erase(0x1000, W25QXX_SECTOR_SIZE).unwrap();
+    let mut buffer = [0u8; 16];
+    flash.read(0x8000, &mut buffer).unwrap();
+
+    assert_eq!(buffer, [0x5A; 16]);
+    assert!(!board.take_commands().iter().any(|frame| frame[..] == [SUSPEND]));
+}
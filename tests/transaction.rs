@@ -0,0 +1,98 @@
+//! Journaled transactions against the simulated chip.
+
+mod common;
+
+use common::Board;
+use embedded_hal::spi::ErrorKind;
+use w25qxx::transaction::Recovery;
+use w25qxx::{Error, Journal, W25QXX_SECTOR_SIZE};
+
+fn journal() -> Journal {
+    Journal::new::<ErrorKind>(0x10000, 4 * W25QXX_SECTOR_SIZE).unwrap()
+}
+
+#[test]
+fn commit_copies_every_region() {
+    let board = Board::new();
+    let mut flash = board.flash();
+    let journal = journal();
+
+    flash.write(0x1000, &[0x00; 64]).unwrap();
+    let mut transaction = journal.begin(&mut flash).unwrap();
+    transaction.write(0x1000, &[0x11; 300]).unwrap();
+    transaction.write(0x1000 + 300, &[0x22; 100]).unwrap();
+    transaction.write(0x4000, &[0x33; 10]).unwrap();
+    transaction.commit().unwrap();
+
+    assert_eq!(board.memory(0x1000, 300), [0x11; 300]);
+    assert_eq!(board.memory(0x1000 + 300, 100), [0x22; 100]);
+    assert_eq!(board.memory(0x4000, 10), [0x33; 10]);
+    assert_eq!(journal.recover(&mut flash).unwrap(), Recovery::Clean);
+}
+
+#[test]
+fn write_rejects_regions_sharing_sectors() {
+    let board = Board::new();
+    let mut flash = board.flash();
+    let journal = journal();
+
+    let mut transaction = journal.begin(&mut flash).unwrap();
+    transaction.write(0x2000, &[0x11; W25QXX_SECTOR_SIZE + 16]).unwrap();
+    transaction.write(0x1000, &[0x22; 16]).unwrap();
+
+    assert!(matches!(transaction.write(0x2000, &[0x33; 16]), Err(Error::InvalidAddress)));
+    assert!(matches!(transaction.write(0x3000, &[0x33; 16]), Err(Error::InvalidAddress)));
+    /* Continuing the region at 0x1000 would run into the one at 0x2000 */
+    assert!(matches!(transaction.write(0x1000 + 16, &[0x33; W25QXX_SECTOR_SIZE]), Err(Error::InvalidAddress)));
+    transaction.write(0x1000 + 16, &[0x33; 16]).unwrap();
+    transaction.write(0x4000, &[0x44; 16]).unwrap();
+}
+
+#[test]
+fn write_rejects_data_past_the_chip() {
+    let board = Board::new();
+    let mut flash = board.flash();
+    let capacity = flash.capacity() as u32;
+    let journal = journal();
+
+    let mut transaction = journal.begin(&mut flash).unwrap();
+
+    assert!(matches!(transaction.write(capacity - W25QXX_SECTOR_SIZE as u32, &[0x11; W25QXX_SECTOR_SIZE + 1]), Err(Error::InvalidAddress)));
+    assert!(matches!(transaction.write(u32::MAX - 0xFFF, &[0x11; 0x2000]), Err(Error::InvalidAddress)));
+    transaction.write(capacity - W25QXX_SECTOR_SIZE as u32, &[0x11; W25QXX_SECTOR_SIZE]).unwrap();
+}
+
+#[test]
+fn recover_replays_a_transaction_cut_short_while_applied() {
+    let board = Board::new();
+    let journal = journal();
+    let mut replayed = 0;
+
+    for cut in 0..20 {
+        let mut flash = board.flash();
+        flash.write(0x1000, &[0x00; 16]).unwrap();
+        let mut transaction = journal.begin(&mut flash).unwrap();
+        transaction.write(0x1000, &[0x11; 600]).unwrap();
+        transaction.write(0x3000, &[0x22; 600]).unwrap();
+
+        board.cut_after(cut);
+        let committed = transaction.commit();
+
+        let mut flash = board.flash();
+        let recovery = journal.recover(&mut flash).unwrap();
+        let first = board.memory(0x1000, 600);
+        let second = board.memory(0x3000, 600);
+        if committed.is_ok() {
+            break;
+        }
+        if recovery == Recovery::Replayed {
+            replayed += 1;
+            assert_eq!((first, second), (vec![0x11; 600], vec![0x22; 600]), "cut after {cut}");
+        } else {
+            assert_eq!(&first[..16], [0x00; 16], "cut after {cut}");
+        }
+        flash.erase(0, 0x4000).unwrap();
+    }
+
+    assert!(replayed > 0);
+}
@@ -0,0 +1,51 @@
+//! Wear tracking against the simulated chip, with power cuts before each
+//! program and erase.
+
+mod common;
+
+use common::Board;
+use w25qxx::{WearTracked, W25QXX_SECTOR_SIZE};
+
+const TRACKED: u32 = 0x10000;
+const TABLE: u32 = 0xA0000;
+
+#[test]
+fn counts_erases_of_tracked_sectors() {
+    let board = Board::new();
+    let mut flash = WearTracked::<_, _, 16>::new(board.flash(), TRACKED);
+
+    flash.erase(TRACKED, 2 * W25QXX_SECTOR_SIZE).unwrap();
+    flash.erase(TRACKED + W25QXX_SECTOR_SIZE as u32, W25QXX_SECTOR_SIZE).unwrap();
+    flash.erase(0, W25QXX_SECTOR_SIZE).unwrap();
+
+    assert_eq!(flash.wear_map().erase_count(TRACKED), Some(1));
+    assert_eq!(flash.wear_map().erase_count(TRACKED + W25QXX_SECTOR_SIZE as u32), Some(2));
+    assert_eq!(flash.wear_map().total(), 3);
+}
+
+#[test]
+fn persisted_table_survives_power_cuts() {
+    let board = Board::new();
+    let mut flash = WearTracked::<_, _, 16>::new(board.flash(), TRACKED);
+    flash.erase(TRACKED, W25QXX_SECTOR_SIZE).unwrap();
+    flash.persist(TABLE).unwrap();
+
+    for cut in 0..8 {
+        let mut flash = WearTracked::<_, _, 16>::new(board.flash(), TRACKED);
+        assert!(flash.load(TABLE).unwrap());
+        let before = *flash.wear_map().counts();
+        flash.erase(TRACKED, W25QXX_SECTOR_SIZE).unwrap();
+        let after = *flash.wear_map().counts();
+
+        board.cut_after(cut);
+        let persisted = flash.persist(TABLE);
+
+        let mut flash = WearTracked::<_, _, 16>::new(board.flash(), TRACKED);
+        assert!(flash.load(TABLE).unwrap(), "cut after {cut} writes");
+        let loaded = *flash.wear_map().counts();
+        match persisted {
+            Ok(()) => assert_eq!(loaded, after, "cut after {cut} writes"),
+            Err(_) => assert!(loaded == before || loaded == after, "cut after {cut} writes"),
+        }
+    }
+}